@@ -0,0 +1,441 @@
+use crate::messages::tr;
+
+/// How deeply variables may hold expressions referring to other variables
+const MAX_DEPTH: usize = 100;
+
+/// Operators, longest first so that `<<=` isn't read as `<` `<=`
+const OPERATORS: &[&str] = &[
+    "<<=", ">>=", "**", "++", "--", "<<", ">>", "<=", ">=", "==", "!=", "&&", "||", "*=", "/=", "%=", "+=", "-=",
+    "&=", "^=", "|=", "+", "-", "*", "/", "%", "<", ">", "=", "!", "~", "&", "^", "|", "?", ":", ",", "(", ")",
+];
+
+/// The variables an expression reads and assigns
+pub trait Variables {
+    fn get(&self, name: &str) -> String;
+    fn set(&mut self, name: &str, value: &str);
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(String),
+    Name(String),
+    Operator(&'static str),
+}
+
+/// Evaluate a shell arithmetic expression, as in `(( ... ))`: integers
+/// with C's operators and precedence, where variables are read by name and
+/// can be assigned. Values wrap around on overflow, as in bash.
+pub fn evaluate(expression: &str, vars: &mut dyn Variables) -> Result<i64, String> {
+    evaluate_at(expression, vars, 0)
+}
+
+fn evaluate_at(expression: &str, vars: &mut dyn Variables, depth: usize) -> Result<i64, String> {
+    if depth > MAX_DEPTH {
+        return Err(tr!("expression recursion level exceeded"));
+    }
+    let tokens = tokenize(expression)?;
+    if tokens.is_empty() {
+        return Ok(0);
+    }
+    let mut evaluator = Evaluator { tokens, position: 0, vars, skipping: false, depth };
+    let value = evaluator.comma()?;
+    match evaluator.tokens.get(evaluator.position) {
+        None => Ok(value),
+        Some(token) => Err(evaluator.syntax_error(token)),
+    }
+}
+
+fn tokenize(expression: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = expression;
+    loop {
+        rest = rest.trim_start();
+        let Some(ch) = rest.chars().next() else {
+            return Ok(tokens);
+        };
+        let word_len = |rest: &str| rest.find(|c: char| !(c.is_ascii_alphanumeric() || matches!(c, '_' | '#' | '@'))).unwrap_or(rest.len());
+        if ch.is_ascii_digit() {
+            let len = word_len(rest);
+            tokens.push(Token::Number(rest[..len].to_string()));
+            rest = &rest[len..];
+        } else if ch.is_ascii_alphabetic() || ch == '_' {
+            let len = rest.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_')).unwrap_or(rest.len());
+            tokens.push(Token::Name(rest[..len].to_string()));
+            rest = &rest[len..];
+        } else if let Some(operator) = OPERATORS.iter().find(|operator| rest.starts_with(*operator)) {
+            tokens.push(Token::Operator(operator));
+            rest = &rest[operator.len()..];
+        } else {
+            return Err(tr!("syntax error: invalid arithmetic operator (error token is \"{}\")", rest));
+        }
+    }
+}
+
+/// A number in C's notation (`0x1f`, `017`) or bash's `base#digits`
+fn parse_number(text: &str) -> Result<i64, String> {
+    let invalid = || tr!("value too great for base (error token is \"{}\")", text);
+    let (base, digits) = if let Some((base, digits)) = text.split_once('#') {
+        match base.parse::<u32>() {
+            Ok(base) if (2..=64).contains(&base) => (base, digits),
+            _ => return Err(tr!("invalid arithmetic base (error token is \"{}\")", text)),
+        }
+    } else if let Some(digits) = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        (16, digits)
+    } else if text.len() > 1 && text.starts_with('0') {
+        (8, &text[1..])
+    } else {
+        (10, text)
+    };
+    if digits.is_empty() {
+        return Err(invalid());
+    }
+
+    let mut value: i64 = 0;
+    for ch in digits.chars() {
+        // Up to base 36 letters are digits whatever their case; above it
+        // lowercase comes first, then uppercase, `@` and `_`
+        let digit = match ch {
+            '0'..='9' => ch as u32 - '0' as u32,
+            'a'..='z' => ch as u32 - 'a' as u32 + 10,
+            'A'..='Z' if base <= 36 => ch as u32 - 'A' as u32 + 10,
+            'A'..='Z' => ch as u32 - 'A' as u32 + 36,
+            '@' => 62,
+            '_' => 63,
+            _ => return Err(invalid()),
+        };
+        if digit >= base {
+            return Err(invalid());
+        }
+        value = value.wrapping_mul(i64::from(base)).wrapping_add(i64::from(digit));
+    }
+    Ok(value)
+}
+
+/// Evaluates while parsing. The operands that `&&`, `||` and `?:` skip
+/// are still parsed, but with `skipping` set so that they neither assign
+/// nor fail.
+struct Evaluator<'a> {
+    tokens: Vec<Token>,
+    position: usize,
+    vars: &'a mut dyn Variables,
+    skipping: bool,
+    depth: usize,
+}
+
+impl Evaluator<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    /// Take the next token if it is one of `operators`
+    fn eat(&mut self, operators: &[&str]) -> Option<&'static str> {
+        match self.peek() {
+            Some(Token::Operator(operator)) if operators.contains(operator) => {
+                let operator = *operator;
+                self.position += 1;
+                Some(operator)
+            }
+            _ => None,
+        }
+    }
+
+    fn syntax_error(&self, token: &Token) -> String {
+        let text = match token {
+            Token::Number(text) | Token::Name(text) => text.as_str(),
+            Token::Operator(operator) => operator,
+        };
+        tr!("syntax error in expression (error token is \"{}\")", text)
+    }
+
+    fn expect(&mut self, operator: &str) -> Result<(), String> {
+        match self.peek() {
+            Some(Token::Operator(found)) if *found == operator => {
+                self.position += 1;
+                Ok(())
+            }
+            Some(token) => Err(self.syntax_error(token)),
+            None => Err(tr!("syntax error: operand expected")),
+        }
+    }
+
+    /// Parse an operand while ignoring its value and side effects
+    fn skip(&mut self, operand: fn(&mut Self) -> Result<i64, String>) -> Result<(), String> {
+        let skipping = std::mem::replace(&mut self.skipping, true);
+        let result = operand(self);
+        self.skipping = skipping;
+        result.map(|_| ())
+    }
+
+    fn comma(&mut self) -> Result<i64, String> {
+        let mut value = self.assignment()?;
+        while self.eat(&[","]).is_some() {
+            value = self.assignment()?;
+        }
+        Ok(value)
+    }
+
+    fn assignment(&mut self) -> Result<i64, String> {
+        const ASSIGNMENTS: &[&str] = &["=", "*=", "/=", "%=", "+=", "-=", "<<=", ">>=", "&=", "^=", "|="];
+        if let Some(Token::Name(name)) = self.peek()
+            && let Some(Token::Operator(operator)) = self.tokens.get(self.position + 1)
+            && ASSIGNMENTS.contains(operator)
+        {
+            let (name, operator) = (name.clone(), *operator);
+            self.position += 2;
+            let value = self.assignment()?;
+            let value = match operator.strip_suffix('=').unwrap() {
+                "" => value,
+                binary => {
+                    let current = self.variable(&name)?;
+                    self.binary(binary, current, value)?
+                }
+            };
+            return self.assign(&name, value);
+        }
+        self.conditional()
+    }
+
+    fn conditional(&mut self) -> Result<i64, String> {
+        let condition = self.logical_or()?;
+        if self.eat(&["?"]).is_none() {
+            return Ok(condition);
+        }
+        if condition != 0 {
+            let value = self.comma()?;
+            self.expect(":")?;
+            self.skip(Self::conditional)?;
+            Ok(value)
+        } else {
+            self.skip(Self::comma)?;
+            self.expect(":")?;
+            self.conditional()
+        }
+    }
+
+    fn logical_or(&mut self) -> Result<i64, String> {
+        let mut value = self.logical_and()?;
+        while self.eat(&["||"]).is_some() {
+            if value != 0 {
+                self.skip(Self::logical_and)?;
+                value = 1;
+            } else {
+                value = i64::from(self.logical_and()? != 0);
+            }
+        }
+        Ok(value)
+    }
+
+    fn logical_and(&mut self) -> Result<i64, String> {
+        let mut value = self.binary_level(0)?;
+        while self.eat(&["&&"]).is_some() {
+            if value == 0 {
+                self.skip(|evaluator| evaluator.binary_level(0))?;
+            } else {
+                value = i64::from(self.binary_level(0)? != 0);
+            }
+        }
+        Ok(value)
+    }
+
+    /// The left-associative binary operators, loosest binding first
+    fn binary_level(&mut self, level: usize) -> Result<i64, String> {
+        const LEVELS: &[&[&str]] = &[
+            &["|"],
+            &["^"],
+            &["&"],
+            &["==", "!="],
+            &["<", "<=", ">", ">="],
+            &["<<", ">>"],
+            &["+", "-"],
+            &["*", "/", "%"],
+        ];
+        let Some(operators) = LEVELS.get(level) else {
+            return self.power();
+        };
+        let mut value = self.binary_level(level + 1)?;
+        while let Some(operator) = self.eat(operators) {
+            let right = self.binary_level(level + 1)?;
+            value = self.binary(operator, value, right)?;
+        }
+        Ok(value)
+    }
+
+    fn power(&mut self) -> Result<i64, String> {
+        let base = self.unary()?;
+        if self.eat(&["**"]).is_none() {
+            return Ok(base);
+        }
+        let exponent = self.power()?;
+        self.binary("**", base, exponent)
+    }
+
+    fn unary(&mut self) -> Result<i64, String> {
+        if let Some(operator) = self.eat(&["++", "--"]) {
+            let Some(Token::Name(name)) = self.peek().cloned() else {
+                return Err(tr!("syntax error: operand expected"));
+            };
+            self.position += 1;
+            let step = if operator == "++" { 1 } else { -1 };
+            let value = self.variable(&name)?.wrapping_add(step);
+            return self.assign(&name, value);
+        }
+        match self.eat(&["!", "~", "+", "-"]) {
+            Some("!") => Ok(i64::from(self.unary()? == 0)),
+            Some("~") => Ok(!self.unary()?),
+            Some("+") => self.unary(),
+            Some(_) => Ok(self.unary()?.wrapping_neg()),
+            None => self.postfix(),
+        }
+    }
+
+    fn postfix(&mut self) -> Result<i64, String> {
+        match self.peek().cloned() {
+            Some(Token::Number(text)) => {
+                self.position += 1;
+                parse_number(&text)
+            }
+            Some(Token::Name(name)) => {
+                self.position += 1;
+                let value = self.variable(&name)?;
+                if let Some(operator) = self.eat(&["++", "--"]) {
+                    let step = if operator == "++" { 1 } else { -1 };
+                    self.assign(&name, value.wrapping_add(step))?;
+                }
+                Ok(value)
+            }
+            Some(Token::Operator("(")) => {
+                self.position += 1;
+                let value = self.comma()?;
+                self.expect(")")?;
+                Ok(value)
+            }
+            Some(token) => Err(self.syntax_error(&token)),
+            None => Err(tr!("syntax error: operand expected")),
+        }
+    }
+
+    /// A variable's value, itself an expression; unset or empty is 0
+    fn variable(&mut self, name: &str) -> Result<i64, String> {
+        if self.skipping {
+            return Ok(0);
+        }
+        let value = self.vars.get(name);
+        if let Ok(number) = value.trim().parse() {
+            return Ok(number);
+        }
+        evaluate_at(&value, self.vars, self.depth + 1)
+    }
+
+    fn assign(&mut self, name: &str, value: i64) -> Result<i64, String> {
+        if !self.skipping {
+            self.vars.set(name, &value.to_string());
+        }
+        Ok(value)
+    }
+
+    fn binary(&self, operator: &str, left: i64, right: i64) -> Result<i64, String> {
+        if matches!(operator, "/" | "%") && right == 0 {
+            if self.skipping {
+                return Ok(0);
+            }
+            return Err(tr!("division by 0"));
+        }
+        Ok(match operator {
+            "|" => left | right,
+            "^" => left ^ right,
+            "&" => left & right,
+            "==" => i64::from(left == right),
+            "!=" => i64::from(left != right),
+            "<" => i64::from(left < right),
+            "<=" => i64::from(left <= right),
+            ">" => i64::from(left > right),
+            ">=" => i64::from(left >= right),
+            "<<" => left.wrapping_shl(right as u32),
+            ">>" => left.wrapping_shr(right as u32),
+            "+" => left.wrapping_add(right),
+            "-" => left.wrapping_sub(right),
+            "*" => left.wrapping_mul(right),
+            "/" => left.wrapping_div(right),
+            "%" => left.wrapping_rem(right),
+            "**" => {
+                if right < 0 {
+                    return Err(tr!("exponent less than 0"));
+                }
+                left.wrapping_pow(u32::try_from(right).unwrap_or(u32::MAX))
+            }
+            _ => unreachable!("not a binary operator: {}", operator),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    impl Variables for HashMap<String, String> {
+        fn get(&self, name: &str) -> String {
+            HashMap::get(self, name).cloned().unwrap_or_default()
+        }
+
+        fn set(&mut self, name: &str, value: &str) {
+            self.insert(name.to_string(), value.to_string());
+        }
+    }
+
+    fn eval(expression: &str, vars: &mut HashMap<String, String>) -> Result<i64, String> {
+        evaluate(expression, vars)
+    }
+
+    #[test]
+    fn test_operators_and_precedence() {
+        let vars = &mut HashMap::new();
+        assert_eq!(eval("1 + 2 * 3", vars), Ok(7));
+        assert_eq!(eval("(1 + 2) * 3", vars), Ok(9));
+        assert_eq!(eval("2 ** 3 ** 2", vars), Ok(512));
+        assert_eq!(eval("-2 ** 2", vars), Ok(4));
+        assert_eq!(eval("7 / 2, 7 % 3, -7 / 2", vars), Ok(-3));
+        assert_eq!(eval("1 << 4 | 1", vars), Ok(17));
+        assert_eq!(eval("5 > 3 && 2 >= 2 && !0 && ~0 == -1", vars), Ok(1));
+        assert_eq!(eval("0 || 3 != 3", vars), Ok(0));
+        assert_eq!(eval("1 ? 2 : 3", vars), Ok(2));
+        assert_eq!(eval("0 ? 2 : 0 ? 3 : 4", vars), Ok(4));
+        assert_eq!(eval("0x1f + 017 + 2#101 + 64#_", vars), Ok(31 + 15 + 5 + 63));
+        assert_eq!(eval("9223372036854775807 + 1", vars), Ok(i64::MIN));
+        assert_eq!(eval("", vars), Ok(0));
+    }
+
+    #[test]
+    fn test_variables_and_assignment() {
+        let vars = &mut HashMap::new();
+        vars.insert("x".to_string(), "5".to_string());
+        vars.insert("expr".to_string(), "x * 2".to_string());
+        assert_eq!(eval("x++", vars), Ok(5));
+        assert_eq!(vars["x"], "6");
+        assert_eq!(eval("++x + unset", vars), Ok(7));
+        assert_eq!(eval("y = x *= 2", vars), Ok(14));
+        assert_eq!(vars["y"], "14");
+        assert_eq!(eval("expr + 1", vars), Ok(29));
+        assert_eq!(eval("y <<= 1, y", vars), Ok(28));
+
+        // Operands that aren't evaluated have no effect
+        assert_eq!(eval("0 && (z = 1 / 0)", vars), Ok(0));
+        assert_eq!(eval("1 || z++", vars), Ok(1));
+        assert_eq!(eval("1 ? 2 : z--", vars), Ok(2));
+        assert!(!vars.contains_key("z"));
+    }
+
+    #[test]
+    fn test_errors() {
+        let vars = &mut HashMap::new();
+        assert!(eval("1 / 0", vars).unwrap_err().contains("division by 0"));
+        assert!(eval("1 +", vars).is_err());
+        assert!(eval("(1", vars).is_err());
+        assert!(eval("1 2", vars).is_err());
+        assert!(eval("09", vars).is_err());
+        assert!(eval("2 ** -1", vars).is_err());
+        assert!(eval("1 $ 2", vars).is_err());
+        vars.insert("loop".to_string(), "loop".to_string());
+        assert!(eval("loop", vars).unwrap_err().contains("recursion"));
+    }
+}
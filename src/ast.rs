@@ -1,7 +1,8 @@
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
+use std::fmt;
 
 /// Abstract Syntax Tree for shell commands
-#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Command {
     Simple(SimpleCommand),
     Pipeline(Pipeline),
@@ -15,87 +16,112 @@ pub enum Command {
     FunctionDef(FunctionDef),
     Group(Box<Command>),
     Redirected(RedirectedCommand),
+    Timed(TimedCommand),
+    /// `((expression))`, true when the expression isn't 0
+    Arithmetic(String),
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct RedirectedCommand {
     pub command: Box<Command>,
     pub redirections: Vec<Redirection>,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct SimpleCommand {
     pub assignments: Vec<Assignment>,
     pub words: Vec<Word>,
     pub redirections: Vec<Redirection>,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Assignment {
     pub name: String,
     pub value: String,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Word {
     pub value: String,
+    /// The commands of each `$(...)` in `value`, in order. The parser
+    /// fills these in to check them and for visitors; expansion works
+    /// from `value`, as it does for words kept as plain strings.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub substitutions: Vec<Vec<Command>>,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Redirection {
     pub kind: RedirectionKind,
     pub fd: Option<i32>,
     pub target: RedirectionTarget,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum RedirectionKind {
     Input,          // <
     Output,         // >
     Append,         // >>
     Heredoc,        // <<
     HeredocStrip,   // <<-
+    HereString,     // <<<
     InputDup,       // <&
     OutputDup,      // >&
     InputOutput,    // <>
     Clobber,        // >|
     OutputBoth,     // &>
+    AppendBoth,     // &>>
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum RedirectionTarget {
     File(String),
     Fd(i32),
     Close,          // &- or >&-
+    Heredoc {
+        delimiter: String,
+        body: String,
+        /// Whether any of the delimiter was quoted or escaped, as in
+        /// `<<'EOF'`, which leaves the body as it is rather than expanded
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        quoted: bool,
+    },
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+/// A pipeline run by `time`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TimedCommand {
+    /// `-p`: report in the POSIX format
+    pub posix: bool,
+    pub command: Box<Command>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Pipeline {
     pub negated: bool,
     pub commands: Vec<Command>,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct List {
     pub items: Vec<ListItem>,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct ListItem {
     pub command: Command,
     pub separator: Separator,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Separator {
     Sequential,     // ; or newline
     Background,     // &
     And,            // &&
     Or,             // ||
-    Pipe,           // |
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct IfCommand {
     pub condition: Box<Command>,
     pub then_part: Box<Command>,
@@ -103,38 +129,41 @@ pub struct IfCommand {
     pub else_part: Option<Box<Command>>,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct WhileCommand {
     pub condition: Box<Command>,
     pub body: Box<Command>,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct UntilCommand {
     pub condition: Box<Command>,
     pub body: Box<Command>,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct ForCommand {
     pub variable: String,
     pub words: Vec<String>,
     pub body: Box<Command>,
+    /// `for -P N`: how many iterations may run at once
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parallel: Option<usize>,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct CaseCommand {
     pub word: String,
     pub cases: Vec<CaseClause>,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct CaseClause {
     pub patterns: Vec<String>,
     pub body: Box<Command>,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct FunctionDef {
     pub name: String,
     pub body: Box<Command>,
@@ -159,3 +188,345 @@ impl Default for SimpleCommand {
         Self::new()
     }
 }
+
+impl Word {
+    pub fn new(value: impl Into<String>) -> Self {
+        Self { value: value.into(), substitutions: Vec::new() }
+    }
+}
+
+impl Assignment {
+    pub fn new(name: impl Into<String>, value: impl Into<String>) -> Self {
+        Self { name: name.into(), value: value.into() }
+    }
+}
+
+#[allow(dead_code)]
+impl Redirection {
+    pub fn new(kind: RedirectionKind, target: RedirectionTarget) -> Self {
+        Self { kind, fd: None, target }
+    }
+
+    /// A redirection of `kind` to or from the file at `path`
+    pub fn file(kind: RedirectionKind, path: impl Into<String>) -> Self {
+        Self::new(kind, RedirectionTarget::File(path.into()))
+    }
+
+    /// Redirect `fd` instead of the operator's default
+    pub fn fd(self, fd: i32) -> Self {
+        Self { fd: Some(fd), ..self }
+    }
+}
+
+/// Builders for making and combining commands in code, e.g. to inject
+/// instrumentation into a parsed tree before running or printing it:
+///
+/// `Command::simple(["make"]).and(Command::simple(["echo", "built"])).background()`
+#[allow(dead_code)]
+impl SimpleCommand {
+    pub fn arg(mut self, word: impl Into<String>) -> Self {
+        self.words.push(Word::new(word));
+        self
+    }
+
+    pub fn assign(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.assignments.push(Assignment::new(name, value));
+        self
+    }
+
+    pub fn redirect(mut self, redirection: Redirection) -> Self {
+        self.redirections.push(redirection);
+        self
+    }
+}
+
+impl From<SimpleCommand> for Command {
+    fn from(command: SimpleCommand) -> Self {
+        Command::Simple(command)
+    }
+}
+
+#[allow(dead_code)]
+impl Command {
+    /// A simple command running `words`
+    pub fn simple(words: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        let words = words.into_iter().map(Word::new).collect();
+        Command::Simple(SimpleCommand { words, ..SimpleCommand::new() })
+    }
+
+    /// `self | next`
+    pub fn pipe(self, next: Command) -> Self {
+        match self {
+            Command::Pipeline(mut pipeline) if !pipeline.negated => {
+                pipeline.commands.push(next);
+                Command::Pipeline(pipeline)
+            }
+            command => Command::Pipeline(Pipeline { negated: false, commands: vec![command, next] }),
+        }
+    }
+
+    /// `! self`
+    pub fn negate(self) -> Self {
+        match self {
+            Command::Pipeline(mut pipeline) => {
+                pipeline.negated = !pipeline.negated;
+                Command::Pipeline(pipeline)
+            }
+            command => Command::Pipeline(Pipeline { negated: true, commands: vec![command] }),
+        }
+    }
+
+    /// `self && next`
+    pub fn and(self, next: Command) -> Self {
+        self.chain(Separator::And, next)
+    }
+
+    /// `self || next`
+    pub fn or(self, next: Command) -> Self {
+        self.chain(Separator::Or, next)
+    }
+
+    /// `self; next`
+    pub fn then(self, next: Command) -> Self {
+        self.chain(Separator::Sequential, next)
+    }
+
+    /// `self &`, or `{ self; } &` for a list, which runs in the background
+    /// as a whole
+    pub fn background(self) -> Self {
+        let command = match self {
+            Command::List(list) => Command::Group(Box::new(Command::List(list))),
+            command => command,
+        };
+        Command::List(List { items: vec![ListItem { command, separator: Separator::Background }] })
+    }
+
+    /// `( self )`
+    pub fn subshell(self) -> Self {
+        Command::Subshell(Box::new(self))
+    }
+
+    /// `{ self; }`
+    pub fn group(self) -> Self {
+        Command::Group(Box::new(self))
+    }
+
+    /// Add a redirection, to the command itself if it is a simple one
+    pub fn redirect(self, redirection: Redirection) -> Self {
+        self.redirect_all(vec![redirection])
+    }
+
+    pub fn redirect_all(self, mut redirections: Vec<Redirection>) -> Self {
+        match self {
+            _ if redirections.is_empty() => self,
+            Command::Simple(mut command) => {
+                command.redirections.append(&mut redirections);
+                Command::Simple(command)
+            }
+            Command::Redirected(mut redirected) => {
+                redirected.redirections.append(&mut redirections);
+                Command::Redirected(redirected)
+            }
+            command => Command::Redirected(RedirectedCommand { command: Box::new(command), redirections }),
+        }
+    }
+
+    /// Append `next` to a list, or start one. A list ending in `&` is
+    /// grouped first, unless `next` just follows it.
+    fn chain(self, separator: Separator, next: Command) -> Self {
+        let mut items = match self {
+            Command::List(list)
+                if separator == Separator::Sequential
+                    || list.items.last().is_some_and(|item| item.separator == Separator::Sequential) =>
+            {
+                list.items
+            }
+            Command::List(list) => vec![ListItem { command: Command::List(list).group(), separator: Separator::Sequential }],
+            command => vec![ListItem { command, separator: Separator::Sequential }],
+        };
+        if let Some(last) = items.last_mut()
+            && last.separator == Separator::Sequential
+        {
+            last.separator = separator;
+        }
+        items.push(ListItem { command: next, separator: Separator::Sequential });
+        Command::List(List { items })
+    }
+}
+
+/// Renders commands back as shell source on one line, e.g. for job listings
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Command::Simple(cmd) => write!(f, "{}", cmd),
+            Command::Pipeline(pipeline) => {
+                if pipeline.negated {
+                    write!(f, "! ")?;
+                }
+                write_joined(f, &pipeline.commands, " | ")
+            }
+            Command::List(list) => {
+                for (i, item) in list.items.iter().enumerate() {
+                    let last = i + 1 == list.items.len();
+                    write!(f, "{}", item.command)?;
+                    match item.separator {
+                        Separator::Sequential if last => {}
+                        Separator::Sequential => write!(f, "; ")?,
+                        Separator::Background if last => write!(f, " &")?,
+                        Separator::Background => write!(f, " & ")?,
+                        Separator::And => write!(f, " && ")?,
+                        Separator::Or => write!(f, " || ")?,
+                    }
+                }
+                Ok(())
+            }
+            Command::Subshell(inner) => write!(f, "({})", inner),
+            Command::Arithmetic(expression) => write!(f, "(({}))", expression),
+            Command::Group(inner) => {
+                // A command run in the background already ends in a separator
+                let body = inner.to_string();
+                let separator = if body.ends_with('&') { "" } else { ";" };
+                write!(f, "{{ {}{} }}", body, separator)
+            }
+            Command::If(if_cmd) => {
+                write!(f, "if {}; then {}; ", if_cmd.condition, if_cmd.then_part)?;
+                for (condition, body) in &if_cmd.elif_parts {
+                    write!(f, "elif {}; then {}; ", condition, body)?;
+                }
+                if let Some(else_part) = &if_cmd.else_part {
+                    write!(f, "else {}; ", else_part)?;
+                }
+                write!(f, "fi")
+            }
+            Command::While(while_cmd) => {
+                write!(f, "while {}; do {}; done", while_cmd.condition, while_cmd.body)
+            }
+            Command::Until(until_cmd) => {
+                write!(f, "until {}; do {}; done", until_cmd.condition, until_cmd.body)
+            }
+            Command::For(for_cmd) => {
+                write!(f, "for ")?;
+                if let Some(jobs) = for_cmd.parallel {
+                    write!(f, "-P {} ", jobs)?;
+                }
+                write!(f, "{} in", for_cmd.variable)?;
+                for word in &for_cmd.words {
+                    write!(f, " {}", word)?;
+                }
+                write!(f, "; do {}; done", for_cmd.body)
+            }
+            Command::Case(case_cmd) => {
+                write!(f, "case {} in", case_cmd.word)?;
+                for clause in &case_cmd.cases {
+                    write!(f, " {}) {};;", clause.patterns.join("|"), clause.body)?;
+                }
+                write!(f, " esac")
+            }
+            Command::FunctionDef(def) => write!(f, "{}() {}", def.name, def.body),
+            Command::Timed(timed) => {
+                write!(f, "time")?;
+                if timed.posix {
+                    write!(f, " -p")?;
+                }
+                let command = timed.command.to_string();
+                if !command.is_empty() {
+                    write!(f, " {}", command)?;
+                }
+                Ok(())
+            }
+            Command::Redirected(redirected) => {
+                write!(f, "{}", redirected.command)?;
+                for redirection in &redirected.redirections {
+                    write!(f, " {}", redirection)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl fmt::Display for SimpleCommand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let parts = self
+            .assignments
+            .iter()
+            .map(|a| format!("{}={}", a.name, a.value))
+            .chain(self.words.iter().map(|w| w.value.clone()))
+            .chain(self.redirections.iter().map(|r| r.to_string()));
+        write_joined(f, parts, " ")
+    }
+}
+
+impl fmt::Display for Redirection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(fd) = self.fd {
+            write!(f, "{}", fd)?;
+        }
+        let operator = match self.kind {
+            RedirectionKind::Input => "<",
+            RedirectionKind::Output => ">",
+            RedirectionKind::Append => ">>",
+            RedirectionKind::Heredoc => "<<",
+            RedirectionKind::HeredocStrip => "<<-",
+            RedirectionKind::HereString => "<<<",
+            RedirectionKind::InputDup => "<&",
+            RedirectionKind::OutputDup => ">&",
+            RedirectionKind::InputOutput => "<>",
+            RedirectionKind::Clobber => ">|",
+            RedirectionKind::OutputBoth => "&>",
+            RedirectionKind::AppendBoth => "&>>",
+        };
+        match &self.target {
+            RedirectionTarget::File(word) => write!(f, "{} {}", operator, word),
+            RedirectionTarget::Fd(fd) => write!(f, "{}{}", operator, fd),
+            RedirectionTarget::Close => write!(f, "{}-", operator),
+            RedirectionTarget::Heredoc { delimiter, .. } => write!(f, "{}{}", operator, delimiter),
+        }
+    }
+}
+
+fn write_joined<T: fmt::Display>(
+    f: &mut fmt::Formatter<'_>,
+    items: impl IntoIterator<Item = T>,
+    separator: &str,
+) -> fmt::Result {
+    for (i, item) in items.into_iter().enumerate() {
+        if i > 0 {
+            write!(f, "{}", separator)?;
+        }
+        write!(f, "{}", item)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::script;
+
+    #[test]
+    fn test_builders_match_parser() {
+        let built = Command::simple(["make"])
+            .pipe(Command::simple(["tee", "log"]))
+            .and(SimpleCommand::new().assign("MSG", "ok").arg("echo").arg("built").into())
+            .or(Command::simple(["false"]).negate())
+            .then(Command::simple(["cat"]).group().redirect(Redirection::file(RedirectionKind::Input, "input")));
+        let source = "make | tee log && MSG=ok echo built || ! false; { cat; } < input";
+        assert_eq!(built.to_string(), source);
+        assert_eq!(script::parse(source).unwrap(), vec![built.clone()]);
+        assert_eq!(built.background().to_string(), format!("{{ {}; }} &", source));
+
+        let redirected = Command::simple(["ls"]).redirect(Redirection::file(RedirectionKind::Output, "err").fd(2));
+        assert_eq!(script::parse("ls 2> err").unwrap(), vec![redirected]);
+        let after_background = Command::simple(["sleep", "1"]).background().and(Command::simple(["wait"]));
+        assert_eq!(after_background.to_string(), "{ sleep 1 & } && wait");
+    }
+
+    #[test]
+    fn test_json_round_trip() {
+        let commands = script::parse("for x in a b; do echo $x > out; done\nf() { cat <<EOF\nhi\nEOF\n}").unwrap();
+        let json = serde_json::to_string(&commands).unwrap();
+        let decoded: Vec<Command> = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded, commands);
+    }
+}
@@ -0,0 +1,218 @@
+use crate::lexer;
+use crate::messages::tr;
+
+/// Brace expansion, the first expansion of a word: `a{b,c}d` stands for
+/// `abd acd`, `{1..3}` for `1 2 3` and `{a..e..2}` for `a c e`. Braces
+/// holding neither a comma nor a sequence are left as they are, as are
+/// those of `${...}`. More than `max_words` words is an error, found out
+/// before any of them is made.
+pub fn expand(word: &str, max_words: usize) -> Result<Vec<String>, String> {
+    expand_within(word, max_words).ok_or_else(|| tr!("{}: brace expansion makes more than {} words", word, max_words))
+}
+
+fn expand_within(word: &str, max_words: usize) -> Option<Vec<String>> {
+    let Some((open, close, group)) = find_group(word) else {
+        return Some(vec![word.to_string()]);
+    };
+    let middles = match group {
+        Group::List(items) => {
+            let mut words = Vec::new();
+            for item in items {
+                words.extend(expand_within(item, max_words)?);
+                if words.len() > max_words {
+                    return None;
+                }
+            }
+            words
+        }
+        Group::Sequence(sequence) => sequence.words(max_words)?,
+    };
+    let suffixes = expand_within(&word[close + 1..], max_words)?;
+    if middles.len().saturating_mul(suffixes.len()) > max_words {
+        return None;
+    }
+
+    let prefix = &word[..open];
+    Some(
+        middles
+            .iter()
+            .flat_map(|middle| suffixes.iter().map(move |suffix| format!("{}{}{}", prefix, middle, suffix)))
+            .collect(),
+    )
+}
+
+enum Group<'a> {
+    /// `{a,b,c}`
+    List(Vec<&'a str>),
+    /// `{x..y}` or `{x..y..step}`
+    Sequence(Sequence),
+}
+
+/// The first brace group in `word` that expands: its `{` and `}` and
+/// what it holds
+fn find_group(word: &str) -> Option<(usize, usize, Group<'_>)> {
+    let mut index = 0;
+    while index < word.len() {
+        if word[index..].starts_with('{')
+            && let Some(close) = matching_brace(word, index)
+            && let Some(group) = parse_group(&word[index + 1..close])
+        {
+            return Some((index, close, group));
+        }
+        index = step(word, index);
+    }
+    None
+}
+
+/// The `}` closing the `{` at `open`
+fn matching_brace(word: &str, open: usize) -> Option<usize> {
+    let mut depth = 0;
+    let mut index = open;
+    while index < word.len() {
+        match word.as_bytes()[index] {
+            b'{' => depth += 1,
+            b'}' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(index);
+                }
+            }
+            _ => {}
+        }
+        index = step(word, index);
+    }
+    None
+}
+
+/// The commas at the top level of `inner` separate a list; without any, it
+/// may be a sequence
+fn parse_group(inner: &str) -> Option<Group<'_>> {
+    let mut items = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    let mut index = 0;
+    while index < inner.len() {
+        match inner.as_bytes()[index] {
+            b'{' => depth += 1,
+            b'}' => depth -= 1,
+            b',' if depth == 0 => {
+                items.push(&inner[start..index]);
+                start = index + 1;
+            }
+            _ => {}
+        }
+        index = step(inner, index);
+    }
+    if items.is_empty() {
+        return Sequence::parse(inner).map(Group::Sequence);
+    }
+    items.push(&inner[start..]);
+    Some(Group::List(items))
+}
+
+/// Where the character at `index` ends, taking a backslash with the
+/// character it escapes, a quoted string, and `${...}`, `$(...)` or `<(...)`
+/// as a whole, as braces and commas in them aren't brace expansion's
+fn step(word: &str, index: usize) -> usize {
+    index + lexer::unit_len(&word[index..])
+}
+
+/// A sequence of integers, zero-padded to `width`, or of letters
+enum Sequence {
+    Numbers { start: i64, end: i64, step: i64, width: usize },
+    Letters { start: char, end: char, step: i64 },
+}
+
+impl Sequence {
+    fn parse(inner: &str) -> Option<Self> {
+        let parts: Vec<&str> = inner.split("..").collect();
+        let (first, last) = match parts.as_slice() {
+            [first, last] | [first, last, _] => (*first, *last),
+            _ => return None,
+        };
+        // The direction comes from the ends, so the step's sign is ignored
+        let step = match parts.get(2) {
+            Some(step) => step.parse::<i64>().ok()?.checked_abs()?.max(1),
+            None => 1,
+        };
+
+        if let (Ok(start), Ok(end)) = (first.parse::<i64>(), last.parse::<i64>()) {
+            let padded = |bound: &str| {
+                let digits = bound.trim_start_matches('-');
+                digits.len() > 1 && digits.starts_with('0')
+            };
+            let width = if padded(first) || padded(last) { first.len().max(last.len()) } else { 0 };
+            return Some(Self::Numbers { start, end, step, width });
+        }
+        let letter = |bound: &str| {
+            let mut chars = bound.chars();
+            chars.next().filter(|ch| ch.is_ascii_alphabetic() && chars.next().is_none())
+        };
+        Some(Self::Letters { start: letter(first)?, end: letter(last)?, step })
+    }
+
+    /// The words of the sequence; None if there are more than `max_words`
+    fn words(&self, max_words: usize) -> Option<Vec<String>> {
+        let (start, end, step) = match *self {
+            Self::Numbers { start, end, step, .. } => (start, end, step),
+            Self::Letters { start, end, step } => (i64::from(start as u8), i64::from(end as u8), step),
+        };
+        let count = (i128::from(end) - i128::from(start)).unsigned_abs() / step as u128 + 1;
+        if count > max_words as u128 {
+            return None;
+        }
+
+        let (start, step) = (i128::from(start), i128::from(if start <= end { step } else { -step }));
+        let values = (0..count as i128).map(|n| start + n * step);
+        Some(match *self {
+            Self::Numbers { width, .. } => values.map(|value| format!("{:0width$}", value, width = width)).collect(),
+            Self::Letters { .. } => values.map(|value| char::from(value as u8).to_string()).collect(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    fn expand(word: &str) -> Vec<String> {
+        super::expand(word, 1000).unwrap()
+    }
+
+    #[test]
+    fn test_lists() {
+        assert_eq!(expand("a{b,c}d"), ["abd", "acd"]);
+        assert_eq!(expand("{a,b}{1,2}"), ["a1", "a2", "b1", "b2"]);
+        assert_eq!(expand("x{a,b{1,2},}"), ["xa", "xb1", "xb2", "x"]);
+        assert_eq!(expand("file.{rs,toml}"), ["file.rs", "file.toml"]);
+        // Nothing to expand
+        assert_eq!(expand("{a}"), ["{a}"]);
+        assert_eq!(expand("{}"), ["{}"]);
+        assert_eq!(expand("{a{b,c}"), ["{ab", "{ac"]);
+        assert_eq!(expand("\\{a,b}"), ["\\{a,b}"]);
+        assert_eq!(expand("${HOME}/{a,b}"), ["${HOME}/a", "${HOME}/b"]);
+        assert_eq!(expand("${x:-a,b}"), ["${x:-a,b}"]);
+        assert_eq!(expand("$(echo {a,b})"), ["$(echo {a,b})"]);
+        assert_eq!(expand("<(echo {a,b})"), ["<(echo {a,b})"]);
+        assert_eq!(expand("\"{a,b}\"'{c,d}'"), ["\"{a,b}\"'{c,d}'"]);
+        assert_eq!(expand("{\"a b\",'c,d'}"), ["\"a b\"", "'c,d'"]);
+    }
+
+    #[test]
+    fn test_sequences() {
+        assert_eq!(expand("{1..4}"), ["1", "2", "3", "4"]);
+        assert_eq!(expand("{3..-1..2}"), ["3", "1", "-1"]);
+        assert_eq!(expand("{08..11}"), ["08", "09", "10", "11"]);
+        assert_eq!(expand("{a..f..2}"), ["a", "c", "e"]);
+        assert_eq!(expand("{C..A}"), ["C", "B", "A"]);
+        assert_eq!(expand("v{1..2}.{a,b}"), ["v1.a", "v1.b", "v2.a", "v2.b"]);
+        assert_eq!(expand("{1..b}"), ["{1..b}"]);
+        assert_eq!(expand("{1..2..x}"), ["{1..2..x}"]);
+    }
+
+    #[test]
+    fn test_limit() {
+        assert!(super::expand("{1..100000000}", 1000).is_err());
+        assert!(super::expand("{1..10}{1..10}{1..10}{1..2}", 1000).is_err());
+        assert_eq!(super::expand("{1..10}{1..10}{1..10}", 1000).unwrap().len(), 1000);
+        assert!(super::expand("{-9223372036854775808..9223372036854775807}", 1000).is_err());
+    }
+}
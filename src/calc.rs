@@ -0,0 +1,412 @@
+use crate::arith::Variables;
+use crate::messages::tr;
+
+/// The format of `calc` without `-f`, as awk prints numbers
+pub const DEFAULT_FORMAT: &str = "%g";
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(f64),
+    Name(String),
+    Operator(&'static str),
+}
+
+/// Evaluate a floating-point expression, as `calc` does: `+ - * / %`,
+/// `**` for powers, parentheses, functions such as `sqrt(x)` and
+/// variables read by name. Variables are never assigned.
+pub fn evaluate(expression: &str, vars: &dyn Variables) -> Result<f64, String> {
+    let tokens = tokenize(expression)?;
+    let mut evaluator = Evaluator { tokens, position: 0, vars };
+    let value = evaluator.sum()?;
+    match evaluator.tokens.get(evaluator.position) {
+        None => Ok(value),
+        Some(token) => Err(syntax_error(token)),
+    }
+}
+
+fn tokenize(expression: &str) -> Result<Vec<Token>, String> {
+    const OPERATORS: &[&str] = &["**", "+", "-", "*", "/", "%", "(", ")", ","];
+    let mut tokens = Vec::new();
+    let mut rest = expression;
+    loop {
+        rest = rest.trim_start();
+        let Some(ch) = rest.chars().next() else {
+            return Ok(tokens);
+        };
+        if ch.is_ascii_digit() || ch == '.' {
+            let len = number_len(rest);
+            let number = rest[..len].parse().map_err(|_| tr!("{}: invalid number", &rest[..len]))?;
+            tokens.push(Token::Number(number));
+            rest = &rest[len..];
+        } else if ch.is_ascii_alphabetic() || ch == '_' {
+            let len = rest.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_')).unwrap_or(rest.len());
+            tokens.push(Token::Name(rest[..len].to_string()));
+            rest = &rest[len..];
+        } else if let Some(operator) = OPERATORS.iter().find(|operator| rest.starts_with(*operator)) {
+            tokens.push(Token::Operator(operator));
+            rest = &rest[operator.len()..];
+        } else {
+            return Err(tr!("syntax error: invalid arithmetic operator (error token is \"{}\")", rest));
+        }
+    }
+}
+
+/// The length of the number `text` starts with: digits with a decimal
+/// point and an exponent, as in `1.5e-3`
+fn number_len(text: &str) -> usize {
+    let bytes = text.as_bytes();
+    let digits = |mut index: usize| {
+        while bytes.get(index).is_some_and(u8::is_ascii_digit) {
+            index += 1;
+        }
+        index
+    };
+    let mut len = digits(0);
+    if bytes.get(len) == Some(&b'.') {
+        len = digits(len + 1);
+    }
+    if matches!(bytes.get(len), Some(b'e' | b'E')) {
+        let sign = usize::from(matches!(bytes.get(len + 1), Some(b'+' | b'-')));
+        let end = digits(len + 1 + sign);
+        if end > len + 1 + sign {
+            len = end;
+        }
+    }
+    len
+}
+
+fn syntax_error(token: &Token) -> String {
+    let text = match token {
+        Token::Number(number) => number.to_string(),
+        Token::Name(name) => name.clone(),
+        Token::Operator(operator) => operator.to_string(),
+    };
+    tr!("syntax error in expression (error token is \"{}\")", text)
+}
+
+struct Evaluator<'a> {
+    tokens: Vec<Token>,
+    position: usize,
+    vars: &'a dyn Variables,
+}
+
+impl Evaluator<'_> {
+    fn eat(&mut self, operators: &[&str]) -> Option<&'static str> {
+        match self.tokens.get(self.position) {
+            Some(Token::Operator(operator)) if operators.contains(operator) => {
+                self.position += 1;
+                Some(operator)
+            }
+            _ => None,
+        }
+    }
+
+    fn expect(&mut self, operator: &str) -> Result<(), String> {
+        match self.tokens.get(self.position) {
+            Some(Token::Operator(found)) if *found == operator => {
+                self.position += 1;
+                Ok(())
+            }
+            Some(token) => Err(syntax_error(token)),
+            None => Err(tr!("syntax error: operand expected")),
+        }
+    }
+
+    fn sum(&mut self) -> Result<f64, String> {
+        let mut value = self.product()?;
+        while let Some(operator) = self.eat(&["+", "-"]) {
+            let right = self.product()?;
+            value = if operator == "+" { value + right } else { value - right };
+        }
+        Ok(value)
+    }
+
+    fn product(&mut self) -> Result<f64, String> {
+        let mut value = self.unary()?;
+        while let Some(operator) = self.eat(&["*", "/", "%"]) {
+            let right = self.unary()?;
+            value = match operator {
+                "*" => value * right,
+                _ if right == 0.0 => return Err(tr!("division by 0")),
+                "/" => value / right,
+                _ => value % right,
+            };
+        }
+        Ok(value)
+    }
+
+    fn unary(&mut self) -> Result<f64, String> {
+        match self.eat(&["+", "-"]) {
+            Some("-") => Ok(-self.unary()?),
+            Some(_) => self.unary(),
+            None => self.power(),
+        }
+    }
+
+    fn power(&mut self) -> Result<f64, String> {
+        let base = self.primary()?;
+        if self.eat(&["**"]).is_none() {
+            return Ok(base);
+        }
+        Ok(base.powf(self.unary()?))
+    }
+
+    fn primary(&mut self) -> Result<f64, String> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        match token {
+            Some(Token::Number(number)) => Ok(number),
+            Some(Token::Name(name)) if self.eat(&["("]).is_some() => {
+                let mut args = vec![self.sum()?];
+                while self.eat(&[","]).is_some() {
+                    args.push(self.sum()?);
+                }
+                self.expect(")")?;
+                call(&name, &args)
+            }
+            Some(Token::Name(name)) => self.variable(&name),
+            Some(Token::Operator("(")) => {
+                let value = self.sum()?;
+                self.expect(")")?;
+                Ok(value)
+            }
+            Some(token) => Err(syntax_error(&token)),
+            None => Err(tr!("syntax error: operand expected")),
+        }
+    }
+
+    /// A variable's value, which must be a number; unset or empty is 0
+    fn variable(&self, name: &str) -> Result<f64, String> {
+        let value = self.vars.get(name);
+        match value.trim() {
+            "" => Ok(0.0),
+            value => value.parse().map_err(|_| tr!("{}: not a number: {}", name, value)),
+        }
+    }
+}
+
+fn call(name: &str, args: &[f64]) -> Result<f64, String> {
+    let value = match (name, args) {
+        ("abs", [x]) => x.abs(),
+        ("sqrt", [x]) => x.sqrt(),
+        ("floor", [x]) => x.floor(),
+        ("ceil", [x]) => x.ceil(),
+        ("round", [x]) => x.round(),
+        ("trunc", [x]) => x.trunc(),
+        ("exp", [x]) => x.exp(),
+        ("ln", [x]) => x.ln(),
+        ("log10", [x]) => x.log10(),
+        ("log2", [x]) => x.log2(),
+        ("sin", [x]) => x.sin(),
+        ("cos", [x]) => x.cos(),
+        ("tan", [x]) => x.tan(),
+        ("atan", [x]) => x.atan(),
+        ("atan2", [y, x]) => y.atan2(*x),
+        ("pow", [x, y]) => x.powf(*y),
+        ("min", [first, rest @ ..]) => rest.iter().fold(*first, |min, x| min.min(*x)),
+        ("max", [first, rest @ ..]) => rest.iter().fold(*first, |max, x| max.max(*x)),
+        _ => return Err(tr!("{}: unknown function or wrong number of arguments", name)),
+    };
+    Ok(value)
+}
+
+/// `value` written by `format` as printf(1) would: a single `%f`, `%e`,
+/// `%g` (or `%E`, `%G`) conversion with its flags, width and precision,
+/// amid other text, where `%%` is a `%`
+pub fn format(format: &str, value: f64) -> Result<String, String> {
+    let invalid = || tr!("{}: invalid format", format);
+    let mut output = String::new();
+    let mut converted = false;
+    let mut chars = format.chars().peekable();
+    while let Some(ch) = chars.next() {
+        if ch != '%' {
+            output.push(ch);
+            continue;
+        }
+        if chars.next_if_eq(&'%').is_some() {
+            output.push('%');
+            continue;
+        }
+        if converted {
+            return Err(invalid());
+        }
+        let mut spec = Spec::default();
+        while let Some(flag) = chars.next_if(|c| "-+ 0#".contains(*c)) {
+            match flag {
+                '-' => spec.left = true,
+                '+' => spec.plus = true,
+                ' ' => spec.space = true,
+                '0' => spec.zero = true,
+                _ => spec.alternate = true,
+            }
+        }
+        spec.width = digits(&mut chars).unwrap_or(0);
+        if chars.next_if_eq(&'.').is_some() {
+            spec.precision = Some(digits(&mut chars).unwrap_or(0));
+        }
+        spec.conversion = chars.next().filter(|c| "feEgG".contains(*c)).ok_or_else(invalid)?;
+        output.push_str(&spec.convert(value));
+        converted = true;
+    }
+    if !converted {
+        return Err(invalid());
+    }
+    Ok(output)
+}
+
+fn digits(chars: &mut std::iter::Peekable<std::str::Chars<'_>>) -> Option<usize> {
+    let mut number = None;
+    while let Some(digit) = chars.peek().and_then(|c| c.to_digit(10)) {
+        chars.next();
+        number = Some(number.unwrap_or(0usize).saturating_mul(10).saturating_add(digit as usize));
+    }
+    number
+}
+
+/// A printf conversion
+#[derive(Default)]
+struct Spec {
+    left: bool,
+    plus: bool,
+    space: bool,
+    zero: bool,
+    alternate: bool,
+    width: usize,
+    precision: Option<usize>,
+    conversion: char,
+}
+
+impl Spec {
+    fn convert(&self, value: f64) -> String {
+        let precision = self.precision.unwrap_or(6);
+        let magnitude = value.abs();
+        let mut body = if !value.is_finite() {
+            if value.is_nan() { "nan" } else { "inf" }.to_string()
+        } else {
+            match self.conversion.to_ascii_lowercase() {
+                'f' => self.point(format!("{:.*}", precision, magnitude)),
+                'e' => self.point(exponential(magnitude, precision)),
+                _ => self.general(magnitude, precision),
+            }
+        };
+        if self.conversion.is_ascii_uppercase() {
+            body = body.to_uppercase();
+        }
+
+        let sign = if value.is_sign_negative() && !value.is_nan() {
+            "-"
+        } else if self.plus {
+            "+"
+        } else if self.space {
+            " "
+        } else {
+            ""
+        };
+        let padding = self.width.saturating_sub(sign.len() + body.len());
+        if self.left {
+            format!("{}{}{}", sign, body, " ".repeat(padding))
+        } else if self.zero && value.is_finite() {
+            format!("{}{}{}", sign, "0".repeat(padding), body)
+        } else {
+            format!("{}{}{}", " ".repeat(padding), sign, body)
+        }
+    }
+
+    /// `%g`: `%e` if the exponent is below -4 or not below the precision,
+    /// else `%f`, either with `precision` significant digits and no
+    /// trailing zeros unless `#` is given
+    fn general(&self, magnitude: f64, precision: usize) -> String {
+        let precision = precision.max(1);
+        let scientific = exponential(magnitude, precision - 1);
+        let exponent: i64 = scientific.split_once('e').and_then(|(_, exponent)| exponent.parse().ok()).unwrap_or(0);
+        let body = if exponent < -4 || exponent >= precision as i64 {
+            scientific
+        } else {
+            format!("{:.*}", (precision as i64 - 1 - exponent) as usize, magnitude)
+        };
+        if self.alternate {
+            return self.point(body);
+        }
+        let (mantissa, exponent) = body.split_at(body.find('e').unwrap_or(body.len()));
+        let mantissa = if mantissa.contains('.') { mantissa.trim_end_matches('0').trim_end_matches('.') } else { mantissa };
+        format!("{}{}", mantissa, exponent)
+    }
+
+    /// With `#`, a decimal point even when no digits follow it
+    fn point(&self, body: String) -> String {
+        if !self.alternate || body.contains('.') {
+            return body;
+        }
+        let split = body.find('e').unwrap_or(body.len());
+        format!("{}.{}", &body[..split], &body[split..])
+    }
+}
+
+/// `magnitude` as `d.ddde+XX`, with at least two digits of exponent
+fn exponential(magnitude: f64, precision: usize) -> String {
+    let text = format!("{:.*e}", precision, magnitude);
+    let (mantissa, exponent) = text.split_once('e').unwrap_or((&text, "0"));
+    let exponent: i32 = exponent.parse().unwrap_or(0);
+    format!("{}e{}{:02}", mantissa, if exponent < 0 { '-' } else { '+' }, exponent.abs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    struct Vars(HashMap<&'static str, &'static str>);
+
+    impl Variables for Vars {
+        fn get(&self, name: &str) -> String {
+            self.0.get(name).unwrap_or(&"").to_string()
+        }
+
+        fn set(&mut self, _: &str, _: &str) {}
+    }
+
+    fn eval(expression: &str) -> Result<f64, String> {
+        evaluate(expression, &Vars(HashMap::from([("rate", "0.25"), ("name", "x")])))
+    }
+
+    #[test]
+    fn test_evaluate() {
+        assert_eq!(eval("1.5 * 2"), Ok(3.0));
+        assert_eq!(eval("1 + 2 * 3 - 4 / 8"), Ok(6.5));
+        assert_eq!(eval("-2 ** 2 + 2 ** -1"), Ok(-3.5));
+        assert_eq!(eval("2 ** 3 ** 2"), Ok(512.0));
+        assert_eq!(eval("7.5 % 2"), Ok(1.5));
+        assert_eq!(eval("1e3 + .5 + 2.5E-1"), Ok(1000.75));
+        assert_eq!(eval("100 * rate + unset"), Ok(25.0));
+        assert_eq!(eval("sqrt(16) + max(1, 5, 3) + round(2.5)"), Ok(12.0));
+
+        assert!(eval("1 / 0").unwrap_err().contains("division by 0"));
+        assert!(eval("name + 1").is_err());
+        assert!(eval("nope(1)").is_err());
+        assert!(eval("sqrt(1, 2)").is_err());
+        assert!(eval("(1").is_err());
+        assert!(eval("1 2").is_err());
+        assert!(eval("").is_err());
+    }
+
+    #[test]
+    fn test_format() {
+        assert_eq!(format("%g", 3.0), Ok("3".to_string()));
+        assert_eq!(format("%g", 0.1 + 0.2), Ok("0.3".to_string()));
+        assert_eq!(format("%g", 1234567.0), Ok("1.23457e+06".to_string()));
+        assert_eq!(format("%g", 0.0001), Ok("0.0001".to_string()));
+        assert_eq!(format("%G", 0.00001), Ok("1E-05".to_string()));
+        assert_eq!(format("%#g", 2.0), Ok("2.00000".to_string()));
+        assert_eq!(format("%.2f", 2.0 / 3.0), Ok("0.67".to_string()));
+        assert_eq!(format("%8.3f|", -1.5), Ok("  -1.500|".to_string()));
+        assert_eq!(format("%-8.1f|", 1.5), Ok("1.5     |".to_string()));
+        assert_eq!(format("%+08.2f", 12.5), Ok("+0012.50".to_string()));
+        assert_eq!(format("%e", 1234.5), Ok("1.234500e+03".to_string()));
+        assert_eq!(format("%.0f%%", 42.4), Ok("42%".to_string()));
+        assert_eq!(format("%f", f64::INFINITY), Ok("inf".to_string()));
+
+        assert!(format("%d", 1.0).is_err());
+        assert!(format("%f %f", 1.0).is_err());
+        assert!(format("none", 1.0).is_err());
+    }
+}
@@ -0,0 +1,114 @@
+use crate::messages::tr;
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+
+/// Marks a file written by HistoryCipher
+const MAGIC: &[u8] = b"CLAMENC1";
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+// Cheap parameters in tests, where keys are derived many times
+const SCRYPT_LOG_N: u8 = if cfg!(test) { 4 } else { 15 };
+
+/// Encrypts data at rest with ChaCha20-Poly1305 under a key derived from a
+/// passphrase with scrypt. Each file carries its own salt and nonce:
+///
+/// MAGIC | salt (16 bytes) | nonce (12 bytes) | ciphertext + tag
+pub struct HistoryCipher {
+    passphrase: String,
+    // The last derived key, reused while the salt stays the same
+    derived: Option<([u8; SALT_LEN], Key)>,
+}
+
+impl HistoryCipher {
+    pub fn new(passphrase: String) -> Self {
+        Self {
+            passphrase,
+            derived: None,
+        }
+    }
+
+    pub fn is_encrypted(data: &[u8]) -> bool {
+        data.starts_with(MAGIC)
+    }
+
+    pub fn encrypt(&mut self, plaintext: &[u8]) -> Result<Vec<u8>, String> {
+        let salt = match &self.derived {
+            Some((salt, _)) => *salt,
+            None => {
+                let mut salt = [0u8; SALT_LEN];
+                OsRng.fill_bytes(&mut salt);
+                salt
+            }
+        };
+        let cipher = ChaCha20Poly1305::new(&self.key(&salt)?);
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = cipher
+            .encrypt(&nonce, plaintext)
+            .map_err(|_| tr!("failed to encrypt history"))?;
+
+        let mut data = Vec::with_capacity(MAGIC.len() + SALT_LEN + NONCE_LEN + ciphertext.len());
+        data.extend_from_slice(MAGIC);
+        data.extend_from_slice(&salt);
+        data.extend_from_slice(&nonce);
+        data.extend_from_slice(&ciphertext);
+        Ok(data)
+    }
+
+    pub fn decrypt(&mut self, data: &[u8]) -> Result<Vec<u8>, String> {
+        let header_len = MAGIC.len() + SALT_LEN + NONCE_LEN;
+        if !Self::is_encrypted(data) || data.len() < header_len {
+            return Err(tr!("not an encrypted history file"));
+        }
+
+        let mut salt = [0u8; SALT_LEN];
+        salt.copy_from_slice(&data[MAGIC.len()..MAGIC.len() + SALT_LEN]);
+        let nonce = Nonce::from_slice(&data[MAGIC.len() + SALT_LEN..header_len]);
+
+        let cipher = ChaCha20Poly1305::new(&self.key(&salt)?);
+        cipher
+            .decrypt(nonce, &data[header_len..])
+            .map_err(|_| tr!("wrong passphrase or corrupted history file"))
+    }
+
+    fn key(&mut self, salt: &[u8; SALT_LEN]) -> Result<Key, String> {
+        if let Some((derived_salt, key)) = &self.derived
+            && derived_salt == salt
+        {
+            return Ok(*key);
+        }
+
+        let params = scrypt::Params::new(SCRYPT_LOG_N, 8, 1, 32).map_err(|e| e.to_string())?;
+        let mut key = Key::default();
+        scrypt::scrypt(self.passphrase.as_bytes(), salt, &params, &mut key).map_err(|e| e.to_string())?;
+
+        self.derived = Some((*salt, key));
+        Ok(key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let mut cipher = HistoryCipher::new("correct horse".to_string());
+        let data = cipher.encrypt(b"ls -la\necho secret\n").unwrap();
+
+        assert!(HistoryCipher::is_encrypted(&data));
+        assert!(!data.windows(6).any(|w| w == b"secret"));
+
+        let mut reader = HistoryCipher::new("correct horse".to_string());
+        assert_eq!(reader.decrypt(&data).unwrap(), b"ls -la\necho secret\n");
+    }
+
+    #[test]
+    fn test_wrong_passphrase_fails() {
+        let mut cipher = HistoryCipher::new("right".to_string());
+        let data = cipher.encrypt(b"history").unwrap();
+
+        let mut reader = HistoryCipher::new("wrong".to_string());
+        assert!(reader.decrypt(&data).is_err());
+    }
+}
@@ -0,0 +1,341 @@
+use rustyline::completion::{Completer, FilenameCompleter, Pair};
+use crate::config::{Config, Theme};
+use crate::highlight::{self, TokenClass};
+use crate::history_stats::SharedFrecency;
+use crate::prompt::RenderedPrompt;
+use rustyline::highlight::{CmdKind, Highlighter};
+use rustyline::hint::Hinter;
+use rustyline::validate::Validator;
+use rustyline::{Context, Helper, Result};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::io::Read;
+use std::os::unix::process::CommandExt;
+use std::process::{Command as ProcessCommand, Stdio};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Enables completing option flags scraped from `cmd --help`
+const HELP_COMPLETION_VAR: &str = "CLAM_HELP_COMPLETION";
+const HELP_TIMEOUT: Duration = Duration::from_secs(2);
+const HELP_OUTPUT_LIMIT: usize = 256 * 1024;
+/// Set to 0 to stop suggesting the rest of the line from history
+const AUTOSUGGEST_VAR: &str = "CLAM_AUTOSUGGEST";
+
+/// rustyline helper providing the shell's tab completion, syntax
+/// highlighting and autosuggestions
+pub struct ShellHelper {
+    filenames: FilenameCompleter,
+    help_flags: Option<HelpFlagCache>,
+    /// Commands from history, best first, for completing command names
+    /// and suggesting the rest of the line
+    frecency: Option<SharedFrecency>,
+    autosuggest: bool,
+    /// Off when NO_COLOR is set, as https://no-color.org asks
+    colors: bool,
+    theme: Theme,
+    /// The prompt about to be shown, without and with its colors
+    prompt: Option<RenderedPrompt>,
+}
+
+impl ShellHelper {
+    /// Variables set for the shell win over the same settings in `config`
+    pub fn new(config: &Config) -> Self {
+        let help_enabled = std::env::var(HELP_COMPLETION_VAR)
+            .map(|value| !value.is_empty() && value != "0")
+            .unwrap_or(config.completion.help_flags);
+
+        Self {
+            filenames: FilenameCompleter::new(),
+            help_flags: if help_enabled { Some(HelpFlagCache::new()) } else { None },
+            frecency: None,
+            autosuggest: std::env::var(AUTOSUGGEST_VAR).map_or(config.completion.autosuggest, |value| value != "0"),
+            colors: config.theme.colors && std::env::var_os("NO_COLOR").is_none_or(|value| value.is_empty()),
+            theme: config.theme.clone(),
+            prompt: None,
+        }
+    }
+
+    pub fn set_frecency(&mut self, frecency: SharedFrecency) {
+        self.frecency = Some(frecency);
+    }
+
+    /// Color the next prompt read with `prompt.plain` as `prompt.styled`
+    pub fn set_prompt(&mut self, prompt: RenderedPrompt) {
+        self.prompt = Some(prompt);
+    }
+
+    /// The names of the commands in history starting with `prefix`, the
+    /// best ranked first
+    fn frecent_commands(&self, prefix: &str) -> Vec<String> {
+        let Some(frecency) = &self.frecency else {
+            return Vec::new();
+        };
+        let frecency = frecency.lock().unwrap();
+        let mut names: Vec<String> = Vec::new();
+        for name in frecency.ranking().iter().filter_map(|line| command_name(line)) {
+            if name.starts_with(prefix) && !names.iter().any(|known| known == name) {
+                names.push(name.to_string());
+            }
+        }
+        names
+    }
+}
+
+impl Default for ShellHelper {
+    fn default() -> Self {
+        Self::new(&Config::default())
+    }
+}
+
+impl Completer for ShellHelper {
+    type Candidate = Pair;
+
+    fn complete(&self, line: &str, pos: usize, ctx: &Context<'_>) -> Result<(usize, Vec<Pair>)> {
+        let before_cursor = &line[..pos];
+        let word_start = before_cursor
+            .rfind(|c: char| c.is_whitespace())
+            .map(|i| i + 1)
+            .unwrap_or(0);
+        let word = &before_cursor[word_start..];
+
+        if let Some(cache) = &self.help_flags
+            && word.starts_with('-')
+            && let Some(command) = command_name(&before_cursor[..word_start])
+        {
+            let candidates: Vec<Pair> = cache
+                .flags(command)
+                .into_iter()
+                .filter(|flag| flag.starts_with(word))
+                .map(|flag| Pair {
+                    display: flag.clone(),
+                    replacement: flag,
+                })
+                .collect();
+            if !candidates.is_empty() {
+                return Ok((word_start, candidates));
+            }
+        }
+
+        let (start, mut candidates) = self.filenames.complete(line, pos, ctx)?;
+        if !word.is_empty() && command_name(&before_cursor[..word_start]).is_none() {
+            // Commands run often lately come before files
+            let commands = self.frecent_commands(word);
+            if start == word_start || candidates.is_empty() {
+                candidates.retain(|file| !commands.contains(&file.replacement));
+                let commands = commands.into_iter().map(|name| Pair { display: name.clone(), replacement: name });
+                candidates.splice(0..0, commands);
+                return Ok((word_start, candidates));
+            }
+        }
+        Ok((start, candidates))
+    }
+}
+
+impl Hinter for ShellHelper {
+    type Hint = String;
+
+    /// The rest of the best-ranked command from history that starts with
+    /// the line, when the cursor is at its end
+    fn hint(&self, line: &str, pos: usize, _ctx: &Context<'_>) -> Option<String> {
+        if !self.autosuggest || line.trim().is_empty() || pos < line.len() {
+            return None;
+        }
+        let frecency = self.frecency.as_ref()?.lock().unwrap();
+        frecency.suggest(line).map(|command| command[line.len()..].to_string())
+    }
+}
+
+impl Highlighter for ShellHelper {
+    fn highlight<'l>(&self, line: &'l str, _pos: usize) -> Cow<'l, str> {
+        let spans = highlight::highlight_spans(line);
+        if !self.colors || spans.is_empty() {
+            return Cow::Borrowed(line);
+        }
+        let mut colored = String::with_capacity(line.len() + spans.len() * 9);
+        let mut end = 0;
+        for (span, class) in spans {
+            colored.push_str(&line[end..span.start]);
+            colored.push_str(&format!("\x1b[{}m", color(&self.theme, class)));
+            colored.push_str(&line[span.start..span.end]);
+            colored.push_str("\x1b[0m");
+            end = span.end;
+        }
+        colored.push_str(&line[end..]);
+        Cow::Owned(colored)
+    }
+
+    fn highlight_prompt<'b, 's: 'b, 'p: 'b>(&'s self, prompt: &'p str, _default: bool) -> Cow<'b, str> {
+        match &self.prompt {
+            Some(rendered) if self.colors && rendered.plain == prompt => Cow::Borrowed(&rendered.styled),
+            _ => Cow::Borrowed(prompt),
+        }
+    }
+
+    fn highlight_hint<'h>(&self, hint: &'h str) -> Cow<'h, str> {
+        if !self.colors {
+            return Cow::Borrowed(hint);
+        }
+        Cow::Owned(format!("\x1b[{}m{}\x1b[0m", self.theme.hint, hint))
+    }
+
+    fn highlight_char(&self, _line: &str, _pos: usize, kind: CmdKind) -> bool {
+        // Colors don't depend on the cursor, only on the text
+        self.colors && kind != CmdKind::MoveCursor
+    }
+}
+
+/// The SGR parameters coloring a class of span
+fn color(theme: &Theme, class: TokenClass) -> &str {
+    match class {
+        TokenClass::Keyword => &theme.keyword,
+        TokenClass::String => &theme.string,
+        TokenClass::Variable => &theme.variable,
+        TokenClass::Operator => &theme.operator,
+        TokenClass::Comment => &theme.comment,
+    }
+}
+
+impl Validator for ShellHelper {}
+
+impl Helper for ShellHelper {}
+
+/// The command word of the simple command being typed, if any
+fn command_name(line: &str) -> Option<&str> {
+    let segment = line
+        .rsplit(['|', ';', '&', '(', ')', '{', '}'])
+        .next()
+        .unwrap_or(line);
+    segment
+        .split_whitespace()
+        .find(|word| !word.contains('='))
+}
+
+/// Option flags discovered by running `cmd --help`, cached per command
+/// (including commands that produced none, so they are only probed once)
+struct HelpFlagCache {
+    flags: Mutex<HashMap<String, Vec<String>>>,
+}
+
+impl HelpFlagCache {
+    fn new() -> Self {
+        Self {
+            flags: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn flags(&self, command: &str) -> Vec<String> {
+        if let Some(flags) = self.flags.lock().unwrap().get(command) {
+            return flags.clone();
+        }
+
+        let flags = run_help(command)
+            .map(|output| parse_help_flags(&output))
+            .unwrap_or_default();
+        self.flags
+            .lock()
+            .unwrap()
+            .insert(command.to_string(), flags.clone());
+        flags
+    }
+}
+
+/// Run `command --help` detached from the terminal, with no input, a
+/// scratch working directory, a time limit and a cap on captured output
+fn run_help(command: &str) -> Option<String> {
+    let mut child = ProcessCommand::new(command)
+        .arg("--help")
+        .current_dir(std::env::temp_dir())
+        .env("PAGER", "cat")
+        .env("MANPAGER", "cat")
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .process_group(0)
+        .spawn()
+        .ok()?;
+
+    let stdout = child.stdout.take()?;
+    let stderr = child.stderr.take()?;
+    let read_limited = |stream: Box<dyn Read + Send>| {
+        std::thread::spawn(move || {
+            let mut output = Vec::new();
+            let _ = stream.take(HELP_OUTPUT_LIMIT as u64).read_to_end(&mut output);
+            output
+        })
+    };
+    let stdout_reader = read_limited(Box::new(stdout));
+    let stderr_reader = read_limited(Box::new(stderr));
+
+    let started = Instant::now();
+    loop {
+        match child.try_wait() {
+            Ok(Some(_)) => break,
+            Ok(None) if started.elapsed() < HELP_TIMEOUT => {
+                std::thread::sleep(Duration::from_millis(10));
+            }
+            _ => {
+                // Kill the whole group so grandchildren can't hold the pipes open
+                unsafe { libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL) };
+                let _ = child.wait();
+                break;
+            }
+        }
+    }
+
+    let mut output = stdout_reader.join().ok()?;
+    output.extend(stderr_reader.join().ok()?);
+    Some(String::from_utf8_lossy(&output).into_owned())
+}
+
+/// Extract `--long-option` names from help text, sorted and deduplicated
+fn parse_help_flags(text: &str) -> Vec<String> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut flags = Vec::new();
+    let mut i = 0;
+
+    while i + 2 < chars.len() {
+        let at_boundary = i == 0 || !(chars[i - 1].is_alphanumeric() || chars[i - 1] == '-');
+        if at_boundary && chars[i] == '-' && chars[i + 1] == '-' && chars[i + 2].is_alphanumeric() {
+            let mut flag = String::from("--");
+            let mut j = i + 2;
+            while j < chars.len() && (chars[j].is_alphanumeric() || chars[j] == '-' || chars[j] == '_') {
+                flag.push(chars[j]);
+                j += 1;
+            }
+            // A trailing dash is punctuation, not part of the option
+            while flag.ends_with('-') {
+                flag.pop();
+            }
+            flags.push(flag);
+            i = j;
+        } else {
+            i += 1;
+        }
+    }
+
+    flags.sort();
+    flags.dedup();
+    flags
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_help_flags() {
+        let help = "Usage: tool [OPTIONS]\n  -v, --verbose       be loud\n      --color=WHEN    colorize\n      --dry-run, --force\n  see also foo--bar\n";
+        assert_eq!(
+            parse_help_flags(help),
+            vec!["--color", "--dry-run", "--force", "--verbose"]
+        );
+    }
+
+    #[test]
+    fn test_command_name() {
+        assert_eq!(command_name("git "), Some("git"));
+        assert_eq!(command_name("ls | FOO=1 grep "), Some("grep"));
+        assert_eq!(command_name("   "), None);
+    }
+}
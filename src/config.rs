@@ -0,0 +1,329 @@
+//! Settings for the interactive shell that don't fit shell commands, read
+//! from `~/.config/clam/config.toml`. Variables that already control the
+//! same thing, like `CLAM_HISTORY_BACKEND`, still win when they are set.
+
+use crate::messages::tr;
+use rustyline::KeyEvent;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use toml_edit::{DocumentMut, Item, Table};
+
+const CONFIG_FILE: &str = "clam/config.toml";
+/// The widgets keys can be bound to
+const WIDGETS: [&str; 2] = ["file-search", "history-search"];
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub theme: Theme,
+    pub completion: Completion,
+    pub history: History,
+    /// The key starting each widget, as `ctrl-r` or `alt-x`, or `""` to
+    /// leave it unbound
+    pub keybindings: BTreeMap<String, String>,
+}
+
+/// The colors of syntax highlighting, as SGR parameters such as `"1;35"`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Theme {
+    /// False to show everything uncolored, as NO_COLOR does
+    pub colors: bool,
+    pub keyword: String,
+    pub string: String,
+    pub variable: String,
+    pub operator: String,
+    pub comment: String,
+    /// The rest of the line suggested from history
+    pub hint: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Completion {
+    /// Suggest the rest of the line from history, unless CLAM_AUTOSUGGEST
+    /// says otherwise
+    pub autosuggest: bool,
+    /// Complete options scraped from `cmd --help`, unless
+    /// CLAM_HELP_COMPLETION says otherwise
+    pub help_flags: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct History {
+    /// `file`, `sqlite` or `command`, unless CLAM_HISTORY_BACKEND is set
+    pub backend: String,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            theme: Theme::default(),
+            completion: Completion::default(),
+            history: History::default(),
+            keybindings: BTreeMap::from([
+                ("file-search".to_string(), "ctrl-t".to_string()),
+                ("history-search".to_string(), "ctrl-r".to_string()),
+            ]),
+        }
+    }
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self {
+            colors: true,
+            keyword: "1;35".to_string(),
+            string: "33".to_string(),
+            variable: "36".to_string(),
+            operator: "1".to_string(),
+            comment: "2".to_string(),
+            hint: "2".to_string(),
+        }
+    }
+}
+
+impl Default for Completion {
+    fn default() -> Self {
+        Self { autosuggest: true, help_flags: false }
+    }
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self { backend: "file".to_string() }
+    }
+}
+
+impl Config {
+    /// The settings in the file at `path`, over the defaults. A missing
+    /// file is no error.
+    pub fn load(path: &Path) -> Result<Config, String> {
+        match std::fs::read_to_string(path) {
+            Ok(text) => from_document(parse(&text)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Config::default()),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+
+    /// The key bound to `widget`, if it has one
+    pub fn key(&self, widget: &str) -> Option<KeyEvent> {
+        self.keybindings.get(widget).and_then(|spec| parse_key(spec).ok().flatten())
+    }
+
+    fn check(&self) -> Result<(), String> {
+        if !matches!(self.history.backend.as_str(), "file" | "sqlite" | "command") {
+            return Err(tr!("history.backend: {}: unknown history backend", self.history.backend));
+        }
+        for (widget, spec) in &self.keybindings {
+            if !WIDGETS.contains(&widget.as_str()) {
+                return Err(tr!("keybindings.{}: unknown widget", widget));
+            }
+            parse_key(spec).map_err(|e| format!("keybindings.{}: {}", widget, e))?;
+        }
+        Ok(())
+    }
+}
+
+/// `$XDG_CONFIG_HOME/clam/config.toml`, or under `~/.config` without it
+pub fn path() -> Option<PathBuf> {
+    let dir = match std::env::var_os("XDG_CONFIG_HOME").filter(|dir| !dir.is_empty()) {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(std::env::var_os("HOME").filter(|home| !home.is_empty())?).join(".config"),
+    };
+    Some(dir.join(CONFIG_FILE))
+}
+
+/// The setting `key`, as `section.name`: strings as they are, other
+/// values as TOML writes them
+pub fn get(config: &Config, key: &str) -> Result<String, String> {
+    let document = toml_edit::ser::to_document(config).map_err(|e| e.to_string())?;
+    match setting(document.as_table(), key) {
+        Some(Item::Value(toml_edit::Value::String(text))) => Ok(text.value().clone()),
+        Some(Item::Value(value)) => Ok(value.to_string().trim().to_string()),
+        _ => Err(tr!("{}: unknown setting", key)),
+    }
+}
+
+/// Change the setting `key` in the file at `path` to `value`, keeping the
+/// rest of the file and its comments as they are. The new file must still
+/// make a valid configuration.
+pub fn set(path: &Path, key: &str, value: &str) -> Result<(), String> {
+    let text = match std::fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e.to_string()),
+    };
+    let mut document = parse(&text)?;
+    let Some((section, name)) = key.split_once('.') else {
+        return Err(tr!("{}: unknown setting", key));
+    };
+    // Take the type from the default, so `true` can be a string elsewhere
+    let defaults = toml_edit::ser::to_document(&Config::default()).map_err(|e| e.to_string())?;
+    let value = match setting(defaults.as_table(), key) {
+        Some(Item::Value(toml_edit::Value::Boolean(_))) => match value {
+            "true" => toml_edit::value(true),
+            "false" => toml_edit::value(false),
+            _ => return Err(tr!("{}: {}: expected true or false", key, value)),
+        },
+        _ => toml_edit::value(value),
+    };
+    let table = document.entry(section).or_insert_with(|| Item::Table(Table::new()));
+    let Some(table) = table.as_table_like_mut() else {
+        return Err(tr!("{}: unknown setting", key));
+    };
+    table.insert(name, value);
+    from_document(document.clone())?;
+
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+    }
+    std::fs::write(path, document.to_string()).map_err(|e| e.to_string())
+}
+
+/// `clam config get [KEY]`, `clam config set KEY VALUE` or `clam config
+/// path`, returning the exit status. `get` without a key prints every
+/// setting.
+pub fn run(args: &[String]) -> i32 {
+    let Some(path) = path() else {
+        eprintln!("clam: {}", tr!("config: HOME not set"));
+        return 1;
+    };
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let result = match args.as_slice() {
+        ["path"] => Ok(path.display().to_string()),
+        ["get", key @ ..] if key.len() <= 1 => Config::load(&path)
+            .map_err(|e| format!("{}: {}", path.display(), e))
+            .and_then(|config| match key.first() {
+                Some(key) => get(&config, key),
+                None => toml_edit::ser::to_string_pretty(&config).map(|text| text.trim_end().to_string()).map_err(|e| e.to_string()),
+            }),
+        ["set", key, value] => set(&path, key, value).map(|()| String::new()),
+        _ => {
+            eprintln!("{}", tr!("config: usage: clam config get [KEY] | set KEY VALUE | path"));
+            return 2;
+        }
+    };
+    match result {
+        Ok(text) if text.is_empty() => 0,
+        Ok(text) => {
+            println!("{}", text);
+            0
+        }
+        Err(e) => {
+            eprintln!("clam: {}", tr!("config: {}", e));
+            1
+        }
+    }
+}
+
+fn setting<'a>(table: &'a Table, key: &str) -> Option<&'a Item> {
+    let (section, name) = key.split_once('.')?;
+    table.get(section)?.as_table_like()?.get(name)
+}
+
+fn parse(text: &str) -> Result<DocumentMut, String> {
+    text.parse::<DocumentMut>().map_err(|e| match e.span() {
+        Some(span) => tr!("line {}: {}", line_number(text, span.start), e.message().trim_end()),
+        None => e.message().trim_end().to_string(),
+    })
+}
+
+fn from_document(document: DocumentMut) -> Result<Config, String> {
+    let mut config: Config = toml_edit::de::from_document(document).map_err(|e| e.message().trim_end().to_string())?;
+    // Binding one widget leaves the others their default keys
+    for (widget, key) in Config::default().keybindings {
+        config.keybindings.entry(widget).or_insert(key);
+    }
+    config.check()?;
+    Ok(config)
+}
+
+fn line_number(text: &str, offset: usize) -> usize {
+    text[..offset.min(text.len())].matches('\n').count() + 1
+}
+
+/// `ctrl-` or `alt-` and a character, or nothing for no key
+fn parse_key(spec: &str) -> Result<Option<KeyEvent>, String> {
+    if spec.is_empty() {
+        return Ok(None);
+    }
+    let lower = spec.to_ascii_lowercase();
+    let key = |rest: &str| {
+        let mut chars = rest.chars();
+        match (chars.next(), chars.next()) {
+            (Some(ch), None) => Some(ch),
+            _ => None,
+        }
+    };
+    let event = if let Some(ch) = lower.strip_prefix("ctrl-").and_then(key) {
+        KeyEvent::ctrl(ch.to_ascii_uppercase())
+    } else if let Some(ch) = spec.get(4..).filter(|_| lower.starts_with("alt-")).and_then(key) {
+        KeyEvent::alt(ch)
+    } else {
+        return Err(tr!("{}: invalid key; expected ctrl-KEY or alt-KEY", spec));
+    };
+    Ok(Some(event))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("clam-test-{}-{}", std::process::id(), name))
+    }
+
+    #[test]
+    fn test_load() {
+        let path = temp_path("config.toml");
+        assert_eq!(Config::load(&path).unwrap(), Config::default());
+
+        std::fs::write(&path, "[theme]\nkeyword = \"1;31\"\n\n[keybindings]\nfile-search = \"\"\n").unwrap();
+        let config = Config::load(&path).unwrap();
+        assert_eq!(config.theme.keyword, "1;31");
+        assert_eq!(config.theme.string, "33");
+        assert_eq!(config.key("file-search"), None);
+        assert_eq!(config.key("history-search"), Some(KeyEvent::ctrl('R')));
+
+        std::fs::write(&path, "[history]\nbackend = 3\n").unwrap();
+        assert!(Config::load(&path).is_err());
+        std::fs::write(&path, "[theme]\ncolour = true\n").unwrap();
+        assert!(Config::load(&path).unwrap_err().contains("colour"));
+        std::fs::write(&path, "\n[theme\n").unwrap();
+        assert!(Config::load(&path).unwrap_err().starts_with("line 2: "));
+        std::fs::write(&path, "[keybindings]\nhistory-search = \"f5\"\n").unwrap();
+        assert!(Config::load(&path).is_err());
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_get_and_set() {
+        let dir = temp_path("config-dir");
+        let path = dir.join("clam/config.toml");
+        let _ = std::fs::remove_dir_all(&dir);
+
+        set(&path, "completion.autosuggest", "false").unwrap();
+        set(&path, "history.backend", "sqlite").unwrap();
+        std::fs::write(&path, format!("# mine\n{}", std::fs::read_to_string(&path).unwrap())).unwrap();
+        set(&path, "keybindings.history-search", "alt-s").unwrap();
+
+        let text = std::fs::read_to_string(&path).unwrap();
+        assert!(text.starts_with("# mine\n"));
+        let config = Config::load(&path).unwrap();
+        assert_eq!(get(&config, "completion.autosuggest").unwrap(), "false");
+        assert_eq!(get(&config, "history.backend").unwrap(), "sqlite");
+        assert_eq!(get(&config, "theme.keyword").unwrap(), "1;35");
+        assert_eq!(config.key("history-search"), Some(KeyEvent::alt('s')));
+
+        assert!(set(&path, "completion.autosuggest", "yes").is_err());
+        assert!(set(&path, "history.backend", "cloud").is_err());
+        assert!(set(&path, "theme.font", "mono").is_err());
+        assert!(set(&path, "theme", "dark").is_err());
+        assert!(get(&config, "history").is_err());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), text);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,71 @@
+use crate::messages::tr;
+use std::ffi::CString;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The format of `datetime` without one, as date(1) prints the time
+pub const DEFAULT_FORMAT: &str = "%a %b %e %H:%M:%S %Z %Y";
+/// The most a formatted time may take up, to stop a format from growing
+/// the buffer without end
+const MAX_LEN: usize = 64 * 1024;
+
+unsafe extern "C" {
+    fn tzset();
+}
+
+/// The seconds since the epoch now
+pub fn now() -> i64 {
+    match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(elapsed) => elapsed.as_secs() as i64,
+        Err(e) => -(e.duration().as_secs() as i64),
+    }
+}
+
+/// `time`, in seconds since the epoch, formatted with strftime(3)'s
+/// conversions, in UTC or the local time zone `TZ` names
+pub fn format(format: &str, time: i64, utc: bool) -> Result<String, String> {
+    if format.is_empty() {
+        return Ok(String::new());
+    }
+    let c_format = CString::new(format).map_err(|_| tr!("{}: invalid format", format))?;
+    let time = time as libc::time_t;
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    let converted = unsafe {
+        if utc {
+            libc::gmtime_r(&time, &mut tm)
+        } else {
+            tzset();
+            libc::localtime_r(&time, &mut tm)
+        }
+    };
+    if converted.is_null() {
+        return Err(tr!("{}: time out of range", time));
+    }
+
+    // strftime returns 0 when the buffer is too small, but also when the
+    // result is empty, as `%p` can be; so grow it only up to a limit
+    let mut buffer = vec![0u8; 256];
+    loop {
+        let len = unsafe { libc::strftime(buffer.as_mut_ptr().cast(), buffer.len(), c_format.as_ptr(), &tm) };
+        if len > 0 || buffer.len() >= MAX_LEN {
+            buffer.truncate(len);
+            return Ok(String::from_utf8_lossy(&buffer).into_owned());
+        }
+        buffer.resize(buffer.len() * 4, 0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format() {
+        assert_eq!(format("%Y-%m-%d %H:%M:%S", 0, true).unwrap(), "1970-01-01 00:00:00");
+        assert_eq!(format("%F %T %Z", 1_700_000_000, true).unwrap(), "2023-11-14 22:13:20 GMT");
+        assert_eq!(format("%j|%A|%%", 86_400 * 40, true).unwrap(), "041|Tuesday|%");
+        assert_eq!(format("", 0, true).unwrap(), "");
+        assert_eq!(format(&"%Y".repeat(200), 0, true).unwrap().len(), 800);
+        assert!(format("a\0b", 0, true).is_err());
+        assert!(now() > 1_700_000_000);
+    }
+}
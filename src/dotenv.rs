@@ -0,0 +1,137 @@
+use crate::messages::tr;
+
+/// The variables a .env file sets, in order: `NAME=value` lines, each
+/// maybe after `export`, with blank lines and `#` comments between them.
+/// A value may be single-quoted, taken as it is, or double-quoted, where
+/// `\n`, `\t`, `\"`, `\\` and `\$` are escapes; either may span lines.
+/// An unquoted value ends at the line's end or a ` #` comment. Nothing is
+/// expanded, so the file can't run commands.
+pub fn parse(text: &str) -> Result<Vec<(String, String)>, String> {
+    let mut variables = Vec::new();
+    let mut lines = text.lines().enumerate();
+    while let Some((index, line)) = lines.next() {
+        let number = index + 1;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export").filter(|rest| rest.starts_with([' ', '\t'])).unwrap_or(line);
+        let Some((name, rest)) = line.split_once('=') else {
+            return Err(tr!("line {}: missing `=`", number));
+        };
+        let name = name.trim();
+        if !is_name(name) {
+            return Err(tr!("line {}: {}: invalid variable name", number, name));
+        }
+
+        let rest = rest.trim_start();
+        let value = match rest.chars().next() {
+            Some(quote @ ('\'' | '"')) => {
+                let mut quoted = rest[1..].to_string();
+                let (value, after) = loop {
+                    if let Some(end) = closing_quote(&quoted, quote) {
+                        let after = quoted[end + 1..].to_string();
+                        quoted.truncate(end);
+                        break (quoted, after);
+                    }
+                    let Some((_, next)) = lines.next() else {
+                        return Err(tr!("line {}: unterminated quoted value", number));
+                    };
+                    quoted.push('\n');
+                    quoted.push_str(next);
+                };
+                let after = after.trim();
+                if !after.is_empty() && !after.starts_with('#') {
+                    return Err(tr!("line {}: unexpected text after the quoted value", number));
+                }
+                if quote == '"' { unescape(&value) } else { value }
+            }
+            _ => {
+                let end = rest.find(" #").or_else(|| rest.find("\t#")).unwrap_or(rest.len());
+                rest[..end].trim_end().to_string()
+            }
+        };
+        variables.push((name.to_string(), value));
+    }
+    Ok(variables)
+}
+
+/// Where the `quote` closing a value that started before `text` is; a
+/// double quote may be escaped
+fn closing_quote(text: &str, quote: char) -> Option<usize> {
+    let mut chars = text.char_indices();
+    while let Some((index, ch)) = chars.next() {
+        match ch {
+            '\\' if quote == '"' => {
+                chars.next();
+            }
+            _ if ch == quote => return Some(index),
+            _ => {}
+        }
+    }
+    None
+}
+
+fn unescape(value: &str) -> String {
+    let mut result = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(ch) = chars.next() {
+        if ch != '\\' {
+            result.push(ch);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => result.push('\n'),
+            Some('t') => result.push('\t'),
+            Some(escaped @ ('"' | '\\' | '$')) => result.push(escaped),
+            Some(other) => {
+                result.push('\\');
+                result.push(other);
+            }
+            None => result.push('\\'),
+        }
+    }
+    result
+}
+
+fn is_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_') && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pairs(variables: &[(&str, &str)]) -> Vec<(String, String)> {
+        variables.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect()
+    }
+
+    #[test]
+    fn test_parse() {
+        let text = "# settings\n\nexport HOST=example.com  # the server\nPORT = 8080\nEMPTY=\n\
+                    GREETING='hello $USER' # literal\nMOTD=\"line one\\n\\\"two\\\" \\$HOME\"\n\
+                    KEY=\"-----BEGIN\nabc\n-----END\"\nURL=http://a/#anchor\n";
+        assert_eq!(
+            parse(text).unwrap(),
+            pairs(&[
+                ("HOST", "example.com"),
+                ("PORT", "8080"),
+                ("EMPTY", ""),
+                ("GREETING", "hello $USER"),
+                ("MOTD", "line one\n\"two\" $HOME"),
+                ("KEY", "-----BEGIN\nabc\n-----END"),
+                ("URL", "http://a/#anchor"),
+            ])
+        );
+        assert_eq!(parse("exported=1").unwrap(), pairs(&[("exported", "1")]));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(parse("A=1\njust text"), Err("line 2: missing `=`".to_string()));
+        assert_eq!(parse("1A=x"), Err("line 1: 1A: invalid variable name".to_string()));
+        assert_eq!(parse("A='open\nB=2"), Err("line 1: unterminated quoted value".to_string()));
+        assert!(parse("A=\"x\" y").is_err());
+    }
+}
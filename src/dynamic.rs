@@ -0,0 +1,168 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// When set to anything but `0`, the variables below are reproducible:
+/// `RANDOM` starts from `CLAM_RANDOM_SEED` (default 0), the clock is frozen
+/// at `SOURCE_DATE_EPOCH` (default 0) and `HOSTNAME` defaults to
+/// `localhost`, so a script gives byte-identical output from run to run
+const DETERMINISTIC_VAR: &str = "CLAM_DETERMINISTIC";
+const RANDOM_SEED_VAR: &str = "CLAM_RANDOM_SEED";
+const EPOCH_VAR: &str = "SOURCE_DATE_EPOCH";
+
+/// The variables whose value changes by itself: `RANDOM`, `SECONDS`,
+/// `EPOCHSECONDS` and `EPOCHREALTIME`
+pub struct DynamicVariables {
+    /// State of the generator behind `RANDOM`
+    random: std::cell::Cell<u32>,
+    /// `SECONDS` counts from this time, starting at `seconds_base`
+    seconds_start: Duration,
+    seconds_base: u64,
+    /// The clock stays at this time since the epoch when set, which is
+    /// only in deterministic mode
+    frozen: Option<Duration>,
+}
+
+impl DynamicVariables {
+    /// Variables following the real clock, with a seed that differs per run
+    pub fn new() -> Self {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        Self::with_clock(clock_seed(now), now, None)
+    }
+
+    /// Variables with a fixed seed, and the clock frozen at `epoch`
+    pub fn deterministic(seed: u32, epoch: Duration) -> Self {
+        Self::with_clock(seed, epoch, Some(epoch))
+    }
+
+    /// Deterministic when `CLAM_DETERMINISTIC` asks for it
+    pub fn from_env() -> Self {
+        if !is_deterministic() {
+            return Self::new();
+        }
+        let seed = std::env::var(RANDOM_SEED_VAR).ok().and_then(|value| value.parse().ok());
+        let epoch = std::env::var(EPOCH_VAR).ok().and_then(|value| value.parse().ok());
+        Self::deterministic(seed.unwrap_or(0), Duration::from_secs(epoch.unwrap_or(0)))
+    }
+
+    fn with_clock(seed: u32, now: Duration, frozen: Option<Duration>) -> Self {
+        Self { random: std::cell::Cell::new(seed), seconds_start: now, seconds_base: 0, frozen }
+    }
+
+    /// The value of `name` if it is one of these variables
+    pub fn get(&self, name: &str) -> Option<String> {
+        let now = self.now();
+        match name {
+            "RANDOM" => Some(self.next_random().to_string()),
+            "SECONDS" => Some((self.seconds_base + now.saturating_sub(self.seconds_start).as_secs()).to_string()),
+            "EPOCHSECONDS" => Some(now.as_secs().to_string()),
+            "EPOCHREALTIME" => Some(format!("{}.{:06}", now.as_secs(), now.subsec_micros())),
+            _ => None,
+        }
+    }
+
+    /// Assign to one of these variables as bash does: a number assigned to
+    /// `RANDOM` seeds it and `SECONDS` counts on from the one assigned to
+    /// it. False when `name` is not one of them.
+    pub fn assign(&mut self, name: &str, value: &str) -> bool {
+        match name {
+            "RANDOM" => self.random.set(value.parse().unwrap_or(0)),
+            "SECONDS" => {
+                self.seconds_start = self.now();
+                self.seconds_base = value.parse().unwrap_or(0);
+            }
+            "EPOCHSECONDS" | "EPOCHREALTIME" => {}
+            _ => return false,
+        }
+        true
+    }
+
+    /// Called before forking: the seed for the child's `RANDOM`, so that
+    /// subshells and command substitutions don't repeat the shell's numbers
+    /// or each other's. A deterministic shell draws it from its own
+    /// generator; otherwise the child seeds itself from its pid and the
+    /// time, as bash does.
+    pub fn child_seed(&self) -> Option<u32> {
+        // Turned, so the child's numbers aren't the shell's next ones
+        self.frozen.is_some().then(|| self.step().rotate_left(16))
+    }
+
+    /// Called in the forked child with what `child_seed` returned
+    pub fn reseed_child(&self, seed: Option<u32>) {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        self.random.set(seed.unwrap_or_else(|| clock_seed(now)));
+    }
+
+    fn now(&self) -> Duration {
+        self.frozen
+            .unwrap_or_else(|| SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default())
+    }
+
+    /// The next number from 0 to 32767, from the generator in POSIX's
+    /// example `rand()`
+    fn next_random(&self) -> u32 {
+        (self.step() >> 16) & 0x7fff
+    }
+
+    /// Advance the generator, returning its new state
+    fn step(&self) -> u32 {
+        let state = self.random.get().wrapping_mul(1103515245).wrapping_add(12345);
+        self.random.set(state);
+        state
+    }
+}
+
+impl Default for DynamicVariables {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A seed that differs from process to process and from run to run
+fn clock_seed(now: Duration) -> u32 {
+    now.subsec_nanos() ^ std::process::id().rotate_left(16)
+}
+
+pub fn is_deterministic() -> bool {
+    std::env::var(DETERMINISTIC_VAR).is_ok_and(|value| value != "0")
+}
+
+/// `HOSTNAME`: inherited from the environment if set there, so a harness
+/// can pick it, otherwise the machine's name
+pub fn hostname() -> String {
+    if let Ok(name) = std::env::var("HOSTNAME") {
+        return name;
+    }
+    if is_deterministic() {
+        return "localhost".to_string();
+    }
+    let mut buffer = [0u8; 256];
+    if unsafe { libc::gethostname(buffer.as_mut_ptr().cast(), buffer.len()) } != 0 {
+        return String::new();
+    }
+    let len = buffer.iter().position(|&byte| byte == 0).unwrap_or(buffer.len());
+    String::from_utf8_lossy(&buffer[..len]).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deterministic_variables() {
+        let mut vars = DynamicVariables::deterministic(42, Duration::from_millis(1_700_000_000_250));
+        let first: Vec<String> = (0..3).map(|_| vars.get("RANDOM").unwrap()).collect();
+        let again = DynamicVariables::deterministic(42, Duration::ZERO);
+        assert_eq!(first, (0..3).map(|_| again.get("RANDOM").unwrap()).collect::<Vec<_>>());
+        assert!(first.iter().all(|n| n.parse::<u32>().unwrap() < 32768));
+
+        vars.assign("RANDOM", "42");
+        assert_eq!(vars.get("RANDOM").unwrap(), first[0]);
+
+        assert_eq!(vars.get("EPOCHSECONDS").unwrap(), "1700000000");
+        assert_eq!(vars.get("EPOCHREALTIME").unwrap(), "1700000000.250000");
+        assert_eq!(vars.get("SECONDS").unwrap(), "0");
+        assert!(vars.assign("SECONDS", "30"));
+        assert_eq!(vars.get("SECONDS").unwrap(), "30");
+        assert!(!vars.assign("PATH", "/bin"));
+        assert_eq!(vars.get("PATH"), None);
+    }
+}
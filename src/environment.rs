@@ -0,0 +1,160 @@
+//! The shell's variables and the environment commands get from them.
+//!
+//! Building a command's environment means merging the variables over the
+//! shell's own environment and copying the result into C strings, which
+//! adds up when a prompt runs a handful of command substitutions per line.
+//! `Environment` keeps the merged block until a variable changes, so
+//! consecutive substitutions share one: it is built in the shell before
+//! forking, the child gets it copy-on-write, and there points `environ`
+//! at it so the commands it runs inherit it without another copy.
+
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::{CString, OsString, c_char};
+use std::os::unix::ffi::OsStringExt;
+use std::process::Command as ProcessCommand;
+use std::rc::Rc;
+
+unsafe extern "C" {
+    static mut environ: *const *const c_char;
+}
+
+#[derive(Default)]
+pub struct Environment {
+    variables: HashMap<String, String>,
+    /// The environment the shell started with, taken before any block is
+    /// installed over it
+    inherited: Option<Vec<(OsString, OsString)>>,
+    /// The variables over `inherited`, until one of them changes
+    block: Option<Rc<EnvBlock>>,
+    /// The block `environ` points at, in a forked child
+    installed: Option<Rc<EnvBlock>>,
+    forked: bool,
+}
+
+/// `NAME=value` strings and the null-terminated array of pointers to them
+/// that `environ` wants
+struct EnvBlock {
+    _entries: Vec<CString>,
+    pointers: Vec<*const c_char>,
+}
+
+impl Environment {
+    pub fn get(&self, name: &str) -> Option<&String> {
+        self.variables.get(name)
+    }
+
+    pub fn contains_key(&self, name: &str) -> bool {
+        self.variables.contains_key(name)
+    }
+
+    pub fn insert(&mut self, name: String, value: String) -> Option<String> {
+        if self.variables.get(&name) != Some(&value) {
+            self.block = None;
+        }
+        self.variables.insert(name, value)
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<String> {
+        let previous = self.variables.remove(name);
+        if previous.is_some() {
+            self.block = None;
+        }
+        previous
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &String)> {
+        self.variables.iter()
+    }
+
+    /// Build the block now, so children forked next share it
+    pub fn prepare(&mut self) {
+        self.block();
+    }
+
+    /// Mark this copy as a forked child's, which is single-threaded and
+    /// so free to change `environ`
+    pub fn enter_child(&mut self) {
+        self.forked = true;
+    }
+
+    /// Give `process` the variables. A forked child installs the block as
+    /// its own environment, which `process` then inherits as it is; the
+    /// shell itself has threads reading `environ` and sets each variable
+    /// on `process` instead.
+    pub fn apply(&mut self, process: &mut ProcessCommand) {
+        if !self.forked {
+            for (name, value) in &self.variables {
+                process.env(name, value);
+            }
+            return;
+        }
+        let block = self.block();
+        if self.installed.as_ref().is_some_and(|installed| Rc::ptr_eq(installed, &block)) {
+            return;
+        }
+        // SAFETY: a forked child has only this thread, and the block stays
+        // alive in `installed` for as long as `environ` points at it
+        unsafe { environ = block.pointers.as_ptr() };
+        self.installed = Some(block);
+    }
+
+    fn block(&mut self) -> Rc<EnvBlock> {
+        if let Some(block) = &self.block {
+            return Rc::clone(block);
+        }
+        let inherited = self.inherited.get_or_insert_with(|| std::env::vars_os().collect());
+        let mut merged: BTreeMap<OsString, OsString> = inherited.iter().cloned().collect();
+        for (name, value) in &self.variables {
+            merged.insert(name.into(), value.into());
+        }
+        let entries: Vec<CString> = merged
+            .into_iter()
+            .filter_map(|(name, value)| {
+                let mut entry = name.into_vec();
+                entry.push(b'=');
+                entry.extend(value.into_vec());
+                CString::new(entry).ok()
+            })
+            .collect();
+        let mut pointers: Vec<*const c_char> = entries.iter().map(|entry| entry.as_ptr()).collect();
+        pointers.push(std::ptr::null());
+        let block = Rc::new(EnvBlock { _entries: entries, pointers });
+        self.block = Some(Rc::clone(&block));
+        block
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::CStr;
+
+    fn entries(block: &EnvBlock) -> Vec<String> {
+        block.pointers[..block.pointers.len() - 1]
+            .iter()
+            .map(|&pointer| unsafe { CStr::from_ptr(pointer) }.to_string_lossy().into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn test_block_is_reused_until_a_variable_changes() {
+        let mut environment = Environment::default();
+        environment.insert("CLAM_TEST_BLOCK".to_string(), "1".to_string());
+        let block = environment.block();
+        assert!(entries(&block).contains(&"CLAM_TEST_BLOCK=1".to_string()));
+        assert_eq!(block.pointers.last(), Some(&std::ptr::null()));
+
+        environment.prepare();
+        assert!(Rc::ptr_eq(&block, &environment.block()));
+        environment.insert("CLAM_TEST_BLOCK".to_string(), "1".to_string());
+        assert!(Rc::ptr_eq(&block, &environment.block()));
+
+        environment.insert("CLAM_TEST_BLOCK".to_string(), "2".to_string());
+        let changed = environment.block();
+        assert!(!Rc::ptr_eq(&block, &changed));
+        assert!(entries(&changed).contains(&"CLAM_TEST_BLOCK=2".to_string()));
+
+        environment.remove("CLAM_TEST_BLOCK");
+        assert!(!entries(&environment.block()).iter().any(|entry| entry.starts_with("CLAM_TEST_BLOCK=")));
+    }
+}
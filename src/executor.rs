@@ -1,238 +1,6462 @@
+use crate::arith;
 use crate::ast::*;
-use std::collections::HashMap;
-use std::process::{Command as ProcessCommand, Stdio};
+use crate::brace;
+use crate::calc;
+use crate::datetime;
+use crate::dotenv;
+use crate::dynamic::DynamicVariables;
+use crate::environment::Environment;
+#[cfg(feature = "extras")]
+use crate::extras;
+#[cfg(feature = "fetch")]
+use crate::fetch;
+use crate::glob;
+use crate::history::{HistoryQuery, SharedBackend};
+use crate::history_stats;
+use crate::jobs::{JobState, JobTable};
+use crate::jobs;
+use crate::lexer;
+use crate::lint;
+use crate::messages::tr;
+use crate::parameter::{self, Operation, Substitution};
+use crate::pathname;
+use crate::pattern;
+use crate::script;
+use crate::session::{self, Session};
+use crate::signals;
+use crate::spawner::{IoClass, Priority, ProcessSpawner, Spawned, Spawner};
+use crate::structured;
+use crate::terminal::{self, TerminalModes};
+use crate::testing::Assertions;
+use crate::text;
+use crate::traps::{Condition, TrapTable};
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::CString;
+use std::fs::OpenOptions;
+use std::io::{Seek, SeekFrom, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd, RawFd};
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::Command as ProcessCommand;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+/// Overrides the size limit for here-documents passed through a pipe
+const HEREDOC_PIPE_MAX_VAR: &str = "CLAM_HEREDOC_PIPE_MAX";
+/// When set, an interactive shell sends SIGHUP to its jobs as it exits
+const HUPONEXIT_VAR: &str = "CLAM_HUPONEXIT";
+/// Caps the number of fields a single word may expand to
+const MAX_EXPANSION_VAR: &str = "CLAM_MAX_EXPANSION";
+const DEFAULT_MAX_EXPANSION: usize = 100_000;
+/// Caps the directory entries pathname expansion of one word may read
+const GLOB_MAX_ENTRIES_VAR: &str = "CLAM_GLOB_MAX_ENTRIES";
+const DEFAULT_GLOB_MAX_ENTRIES: usize = 1_000_000;
+/// How many paths one word may expand to in a recursive `rm` before
+/// `set -o globguard` asks first
+const GLOB_GUARD_MAX_VAR: &str = "CLAM_GLOB_GUARD_MAX";
+const DEFAULT_GLOB_GUARD_MAX: usize = 100;
+/// Caps the FIFOs process substitutions may use at once without /dev/fd
+const FIFO_MAX_VAR: &str = "CLAM_FIFO_MAX";
+const DEFAULT_FIFO_MAX: usize = 64;
+/// The smallest default pipe buffer among common platforms (macOS)
+const DEFAULT_HEREDOC_PIPE_MAX: usize = 16 * 1024;
+/// Status of a command line abandoned because of SIGINT
+const INTERRUPTED_STATUS: i32 = 128 + libc::SIGINT;
+/// Signals that kill a script, which still runs its EXIT trap first
+const FATAL_SIGNALS: [libc::c_int; 3] = [libc::SIGHUP, libc::SIGINT, libc::SIGTERM];
+/// Holds the command about to run while the DEBUG trap runs
+const DEBUG_COMMAND_VAR: &str = "CLAM_COMMAND";
+
+/// Variables the shell sets for itself at startup, which describe this
+/// process and machine rather than the user's session
+const PROCESS_VARIABLES: &[&str] = &["HOSTNAME", "SHELL", DEBUG_COMMAND_VAR];
+
+/// `set` flags and the options they stand for
+const SHORT_OPTIONS: &[(char, &str)] = &[('C', "noclobber"), ('E', "errtrace"), ('T', "functrace")];
+
+/// The terminal and the shell's own process group, while job control is on
+#[derive(Clone, Copy)]
+struct JobControl {
+    terminal: RawFd,
+    shell_pgid: libc::pid_t,
+}
 
 pub struct Executor {
-    env_vars: HashMap<String, String>,
+    env_vars: Environment,
+    functions: HashMap<String, Rc<Command>>,
+    /// `$0`: the name the shell was started with, or the script it runs.
+    /// Functions and sourced files leave it unchanged.
+    shell_name: String,
+    /// Positional parameters `$1..$N` of the running function
+    positional: Vec<String>,
+    function_depth: usize,
+    /// Set by `return` to unwind out of the running function body
+    returning: bool,
+    /// Set when the command line is abandoned because of SIGINT
+    interrupted: bool,
+    /// Files being sourced, innermost last
+    source_stack: Vec<PathBuf>,
+    jobs: JobTable,
+    /// `$!`: the pid of the last command started in the background
+    last_background_pid: Option<libc::pid_t>,
+    /// `$$`: the shell's pid, which subshells keep
+    shell_pid: u32,
+    /// `$_`: the last argument of the last command run, once it is done
+    last_argument: String,
+    traps: TrapTable,
+    /// Set while a trap runs, so signals arriving meanwhile wait for it
+    running_trap: bool,
+    /// How deeply nested the running command is in conditions whose
+    /// failure is expected, such as `if` tests, which don't trigger ERR
+    condition_depth: usize,
+    /// Results of the assertion builtins, which exist only in test mode
+    assertions: Option<Assertions>,
+    /// `set -o pipefail`: a pipeline fails if any of its commands does
+    pipefail: bool,
+    /// `set -C`: `>` won't overwrite an existing file, only `>|` will
+    noclobber: bool,
+    /// `set -E`: functions inherit the ERR trap
+    errtrace: bool,
+    /// `set -T`: functions inherit the RETURN trap. The DEBUG trap, unlike
+    /// in bash, is inherited either way.
+    functrace: bool,
+    /// `set -o globguard`: an interactive shell asks before a recursive
+    /// `rm` whose words expanded to something alarming
+    globguard: bool,
+    /// `set -o globstar`: `**` in a pattern matches across directories
+    globstar: bool,
+    /// `shopt -s nullglob`: a pattern that matches nothing expands to nothing
+    nullglob: bool,
+    /// `shopt -s failglob`: a pattern that matches nothing is an error
+    failglob: bool,
+    /// `shopt -s dotglob`: patterns match names starting with `.` too
+    dotglob: bool,
+    /// `shopt -s nocaseglob`: pathname expansion ignores case
+    nocaseglob: bool,
+    /// `shopt -s nocasematch`: `case` patterns match in either case
+    nocasematch: bool,
+    /// `shopt -s extglob`: patterns may hold `@(a|b)` and the other
+    /// extended forms
+    extglob: bool,
+    /// `set -o structured`: the builtins passing JSON records down a
+    /// pipeline, such as `from-json` and `where`, are available
+    structured: bool,
+    /// `PIPESTATUS`: the status of each command of the last pipeline
+    pipe_status: Vec<i32>,
+    /// Status of the last `$(...)` expanded for the running command
+    substitution_status: Option<i32>,
+    /// The helpers of the `<(...)` and `>(...)` expanded so far by the
+    /// commands being run
+    process_substitutions: Vec<ProcessSubstitution>,
+    /// Whether commands can open the shell's fds as /dev/fd/N; if not,
+    /// process substitutions go through FIFOs instead
+    has_dev_fd: bool,
+    /// The private directory this shell makes its FIFOs in, removed as it
+    /// exits
+    fifo_dir: Option<PathBuf>,
+    /// FIFOs made so far, which numbers the next one
+    fifo_count: usize,
+    /// The directories `pushd` left, the latest first, below the working
+    /// directory at the top of the stack
+    dir_stack: Vec<PathBuf>,
+    /// Starts the external commands run without forking
+    spawner: Box<dyn Spawner>,
+    /// `RANDOM`, `SECONDS` and the like
+    dynamic: DynamicVariables,
+    /// The history the `history` builtin searches; only interactive
+    /// shells keep one
+    history: Option<SharedBackend>,
+    interactive: bool,
+    job_control: Option<JobControl>,
+    /// The shell's terminal settings, put back when a foreground job is
+    /// killed or stopped
+    terminal_modes: Option<TerminalModes>,
+    /// Largest here-document body sent through a pipe instead of a temp file
+    heredoc_pipe_max: usize,
     last_exit_status: i32,
 }
 
 impl Executor {
     pub fn new() -> Self {
         Self {
-            env_vars: HashMap::new(),
+            env_vars: Environment::default(),
+            functions: HashMap::new(),
+            shell_name: "clam".to_string(),
+            positional: Vec::new(),
+            function_depth: 0,
+            returning: false,
+            interrupted: false,
+            source_stack: Vec::new(),
+            jobs: JobTable::new(),
+            last_background_pid: None,
+            shell_pid: std::process::id(),
+            last_argument: String::new(),
+            traps: TrapTable::default(),
+            running_trap: false,
+            condition_depth: 0,
+            assertions: None,
+            pipefail: false,
+            noclobber: false,
+            errtrace: false,
+            functrace: false,
+            globguard: false,
+            globstar: false,
+            nullglob: false,
+            failglob: false,
+            dotglob: false,
+            extglob: false,
+            nocaseglob: false,
+            nocasematch: false,
+            structured: false,
+            pipe_status: Vec::new(),
+            substitution_status: None,
+            process_substitutions: Vec::new(),
+            has_dev_fd: Path::new("/dev/fd").is_dir(),
+            fifo_dir: None,
+            fifo_count: 0,
+            dir_stack: Vec::new(),
+            spawner: Box::new(ProcessSpawner),
+            dynamic: DynamicVariables::from_env(),
+            history: None,
+            interactive: false,
+            job_control: None,
+            terminal_modes: None,
+            heredoc_pipe_max: std::env::var(HEREDOC_PIPE_MAX_VAR)
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(DEFAULT_HEREDOC_PIPE_MAX),
             last_exit_status: 0,
         }
     }
 
+    pub fn set_history(&mut self, history: SharedBackend) {
+        self.history = Some(history);
+    }
+
+    /// Interactive shells report the jobs they start in the background, and
+    /// do job control when attached to a terminal
+    pub fn set_interactive(&mut self, interactive: bool) {
+        self.interactive = interactive;
+        if interactive {
+            signals::catch_interrupts();
+        }
+        if interactive && unsafe { libc::isatty(0) } == 1 {
+            self.enable_job_control();
+            self.terminal_modes = Some(TerminalModes::save(self.terminal_fd()));
+        }
+    }
+
+    /// Run each job in a process group of its own and hand it the terminal
+    /// while it is in the foreground, so Ctrl-Z stops just that job
+    fn enable_job_control(&mut self) {
+        // Keep a descriptor of our own, as fd 0 may be redirected
+        let terminal = unsafe { libc::fcntl(0, libc::F_DUPFD_CLOEXEC, 10) };
+        if terminal < 0 {
+            return;
+        }
+        jobs::ignore_stop_signals();
+        // Fails harmlessly if the shell already leads its session
+        unsafe { libc::setpgid(0, 0) };
+        let shell_pgid = unsafe { libc::getpgrp() };
+        terminal::give_terminal(terminal, shell_pgid);
+        self.job_control = Some(JobControl { terminal, shell_pgid });
+    }
+
+    /// Execute a complete command line. If it is aborted by an error, the
+    /// state it may have left half-changed is rolled back so the next
+    /// command starts from a consistent shell.
+    pub fn execute_isolated(&mut self, command: &Command) -> Result<i32, String> {
+        for warning in lint::check(command) {
+            eprintln!("clam: {}", tr!("warning: {}", warning));
+        }
+
+        signals::take_interrupt();
+        self.interrupted = false;
+        // Scripts never show a prompt, so reap their finished jobs here
+        self.jobs.update();
+        let checkpoint = self.checkpoint();
+        let result = self.execute(command);
+        if result.is_err() {
+            self.rollback(checkpoint);
+            self.last_exit_status = 1;
+        }
+
+        // Unwinding from an interrupt runs the usual cleanup, like a return
+        if self.interrupted() && result.is_ok() {
+            self.interrupted = false;
+            self.last_exit_status = INTERRUPTED_STATUS;
+            if self.interactive {
+                // Like bash, so the prompt doesn't follow the ^C echo
+                eprintln!();
+            }
+            return Ok(INTERRUPTED_STATUS);
+        }
+        result
+    }
+
+    /// Whether the rest of the running list, loop or function is skipped
+    fn unwinding(&mut self) -> bool {
+        self.returning || self.interrupted()
+    }
+
+    /// Whether SIGINT arrived or a foreground job was killed by it. Either
+    /// way, the rest of the command line is abandoned.
+    fn interrupted(&mut self) -> bool {
+        if signals::take_interrupt() {
+            self.interrupted = true;
+        }
+        self.interrupted
+    }
+
+    fn checkpoint(&self) -> Checkpoint {
+        Checkpoint {
+            positional: self.positional.clone(),
+            function_depth: self.function_depth,
+            cwd: std::env::current_dir().ok(),
+            options: self.options(),
+            shopt_options: self.shopt_options(),
+        }
+    }
+
+    fn rollback(&mut self, checkpoint: Checkpoint) {
+        self.positional = checkpoint.positional;
+        self.function_depth = checkpoint.function_depth;
+        self.returning = false;
+        for (name, enable) in checkpoint.options {
+            self.set_option(name, enable);
+        }
+        for (name, enable) in checkpoint.shopt_options {
+            self.set_shopt_option(name, enable);
+        }
+
+        // Leave a working directory that was removed while the command ran
+        if std::env::current_dir().is_err()
+            && let Some(cwd) = checkpoint.cwd
+        {
+            let _ = std::env::set_current_dir(cwd);
+        }
+    }
+
     pub fn execute(&mut self, command: &crate::ast::Command) -> Result<i32, String> {
-        match command {
+        if self.interrupted() {
+            return Ok(INTERRUPTED_STATUS);
+        }
+        let process_substitutions = self.process_substitutions.len();
+        let status = match command {
             Command::Simple(cmd) => self.execute_simple_command(cmd),
             Command::Pipeline(pipeline) => self.execute_pipeline(pipeline),
             Command::List(list) => self.execute_list(list),
+            Command::Subshell(inner) => self.execute_subshell(inner),
+            Command::Group(inner) => self.execute(inner),
             Command::If(if_cmd) => self.execute_if(if_cmd),
             Command::While(while_cmd) => self.execute_while(while_cmd),
             Command::Until(until_cmd) => self.execute_until(until_cmd),
             Command::For(for_cmd) => self.execute_for(for_cmd),
+            Command::Case(case_cmd) => self.execute_case(case_cmd),
             Command::Redirected(redirected) => self.execute_redirected(redirected),
-            _ => Err(format!("Command type not yet implemented: {:?}", command)),
+            Command::Timed(timed) => self.execute_timed(timed),
+            Command::Arithmetic(expression) => self.execute_arithmetic(expression),
+            Command::FunctionDef(def) => {
+                self.functions.insert(def.name.clone(), Rc::new((*def.body).clone()));
+                Ok(0)
+            }
+        };
+        self.finish_process_substitutions(process_substitutions);
+        let status = status?;
+
+        self.last_exit_status = status;
+        if let Command::Simple(_) = command {
+            self.pipe_status = vec![status];
+        }
+        if status != 0 && self.condition_depth == 0 && Self::reports_errors(command) {
+            self.run_condition_trap(Condition::Err);
+        }
+        self.run_pending_traps();
+        Ok(status)
+    }
+
+    /// Whether a failure of `command` itself triggers ERR, rather than only
+    /// a failure of the commands inside it
+    fn reports_errors(command: &Command) -> bool {
+        match command {
+            Command::Simple(_) | Command::Subshell(_) | Command::Arithmetic(_) => true,
+            // A single command has reported already, and `!` inverts the test
+            Command::Pipeline(pipeline) => pipeline.commands.len() > 1 && !pipeline.negated,
+            _ => false,
         }
     }
 
+    /// Execute a command whose status is being tested, so its failure does
+    /// not trigger ERR
+    fn execute_condition(&mut self, command: &Command) -> Result<i32, String> {
+        self.condition_depth += 1;
+        let result = self.execute(command);
+        self.condition_depth -= 1;
+        result
+    }
+
     fn execute_simple_command(&mut self, cmd: &SimpleCommand) -> Result<i32, String> {
+        if !self.running_trap && self.traps.get(Condition::Debug).is_some() {
+            self.env_vars.insert(DEBUG_COMMAND_VAR.to_string(), cmd.to_string());
+            self.run_condition_trap(Condition::Debug);
+        }
+        let Some(saved_fds) = self.apply_redirections(&cmd.redirections) else {
+            return Ok(1);
+        };
+        let result = self.run_simple_command(cmd);
+        // `exec` without a command keeps its redirections for good
+        if result.is_ok() && matches!(cmd.words.as_slice(), [word] if word.value == "exec") {
+            saved_fds.keep();
+        } else {
+            drop(saved_fds);
+        }
+        result
+    }
+
+    fn run_simple_command(&mut self, cmd: &SimpleCommand) -> Result<i32, String> {
+        // Without a command to run, the status is that of the last `$(...)`
+        self.substitution_status = None;
         if cmd.words.is_empty() {
             // Assignment-only command
             for assignment in &cmd.assignments {
-                self.env_vars.insert(assignment.name.clone(), assignment.value.clone());
+                let value = self.expand_assignment(&assignment.value)?;
+                self.set_variable(&assignment.name, &value);
             }
-            return Ok(0);
+            return Ok(self.substitution_status.unwrap_or(0));
         }
 
-        // Expand variables in words and perform word splitting
-        let mut expanded_words: Vec<String> = Vec::new();
+        let mut expanded_words = Vec::new();
+        // The most fields any one word expanded to
+        let mut widest = 0;
         for word in &cmd.words {
-            let expanded = self.expand_variables(&word.value);
-            // Perform word splitting on expanded value
-            for split_word in self.word_split(&expanded) {
-                expanded_words.push(split_word);
-            }
+            let fields = self.expand_word(&word.value)?;
+            widest = widest.max(fields.len());
+            expanded_words.extend(fields);
         }
 
         if expanded_words.is_empty() {
-            return Ok(0);
+            return Ok(self.substitution_status.unwrap_or(0));
+        }
+        if self.globguard && self.interactive {
+            let max = self.limit(GLOB_GUARD_MAX_VAR, DEFAULT_GLOB_GUARD_MAX);
+            if let Some(risk) = removal_risk(&expanded_words, widest, max, &self.get_variable("HOME"))
+                && !self.confirm_removal(&expanded_words, &risk)
+            {
+                return Ok(1);
+            }
         }
+        let result = self.run_words(&cmd.assignments, &expanded_words, cmd);
+        self.last_argument = expanded_words.last().cloned().unwrap_or_default();
+        result
+    }
 
+    /// Run an already expanded command: a builtin, a function or a program.
+    /// `command` names it if it becomes a job.
+    fn run_words(&mut self, assignments: &[Assignment], expanded_words: &[String], command: &dyn std::fmt::Display) -> Result<i32, String> {
         let program = &expanded_words[0];
-        let args: Vec<&str> = expanded_words[1..].iter().map(|s| s.as_str()).collect();
+        match program.as_str() {
+            "return" => return self.execute_return(&expanded_words[1..]),
+            "shift" => return Ok(self.execute_shift(&expanded_words[1..])),
+            "source" | "." => return self.execute_source(&expanded_words[1..]),
+            "jobs" => return self.execute_jobs(&expanded_words[1..]),
+            "fg" => return self.execute_fg(&expanded_words[1..]),
+            "wait" => return self.execute_wait(&expanded_words[1..]),
+            "bg" => return self.execute_bg(&expanded_words[1..]),
+            "kill" => return self.execute_kill(&expanded_words[1..]),
+            "disown" => return self.execute_disown(&expanded_words[1..]),
+            "trap" => return self.execute_trap(&expanded_words[1..]),
+            "read" => return self.execute_read(&expanded_words[1..]),
+            "let" => return self.execute_let(&expanded_words[1..]),
+            "calc" => return Ok(self.execute_calc(&expanded_words[1..])),
+            "str" => return Ok(self.execute_str(&expanded_words[1..])),
+            "path" => return Ok(self.execute_path(&expanded_words[1..])),
+            #[cfg(feature = "fetch")]
+            "fetch" => return Ok(self.execute_fetch(&expanded_words[1..])),
+            #[cfg(feature = "extras")]
+            "hash-file" => return Ok(self.execute_hash_file(&expanded_words[1..])),
+            #[cfg(feature = "extras")]
+            "uuidgen" => return Ok(self.execute_uuidgen(&expanded_words[1..])),
+            #[cfg(feature = "extras")]
+            "random" => return Ok(self.execute_random(&expanded_words[1..])),
+            "repeat" => return self.execute_repeat(&expanded_words[1..]),
+            "sleep" => return Ok(self.execute_sleep(&expanded_words[1..])),
+            "datetime" => return Ok(self.execute_datetime(&expanded_words[1..])),
+            "loadenv" => return Ok(self.execute_loadenv(&expanded_words[1..])),
+            "retry" => return self.execute_retry(&expanded_words[1..]),
+            "reset" => return self.execute_reset(),
+            "history" => return self.execute_history(&expanded_words[1..]),
+            "session" => return self.execute_session(&expanded_words[1..]),
+            "set" => return self.execute_set(&expanded_words[1..]),
+            "declare" => return Ok(self.execute_declare(&expanded_words[1..])),
+            "pushd" => return Ok(self.execute_pushd(&expanded_words[1..])),
+            "popd" => return Ok(self.execute_popd(&expanded_words[1..])),
+            "dirs" => return Ok(self.execute_dirs(&expanded_words[1..])),
+            "shopt" => return Ok(self.execute_shopt(&expanded_words[1..])),
+            "exec" => return self.execute_exec(assignments, &expanded_words[1..]),
+            "with" => return self.execute_with(assignments, &expanded_words[1..], command),
+            "prio" => return self.execute_prio(assignments, &expanded_words[1..], command),
+            "assert_eq" | "assert_status" | "assert_output" if self.assertions.is_some() => {
+                return self.execute_assert(program, &expanded_words[1..]);
+            }
+            "from-json" | "where" | "select-field" | "to-json" if self.structured => {
+                return Ok(self.execute_structured(program, &expanded_words[1..]));
+            }
+            _ => {}
+        }
+        if let Some(body) = self.functions.get(program).cloned() {
+            return self.call_function(&body, assignments, &expanded_words[1..]);
+        }
 
-        let mut process = ProcessCommand::new(program);
-        process.args(&args);
+        let process = self.program_command(program, &expanded_words[1..], assignments)?;
+        self.spawn_program(process, program, command)
+    }
+
+    /// Start `process` as the foreground job and wait for it
+    fn spawn_program(&mut self, mut process: ProcessCommand, program: &str, command: &dyn std::fmt::Display) -> Result<i32, String> {
+        // Commands must not inherit the signals the shell blocks or catches
+        let terminal = self.job_control.map(|control| control.terminal);
+        unsafe {
+            process.pre_exec(move || {
+                signals::reset_in_child();
+                if let Some(terminal) = terminal {
+                    jobs::enter_job(terminal, 0, true);
+                }
+                Ok(())
+            });
+        }
 
-        // Apply assignments as environment variables
-        for assignment in &cmd.assignments {
-            process.env(&assignment.name, &assignment.value);
+        match self.spawner.spawn(&mut process) {
+            Ok(Spawned::Running(pid)) => {
+                self.place_job(pid, 0);
+                self.wait_foreground(&[pid], command.to_string())
+            }
+            Ok(Spawned::Exited(status)) => Ok(status),
+            // A missing command is an ordinary failure, not an aborted one
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                eprintln!("clam: {}", tr!("{}: command not found", program));
+                Ok(127)
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::PermissionDenied => {
+                eprintln!("clam: {}", tr!("{}: {}", program, e));
+                Ok(126)
+            }
+            Err(e) => Err(tr!("Failed to execute '{}': {}", program, e)),
         }
+    }
+
+    /// `program` run with `args`, and the shell's variables and
+    /// `assignments` in its environment
+    fn program_command(&mut self, program: &str, args: &[String], assignments: &[Assignment]) -> Result<ProcessCommand, String> {
+        let mut process = ProcessCommand::new(program);
+        process.args(args);
 
         // Add existing environment variables
-        for (key, value) in &self.env_vars {
-            process.env(key, value);
+        self.env_vars.apply(&mut process);
+
+        // Apply assignments as environment variables, overriding the above
+        for assignment in assignments {
+            process.env(&assignment.name, self.expand_assignment(&assignment.value)?);
         }
+        Ok(process)
+    }
 
-        match process.status() {
-            Ok(status) => {
-                let exit_code = status.code().unwrap_or(1);
-                self.last_exit_status = exit_code;
-                Ok(exit_code)
+    /// `exec [-cl] [-a name] [command [args...]]`: replace the shell with
+    /// `command`, which inherits the redirections in effect. `-c` clears
+    /// its environment, `-l` puts a `-` before its name as for a login
+    /// shell and `-a` gives it another name. Without a command, the
+    /// redirections stay in effect instead; see `execute_simple_command`.
+    fn execute_exec(&mut self, assignments: &[Assignment], args: &[String]) -> Result<i32, String> {
+        let (mut clear_env, mut login, mut name) = (false, false, None);
+        let mut args = args;
+        while let Some(option) = args.first().and_then(|arg| arg.strip_prefix('-')) {
+            args = &args[1..];
+            if option == "-" {
+                break;
+            }
+            for flag in option.chars() {
+                match flag {
+                    'c' => clear_env = true,
+                    'l' => login = true,
+                    'a' if !args.is_empty() => {
+                        name = Some(args[0].clone());
+                        args = &args[1..];
+                    }
+                    _ => {
+                        eprintln!("clam: {}", tr!("exec: usage: exec [-cl] [-a name] [command [args...]]"));
+                        return Ok(2);
+                    }
+                }
             }
-            Err(e) => Err(format!("Failed to execute '{}': {}", program, e)),
         }
-    }
+        let Some(program) = args.first() else {
+            return Ok(0);
+        };
+
+        // Look the program up first, as a failed exec leaves the shell's
+        // signal handling undone
+        let Some(path) = find_executable(program, &self.get_variable("PATH")) else {
+            eprintln!("clam: {}", tr!("exec: {}: not found", program));
+            return self.exec_failed(127);
+        };
+        let mut process = self.program_command(&path.display().to_string(), &args[1..], assignments)?;
+        if clear_env {
+            process.env_clear();
+        }
+        let name = name.unwrap_or_else(|| program.clone());
+        process.arg0(if login { format!("-{}", name) } else { name });
+        unsafe {
+            process.pre_exec(|| {
+                signals::reset_in_child();
+                jobs::restore_stop_signals();
+                Ok(())
+            });
+        }
 
-    fn execute_pipeline(&mut self, _pipeline: &Pipeline) -> Result<i32, String> {
-        Err("Pipeline execution not yet implemented".to_string())
+        let _ = std::io::stdout().flush();
+        let error = process.exec();
+        eprintln!("clam: {}", tr!("exec: {}: {}", program, error));
+        self.exec_failed(126).and_then(|_| std::process::exit(126))
     }
 
-    fn execute_list(&mut self, list: &List) -> Result<i32, String> {
-        let mut last_status = 0;
+    /// `with [--cwd dir] [--env name=value]... [--umask mode] [--] command...`:
+    /// run the external `command` in another directory, with more variables
+    /// or with another umask, leaving the shell's own as they are
+    fn execute_with(&mut self, assignments: &[Assignment], args: &[String], command: &dyn std::fmt::Display) -> Result<i32, String> {
+        let usage = || {
+            eprintln!("{}", tr!("with: usage: with [--cwd dir] [--env name=value]... [--umask mode] [--] command [args ...]"));
+            Ok(2)
+        };
+        let (mut cwd, mut env, mut umask) = (None, Vec::new(), None);
+        let mut rest = args;
+        while let Some(option) = rest.first().filter(|arg| arg.starts_with('-')) {
+            rest = &rest[1..];
+            if option == "--" {
+                break;
+            }
+            let Some((value, after)) = rest.split_first() else {
+                eprintln!("clam: {}", tr!("with: {}: option requires an argument", option));
+                return usage();
+            };
+            rest = after;
+            match option.as_str() {
+                "--cwd" => cwd = Some(value),
+                "--env" => match value.split_once('=') {
+                    Some((name, value)) if parameter::is_assignable(name) => env.push((name, value)),
+                    _ => {
+                        eprintln!("clam: {}", tr!("with: {}: not a valid assignment", value));
+                        return Ok(1);
+                    }
+                },
+                "--umask" => match u32::from_str_radix(value, 8) {
+                    Ok(mask) if mask <= 0o777 => umask = Some(mask as libc::mode_t),
+                    _ => {
+                        eprintln!("clam: {}", tr!("with: {}: octal number out of range", value));
+                        return Ok(1);
+                    }
+                },
+                _ => {
+                    eprintln!("clam: {}", tr!("with: {}: invalid option", option));
+                    return usage();
+                }
+            }
+        }
+        let Some(program) = rest.first() else {
+            return usage();
+        };
+        // Checked here, as the spawn failing would look like a missing command
+        if let Some(dir) = cwd
+            && !Path::new(dir).is_dir()
+        {
+            eprintln!("clam: {}", tr!("with: {}: not a directory", dir));
+            return Ok(1);
+        }
 
-        for item in &list.items {
-            last_status = self.execute(&item.command)?;
+        let mut process = self.program_command(program, &rest[1..], assignments)?;
+        if let Some(dir) = cwd {
+            process.current_dir(dir);
+        }
+        process.envs(env);
+        if let Some(mask) = umask {
+            unsafe {
+                process.pre_exec(move || {
+                    libc::umask(mask);
+                    Ok(())
+                });
+            }
+        }
+        self.spawn_program(process, program, command)
+    }
 
-            match item.separator {
-                Separator::And => {
-                    // && - execute next only if this succeeded
-                    if last_status != 0 {
-                        break;
+    /// `prio [-n increment] [--io class[:level]] [--] command...`: run the
+    /// external `command` nicer, or less nice, by `increment`, and in the
+    /// I/O scheduling class `idle`, `best-effort` or `realtime`
+    fn execute_prio(&mut self, assignments: &[Assignment], args: &[String], command: &dyn std::fmt::Display) -> Result<i32, String> {
+        let usage = || {
+            eprintln!("{}", tr!("prio: usage: prio [-n increment] [--io class[:level]] [--] command [args ...]"));
+            Ok(2)
+        };
+        let mut priority = Priority::default();
+        let mut rest = args;
+        while let Some(option) = rest.first().filter(|arg| arg.starts_with('-')) {
+            rest = &rest[1..];
+            if option == "--" {
+                break;
+            }
+            let Some((value, after)) = rest.split_first() else {
+                eprintln!("clam: {}", tr!("prio: {}: option requires an argument", option));
+                return usage();
+            };
+            rest = after;
+            match option.as_str() {
+                "-n" => match value.parse() {
+                    Ok(increment) => priority.nice = Some(increment),
+                    Err(_) => {
+                        eprintln!("clam: {}", tr!("prio: {}: invalid increment", value));
+                        return Ok(1);
                     }
-                }
-                Separator::Or => {
-                    // || - execute next only if this failed
-                    if last_status == 0 {
-                        break;
+                },
+                "--io" => match IoClass::parse(value) {
+                    Some(class) => priority.io = Some(class),
+                    None => {
+                        eprintln!("clam: {}", tr!("prio: {}: invalid I/O class (idle, best-effort[:0-7] or realtime[:0-7])", value));
+                        return Ok(1);
                     }
+                },
+                _ => {
+                    eprintln!("clam: {}", tr!("prio: {}: invalid option", option));
+                    return usage();
                 }
-                Separator::Sequential | Separator::Background => {
-                    // ; or & - always continue
-                    // TODO: background jobs
+            }
+        }
+        let Some(program) = rest.first() else {
+            return usage();
+        };
+
+        let mut process = self.program_command(program, &rest[1..], assignments)?;
+        priority.apply(&mut process);
+        self.spawn_program(process, program, command)
+    }
+
+    /// An interactive shell survives a command `exec` could not run; any
+    /// other exits with `status`
+    fn exec_failed(&mut self, status: i32) -> Result<i32, String> {
+        if !self.interactive {
+            self.clean_up_on_exit();
+            std::process::exit(status);
+        }
+        Ok(status)
+    }
+
+    /// Run a function body with its own positional parameters. Assignments
+    /// before the call are visible only for its duration. As in bash, the
+    /// function doesn't inherit the ERR trap without `set -E` nor RETURN
+    /// without `set -T`; those it sets itself stay once it returns.
+    fn call_function(&mut self, body: &Command, assignments: &[Assignment], args: &[String]) -> Result<i32, String> {
+        let values = assignments.iter().map(|a| self.expand_assignment(&a.value)).collect::<Result<Vec<_>, _>>()?;
+        let saved_vars: Vec<(String, Option<String>)> = assignments
+            .iter()
+            .zip(values)
+            .map(|(a, value)| {
+                let previous = self.env_vars.insert(a.name.clone(), value);
+                (a.name.clone(), previous)
+            })
+            .collect();
+        let saved_positional = std::mem::replace(&mut self.positional, args.to_vec());
+        let hidden_traps: Vec<(Condition, String)> = [(Condition::Err, self.errtrace), (Condition::Return, self.functrace)]
+            .into_iter()
+            .filter(|&(_, inherited)| !inherited)
+            .filter_map(|(condition, _)| Some((condition, self.traps.reset(condition)?)))
+            .collect();
+        self.function_depth += 1;
+
+        let result = self.execute(body);
+        self.returning = false;
+        if result.is_ok() {
+            self.run_condition_trap(Condition::Return);
+        }
+
+        for (condition, action) in hidden_traps {
+            if self.traps.get(condition).is_none() {
+                self.traps.set(condition, &action);
+            }
+        }
+        self.function_depth -= 1;
+        self.positional = saved_positional;
+        for (name, previous) in saved_vars.into_iter().rev() {
+            match previous {
+                Some(value) => self.env_vars.insert(name, value),
+                None => self.env_vars.remove(&name),
+            };
+        }
+
+        result
+    }
+
+    fn execute_return(&mut self, args: &[String]) -> Result<i32, String> {
+        if self.function_depth == 0 && self.source_stack.is_empty() {
+            return Err(tr!("return: can only `return' from a function or sourced script"));
+        }
+
+        let status = match args.first() {
+            Some(arg) => arg
+                .parse::<i32>()
+                .map_err(|_| tr!("return: {}: numeric argument required", arg))?
+                & 0xff,
+            None => self.last_exit_status,
+        };
+        self.returning = true;
+        Ok(status)
+    }
+
+    /// `shift [n]`: drop the first `n` positional parameters, or one.
+    /// Fails, dropping none, when there are fewer than `n`.
+    fn execute_shift(&mut self, args: &[String]) -> i32 {
+        let count = match args {
+            [] => 1,
+            [count] => match count.parse::<usize>() {
+                Ok(count) => count,
+                Err(_) => {
+                    eprintln!("clam: {}", tr!("shift: {}: numeric argument required", count));
+                    return 1;
                 }
-                Separator::Pipe => {
-                    // Should not appear in List, only in Pipeline
+            },
+            _ => {
+                eprintln!("{}", tr!("shift: usage: shift [n]"));
+                return 2;
+            }
+        };
+        if count > self.positional.len() {
+            return 1;
+        }
+        self.positional.drain(..count);
+        0
+    }
+
+    /// `source file [args...]`: run a file in the current shell. Names
+    /// without a slash are searched for in PATH, then the current directory.
+    fn execute_source(&mut self, args: &[String]) -> Result<i32, String> {
+        let Some(name) = args.first() else {
+            eprintln!("clam: {}", tr!("source: filename argument required"));
+            return Ok(2);
+        };
+        let Some(path) = find_source_file(name, &self.get_variable("PATH")) else {
+            eprintln!("clam: {}", tr!("source: {}: file not found", name));
+            return Ok(1);
+        };
+
+        // Files that source each other would otherwise recurse until the stack overflows
+        let canonical = std::fs::canonicalize(&path).unwrap_or_else(|_| path.clone());
+        if let Some(first) = self.source_stack.iter().position(|p| *p == canonical) {
+            let chain: Vec<String> = self.source_stack[first..]
+                .iter()
+                .chain(std::iter::once(&canonical))
+                .map(|p| p.display().to_string())
+                .collect();
+            eprintln!("clam: {}", tr!("source: {}: recursive source ({})", name, chain.join(" -> ")));
+            return Ok(1);
+        }
+
+        let source = match std::fs::read_to_string(&path) {
+            Ok(source) => source,
+            Err(e) => {
+                eprintln!("clam: {}", tr!("source: {}: {}", name, e));
+                return Ok(1);
+            }
+        };
+        let commands = match script::parse(&source) {
+            Ok(commands) => commands,
+            Err(error) => {
+                eprintln!("clam: {}", tr!("{}: line {}: syntax error: {}", name, error.line, error.message));
+                return Ok(script::SYNTAX_ERROR_STATUS);
+            }
+        };
+
+        let saved_positional = if args.len() > 1 {
+            Some(std::mem::replace(&mut self.positional, args[1..].to_vec()))
+        } else {
+            None
+        };
+        self.source_stack.push(canonical);
+
+        let mut status = 0;
+        let mut result = Ok(());
+        for command in &commands {
+            match self.execute(command) {
+                Ok(command_status) => status = command_status,
+                Err(e) => {
+                    result = Err(e);
+                    break;
                 }
             }
+            if self.unwinding() {
+                break;
+            }
         }
 
-        Ok(last_status)
+        self.source_stack.pop();
+        self.returning = false;
+        if result.is_ok() {
+            self.run_condition_trap(Condition::Return);
+        }
+        if let Some(positional) = saved_positional {
+            self.positional = positional;
+        }
+
+        result.map(|()| status)
     }
 
-    fn execute_if(&mut self, if_cmd: &IfCommand) -> Result<i32, String> {
-        let condition_status = self.execute(&if_cmd.condition)?;
+    fn execute_pipeline(&mut self, pipeline: &Pipeline) -> Result<i32, String> {
+        // A lone command, as in `! cmd`, runs in the current shell
+        let status = match pipeline.commands.as_slice() {
+            [command] if pipeline.negated => self.execute_condition(command)?,
+            [command] => self.execute(command)?,
+            commands => {
+                let status = self.execute_pipeline_stages(commands)?;
+                match self.pipe_status.iter().rfind(|&&status| status != 0) {
+                    Some(&failure) if self.pipefail && self.pipe_status.len() == commands.len() => failure,
+                    _ => status,
+                }
+            }
+        };
 
-        if condition_status == 0 {
-            self.execute(&if_cmd.then_part)
+        if pipeline.negated {
+            Ok(if status == 0 { 1 } else { 0 })
         } else {
-            // Check elif clauses
-            for (elif_condition, elif_body) in &if_cmd.elif_parts {
-                let elif_status = self.execute(elif_condition)?;
-                if elif_status == 0 {
-                    return self.execute(elif_body);
+            Ok(status)
+        }
+    }
+
+    /// Run each command in its own forked child, stdout of one connected to
+    /// stdin of the next. The status is that of the last command.
+    fn execute_pipeline_stages(&mut self, commands: &[Command]) -> Result<i32, String> {
+        let mut pids = Vec::new();
+        let mut pgid = 0;
+        let mut input: Option<RawFd> = None;
+        let mut result = Ok(());
+
+        for (index, command) in commands.iter().enumerate() {
+            let pipe = if index + 1 < commands.len() {
+                match create_pipe() {
+                    Ok(pipe) => Some(pipe),
+                    Err(e) => {
+                        result = Err(e);
+                        break;
+                    }
+                }
+            } else {
+                None
+            };
+
+            let pid = match self.fork() {
+                Ok(pid) => pid,
+                Err(e) => {
+                    if let Some((read_end, write_end)) = pipe {
+                        unsafe {
+                            libc::close(read_end);
+                            libc::close(write_end);
+                        }
+                    }
+                    result = Err(e);
+                    break;
+                }
+            };
+
+            if pid == 0 {
+                self.enter_job(pgid, true);
+                unsafe {
+                    if let Some(fd) = input {
+                        libc::dup2(fd, 0);
+                        libc::close(fd);
+                    }
+                    if let Some((read_end, write_end)) = pipe {
+                        libc::dup2(write_end, 1);
+                        libc::close(write_end);
+                        libc::close(read_end);
+                    }
                 }
+                self.run_child(command);
             }
 
-            // Execute else part if present
-            if let Some(else_part) = &if_cmd.else_part {
-                self.execute(else_part)
-            } else {
-                Ok(0)
+            self.place_job(pid, pgid);
+            if pgid == 0 {
+                pgid = pid;
+            }
+            pids.push(pid);
+            if let Some(fd) = input.take() {
+                unsafe { libc::close(fd) };
+            }
+            if let Some((read_end, write_end)) = pipe {
+                unsafe { libc::close(write_end) };
+                input = Some(read_end);
             }
         }
+
+        if let Some(fd) = input {
+            unsafe { libc::close(fd) };
+        }
+
+        // Always reap the children that did start
+        if pids.is_empty() {
+            return result.map(|()| 0);
+        }
+        let text = commands.iter().map(|c| c.to_string()).collect::<Vec<_>>().join(" | ");
+        let status = self.wait_foreground(&pids, text)?;
+        result.map(|()| status)
     }
 
-    fn execute_while(&mut self, while_cmd: &WhileCommand) -> Result<i32, String> {
-        loop {
-            let condition_status = self.execute(&while_cmd.condition)?;
-            if condition_status != 0 {
+    fn execute_list(&mut self, list: &List) -> Result<i32, String> {
+        let mut last_status = 0;
+        let mut start = 0;
+
+        while start < list.items.len() {
+            // An and-or chain extends up to the next `;`, newline or `&`
+            let end = list.items[start..]
+                .iter()
+                .position(|item| !matches!(item.separator, Separator::And | Separator::Or))
+                .map_or(list.items.len(), |offset| start + offset + 1);
+            let chain = &list.items[start..end];
+
+            last_status = if chain[chain.len() - 1].separator == Separator::Background {
+                self.execute_background(chain)?
+            } else {
+                self.execute_and_or(chain)?
+            };
+            if self.unwinding() {
                 break;
             }
-            self.execute(&while_cmd.body)?;
+            start = end;
         }
-        Ok(0)
+
+        Ok(last_status)
     }
 
-    fn execute_until(&mut self, until_cmd: &UntilCommand) -> Result<i32, String> {
-        loop {
-            let condition_status = self.execute(&until_cmd.condition)?;
-            if condition_status == 0 {
+    fn execute_and_or(&mut self, chain: &[ListItem]) -> Result<i32, String> {
+        // Only the last command of the chain is not being tested
+        let last = chain.len() - 1;
+        let mut status = if last == 0 {
+            self.execute(&chain[0].command)?
+        } else {
+            self.execute_condition(&chain[0].command)?
+        };
+
+        for (index, pair) in chain.windows(2).enumerate() {
+            if self.unwinding() {
                 break;
             }
-            self.execute(&until_cmd.body)?;
+            // A skipped command leaves the status as is, so in
+            // `false && a || b` the failure still reaches `|| b`
+            let run_next = match pair[0].separator {
+                Separator::And => status == 0,
+                Separator::Or => status != 0,
+                _ => true,
+            };
+            if run_next {
+                status = if index + 1 == last {
+                    self.execute(&pair[1].command)?
+                } else {
+                    self.execute_condition(&pair[1].command)?
+                };
+            }
         }
-        Ok(0)
+
+        Ok(status)
     }
 
-    fn execute_for(&mut self, for_cmd: &ForCommand) -> Result<i32, String> {
-        for word in &for_cmd.words {
-            self.env_vars.insert(for_cmd.variable.clone(), word.clone());
-            self.execute(&for_cmd.body)?;
+    /// Start an and-or chain terminated by `&` in a forked child without
+    /// waiting for it. The child is recorded so it can be reaped later.
+    fn execute_background(&mut self, chain: &[ListItem]) -> Result<i32, String> {
+        let pid = self.fork()?;
+        if pid == 0 {
+            if self.job_control.is_some() {
+                self.enter_job(0, false);
+            } else if let Ok(null) = std::fs::File::open("/dev/null") {
+                // Without job control, background commands must not compete
+                // with the shell for terminal input
+                let fd = null.into_raw_fd();
+                unsafe {
+                    libc::dup2(fd, 0);
+                    libc::close(fd);
+                }
+            }
+            let result = self.execute_and_or(chain);
+            self.finish_child(result);
+        }
+
+        self.place_job(pid, 0);
+        let command = Command::List(List { items: chain.to_vec() }).to_string();
+        let pgid = self.job_control.map(|_| pid);
+        let id = self.jobs.add(&[pid], pgid, command.trim_end_matches(" &").to_string());
+        self.last_background_pid = Some(pid);
+        if self.interactive {
+            eprintln!("[{}] {}", id, pid);
         }
         Ok(0)
     }
 
-    fn execute_redirected(&mut self, _redirected: &RedirectedCommand) -> Result<i32, String> {
-        Err("Redirected command execution not yet implemented".to_string())
+    /// In a forked child, join the job's process group. The child does not
+    /// do job control itself.
+    fn enter_job(&mut self, pgid: libc::pid_t, foreground: bool) {
+        if let Some(control) = self.job_control.take() {
+            jobs::enter_job(control.terminal, pgid, foreground);
+        }
     }
 
-    pub fn get_last_exit_status(&self) -> i32 {
-        self.last_exit_status
+    /// In the parent, put a forked child into process group `pgid` (0 for a
+    /// new group it leads)
+    fn place_job(&self, pid: libc::pid_t, pgid: libc::pid_t) {
+        if self.job_control.is_some() {
+            unsafe { libc::setpgid(pid, pgid) };
+        }
     }
 
-    fn expand_variables(&self, input: &str) -> String {
-        let mut result = String::new();
-        let mut chars = input.chars().peekable();
-
-        while let Some(ch) = chars.next() {
-            if ch == '$' {
-                if chars.peek() == Some(&'{') {
-                    // ${VAR} syntax
-                    chars.next(); // consume '{'
-                    let mut var_name = String::new();
-
-                    while let Some(&c) = chars.peek() {
-                        if c == '}' {
-                            chars.next(); // consume '}'
-                            break;
-                        }
-                        var_name.push(chars.next().unwrap());
-                    }
+    /// Wait for the processes of a job started in the foreground. A job
+    /// that stops is kept, to be resumed with `fg` or `bg` or killed,
+    /// rather than leaving the shell waiting for it forever.
+    fn wait_foreground(&mut self, pids: &[libc::pid_t], command: String) -> Result<i32, String> {
+        let pgid = self.job_control.map(|_| pids[0]);
+        self.jobs.add(pids, pgid, command);
+        self.wait_job(self.jobs.jobs().len() - 1, false)
+    }
 
-                    result.push_str(&self.get_variable(&var_name));
-                } else {
-                    // $VAR syntax
-                    let mut var_name = String::new();
-
-                    while let Some(&c) = chars.peek() {
-                        if c.is_alphanumeric() || c == '_' {
-                            var_name.push(chars.next().unwrap());
-                        } else {
-                            break;
-                        }
-                    }
+    /// Give the job at `index` the terminal, optionally continue it, and
+    /// wait until it finishes or stops
+    fn wait_job(&mut self, index: usize, resume: bool) -> Result<i32, String> {
+        if let (Some(control), Some(pgid)) = (self.job_control, self.jobs.jobs()[index].pgid) {
+            terminal::give_terminal(control.terminal, pgid);
+        }
+        if resume {
+            // Put back what the job had set up, e.g. an editor's raw mode
+            if self.terminal_modes.is_some()
+                && let Some(modes) = &self.jobs.jobs()[index].modes
+            {
+                modes.restore();
+            }
+            self.jobs.signal(index, libc::SIGCONT).map_err(|e| tr!("kill: {}", e))?;
+            self.jobs.set_state(index, JobState::Running);
+        }
 
-                    result.push_str(&self.get_variable(&var_name));
+        let state = self.jobs.wait(index);
+        if let Some(control) = self.job_control {
+            terminal::give_terminal(control.terminal, control.shell_pgid);
+        }
+        let state = state?;
+        if state == JobState::Stopped && self.terminal_modes.is_some() {
+            self.jobs.save_modes(index, TerminalModes::save(self.terminal_fd()));
+        }
+        self.settle_terminal(state);
+
+        match state {
+            JobState::Done(status) => {
+                self.pipe_status = self.jobs.remove(index).statuses();
+                // The shell didn't get the SIGINT itself, but should act on it
+                if status == INTERRUPTED_STATUS {
+                    self.act_on_interrupt();
                 }
-            } else {
-                result.push(ch);
+                Ok(status)
+            }
+            _ => {
+                eprintln!("\n{}", self.jobs.format(index, false));
+                self.pipe_status = vec![128 + libc::SIGTSTP];
+                Ok(128 + libc::SIGTSTP)
             }
         }
+    }
 
-        result
+    /// After a foreground job, keep terminal settings it changed if it
+    /// exited normally, as `stty` intends, but put back the shell's own if
+    /// it was killed or stopped, e.g. an editor that had no chance to
+    /// restore them
+    fn settle_terminal(&mut self, state: JobState) {
+        let Some(modes) = &self.terminal_modes else {
+            return;
+        };
+        match state {
+            JobState::Done(status) if status <= 128 => {
+                self.terminal_modes = Some(TerminalModes::save(self.terminal_fd()));
+            }
+            _ => modes.restore(),
+        }
     }
 
-    fn get_variable(&self, name: &str) -> String {
-        // Check shell variables first
-        if let Some(value) = self.env_vars.get(name) {
-            return value.clone();
+    /// The terminal the shell reads commands from
+    fn terminal_fd(&self) -> RawFd {
+        self.job_control.map_or(0, |control| control.terminal)
+    }
+
+    /// `set -o name` / `set +o name`: turn a shell option on or off. Without
+    /// a name, list the options, as a table or as `set` commands for `+o`;
+    /// `set -o --json` lists them as a JSON object.
+    fn execute_set(&mut self, args: &[String]) -> Result<i32, String> {
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            // The positional parameters follow the options, after `--` if
+            // they could be taken for one
+            if arg == "--" {
+                self.positional = args.cloned().collect();
+                return Ok(0);
+            }
+            let (enable, flags) = match arg.split_at_checked(1) {
+                Some(("-", flags)) if !flags.is_empty() => (true, flags),
+                Some(("+", flags)) if !flags.is_empty() => (false, flags),
+                _ if !arg.starts_with(['-', '+']) => {
+                    self.positional = std::iter::once(arg).chain(args).cloned().collect();
+                    return Ok(0);
+                }
+                _ => {
+                    eprintln!("clam: {}", tr!("set: {}: invalid option", arg));
+                    return Ok(2);
+                }
+            };
+            if flags == "o" {
+                let Some(name) = args.next() else {
+                    self.print_options(enable);
+                    return Ok(0);
+                };
+                if name == "--json" {
+                    print_json(&self.options().into_iter().collect::<BTreeMap<_, _>>());
+                    return Ok(0);
+                }
+                if !self.set_option(name, enable) {
+                    eprintln!("clam: {}", tr!("set: {}: invalid option name", name));
+                    return Ok(1);
+                }
+                continue;
+            }
+            for flag in flags.chars() {
+                let name = SHORT_OPTIONS.iter().find(|(short, _)| *short == flag).map(|(_, name)| *name);
+                if !name.is_some_and(|name| self.set_option(name, enable)) {
+                    eprintln!("clam: {}", tr!("set: {}: invalid option", format!("{}{}", &arg[..1], flag)));
+                    return Ok(2);
+                }
+            }
+        }
+        Ok(0)
+    }
+
+    /// `declare [-p] [--json] [name ...]`: show the variables, or those
+    /// named, as `declare -- name="value"` lines the shell can read back, or
+    /// with `--json` as one JSON object
+    fn execute_declare(&mut self, args: &[String]) -> i32 {
+        let mut json = false;
+        let mut names = Vec::new();
+        for arg in args {
+            match arg.as_str() {
+                "-p" => {}
+                "--json" => json = true,
+                _ if !arg.starts_with('-') => names.push(arg.as_str()),
+                _ => {
+                    eprintln!("clam: {}", tr!("declare: {}: invalid option", arg));
+                    eprintln!("{}", tr!("declare: usage: declare [-p] [--json] [name ...]"));
+                    return 2;
+                }
+            }
         }
 
-        // Then check environment variables
-        std::env::var(name).unwrap_or_default()
+        let mut status = 0;
+        let variables: BTreeMap<String, String> = if names.is_empty() {
+            let mut variables: BTreeMap<String, String> = std::env::vars().collect();
+            variables.extend(self.env_vars.iter().map(|(name, value)| (name.clone(), value.clone())));
+            variables
+        } else {
+            let mut variables = BTreeMap::new();
+            for name in names {
+                if self.is_set(name) {
+                    variables.insert(name.to_string(), self.get_variable(name));
+                } else {
+                    eprintln!("clam: {}", tr!("declare: {}: not found", name));
+                    status = 1;
+                }
+            }
+            variables
+        };
+        if json {
+            print_json(&variables);
+            return status;
+        }
+        let mut out = std::io::stdout().lock();
+        for (name, value) in &variables {
+            let _ = writeln!(out, "declare -- {}=\"{}\"", name, escape_double_quoted(value));
+        }
+        status
+    }
+
+    /// The builtins of `set -o structured`, which read records from stdin
+    /// and write them to stdout, one JSON value per line:
+    /// `from-json` turns JSON into records, `where FIELD OPERATOR VALUE`
+    /// and `select-field FIELD...` filter them, and `to-json [-p]` makes
+    /// them one JSON array again
+    fn execute_structured(&mut self, name: &str, args: &[String]) -> i32 {
+        // Read unbuffered from fd 0, which may be redirected for this command
+        let stdin = std::mem::ManuallyDrop::new(unsafe { std::fs::File::from_raw_fd(0) });
+        let input = std::io::BufReader::new(&*stdin);
+        let mut stdout = std::io::stdout().lock();
+        let result = match (name, args) {
+            ("from-json", []) => structured::from_json(input, &mut stdout),
+            ("to-json", []) => structured::to_json(input, &mut stdout, false),
+            ("to-json", [pretty]) if pretty == "-p" => structured::to_json(input, &mut stdout, true),
+            ("where", _) => match structured::Condition::parse(args) {
+                Ok(condition) => structured::filter(input, &mut stdout, &condition),
+                Err(e) => {
+                    eprintln!("clam: {}", e);
+                    return 2;
+                }
+            },
+            ("select-field", [_, ..]) => structured::select(input, &mut stdout, args),
+            _ => {
+                let usage = match name {
+                    "select-field" => "FIELD...",
+                    "to-json" => "[-p]",
+                    _ => "",
+                };
+                eprintln!("clam: {}", tr!("{}: usage: {} {}", name, name, usage).trim_end());
+                return 2;
+            }
+        };
+        let _ = stdout.flush();
+        match result {
+            Ok(()) => 0,
+            Err(e) => {
+                eprintln!("clam: {}", tr!("{}: {}", name, e));
+                1
+            }
+        }
+    }
+
+    /// Ask on the terminal whether to run the recursive `rm` in `words`
+    /// anyway, after saying what makes it `risk`y and listing what it would
+    /// remove. Without a terminal to ask on, the answer is no.
+    fn confirm_removal(&self, words: &[String], risk: &str) -> bool {
+        eprintln!("clam: {}", risk);
+        for word in &words[1..] {
+            eprintln!("  {}", word);
+        }
+        let terminal = match self.job_control {
+            Some(job_control) => job_control.terminal,
+            None if unsafe { libc::isatty(0) } == 1 => 0,
+            None => {
+                eprintln!("clam: {}", tr!("{}: not run, as there is no terminal to confirm it on", words[0]));
+                return false;
+            }
+        };
+        eprint!("{}", tr!("Run it anyway? [y/N] "));
+        let (answer, _) = read_line_from(terminal, true);
+        let confirmed = matches!(answer.trim(), "y" | "Y" | "yes" | "Yes");
+        if !confirmed {
+            eprintln!("clam: {}", tr!("{}: not run", words[0]));
+        }
+        confirmed
+    }
+
+    /// Turn the option `name` on or off; false if there is no such option
+    fn set_option(&mut self, name: &str, enable: bool) -> bool {
+        match name {
+            "errtrace" => self.errtrace = enable,
+            "functrace" => self.functrace = enable,
+            "globguard" => self.globguard = enable,
+            "globstar" => self.globstar = enable,
+            "noclobber" => self.noclobber = enable,
+            "pipefail" => self.pipefail = enable,
+            "structured" => self.structured = enable,
+            _ => return false,
+        }
+        true
+    }
+
+    /// `shopt [-pqsu] [-o] [name...]`: turn the options named on with `-s`
+    /// or off with `-u`. Otherwise list them, or all of them, or those on
+    /// or off, as a table or with `-p` as `shopt` commands; `-q` only sets
+    /// the status, 1 if a named option is off. `-o` works on the options of
+    /// `set -o` instead.
+    fn execute_shopt(&mut self, args: &[String]) -> i32 {
+        let (mut enable, mut print, mut quiet, mut set_options) = (None, false, false, false);
+        let mut args = args;
+        while let Some(flags) = args.first().and_then(|arg| arg.strip_prefix('-')).filter(|flags| !flags.is_empty()) {
+            args = &args[1..];
+            if flags == "-" {
+                break;
+            }
+            for flag in flags.chars() {
+                match flag {
+                    's' => enable = Some(true),
+                    'u' => enable = Some(false),
+                    'p' => print = true,
+                    'q' => quiet = true,
+                    'o' => set_options = true,
+                    _ => {
+                        eprintln!("clam: {}", tr!("shopt: {}: invalid option", format!("-{}", flag)));
+                        eprintln!("{}", tr!("shopt: usage: shopt [-pqsu] [-o] [optname ...]"));
+                        return 2;
+                    }
+                }
+            }
+        }
+        let options = if set_options { self.options() } else { self.shopt_options() };
+        if let Some(name) = args.iter().find(|name| !options.iter().any(|(option, _)| option == name)) {
+            eprintln!("clam: {}", tr!("shopt: {}: invalid shell option name", name));
+            return 1;
+        }
+        if let Some(enable) = enable
+            && !args.is_empty()
+        {
+            for name in args {
+                if set_options {
+                    self.set_option(name, enable);
+                } else {
+                    self.set_shopt_option(name, enable);
+                }
+            }
+            return 0;
+        }
+
+        let listed: Vec<_> = if args.is_empty() {
+            options.into_iter().filter(|(_, on)| enable.is_none_or(|enable| *on == enable)).collect()
+        } else {
+            args.iter().filter_map(|arg| options.iter().find(|(name, _)| name == arg).copied()).collect()
+        };
+        if !quiet {
+            let mut stdout = std::io::stdout();
+            for (name, on) in &listed {
+                let _ = match (print, set_options) {
+                    (true, true) => writeln!(stdout, "set {}o {}", if *on { '-' } else { '+' }, name),
+                    (true, false) => writeln!(stdout, "shopt {} {}", if *on { "-s" } else { "-u" }, name),
+                    (false, _) => writeln!(stdout, "{:<15}\t{}", name, if *on { "on" } else { "off" }),
+                };
+            }
+        }
+        i32::from(!args.is_empty() && listed.iter().any(|(_, on)| !on))
+    }
+
+    /// Turn the `shopt` option `name` on or off; false if there is no such
+    /// option
+    fn set_shopt_option(&mut self, name: &str, enable: bool) -> bool {
+        match name {
+            "dotglob" => self.dotglob = enable,
+            "extglob" => self.extglob = enable,
+            "failglob" => self.failglob = enable,
+            "globstar" => self.globstar = enable,
+            "nocaseglob" => self.nocaseglob = enable,
+            "nocasematch" => self.nocasematch = enable,
+            "nullglob" => self.nullglob = enable,
+            _ => return false,
+        }
+        true
+    }
+
+    /// The options of `shopt`, with whether each is on. `globstar` is one
+    /// of `set -o` too.
+    fn shopt_options(&self) -> Vec<(&'static str, bool)> {
+        vec![
+            ("dotglob", self.dotglob),
+            ("extglob", self.extglob),
+            ("failglob", self.failglob),
+            ("globstar", self.globstar),
+            ("nocaseglob", self.nocaseglob),
+            ("nocasematch", self.nocasematch),
+            ("nullglob", self.nullglob),
+        ]
+    }
+
+    /// Shell options with whether each is on
+    fn options(&self) -> Vec<(&'static str, bool)> {
+        vec![
+            ("errtrace", self.errtrace),
+            ("functrace", self.functrace),
+            ("globguard", self.globguard),
+            ("globstar", self.globstar),
+            ("noclobber", self.noclobber),
+            ("pipefail", self.pipefail),
+            ("structured", self.structured),
+        ]
+    }
+
+    /// `$-`: the letters `set` takes for the options that are on, then `i`
+    /// in an interactive shell and `m` while job control is on
+    fn option_flags(&self) -> String {
+        let options = self.options();
+        let mut flags: String = SHORT_OPTIONS
+            .iter()
+            .filter(|(_, name)| options.contains(&(name, true)))
+            .map(|(short, _)| *short)
+            .collect();
+        if self.interactive {
+            flags.push('i');
+        }
+        if self.job_control.is_some() {
+            flags.push('m');
+        }
+        flags
+    }
+
+    fn print_options(&self, table: bool) {
+        let mut stdout = std::io::stdout();
+        for (name, on) in self.options() {
+            let _ = if table {
+                writeln!(stdout, "{:<15}\t{}", name, if on { "on" } else { "off" })
+            } else {
+                writeln!(stdout, "set {}o {}", if on { '-' } else { '+' }, name)
+            };
+        }
+    }
+
+    /// `reset`: put the terminal back into a usable state after a program
+    /// left it garbled
+    fn execute_reset(&mut self) -> Result<i32, String> {
+        let fd = self.terminal_fd();
+        if let Err(e) = terminal::set_sane_modes(fd) {
+            eprintln!("clam: {}", tr!("reset: {}", e));
+            return Ok(1);
+        }
+        if self.terminal_modes.is_some() {
+            self.terminal_modes = Some(TerminalModes::save(fd));
+        }
+        Ok(0)
+    }
+
+    /// `history [--stats] [-n count] [--here | --cwd dir] [--failed] [text]`:
+    /// list the commands in the history, oldest first, that match all the
+    /// conditions given. With `--stats`, summarize them per command
+    /// instead, the most used first.
+    fn execute_history(&mut self, args: &[String]) -> Result<i32, String> {
+        let usage = |arg: &str| {
+            eprintln!("clam: {}", tr!("history: {}: invalid option", arg));
+            eprintln!("{}", tr!("history: usage: history [--stats] [-n count] [--here | --cwd dir] [--failed] [text]"));
+            Ok(2)
+        };
+        let mut query = HistoryQuery::default();
+        let mut stats = false;
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-n" => match args.next().and_then(|count| count.parse().ok()) {
+                    Some(count) => query.limit = Some(count),
+                    None => return usage(arg),
+                },
+                "--cwd" => match args.next() {
+                    Some(dir) => query.cwd = Some(std::fs::canonicalize(dir).unwrap_or_else(|_| dir.into())),
+                    None => return usage(arg),
+                },
+                "--here" => query.cwd = std::env::current_dir().ok(),
+                "--failed" => query.failed = true,
+                "--stats" => stats = true,
+                _ if !arg.starts_with('-') && query.text.is_none() => query.text = Some(arg.clone()),
+                _ => return usage(arg),
+            }
+        }
+
+        let Some(history) = &self.history else {
+            return Ok(0);
+        };
+        // With --stats, -n counts commands rather than entries
+        let rows = if stats { query.limit.take() } else { None };
+        let entries = match history.lock().unwrap().search(&query) {
+            Ok(entries) => entries,
+            Err(e) => {
+                eprintln!("clam: {}", tr!("history: {}", e));
+                return Ok(1);
+            }
+        };
+        let mut out = std::io::stdout().lock();
+        if stats {
+            let _ = writeln!(out, "{:>6}  {:>6}  {:>7}  {}", tr!("RUNS"), tr!("FAILED"), tr!("AVERAGE"), tr!("COMMAND"));
+            let stats = history_stats::command_stats(&entries);
+            for command in stats.iter().take(rows.unwrap_or(usize::MAX)) {
+                let failed = command.failure_rate().map_or("-".to_string(), |rate| format!("{}%", rate));
+                let average = command.average_ms().map_or("-".to_string(), history_stats::format_duration);
+                let _ = writeln!(out, "{:>6}  {:>6}  {:>7}  {}", command.runs, failed, average, command.name);
+            }
+            return Ok(0);
+        }
+        for entry in entries {
+            let _ = writeln!(out, "{}", entry.command);
+        }
+        Ok(0)
+    }
+
+    /// `pushd [dir]`: change to `dir`, saving the working directory on the
+    /// directory stack, or without one swap the top two directories
+    fn execute_pushd(&mut self, args: &[String]) -> i32 {
+        let target = match args {
+            [] => match self.dir_stack.first() {
+                Some(dir) => dir.clone(),
+                None => {
+                    eprintln!("clam: {}", tr!("pushd: no other directory"));
+                    return 1;
+                }
+            },
+            [dir] if !dir.starts_with('-') => PathBuf::from(dir),
+            _ => {
+                eprintln!("{}", tr!("pushd: usage: pushd [dir]"));
+                return 2;
+            }
+        };
+        let cwd = match std::env::current_dir() {
+            Ok(cwd) => cwd,
+            Err(e) => {
+                eprintln!("clam: {}", tr!("pushd: {}", e));
+                return 1;
+            }
+        };
+        if let Err(e) = std::env::set_current_dir(&target) {
+            eprintln!("clam: {}", tr!("pushd: {}: {}", target.display(), e));
+            return 1;
+        }
+        if args.is_empty() {
+            self.dir_stack[0] = cwd;
+        } else {
+            self.dir_stack.insert(0, cwd);
+        }
+        self.execute_dirs(&[])
+    }
+
+    /// `popd`: change to the directory on top of the directory stack and
+    /// take it off
+    fn execute_popd(&mut self, args: &[String]) -> i32 {
+        if !args.is_empty() {
+            eprintln!("{}", tr!("popd: usage: popd"));
+            return 2;
+        }
+        let Some(dir) = self.dir_stack.first() else {
+            eprintln!("clam: {}", tr!("popd: directory stack empty"));
+            return 1;
+        };
+        if let Err(e) = std::env::set_current_dir(dir) {
+            eprintln!("clam: {}", tr!("popd: {}: {}", dir.display(), e));
+            return 1;
+        }
+        self.dir_stack.remove(0);
+        self.execute_dirs(&[])
+    }
+
+    /// `dirs [-clpv] [--json]`: show the directory stack, the working
+    /// directory first, on one line with `~` for home. `-l` shows home in
+    /// full, `-p` puts each directory on a line of its own and `-v`
+    /// numbers them too. `-c` empties the stack instead, and `--json`
+    /// shows it as a JSON array of full paths.
+    fn execute_dirs(&mut self, args: &[String]) -> i32 {
+        let (mut full, mut lines, mut numbered, mut json) = (false, false, false, false);
+        for arg in args {
+            let flags = match arg.as_str() {
+                "--json" => {
+                    json = true;
+                    continue;
+                }
+                arg => arg.strip_prefix('-').filter(|flags| !flags.is_empty()),
+            };
+            for flag in flags.unwrap_or("?").chars() {
+                match flag {
+                    'c' => {
+                        self.dir_stack.clear();
+                        return 0;
+                    }
+                    'l' => full = true,
+                    'p' => lines = true,
+                    'v' => numbered = true,
+                    _ => {
+                        eprintln!("clam: {}", tr!("dirs: {}: invalid option", arg));
+                        eprintln!("{}", tr!("dirs: usage: dirs [-clpv] [--json]"));
+                        return 2;
+                    }
+                }
+            }
+        }
+
+        let cwd = std::env::current_dir().unwrap_or_else(|_| PathBuf::from(self.get_variable("PWD")));
+        let stack: Vec<&Path> = std::iter::once(cwd.as_path()).chain(self.dir_stack.iter().map(PathBuf::as_path)).collect();
+        if json {
+            print_json(&stack);
+            return 0;
+        }
+        let home = self.get_variable("HOME");
+        let shown: Vec<String> = stack
+            .iter()
+            .map(|dir| match dir.strip_prefix(&home).ok().filter(|_| !full && !home.is_empty() && home != "/") {
+                Some(rest) if rest.as_os_str().is_empty() => "~".to_string(),
+                Some(rest) => format!("~/{}", rest.display()),
+                None => dir.display().to_string(),
+            })
+            .collect();
+        let mut out = std::io::stdout().lock();
+        let _ = if numbered {
+            shown.iter().enumerate().try_for_each(|(index, dir)| writeln!(out, "{:2}  {}", index, dir))
+        } else if lines {
+            shown.iter().try_for_each(|dir| writeln!(out, "{}", dir))
+        } else {
+            writeln!(out, "{}", shown.join(" "))
+        };
+        0
+    }
+
+    /// `session save NAME`, `session restore NAME` or `session list`:
+    /// checkpoint the shell variables, functions, options and working
+    /// directory, to carry on with them later or in another terminal
+    fn execute_session(&mut self, args: &[String]) -> Result<i32, String> {
+        let dir = session::directory(&self.get_variable(session::SESSION_DIR_VAR));
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+        let result = match args.as_slice() {
+            ["save", name] => self.session().save(&dir, name).map(|_| 0),
+            ["restore", name] => Session::load(&dir, name).map(|session| self.restore_session(session)),
+            ["list"] => {
+                let mut out = std::io::stdout().lock();
+                for name in session::list(&dir) {
+                    let _ = writeln!(out, "{}", name);
+                }
+                Ok(0)
+            }
+            _ => {
+                eprintln!("{}", tr!("session: usage: session save NAME | session restore NAME | session list"));
+                return Ok(2);
+            }
+        };
+        result.or_else(|e| {
+            eprintln!("clam: {}", tr!("session: {}", e));
+            Ok(1)
+        })
+    }
+
+    fn session(&self) -> Session {
+        Session {
+            cwd: std::env::current_dir().ok(),
+            variables: self
+                .env_vars
+                .iter()
+                .filter(|(name, _)| !PROCESS_VARIABLES.contains(&name.as_str()))
+                .map(|(name, value)| (name.clone(), value.clone()))
+                .collect(),
+            functions: self.functions.iter().map(|(name, body)| (name.clone(), (**body).clone())).collect(),
+            options: self.options().into_iter().map(|(name, on)| (name.to_string(), on)).collect(),
+        }
+    }
+
+    /// Bring back a saved session on top of the current state
+    fn restore_session(&mut self, session: Session) -> i32 {
+        for (name, value) in session.variables {
+            self.set_variable(&name, &value);
+        }
+        for (name, body) in session.functions {
+            self.functions.insert(name, Rc::new(body));
+        }
+        for (name, on) in session.options {
+            self.set_option(&name, on);
+        }
+        if let Some(cwd) = session.cwd
+            && let Err(e) = std::env::set_current_dir(&cwd)
+        {
+            eprintln!("clam: {}", tr!("session: {}: {}", cwd.display(), e));
+            return 1;
+        }
+        0
+    }
+
+    /// Reap background jobs without blocking, and report the ones that
+    /// finished or stopped since the last prompt
+    pub fn reap_jobs(&mut self) {
+        self.run_pending_traps();
+        self.jobs.update();
+        for line in self.jobs.take_notifications() {
+            eprintln!("{}", line);
+        }
+    }
+
+    /// The jobs still running or stopped, for the prompt
+    pub fn job_count(&self) -> usize {
+        self.jobs.jobs().iter().filter(|job| !matches!(job.state, JobState::Done(_))).count()
+    }
+
+    /// `read [-r] [name...]`: read a line from stdin and split it into
+    /// variables, the last one taking the rest of the line
+    fn execute_read(&mut self, args: &[String]) -> Result<i32, String> {
+        let raw = args.first().is_some_and(|arg| arg == "-r");
+        let names: Vec<&str> = args[usize::from(raw)..].iter().map(|s| s.as_str()).collect();
+        if let Some(option) = names.iter().find(|name| name.starts_with('-')) {
+            eprintln!("clam: {}", tr!("read: {}: invalid option", option));
+            return Ok(2);
+        }
+
+        let (line, complete) = read_line_from(0, raw);
+        let fields = split_fields(&line, names.len().max(1));
+        if names.is_empty() {
+            self.env_vars.insert("REPLY".to_string(), line);
+        } else {
+            for (i, name) in names.iter().enumerate() {
+                let value = fields.get(i).cloned().unwrap_or_default();
+                self.env_vars.insert(name.to_string(), value);
+            }
+        }
+
+        Ok(if complete { 0 } else { 1 })
+    }
+
+    /// `let EXPRESSION...`: evaluate each expression in turn, succeeding if
+    /// the last one isn't 0
+    fn execute_let(&mut self, args: &[String]) -> Result<i32, String> {
+        if args.is_empty() {
+            eprintln!("clam: {}", tr!("let: expression expected"));
+            return Ok(2);
+        }
+        let mut value = 0;
+        for expression in args {
+            match arith::evaluate(expression, self) {
+                Ok(result) => value = result,
+                Err(e) => {
+                    eprintln!("clam: {}", tr!("let: {}: {}", expression, e));
+                    return Ok(1);
+                }
+            }
+        }
+        Ok(i32::from(value == 0))
+    }
+
+    /// `calc [-f format] [-v name] EXPRESSION...`: evaluate a floating-point
+    /// expression and print it in the printf `format`, `%g` by default, or
+    /// assign it to the variable `name`
+    fn execute_calc(&mut self, args: &[String]) -> i32 {
+        let mut format = calc::DEFAULT_FORMAT.to_string();
+        let mut name = None;
+        let mut args = args;
+        while let [option, value, rest @ ..] = args {
+            match option.as_str() {
+                "-f" => format = value.clone(),
+                "-v" => name = Some(value.clone()),
+                _ => break,
+            }
+            args = rest;
+        }
+        if args.is_empty() {
+            eprintln!("{}", tr!("calc: usage: calc [-f format] [-v name] expression"));
+            return 2;
+        }
+
+        let expression = args.join(" ");
+        let result = calc::evaluate(&expression, self).and_then(|value| calc::format(&format, value));
+        match (result, name) {
+            (Ok(text), Some(name)) => self.set_variable(&name, &text),
+            (Ok(text), None) => {
+                let _ = writeln!(std::io::stdout().lock(), "{}", text);
+            }
+            (Err(e), _) => {
+                eprintln!("clam: {}", tr!("calc: {}: {}", expression, e));
+                return 1;
+            }
+        }
+        0
+    }
+
+    /// `str [-v name] operation [args ...]`: upper, lower, trim, length,
+    /// `split SEP`, `join SEP` or `replace FROM TO`, applied to the args as
+    /// one text, or as words for join, and to each line of stdin if there
+    /// are none. With `-v`, the output lines go into the variable instead.
+    fn execute_str(&mut self, args: &[String]) -> i32 {
+        let (name, args) = match args {
+            [option, name, rest @ ..] if option == "-v" => (Some(name.clone()), rest),
+            _ => (None, args),
+        };
+        let (operation, operands) = match text::Operation::parse(args) {
+            Ok(parsed) => parsed,
+            Err(e) => {
+                eprintln!("clam: {}", e);
+                return 2;
+            }
+        };
+
+        let pieces = if operands.is_empty() {
+            use std::io::BufRead;
+            // Read unbuffered from fd 0, which may be redirected for this command
+            let stdin = std::mem::ManuallyDrop::new(unsafe { std::fs::File::from_raw_fd(0) });
+            match std::io::BufReader::new(&*stdin).lines().collect::<Result<Vec<_>, _>>() {
+                Ok(lines) => lines,
+                Err(e) => {
+                    eprintln!("clam: {}", tr!("str: {}", e));
+                    return 1;
+                }
+            }
+        } else if operation.takes_words() {
+            operands.to_vec()
+        } else {
+            vec![operands.join(" ")]
+        };
+        let lines = operation.apply(&pieces);
+        match name {
+            Some(name) => self.set_variable(&name, &lines.join("\n")),
+            None => {
+                let mut stdout = std::io::stdout().lock();
+                for line in lines {
+                    let _ = writeln!(stdout, "{}", line);
+                }
+            }
+        }
+        0
+    }
+
+    /// `path [-v name] operation path...`: normalize, absolute, relative,
+    /// join, basename, dirname or ext, worked out from the text alone, with
+    /// a line of output for each path. With `-v`, the lines go into the
+    /// variable instead.
+    fn execute_path(&mut self, args: &[String]) -> i32 {
+        let (name, args) = match args {
+            [option, name, rest @ ..] if option == "-v" => (Some(name.clone()), rest),
+            _ => (None, args),
+        };
+        let Some((operation, paths)) = args.split_first().filter(|(_, paths)| !paths.is_empty()) else {
+            eprintln!("clam: {}", tr!("path: usage: path [-v name] operation path [path ...]"));
+            return 2;
+        };
+        let cwd = std::env::current_dir().map(|cwd| cwd.display().to_string()).unwrap_or_else(|_| self.get_variable("PWD"));
+        let lines = match pathname::Operation::parse(operation).and_then(|operation| operation.apply(paths, &cwd)) {
+            Ok(lines) => lines,
+            Err(e) => {
+                eprintln!("clam: {}", e);
+                return 2;
+            }
+        };
+        match name {
+            Some(name) => self.set_variable(&name, &lines.join("\n")),
+            None => {
+                let mut stdout = std::io::stdout().lock();
+                for line in lines {
+                    let _ = writeln!(stdout, "{}", line);
+                }
+            }
+        }
+        0
+    }
+
+    /// `fetch URL [-o file] [-H header]...`: download URL to stdout or the
+    /// file, through the proxy the `*_proxy` variables name
+    #[cfg(feature = "fetch")]
+    fn execute_fetch(&mut self, args: &[String]) -> i32 {
+        let request = match fetch::Request::parse(args) {
+            Ok(request) => request,
+            Err(e) => {
+                eprintln!("clam: {}", e);
+                eprintln!("{}", tr!("fetch: usage: fetch URL [-o file] [-H header] ..."));
+                return 2;
+            }
+        };
+        let proxy = fetch::proxy_for(&request.url, |name| Some(self.get_variable(name)));
+        let result = request.send(proxy.as_deref()).and_then(|mut body| {
+            let copied = match &request.output {
+                Some(path) => std::fs::File::create(path).and_then(|mut file| std::io::copy(&mut body, &mut file)),
+                None => std::io::copy(&mut body, &mut std::io::stdout().lock()),
+            };
+            copied.map_err(|e| e.to_string())
+        });
+        match result {
+            Ok(_) => 0,
+            Err(e) => {
+                eprintln!("clam: {}", tr!("fetch: {}: {}", request.url, e));
+                1
+            }
+        }
+    }
+
+    /// `hash-file ALGORITHM FILE...`: print the digest of each file, `-`
+    /// for stdin, as sha256sum(1) does
+    #[cfg(feature = "extras")]
+    fn execute_hash_file(&mut self, args: &[String]) -> i32 {
+        let Some((algorithm, paths)) = args.split_first().filter(|(_, paths)| !paths.is_empty()) else {
+            eprintln!("{}", tr!("hash-file: usage: hash-file ALGORITHM FILE ..."));
+            return 2;
+        };
+        if !extras::ALGORITHMS.contains(&algorithm.as_str()) {
+            eprintln!("clam: hash-file: {}", tr!("{}: unknown algorithm (one of {})", algorithm, extras::ALGORITHMS.join(", ")));
+            return 2;
+        }
+        let mut status = 0;
+        for path in paths {
+            let digest = if path == "-" {
+                let stdin = std::mem::ManuallyDrop::new(unsafe { std::fs::File::from_raw_fd(0) });
+                extras::hash(algorithm, &*stdin)
+            } else {
+                std::fs::File::open(path).map_err(|e| e.to_string()).and_then(|file| extras::hash(algorithm, file))
+            };
+            match digest {
+                Ok(digest) => {
+                    let _ = writeln!(std::io::stdout().lock(), "{}  {}", digest, path);
+                }
+                Err(e) => {
+                    eprintln!("clam: {}", tr!("hash-file: {}: {}", path, e));
+                    status = 1;
+                }
+            }
+        }
+        status
+    }
+
+    /// `uuidgen`: print a random UUID
+    #[cfg(feature = "extras")]
+    fn execute_uuidgen(&mut self, args: &[String]) -> i32 {
+        if !args.is_empty() {
+            eprintln!("{}", tr!("uuidgen: usage: uuidgen"));
+            return 2;
+        }
+        let _ = writeln!(std::io::stdout().lock(), "{}", extras::uuid());
+        0
+    }
+
+    /// `random [-n bytes] [-e hex|base64]`: print random bytes, 16 in hex by
+    /// default
+    #[cfg(feature = "extras")]
+    fn execute_random(&mut self, args: &[String]) -> i32 {
+        let usage = || {
+            eprintln!("{}", tr!("random: usage: random [-n bytes] [-e hex|base64]"));
+            2
+        };
+        let mut count = 16;
+        let mut encoding = "hex";
+        let mut args = args;
+        while let [option, value, rest @ ..] = args {
+            match option.as_str() {
+                "-n" => match value.parse() {
+                    Ok(value) => count = value,
+                    Err(_) => return usage(),
+                },
+                "-e" => encoding = value,
+                _ => return usage(),
+            }
+            args = rest;
+        }
+        if !args.is_empty() {
+            return usage();
+        }
+        match extras::encode(&extras::random_bytes(count), encoding) {
+            Ok(text) => {
+                let _ = writeln!(std::io::stdout().lock(), "{}", text);
+                0
+            }
+            Err(e) => {
+                eprintln!("clam: {}", tr!("random: {}", e));
+                2
+            }
+        }
+    }
+
+    /// `datetime [+FORMAT] [-u|--utc] [--epoch N]`: print the time now, or
+    /// N seconds since the epoch, with strftime(3)'s conversions
+    fn execute_datetime(&mut self, args: &[String]) -> i32 {
+        let usage = |arg: &str| {
+            eprintln!("clam: {}", tr!("datetime: {}: invalid option", arg));
+            eprintln!("{}", tr!("datetime: usage: datetime [+format] [-u|--utc] [--epoch seconds]"));
+            2
+        };
+        let mut format = datetime::DEFAULT_FORMAT;
+        let mut utc = false;
+        let mut time = None;
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-u" | "--utc" => utc = true,
+                "--epoch" => match args.next().and_then(|seconds| seconds.parse::<i64>().ok()) {
+                    Some(seconds) => time = Some(seconds),
+                    None => return usage(arg),
+                },
+                _ if arg.starts_with('+') => format = &arg[1..],
+                _ => return usage(arg),
+            }
+        }
+        match datetime::format(format, time.unwrap_or_else(datetime::now), utc) {
+            Ok(text) => {
+                let _ = writeln!(std::io::stdout().lock(), "{}", text);
+                0
+            }
+            Err(e) => {
+                eprintln!("clam: {}", tr!("datetime: {}", e));
+                1
+            }
+        }
+    }
+
+    /// `loadenv [-x] [--print] FILE`: set the variables a .env file holds,
+    /// without running anything in it as `source` would. `--print` only
+    /// shows the assignments that would change a variable. Every variable
+    /// reaches the environment of commands already, so `-x` just writes
+    /// those as `export`s.
+    fn execute_loadenv(&mut self, args: &[String]) -> i32 {
+        let usage = || {
+            eprintln!("{}", tr!("loadenv: usage: loadenv [-x] [--print] file"));
+            2
+        };
+        let mut export = false;
+        let mut print = false;
+        let mut files = Vec::new();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-x" => export = true,
+                "--print" => print = true,
+                "--" => files.extend(args.by_ref()),
+                _ if arg.starts_with('-') => {
+                    eprintln!("clam: {}", tr!("loadenv: {}: invalid option", arg));
+                    return usage();
+                }
+                _ => files.push(arg),
+            }
+        }
+        let [file] = files.as_slice() else {
+            return usage();
+        };
+        let variables = match std::fs::read_to_string(file).map_err(|e| e.to_string()).and_then(|text| dotenv::parse(&text)) {
+            Ok(variables) => variables,
+            Err(e) => {
+                eprintln!("clam: {}", tr!("loadenv: {}: {}", file, e));
+                return 1;
+            }
+        };
+
+        let mut out = std::io::stdout().lock();
+        for (name, value) in variables {
+            if !print {
+                self.set_variable(&name, &value);
+            } else if !self.is_set(&name) || self.get_variable(&name) != value {
+                let prefix = if export { "export " } else { "" };
+                let _ = writeln!(out, "{}{}={}", prefix, name, lexer::quote(&value));
+            }
+        }
+        0
+    }
+
+    /// `repeat [-n seconds] [-t times] [-c] [-e] [--] command...`: run the
+    /// command over and over, `seconds` apart (2 by default), like watch(1).
+    /// It stops after `times` runs, at the first failure with `-e`, or on
+    /// Ctrl-C. `-c` clears the screen before each run.
+    fn execute_repeat(&mut self, args: &[String]) -> Result<i32, String> {
+        let usage = |arg: &str| {
+            eprintln!("clam: {}", tr!("repeat: {}: invalid option", arg));
+            eprintln!("{}", tr!("repeat: usage: repeat [-n seconds] [-t times] [-c] [-e] [--] command [args ...]"));
+            Ok(2)
+        };
+        let mut interval = Duration::from_secs(2);
+        let mut times = None;
+        let mut clear = false;
+        let mut stop_on_failure = false;
+        let mut index = 0;
+        while let Some(arg) = args.get(index) {
+            let value = args.get(index + 1);
+            match arg.as_str() {
+                "-n" => match value.and_then(|seconds| Duration::try_from_secs_f64(seconds.parse().ok()?).ok()) {
+                    Some(seconds) => interval = seconds,
+                    None => return usage(arg),
+                },
+                "-t" => match value.and_then(|count| count.parse::<usize>().ok()).filter(|&count| count > 0) {
+                    Some(count) => times = Some(count),
+                    None => return usage(arg),
+                },
+                "-c" => clear = true,
+                "-e" => stop_on_failure = true,
+                "--" => {
+                    index += 1;
+                    break;
+                }
+                _ if !arg.starts_with('-') => break,
+                _ => return usage(arg),
+            }
+            index += if matches!(arg.as_str(), "-n" | "-t") { 2 } else { 1 };
+        }
+        let rest = &args[index..];
+        if rest.is_empty() {
+            eprintln!("{}", tr!("repeat: usage: repeat [-n seconds] [-t times] [-c] [-e] [--] command [args ...]"));
+            return Ok(2);
+        }
+
+        let command = rest.join(" ");
+        let mut status = 0;
+        for run in 1.. {
+            if clear {
+                let mut out = std::io::stdout().lock();
+                let _ = write!(out, "\x1b[H\x1b[2J{}\n\n", tr!("Every {}s: {}", interval.as_secs_f64(), command));
+                let _ = out.flush();
+            }
+            status = self.run_words(&[], rest, &command)?;
+            if self.unwinding() || (stop_on_failure && status != 0) || times == Some(run) {
+                break;
+            }
+            if let Some(status) = self.pause(interval) {
+                return Ok(status);
+            }
+        }
+        Ok(status)
+    }
+
+    /// `retry [-n attempts] [-d seconds] [-m seconds] [--] command...`: run
+    /// the command until it succeeds, at most `attempts` times (3 by
+    /// default). The delay before each new attempt starts at `-d` (1 second)
+    /// and doubles every time, up to `-m` (60 seconds). Returns the status
+    /// of the last attempt.
+    fn execute_retry(&mut self, args: &[String]) -> Result<i32, String> {
+        let usage = |arg: &str| {
+            eprintln!("clam: {}", tr!("retry: {}: invalid option", arg));
+            eprintln!("{}", tr!("retry: usage: retry [-n attempts] [-d seconds] [-m seconds] [--] command [args ...]"));
+            Ok(2)
+        };
+        let seconds = |value: Option<&String>| value.and_then(|seconds| Duration::try_from_secs_f64(seconds.parse().ok()?).ok());
+        let mut attempts = 3;
+        let mut delay = Duration::from_secs(1);
+        let mut max_delay = Duration::from_secs(60);
+        let mut index = 0;
+        while let Some(arg) = args.get(index) {
+            let value = args.get(index + 1);
+            match arg.as_str() {
+                "-n" => match value.and_then(|count| count.parse::<usize>().ok()).filter(|&count| count > 0) {
+                    Some(count) => attempts = count,
+                    None => return usage(arg),
+                },
+                "-d" => match seconds(value) {
+                    Some(seconds) => delay = seconds,
+                    None => return usage(arg),
+                },
+                "-m" => match seconds(value) {
+                    Some(seconds) => max_delay = seconds,
+                    None => return usage(arg),
+                },
+                "--" => {
+                    index += 1;
+                    break;
+                }
+                _ if !arg.starts_with('-') => break,
+                _ => return usage(arg),
+            }
+            index += 2;
+        }
+        let command = &args[index..];
+        if command.is_empty() {
+            eprintln!("{}", tr!("retry: usage: retry [-n attempts] [-d seconds] [-m seconds] [--] command [args ...]"));
+            return Ok(2);
+        }
+
+        let line = command.join(" ");
+        let mut status = 0;
+        for attempt in 1..=attempts {
+            status = self.run_words(&[], command, &line)?;
+            if status == 0 || self.unwinding() || attempt == attempts {
+                break;
+            }
+            let wait = delay.min(max_delay);
+            eprintln!(
+                "clam: {}",
+                tr!("retry: {}: attempt {} of {} failed with status {}; retrying in {}s", line, attempt, attempts, status, wait.as_secs_f64())
+            );
+            if let Some(status) = self.pause(wait) {
+                return Ok(status);
+            }
+            delay = delay.saturating_mul(2);
+        }
+        Ok(status)
+    }
+
+    /// Sleep for `duration` in short steps, so that Ctrl-C or a trapped
+    /// signal is noticed; the status that ends the wait early, if one does
+    fn pause(&mut self, duration: Duration) -> Option<i32> {
+        let start = Instant::now();
+        while start.elapsed() < duration {
+            if let Some(status) = self.wait_interruption() {
+                return Some(status);
+            }
+            std::thread::sleep(duration.saturating_sub(start.elapsed()).min(Duration::from_millis(50)));
+        }
+        None
+    }
+
+    /// `sleep DURATION...`: wait for the sum of the durations, seconds
+    /// with an optional fraction and an `s`, `m`, `h` or `d` suffix
+    fn execute_sleep(&mut self, args: &[String]) -> i32 {
+        if args.is_empty() {
+            eprintln!("{}", tr!("sleep: usage: sleep DURATION ..."));
+            return 2;
+        }
+        let mut total = Duration::ZERO;
+        for arg in args {
+            match parse_interval(arg) {
+                Some(duration) => total = total.saturating_add(duration),
+                None => {
+                    eprintln!("clam: {}", tr!("sleep: {}: invalid time interval", arg));
+                    return 1;
+                }
+            }
+        }
+        self.pause(total).unwrap_or(0)
+    }
+
+    /// `jobs [-l|-p] [jobspec...]`: list jobs, then forget the finished ones reported
+    fn execute_jobs(&mut self, args: &[String]) -> Result<i32, String> {
+        let mut long = false;
+        let mut pids_only = false;
+        let mut json = false;
+        let mut specs = Vec::new();
+        for arg in args {
+            match arg.as_str() {
+                "-l" => long = true,
+                "-p" => pids_only = true,
+                "--json" => json = true,
+                "-lp" | "-pl" => {
+                    long = true;
+                    pids_only = true;
+                }
+                _ if !arg.starts_with('-') => specs.push(arg.as_str()),
+                _ => {
+                    eprintln!("clam: {}", tr!("jobs: {}: invalid option", arg));
+                    eprintln!("{}", tr!("jobs: usage: jobs [-lp] [--json] [jobspec ...]"));
+                    return Ok(2);
+                }
+            }
+        }
+
+        self.jobs.update();
+        let mut indices = Vec::new();
+        let mut status = 0;
+        for spec in &specs {
+            match self.jobs.find(Some(spec)) {
+                Ok(index) => indices.push(index),
+                Err(e) => {
+                    eprintln!("clam: {}", tr!("jobs: {}", e));
+                    status = 1;
+                }
+            }
+        }
+        if specs.is_empty() {
+            indices = (0..self.jobs.jobs().len()).collect();
+        }
+
+        if json {
+            let jobs: Vec<jobs::JobInfo> = indices.iter().map(|&index| self.jobs.info(index)).collect();
+            print_json(&jobs);
+            for index in indices {
+                self.jobs.mark_reported(index);
+            }
+            self.jobs.remove_done();
+            return Ok(status);
+        }
+
+        // Written to fd 1 directly rather than via println!, which test
+        // harnesses capture
+        let mut out = std::io::stdout().lock();
+        for index in indices {
+            let _ = if pids_only {
+                writeln!(out, "{}", self.jobs.jobs()[index].pid)
+            } else {
+                writeln!(out, "{}", self.jobs.format(index, long))
+            };
+            self.jobs.mark_reported(index);
+        }
+        drop(out);
+        self.jobs.remove_done();
+        Ok(status)
+    }
+
+    /// `wait [-n] [pid|jobspec...]`: wait for the given jobs, or all of
+    /// them, to finish and return the status of the last one. With `-n`,
+    /// return as soon as any one of them has finished.
+    fn execute_wait(&mut self, args: &[String]) -> Result<i32, String> {
+        self.jobs.update();
+        if let Some(("-n", specs)) = args.split_first().map(|(first, rest)| (first.as_str(), rest)) {
+            return self.wait_any(specs);
+        }
+
+        if args.is_empty() {
+            if let Some(status) = self.wait_until(|jobs| !jobs.iter().any(|job| job.state == JobState::Running))? {
+                return Ok(status);
+            }
+            // Plain `wait` consumes every finished job
+            self.jobs.remove_done();
+            return Ok(0);
+        }
+
+        let mut status = 0;
+        for spec in args {
+            status = self.wait_operand(spec)?;
+            if self.wait_interruption().is_some() {
+                return Ok(status);
+            }
+        }
+        Ok(status)
+    }
+
+    fn wait_operand(&mut self, spec: &str) -> Result<i32, String> {
+        // Jobs already reported as done can still be waited for by pid
+        if let Ok(pid) = spec.parse::<libc::pid_t>()
+            && self.jobs.find_pid(pid).is_none()
+            && let Some(status) = self.jobs.take_retained(pid)
+        {
+            return Ok(status);
+        }
+
+        let id = match self.wait_target(spec) {
+            Ok(index) => self.jobs.jobs()[index].id,
+            Err(status) => return Ok(status),
+        };
+        // Jobs are tracked by number, as indices shift when jobs are removed
+        let running = |jobs: &[jobs::Job]| jobs.iter().any(|job| job.id == id && job.state == JobState::Running);
+        if let Some(status) = self.wait_until(|jobs| !running(jobs))? {
+            return Ok(status);
+        }
+        match self.jobs.jobs().iter().position(|job| job.id == id) {
+            Some(index) => Ok(self.take_status(index)),
+            None => Ok(127),
+        }
+    }
+
+    fn wait_any(&mut self, specs: &[String]) -> Result<i32, String> {
+        let targets: Vec<usize> = specs
+            .iter()
+            .filter_map(|spec| self.wait_target(spec).ok())
+            .map(|index| self.jobs.jobs()[index].id)
+            .collect();
+        if !specs.is_empty() && targets.is_empty() {
+            return Ok(127);
+        }
+
+        let is_target = |job: &jobs::Job| targets.is_empty() || targets.contains(&job.id);
+        let finished = |job: &jobs::Job| is_target(job) && matches!(job.state, JobState::Done(_));
+        let cut_short = self.wait_until(|jobs| {
+            jobs.iter().any(finished) || !jobs.iter().any(|job| is_target(job) && job.state == JobState::Running)
+        })?;
+        if let Some(status) = cut_short {
+            return Ok(status);
+        }
+        Ok(match self.jobs.jobs().iter().position(finished) {
+            Some(index) => self.take_status(index),
+            None => 127,
+        })
+    }
+
+    /// Find the job a `wait` operand refers to, reporting a bad operand
+    /// with the status to return for it
+    fn wait_target(&self, spec: &str) -> Result<usize, i32> {
+        let found = if spec.starts_with('%') {
+            self.jobs.find(Some(spec))
+        } else {
+            match spec.parse::<libc::pid_t>() {
+                Ok(pid) => self
+                    .jobs
+                    .find_pid(pid)
+                    .ok_or_else(|| tr!("pid {} is not a child of this shell", pid)),
+                Err(_) => {
+                    eprintln!("clam: {}", tr!("wait: `{}': not a pid or valid job spec", spec));
+                    return Err(2);
+                }
+            }
+        };
+        found.map_err(|message| {
+            eprintln!("clam: {}", tr!("wait: {}", message));
+            127
+        })
+    }
+
+    /// Block until `settled` holds for the job table. Returns the status
+    /// for `wait` if SIGINT or a trapped signal cut the wait short.
+    fn wait_until(&mut self, settled: impl Fn(&[jobs::Job]) -> bool) -> Result<Option<i32>, String> {
+        loop {
+            self.jobs.update();
+            if settled(self.jobs.jobs()) {
+                return Ok(None);
+            }
+            if !jobs::wait_for_change()?
+                && let Some(status) = self.wait_interruption()
+            {
+                return Ok(Some(status));
+            }
+        }
+    }
+
+    /// 128 plus the signal that should end a `wait` early, if any
+    fn wait_interruption(&mut self) -> Option<i32> {
+        if self.interrupted() {
+            return Some(INTERRUPTED_STATUS);
+        }
+        signals::caught_pending().map(|signal| 128 + signal)
+    }
+
+    /// The status `wait` returns for the job at `index`, forgetting it once
+    /// it has finished
+    fn take_status(&mut self, index: usize) -> i32 {
+        match self.jobs.jobs()[index].state {
+            JobState::Done(status) => {
+                self.jobs.remove(index);
+                status
+            }
+            _ => 128 + libc::SIGTSTP,
+        }
+    }
+
+    /// `fg [jobspec]`: continue a job in the foreground and wait for it
+    fn execute_fg(&mut self, args: &[String]) -> Result<i32, String> {
+        self.jobs.update();
+        let index = match self.jobs.find(args.first().map(|s| s.as_str())) {
+            Ok(index) => index,
+            Err(e) => {
+                eprintln!("clam: {}", tr!("fg: {}", e));
+                return Ok(1);
+            }
+        };
+        let job = &self.jobs.jobs()[index];
+        if let JobState::Done(_) = job.state {
+            eprintln!("clam: {}", tr!("fg: job {} has terminated", job.id));
+            self.jobs.remove(index);
+            return Ok(1);
+        }
+
+        let _ = writeln!(std::io::stdout(), "{}", job.command);
+        let _ = std::io::stdout().flush();
+        self.wait_job(index, true)
+    }
+
+    /// `bg [jobspec...]`: continue stopped jobs in the background
+    fn execute_bg(&mut self, args: &[String]) -> Result<i32, String> {
+        self.jobs.update();
+        let specs: Vec<Option<&str>> = if args.is_empty() {
+            vec![None]
+        } else {
+            args.iter().map(|arg| Some(arg.as_str())).collect()
+        };
+
+        let mut status = 0;
+        for spec in specs {
+            let index = match self.jobs.find(spec) {
+                Ok(index) => index,
+                Err(e) => {
+                    eprintln!("clam: {}", tr!("bg: {}", e));
+                    status = 1;
+                    continue;
+                }
+            };
+            let job = &self.jobs.jobs()[index];
+            match job.state {
+                JobState::Running => eprintln!("clam: {}", tr!("bg: job {} already in background", job.id)),
+                JobState::Done(_) => {
+                    eprintln!("clam: {}", tr!("bg: job {} has terminated", job.id));
+                    status = 1;
+                }
+                JobState::Stopped => {
+                    self.jobs.signal(index, libc::SIGCONT).map_err(|e| tr!("kill: {}", e))?;
+                    self.jobs.set_state(index, JobState::Running);
+                    self.jobs.touch(index);
+                    let job = &self.jobs.jobs()[index];
+                    let _ = writeln!(std::io::stdout(), "[{}]{} {} &", job.id, self.jobs.marker(index), job.command);
+                }
+            }
+        }
+        Ok(status)
+    }
+
+    /// The test mode assertions: `assert_eq left right [message]`,
+    /// `assert_status status command...` and `assert_output text
+    /// command...`, which compares the command's output without trailing
+    /// newlines. A failed assertion is reported and returns 1.
+    fn execute_assert(&mut self, name: &str, args: &[String]) -> Result<i32, String> {
+        let failure = match (name, args) {
+            ("assert_eq", [left, right, message @ ..]) if message.len() <= 1 => {
+                let label = message.first().map(|message| format!("{}: ", message)).unwrap_or_default();
+                (left != right).then(|| format!("{}'{}' != '{}'", label, left, right))
+            }
+            ("assert_status", [expected, command @ ..]) if !command.is_empty() => {
+                let Ok(expected) = expected.parse::<i32>() else {
+                    return Ok(self.assertion_usage(name));
+                };
+                let status = self.run_words(&[], command, &command.join(" "))?;
+                (status != expected)
+                    .then(|| tr!("`{}`: expected status {}, got {}", command.join(" "), expected, status))
+            }
+            ("assert_output", [expected, command @ ..]) if !command.is_empty() => {
+                let text = command.join(" ");
+                let (output, _) = self.capture_output(&text, |executor| executor.run_words(&[], command, &text))?;
+                (output != *expected)
+                    .then(|| tr!("`{}`: expected '{}', got '{}'", command.join(" "), expected, output))
+            }
+            _ => return Ok(self.assertion_usage(name)),
+        };
+
+        if let Some(assertions) = &mut self.assertions {
+            assertions.record(failure.is_none());
+        }
+        match failure {
+            Some(message) => {
+                eprintln!("{}: {}: {}", self.shell_name, name, message);
+                Ok(1)
+            }
+            None => Ok(0),
+        }
+    }
+
+    /// A malformed assertion counts as a failed one
+    fn assertion_usage(&mut self, name: &str) -> i32 {
+        let usage = match name {
+            "assert_eq" => "left right [message]",
+            "assert_status" => "status command...",
+            _ => "text command...",
+        };
+        eprintln!("clam: {}", tr!("{}: usage: {} {}", name, name, usage));
+        if let Some(assertions) = &mut self.assertions {
+            assertions.record(false);
+        }
+        2
+    }
+
+    /// Run `run` in a forked child with its stdout going into a pipe, and
+    /// return what it wrote, without trailing newlines, and its status.
+    /// `command` names the child if it becomes a job.
+    fn capture_output(
+        &mut self,
+        command: &str,
+        run: impl FnOnce(&mut Self) -> Result<i32, String>,
+    ) -> Result<(String, i32), String> {
+        let (read_end, write_end) = create_pipe()?;
+        // Build the child's environment here, for the next substitution too
+        self.env_vars.prepare();
+        let pid = match self.fork() {
+            Ok(pid) => pid,
+            Err(e) => {
+                unsafe {
+                    libc::close(read_end);
+                    libc::close(write_end);
+                }
+                return Err(e);
+            }
+        };
+        if pid == 0 {
+            self.enter_job(0, true);
+            unsafe {
+                libc::dup2(write_end, 1);
+                libc::close(write_end);
+                libc::close(read_end);
+            }
+            let result = run(self);
+            self.finish_child(result);
+        }
+
+        self.place_job(pid, 0);
+        unsafe { libc::close(write_end) };
+        let mut output = Vec::new();
+        let mut reader = unsafe { std::fs::File::from_raw_fd(read_end) };
+        let read = std::io::Read::read_to_end(&mut reader, &mut output);
+        drop(reader);
+        let status = self.wait_foreground(&[pid], command.to_string())?;
+        read.map_err(|e| tr!("read: {}", e))?;
+
+        let mut output = String::from_utf8_lossy(&output).into_owned();
+        output.truncate(output.trim_end_matches('\n').len());
+        Ok((output, status))
+    }
+
+    /// `trap [-lp] [[action] condition...]`: run `action` when one of the
+    /// signals arrives or, for EXIT, when the shell exits. An empty action
+    /// ignores the signals, and `-` (or no action) resets them.
+    fn execute_trap(&mut self, args: &[String]) -> Result<i32, String> {
+        match args.first().map(String::as_str) {
+            None => return Ok(self.print_traps(&[])),
+            Some("-p") => return Ok(self.print_traps(&args[1..])),
+            Some("-l") => return Ok(list_signals(&[])),
+            _ => {}
+        }
+        let args = if args[0] == "--" { &args[1..] } else { args };
+        let Some((first, rest)) = args.split_first() else {
+            return Ok(self.print_traps(&[]));
+        };
+        // A lone condition, or a leading signal number, means reset
+        let (action, specs) = if first == "-" {
+            (None, rest)
+        } else if rest.is_empty() || first.parse::<u32>().is_ok() {
+            (None, args)
+        } else {
+            (Some(first.as_str()), rest)
+        };
+
+        let mut status = 0;
+        for spec in specs {
+            let Some(condition) = Condition::parse(spec) else {
+                eprintln!("clam: {}", tr!("trap: {}: invalid signal specification", spec));
+                status = 1;
+                continue;
+            };
+            match action {
+                Some(action) => self.traps.set(condition, action),
+                None => {
+                    self.traps.reset(condition);
+                }
+            }
+            let applied = match condition {
+                Condition::Signal(signal) => self.apply_trap(signal),
+                // Whether fatal signals must be caught depends on the EXIT trap
+                Condition::Exit => FATAL_SIGNALS.iter().try_for_each(|&signal| self.apply_trap(signal)),
+                Condition::Debug | Condition::Err | Condition::Return => Ok(()),
+            };
+            if let Err(e) = applied {
+                eprintln!("clam: {}", tr!("trap: {}: {}", spec, e));
+                status = 1;
+            }
+        }
+        Ok(status)
+    }
+
+    fn print_traps(&self, specs: &[String]) -> i32 {
+        let mut status = 0;
+        let conditions = if specs.is_empty() {
+            self.traps.conditions()
+        } else {
+            let mut conditions = Vec::new();
+            for spec in specs {
+                match Condition::parse(spec) {
+                    Some(condition) => conditions.push(condition),
+                    None => {
+                        eprintln!("clam: {}", tr!("trap: {}: invalid signal specification", spec));
+                        status = 1;
+                    }
+                }
+            }
+            conditions
+        };
+        for line in conditions.into_iter().filter_map(|condition| self.traps.format(condition)) {
+            let _ = writeln!(std::io::stdout(), "{}", line);
+        }
+        status
+    }
+
+    /// Make the shell handle `signal` as its trap says
+    fn apply_trap(&self, signal: libc::c_int) -> std::io::Result<()> {
+        match self.traps.get(Condition::Signal(signal)) {
+            Some("") => signals::ignore(signal),
+            Some(_) => signals::catch(signal),
+            None if self.catches_to_exit(signal) => signals::catch(signal),
+            None => {
+                signals::restore(signal);
+                Ok(())
+            }
+        }
+    }
+
+    /// A script with an EXIT trap or a FIFO directory catches the signals
+    /// that would kill it, to clean up before dying. An interactive shell
+    /// is woken by `signals::on_exit_signal` instead, as it waits for input.
+    fn catches_to_exit(&self, signal: libc::c_int) -> bool {
+        !self.interactive
+            && FATAL_SIGNALS.contains(&signal)
+            && (self.traps.get(Condition::Exit).is_some() || self.fifo_dir.is_some())
+    }
+
+    /// Run the traps of signals that arrived, between commands
+    fn run_pending_traps(&mut self) {
+        if self.running_trap {
+            return;
+        }
+        while let Some(signal) = signals::take_caught() {
+            match self.traps.get(Condition::Signal(signal)) {
+                Some(action) => {
+                    let action = action.to_string();
+                    self.run_trap(&action);
+                }
+                None if self.catches_to_exit(signal) => self.die_from_signal(signal),
+                None => {}
+            }
+        }
+    }
+
+    /// Run a trap's commands, leaving `$?` as it was
+    fn run_trap(&mut self, action: &str) {
+        let status = self.last_exit_status;
+        let pipe_status = self.pipe_status.clone();
+        self.running_trap = true;
+        match script::parse(action) {
+            Ok(commands) => {
+                for command in &commands {
+                    if let Err(e) = self.execute(command) {
+                        eprintln!("clam: {}", tr!("trap: {}", e));
+                        break;
+                    }
+                }
+            }
+            Err(error) => eprintln!("clam: {}", tr!("trap: syntax error: {}", error.message)),
+        }
+        self.running_trap = false;
+        self.last_exit_status = status;
+        self.pipe_status = pipe_status;
+    }
+
+    /// Clean up after the shell as it exits: run the EXIT trap, once, and
+    /// remove the FIFO directory
+    pub fn clean_up_on_exit(&mut self) {
+        self.run_exit_trap();
+        self.remove_fifo_dir();
+    }
+
+    fn run_exit_trap(&mut self) {
+        if let Some(action) = self.traps.reset(Condition::Exit) {
+            for signal in FATAL_SIGNALS {
+                let _ = self.apply_trap(signal);
+            }
+            self.run_trap(&action);
+        }
+    }
+
+    /// Run the DEBUG, ERR or RETURN trap, if set. Traps don't trigger them.
+    fn run_condition_trap(&mut self, condition: Condition) {
+        if self.running_trap {
+            return;
+        }
+        if let Some(action) = self.traps.get(condition).filter(|action| !action.is_empty()) {
+            let action = action.to_string();
+            self.run_trap(&action);
+        }
+    }
+
+    /// Clean up as the shell exits, then die from `signal`. An interrupt
+    /// that came with it, as when the shell was woken up to exit, doesn't
+    /// cut the EXIT trap short.
+    pub fn die_from_signal(&mut self, signal: libc::c_int) -> ! {
+        signals::take_interrupt();
+        self.interrupted = false;
+        self.clean_up_on_exit();
+        self.hang_up_jobs();
+        let _ = std::io::stdout().flush();
+        signals::reraise(signal);
+    }
+
+    /// A foreground job was killed by SIGINT: abandon the command line,
+    /// unless a trap says otherwise
+    fn act_on_interrupt(&mut self) {
+        match self.traps.get(Condition::Signal(libc::SIGINT)) {
+            Some(action) => {
+                let action = action.to_string();
+                self.run_trap(&action);
+            }
+            None => self.interrupted = true,
+        }
+    }
+
+    /// `disown [-a] [-r] [jobspec...]`: forget jobs, so they are neither
+    /// reported nor hung up when the shell exits. `-a` takes all jobs and
+    /// `-r` only running ones.
+    fn execute_disown(&mut self, args: &[String]) -> Result<i32, String> {
+        self.jobs.update();
+        let mut all = false;
+        let mut running_only = false;
+        let mut specs = Vec::new();
+        for arg in args {
+            match arg.as_str() {
+                "-a" => all = true,
+                "-r" => running_only = true,
+                option if option.len() > 1 && option.starts_with('-') => {
+                    eprintln!("clam: {}", tr!("disown: {}: invalid option", option));
+                    return Ok(2);
+                }
+                spec => specs.push(Some(spec)),
+            }
+        }
+
+        let mut status = 0;
+        let mut ids = Vec::new();
+        if all || (running_only && specs.is_empty()) {
+            ids.extend(self.jobs.jobs().iter().map(|job| job.id));
+        } else if specs.is_empty() {
+            specs.push(None);
+        }
+        for spec in specs {
+            match self.jobs.find(spec) {
+                Ok(index) => ids.push(self.jobs.jobs()[index].id),
+                Err(e) => {
+                    eprintln!("clam: {}", tr!("disown: {}", e));
+                    status = 1;
+                }
+            }
+        }
+
+        for id in ids {
+            let Some(index) = self.jobs.jobs().iter().position(|job| job.id == id) else {
+                continue;
+            };
+            let job = &self.jobs.jobs()[index];
+            if running_only && job.state != JobState::Running {
+                continue;
+            }
+            if job.state == JobState::Stopped {
+                eprintln!("clam: {}", tr!("warning: deleting stopped job {} with process group {}", job.id, job.pgid.unwrap_or(job.pid)));
+            }
+            self.jobs.remove(index);
+        }
+        Ok(status)
+    }
+
+    /// Send SIGHUP to the remaining jobs of an exiting shell, if
+    /// CLAM_HUPONEXIT asks for it
+    pub fn hang_up_jobs(&mut self) {
+        let enabled = self.get_variable(HUPONEXIT_VAR);
+        if enabled.is_empty() || enabled == "0" {
+            return;
+        }
+        self.jobs.update();
+        for index in 0..self.jobs.jobs().len() {
+            if !matches!(self.jobs.jobs()[index].state, JobState::Done(_)) {
+                let _ = self.jobs.terminate(index, libc::SIGHUP);
+            }
+        }
+    }
+
+    /// `kill [-s sig | -sig] pid|jobspec...` sends a signal (TERM by
+    /// default) to processes or whole jobs; `kill -l [status...]` lists
+    /// signal names
+    fn execute_kill(&mut self, args: &[String]) -> Result<i32, String> {
+        let mut signal = libc::SIGTERM;
+        let mut rest = args;
+        match args.first().map(String::as_str) {
+            Some("-l" | "-L") => return Ok(list_signals(&args[1..])),
+            Some("-s" | "-n") => {
+                let Some(spec) = args.get(1) else {
+                    eprintln!("clam: {}", tr!("kill: {}: option requires an argument", args[0]));
+                    return Ok(2);
+                };
+                match signals::parse(spec) {
+                    Some(number) => signal = number,
+                    None => {
+                        eprintln!("clam: {}", tr!("kill: {}: invalid signal specification", spec));
+                        return Ok(1);
+                    }
+                }
+                rest = &args[2..];
+            }
+            Some("--") => rest = &args[1..],
+            Some(option) if option.len() > 1 && option.starts_with('-') => {
+                match signals::parse(&option[1..]) {
+                    Some(number) => signal = number,
+                    None => {
+                        eprintln!("clam: {}", tr!("kill: {}: invalid signal specification", &option[1..]));
+                        return Ok(1);
+                    }
+                }
+                rest = &args[1..];
+            }
+            _ => {}
+        }
+        if let Some(("--", targets)) = rest.split_first().map(|(first, targets)| (first.as_str(), targets)) {
+            rest = targets;
+        }
+        if rest.is_empty() {
+            eprintln!("clam: {}", tr!("kill: usage: kill [-s sigspec | -sigspec] pid | jobspec ... or kill -l [sigspec]"));
+            return Ok(2);
+        }
+
+        self.jobs.update();
+        let mut status = 0;
+        for target in rest {
+            if let Err(e) = self.kill_target(target, signal) {
+                eprintln!("clam: {}", tr!("kill: {}", e));
+                status = 1;
+            }
+        }
+        Ok(status)
+    }
+
+    fn kill_target(&mut self, target: &str, signal: libc::c_int) -> Result<(), String> {
+        if target.starts_with('%') {
+            let index = self.jobs.find(Some(target))?;
+            return self.jobs.terminate(index, signal).map_err(|e| format!("{}: {}", target, e));
+        }
+        let pid: libc::pid_t = target
+            .parse()
+            .map_err(|_| tr!("{}: arguments must be process or job IDs", target))?;
+        if unsafe { libc::kill(pid, signal) } < 0 {
+            return Err(format!("({}) - {}", pid, std::io::Error::last_os_error()));
+        }
+        Ok(())
+    }
+
+    fn execute_if(&mut self, if_cmd: &IfCommand) -> Result<i32, String> {
+        let condition_status = self.execute_condition(&if_cmd.condition)?;
+
+        if condition_status == 0 {
+            self.execute(&if_cmd.then_part)
+        } else {
+            // Check elif clauses
+            for (elif_condition, elif_body) in &if_cmd.elif_parts {
+                let elif_status = self.execute_condition(elif_condition)?;
+                if elif_status == 0 {
+                    return self.execute(elif_body);
+                }
+            }
+
+            // Execute else part if present
+            if let Some(else_part) = &if_cmd.else_part {
+                self.execute(else_part)
+            } else {
+                Ok(0)
+            }
+        }
+    }
+
+    fn execute_while(&mut self, while_cmd: &WhileCommand) -> Result<i32, String> {
+        loop {
+            let condition_status = self.execute_condition(&while_cmd.condition)?;
+            if self.unwinding() {
+                return Ok(condition_status);
+            }
+            if condition_status != 0 {
+                break;
+            }
+            let status = self.execute(&while_cmd.body)?;
+            if self.unwinding() {
+                return Ok(status);
+            }
+        }
+        Ok(0)
+    }
+
+    fn execute_until(&mut self, until_cmd: &UntilCommand) -> Result<i32, String> {
+        loop {
+            let condition_status = self.execute_condition(&until_cmd.condition)?;
+            if self.unwinding() {
+                return Ok(condition_status);
+            }
+            if condition_status == 0 {
+                break;
+            }
+            let status = self.execute(&until_cmd.body)?;
+            if self.unwinding() {
+                return Ok(status);
+            }
+        }
+        Ok(0)
+    }
+
+    fn execute_for(&mut self, for_cmd: &ForCommand) -> Result<i32, String> {
+        let mut words = Vec::new();
+        for word in &for_cmd.words {
+            words.extend(self.expand_word(word)?);
+        }
+        if let Some(jobs) = for_cmd.parallel {
+            return self.execute_parallel_for(for_cmd, words, jobs);
+        }
+
+        for word in words {
+            self.env_vars.insert(for_cmd.variable.clone(), word);
+            let status = self.execute(&for_cmd.body)?;
+            if self.unwinding() {
+                return Ok(status);
+            }
+        }
+        Ok(0)
+    }
+
+    /// `for -P jobs`: run the body for each word in a forked copy of the
+    /// shell, at most `jobs` at a time. The output of each iteration is
+    /// held back and written whole, in the order of the words, so that
+    /// iterations never interleave. $PIPESTATUS gets the status of each
+    /// iteration, and the loop's is that of the first one to fail.
+    fn execute_parallel_for(&mut self, for_cmd: &ForCommand, words: Vec<String>, jobs: usize) -> Result<i32, String> {
+        // (job id, iteration, stdout, stderr) of each running iteration
+        let mut running: Vec<(usize, usize, std::fs::File, std::fs::File)> = Vec::new();
+        let mut finished = BTreeMap::new();
+        let mut statuses = Vec::new();
+        let mut words = words.into_iter().enumerate();
+        loop {
+            while running.len() < jobs
+                && let Some((iteration, word)) = words.next()
+            {
+                let temp_dir = self.temp_dir();
+                let stdout = unlinked_temp_file(&temp_dir, "for")?;
+                let stderr = unlinked_temp_file(&temp_dir, "for")?;
+                let pid = self.fork()?;
+                if pid == 0 {
+                    // Iterations stay in the shell's process group, so
+                    // Ctrl-C reaches all of them
+                    self.job_control = None;
+                    unsafe {
+                        libc::dup2(stdout.as_raw_fd(), 1);
+                        libc::dup2(stderr.as_raw_fd(), 2);
+                    }
+                    self.env_vars.insert(for_cmd.variable.clone(), word);
+                    self.run_child(&for_cmd.body);
+                }
+                let id = self.jobs.add(&[pid], None, for_cmd.body.to_string());
+                running.push((id, iteration, stdout, stderr));
+            }
+            if running.is_empty() {
+                break;
+            }
+
+            let ids: Vec<usize> = running.iter().map(|(id, ..)| *id).collect();
+            let cut_short = self.wait_until(|jobs| {
+                jobs.iter().any(|job| ids.contains(&job.id) && job.state != JobState::Running)
+            })?;
+            if let Some(status) = cut_short {
+                for (id, ..) in &running {
+                    if let Some(index) = self.jobs.jobs().iter().position(|job| job.id == *id) {
+                        let _ = self.jobs.signal(index, libc::SIGTERM);
+                    }
+                }
+                self.wait_until(|jobs| !jobs.iter().any(|job| ids.contains(&job.id) && job.state == JobState::Running))?;
+                self.jobs.remove_done();
+                return Ok(status);
+            }
+
+            let mut still_running = Vec::new();
+            for (id, iteration, stdout, stderr) in running {
+                let index = self.jobs.jobs().iter().position(|job| job.id == id).expect("iteration job");
+                match self.jobs.jobs()[index].state {
+                    JobState::Done(status) => {
+                        self.jobs.remove(index);
+                        finished.insert(iteration, (status, stdout, stderr));
+                    }
+                    state => {
+                        // A stopped iteration can't be resumed on its own
+                        if state == JobState::Stopped {
+                            let _ = self.jobs.signal(index, libc::SIGCONT);
+                            self.jobs.set_state(index, JobState::Running);
+                        }
+                        still_running.push((id, iteration, stdout, stderr));
+                    }
+                }
+            }
+            running = still_running;
+
+            while let Some((status, mut stdout, mut stderr)) = finished.remove(&statuses.len()) {
+                replay_output(&mut stdout, &mut std::io::stdout().lock());
+                replay_output(&mut stderr, &mut std::io::stderr().lock());
+                statuses.push(status);
+            }
+        }
+
+        let status = statuses.iter().copied().find(|&status| status != 0).unwrap_or(0);
+        self.pipe_status = statuses;
+        Ok(status)
+    }
+
+    fn execute_case(&mut self, case_cmd: &CaseCommand) -> Result<i32, String> {
+        let word = self.expand_string(&case_cmd.word)?;
+        let matches = if self.nocasematch { pattern::matches_ignoring_case } else { pattern::matches };
+
+        for clause in &case_cmd.cases {
+            for pattern in &clause.patterns {
+                if matches(&self.expand_pattern(pattern)?, &word) {
+                    return self.execute(&clause.body);
+                }
+            }
+        }
+
+        Ok(0)
+    }
+
+    /// `<(source)` or `>(source)`: run `source` in a forked copy of the
+    /// shell with its stdout, or stdin, going into a pipe, and return the
+    /// path the command opens to read or write the other end. The helper
+    /// lasts until the command that expanded it is done. Without /dev/fd,
+    /// the pipe is a FIFO whose path the command opens instead.
+    fn process_substitution(&mut self, direction: char, source: &str) -> Result<String, String> {
+        let commands = script::parse(source).map_err(|error| tr!("process substitution: syntax error: {}", error.message))?;
+        let feeds_command = direction == '<';
+        let fifo = if self.has_dev_fd { None } else { Some(self.make_fifo()?) };
+        let ends = match &fifo {
+            // The helper opens the FIFO to write to itself, which waits for
+            // the command to open it, or it could be gone before the command
+            // came to read
+            Some(_) if feeds_command => Ok((None, None)),
+            Some(fifo) => open_fifo(fifo).map(|(read_end, write_end)| (Some(write_end), Some(read_end))),
+            None => create_pipe().map(|(read_end, write_end)| {
+                if feeds_command { (Some(read_end), Some(write_end)) } else { (Some(write_end), Some(read_end)) }
+            }),
+        };
+        let (ours, theirs) = ends.inspect_err(|_| remove_fifo(&fifo))?;
+        let close = |fd: Option<RawFd>| {
+            if let Some(fd) = fd {
+                unsafe { libc::close(fd) };
+            }
+        };
+        let pid = match self.fork() {
+            Ok(pid) => pid,
+            Err(e) => {
+                close(ours);
+                close(theirs);
+                remove_fifo(&fifo);
+                return Err(e);
+            }
+        };
+        if pid == 0 {
+            // Helpers stay in the shell's process group, as iterations of
+            // `for -P` do
+            self.job_control = None;
+            close(ours);
+            for substitution in std::mem::take(&mut self.process_substitutions) {
+                close(substitution.fd);
+            }
+            let theirs = match (theirs, &fifo) {
+                (Some(fd), _) => Ok(fd),
+                (None, Some(fifo)) => {
+                    OpenOptions::new().write(true).open(fifo).map(IntoRawFd::into_raw_fd).map_err(|e| format!("clam: {}: {}", fifo.display(), e))
+                }
+                (None, None) => unreachable!("a pipe has both ends"),
+            };
+            let result = theirs.and_then(|theirs| {
+                unsafe {
+                    libc::dup2(theirs, if feeds_command { 1 } else { 0 });
+                    libc::close(theirs);
+                }
+                commands.iter().try_fold(0, |_, command| self.execute(command))
+            });
+            self.finish_child(result);
+        }
+
+        // The command inherits our end of a pipe, and opens it by its path;
+        // a FIFO it opens by its own
+        close(theirs);
+        if let (Some(fd), None) = (ours, &fifo) {
+            unsafe { libc::fcntl(fd, libc::F_SETFD, 0) };
+        }
+        let path = match (&fifo, ours) {
+            (Some(fifo), _) => fifo.display().to_string(),
+            (None, fd) => format!("/dev/fd/{}", fd.unwrap_or_default()),
+        };
+        self.process_substitutions.push(ProcessSubstitution { pid, fd: ours, feeds_command, fifo });
+        Ok(path)
+    }
+
+    /// A new FIFO in the shell's FIFO directory, which is made on first
+    /// use. Only so many may be in use at once.
+    fn make_fifo(&mut self) -> Result<PathBuf, String> {
+        let max = self.limit(FIFO_MAX_VAR, DEFAULT_FIFO_MAX);
+        if self.process_substitutions.iter().filter(|substitution| substitution.fifo.is_some()).count() >= max {
+            return Err(tr!("process substitution: more than {} FIFOs in use", max));
+        }
+        let dir = match &self.fifo_dir {
+            Some(dir) => dir.clone(),
+            None => {
+                let dir = private_temp_dir(&self.temp_dir(), "fifo")?;
+                self.fifo_dir = Some(dir.clone());
+                for signal in FATAL_SIGNALS {
+                    let _ = self.apply_trap(signal);
+                }
+                dir
+            }
+        };
+        loop {
+            self.fifo_count += 1;
+            let path = dir.join(self.fifo_count.to_string());
+            let c_path = CString::new(path.as_os_str().as_bytes()).map_err(|e| e.to_string())?;
+            if unsafe { libc::mkfifo(c_path.as_ptr(), 0o600) } == 0 {
+                return Ok(path);
+            }
+            let error = std::io::Error::last_os_error();
+            if error.kind() != std::io::ErrorKind::AlreadyExists {
+                return Err(format!("{}: {}", path.display(), error));
+            }
+        }
+    }
+
+    /// Remove the FIFO directory, with any FIFOs a command left behind
+    fn remove_fifo_dir(&mut self) {
+        if let Some(dir) = self.fifo_dir.take() {
+            let _ = std::fs::remove_dir_all(dir);
+            for signal in FATAL_SIGNALS {
+                let _ = self.apply_trap(signal);
+            }
+        }
+    }
+
+    /// Replace the process substitution `word` starts with, if any, with
+    /// the path to open it by
+    fn substitute_process(&mut self, word: &str) -> Result<String, String> {
+        match lexer::process_substitution(word) {
+            Some((direction, source, rest)) => Ok(self.process_substitution(direction, source)? + rest),
+            None => Ok(word.to_string()),
+        }
+    }
+
+    /// Close the shell's end of the process substitutions made since there
+    /// were `count`, and reap their helpers. Those feeding the command are
+    /// killed, as nothing reads what they write any more; those reading
+    /// from it finish at the end of their input.
+    fn finish_process_substitutions(&mut self, count: usize) {
+        if self.process_substitutions.len() <= count {
+            return;
+        }
+        let finished = self.process_substitutions.split_off(count);
+        for substitution in &finished {
+            unsafe {
+                if let Some(fd) = substitution.fd {
+                    libc::close(fd);
+                }
+                if substitution.feeds_command {
+                    libc::kill(substitution.pid, libc::SIGTERM);
+                }
+            }
+            remove_fifo(&substitution.fifo);
+        }
+        for substitution in finished {
+            let _ = wait_for_pid(substitution.pid);
+        }
+    }
+
+    /// Run the command in a forked copy of the shell, so changes to
+    /// variables or the working directory never reach the parent
+    fn execute_subshell(&mut self, command: &Command) -> Result<i32, String> {
+        let pid = self.fork()?;
+        if pid == 0 {
+            self.enter_job(0, true);
+            self.run_child(command);
+        }
+
+        self.place_job(pid, 0);
+        self.wait_foreground(&[pid], format!("({})", command))
+    }
+
+    /// `((expression))`: evaluate the expression after expanding its
+    /// parameters and substitutions, succeeding if it isn't 0
+    fn execute_arithmetic(&mut self, expression: &str) -> Result<i32, String> {
+        let expression = self.expand_variables(expression)?;
+        match arith::evaluate(&expression, self) {
+            Ok(value) => Ok(i32::from(value == 0)),
+            Err(e) => {
+                eprintln!("clam: {}", tr!("(({})): {}", expression, e));
+                Ok(1)
+            }
+        }
+    }
+
+    /// Execute `command` in a forked child and exit with its status
+    fn run_child(&mut self, command: &Command) -> ! {
+        let result = self.execute(command);
+        self.finish_child(result);
+    }
+
+    fn finish_child(&mut self, result: Result<i32, String>) -> ! {
+        let status = match result {
+            Ok(status) => status,
+            Err(e) => {
+                eprintln!("{}", e);
+                1
+            }
+        };
+        self.clean_up_on_exit();
+        Self::exit_child(status);
+    }
+
+    fn fork(&mut self) -> Result<libc::pid_t, String> {
+        // Anything still buffered would otherwise be written twice
+        let _ = std::io::stdout().flush();
+        let _ = std::io::stderr().flush();
+
+        let seed = self.dynamic.child_seed();
+        match unsafe { libc::fork() } {
+            -1 => Err(format!("fork: {}", std::io::Error::last_os_error())),
+            0 => {
+                signals::reset_in_child();
+                self.traps.clear_for_subshell();
+                self.env_vars.enter_child();
+                self.dynamic.reseed_child(seed);
+                // The parent removes its FIFO directory; a child makes its own
+                self.fifo_dir = None;
+                Ok(0)
+            }
+            pid => Ok(pid),
+        }
+    }
+
+    /// Terminate a forked child without running the parent's destructors
+    fn exit_child(status: i32) -> ! {
+        let _ = std::io::stdout().flush();
+        let _ = std::io::stderr().flush();
+        unsafe { libc::_exit(status) }
+    }
+
+    /// `time`: run the pipeline, then report on stderr how long it took
+    /// and the CPU time it used, the shell's own included for builtins
+    fn execute_timed(&mut self, timed: &TimedCommand) -> Result<i32, String> {
+        let start = Instant::now();
+        let (user, system) = cpu_times();
+        let status = self.execute(&timed.command)?;
+        let real = start.elapsed();
+        let (user_after, system_after) = cpu_times();
+
+        let times = [("real", real), ("user", user_after.saturating_sub(user)), ("sys", system_after.saturating_sub(system))];
+        let report: String = if timed.posix {
+            times.iter().map(|(name, time)| format!("{} {:.2}\n", name, time.as_secs_f64())).collect()
+        } else {
+            let lines: String = times.iter().map(|(name, time)| format!("{}\t{}\n", name, format_minutes(*time))).collect();
+            format!("\n{}", lines)
+        };
+        let _ = std::io::stderr().write_all(report.as_bytes());
+        Ok(status)
+    }
+
+    fn execute_redirected(&mut self, redirected: &RedirectedCommand) -> Result<i32, String> {
+        let Some(saved_fds) = self.apply_redirections(&redirected.redirections) else {
+            return Ok(1);
+        };
+        let result = self.execute(&redirected.command);
+        drop(saved_fds);
+        result
+    }
+
+    /// Apply redirections to the shell's own file descriptors.
+    /// They stay in effect until the returned SavedFds is dropped. If one
+    /// fails, as bash does, the error is reported, the fds are put back and
+    /// the command isn't run, failing with status 1 without aborting the
+    /// rest of the command line.
+    fn apply_redirections(&mut self, redirections: &[Redirection]) -> Option<SavedFds> {
+        let mut saved_fds = SavedFds::new();
+
+        for redirection in redirections {
+            if let Err(e) = self.apply_redirection(redirection, &mut saved_fds) {
+                eprintln!("clam: {}", e);
+                return None;
+            }
+        }
+
+        Some(saved_fds)
+    }
+
+    fn apply_redirection(&mut self, redirection: &Redirection, saved_fds: &mut SavedFds) -> Result<(), String> {
+        match redirection.kind {
+            RedirectionKind::Input => {
+                let file = self.open_target(&redirection.target, OpenOptions::new().read(true))?;
+                saved_fds.redirect(redirection.fd.unwrap_or(0), file)
+            }
+            RedirectionKind::Output | RedirectionKind::Clobber => {
+                let file = self.open_truncated(&redirection.target, redirection.kind == RedirectionKind::Clobber)?;
+                saved_fds.redirect(redirection.fd.unwrap_or(1), file)
+            }
+            RedirectionKind::Append => {
+                let file = self.open_target(
+                    &redirection.target,
+                    OpenOptions::new().append(true).create(true),
+                )?;
+                saved_fds.redirect(redirection.fd.unwrap_or(1), file)
+            }
+            RedirectionKind::InputOutput => {
+                let file = self.open_target(
+                    &redirection.target,
+                    OpenOptions::new().read(true).write(true).create(true),
+                )?;
+                saved_fds.redirect(redirection.fd.unwrap_or(0), file)
+            }
+            RedirectionKind::OutputBoth => {
+                let file = self.open_truncated(&redirection.target, false)?;
+                saved_fds.redirect_both(file)
+            }
+            RedirectionKind::AppendBoth => {
+                let file = self.open_target(
+                    &redirection.target,
+                    OpenOptions::new().append(true).create(true),
+                )?;
+                saved_fds.redirect_both(file)
+            }
+            RedirectionKind::InputDup | RedirectionKind::OutputDup => {
+                let default_fd = if redirection.kind == RedirectionKind::InputDup { 0 } else { 1 };
+                let fd = redirection.fd.unwrap_or(default_fd);
+                match &redirection.target {
+                    RedirectionTarget::Fd(source) => saved_fds.duplicate(*source, fd),
+                    RedirectionTarget::Close => saved_fds.close(fd),
+                    RedirectionTarget::Heredoc { .. } => Err(tr!("Invalid redirection target")),
+                    RedirectionTarget::File(word) => {
+                        let target = self.expand_string(word)?;
+                        match target.parse::<RawFd>() {
+                            Ok(source) => saved_fds.duplicate(source, fd),
+                            Err(_) if target == "-" => saved_fds.close(fd),
+                            Err(_) if redirection.kind == RedirectionKind::OutputDup
+                                && redirection.fd.is_none() =>
+                            {
+                                // >&word is the same as &>word
+                                let file = self.open_truncated(&RedirectionTarget::File(target), false)?;
+                                saved_fds.redirect_both(file)
+                            }
+                            Err(_) => Err(tr!("{}: ambiguous redirect", word)),
+                        }
+                    }
+                }
+            }
+            RedirectionKind::Heredoc | RedirectionKind::HeredocStrip => {
+                let RedirectionTarget::Heredoc { body, quoted, .. } = &redirection.target else {
+                    return Err(tr!("Missing here-document body"));
+                };
+                let body = if *quoted { body.clone() } else { self.expand_heredoc(body)? };
+                let file = self.heredoc_fd(&body)?;
+                saved_fds.redirect(redirection.fd.unwrap_or(0), file)
+            }
+            RedirectionKind::HereString => {
+                let RedirectionTarget::File(word) = &redirection.target else {
+                    return Err(tr!("Invalid redirection target"));
+                };
+                let body = self.expand_string(word)? + "\n";
+                let file = self.heredoc_fd(&body)?;
+                saved_fds.redirect(redirection.fd.unwrap_or(0), file)
+            }
+        }
+    }
+
+    /// An fd to read a here-document from. The body is written before the
+    /// command starts, so it only goes through a pipe when it is small
+    /// enough to fit the pipe buffer; otherwise writing would block on a
+    /// reader that doesn't exist yet. Larger bodies use an unlinked temp file.
+    fn heredoc_fd(&self, body: &str) -> Result<RawFd, String> {
+        if body.len() <= self.heredoc_pipe_max
+            && let Some(fd) = heredoc_pipe(body)?
+        {
+            return Ok(fd);
+        }
+        heredoc_temp_file(body, &self.temp_dir())
+    }
+
+    /// Open `target` for writing from its start, as `>` does. With
+    /// noclobber on, an existing regular file is an error unless `force`
+    /// (`>|`) is given; other files, such as /dev/null, are still opened.
+    fn open_truncated(&mut self, target: &RedirectionTarget, force: bool) -> Result<RawFd, String> {
+        if force || !self.noclobber {
+            return self.open_target(target, OpenOptions::new().write(true).create(true).truncate(true));
+        }
+        let path = self.target_path(target)?;
+        match std::fs::metadata(&path) {
+            Ok(metadata) if metadata.is_file() => Err(tr!("{}: cannot overwrite existing file", path)),
+            Ok(_) => self.open_target(target, OpenOptions::new().write(true)),
+            // Exclusive, so a file that appears meanwhile is not truncated
+            Err(_) => self.open_target(target, OpenOptions::new().write(true).create_new(true)),
+        }
+    }
+
+    fn open_target(&mut self, target: &RedirectionTarget, options: &mut OpenOptions) -> Result<RawFd, String> {
+        let path = self.target_path(target)?;
+        options
+            .mode(0o666)
+            .open(&path)
+            .map(|file| file.into_raw_fd())
+            .map_err(|e| format!("{}: {}", path, e))
+    }
+
+    fn target_path(&mut self, target: &RedirectionTarget) -> Result<String, String> {
+        match target {
+            RedirectionTarget::File(path) => {
+                let path = self.substitute_process(path)?;
+                self.expand_string(&path)
+            }
+            RedirectionTarget::Fd(fd) => Ok(fd.to_string()),
+            RedirectionTarget::Close | RedirectionTarget::Heredoc { .. } => Err(tr!("Invalid redirection target")),
+        }
+    }
+
+    /// `clam --structured`: start with `set -o structured` on
+    pub fn enable_structured(&mut self) {
+        self.structured = true;
+    }
+
+    /// Start external commands with `spawner` instead of running them
+    pub fn set_spawner(&mut self, spawner: Box<dyn Spawner>) {
+        self.spawner = spawner;
+    }
+
+    /// Turn on the `assert_*` builtins of `clam --test`
+    pub fn enable_assertions(&mut self) {
+        self.assertions = Some(Assertions::default());
+    }
+
+    pub fn assertions(&self) -> Option<Assertions> {
+        self.assertions
+    }
+
+    /// Set `$0`, and `$_` until a command is run, as bash does
+    pub fn set_shell_name(&mut self, name: &str) {
+        self.shell_name = name.to_string();
+        self.last_argument = name.to_string();
+    }
+
+    /// Set a shell variable, which commands also receive in their environment
+    pub fn set_variable(&mut self, name: &str, value: &str) {
+        if self.dynamic.assign(name, value) {
+            return;
+        }
+        self.env_vars.insert(name.to_string(), value.to_string());
+    }
+
+    /// Where the shell keeps its temp files: `$TMPDIR`, which a script may
+    /// set for itself, or the system's
+    fn temp_dir(&self) -> PathBuf {
+        match self.get_variable("TMPDIR") {
+            dir if dir.is_empty() => std::env::temp_dir(),
+            dir => PathBuf::from(dir),
+        }
+    }
+
+    /// Run the file at `path` in the current shell, as `. path` would
+    pub fn source(&mut self, path: &str) -> Result<i32, String> {
+        self.execute_source(&[path.to_string()])
+    }
+
+    pub fn set_positional(&mut self, args: Vec<String>) {
+        self.positional = args;
+    }
+
+    pub fn get_last_exit_status(&self) -> i32 {
+        self.last_exit_status
+    }
+
+    /// Expand `word` the way a command's arguments are: a leading process
+    /// substitution, brace expansion, then for each resulting word tilde
+    /// expansion, variable expansion, word splitting and pathname expansion
+    /// of each field, leaving alone what was quoted. A pattern that matches
+    /// nothing is kept as it is, unless `nullglob` drops it or `failglob`
+    /// makes it an error.
+    pub fn expand_word(&mut self, word: &str) -> Result<Vec<String>, String> {
+        let max_fields = self.limit(MAX_EXPANSION_VAR, DEFAULT_MAX_EXPANSION);
+        let options = glob::Options {
+            max_entries: self.limit(GLOB_MAX_ENTRIES_VAR, DEFAULT_GLOB_MAX_ENTRIES),
+            globstar: self.globstar,
+            dotglob: self.dotglob,
+            nocaseglob: self.nocaseglob,
+        };
+        let word = &self.substitute_process(word)?;
+        let mut fields = Vec::new();
+        for braced in brace::expand(word, max_fields)? {
+            let expanded = self.expand_quoted(&braced, false)?;
+            for field in word_split(&expanded) {
+                let pattern = to_pattern(field, self.extglob);
+                if !glob::is_pattern(&pattern) {
+                    fields.push(to_text(field));
+                    continue;
+                }
+                let paths = glob::expand(&pattern, options)?;
+                if paths.is_empty() && self.failglob {
+                    return Err(tr!("no match: {}", to_text(field)));
+                }
+                if paths.is_empty() && !self.nullglob {
+                    fields.push(to_text(field));
+                }
+                fields.extend(paths);
+            }
+        }
+
+        if fields.len() > max_fields {
+            return Err(tr!("{}: expands to more than {} words", word, max_fields));
+        }
+        Ok(fields)
+    }
+
+    /// The expansion limit set by the variable `name`, so an interactive
+    /// session doesn't freeze on a runaway pattern
+    fn limit(&self, name: &str, default: usize) -> usize {
+        self.get_variable(name).parse().unwrap_or(default)
+    }
+
+    /// Expand the value of an assignment, where a tilde may also follow
+    /// each `:`, as in `PATH=~/bin:~/.local/bin`
+    fn expand_assignment(&mut self, value: &str) -> Result<String, String> {
+        let parts = lexer::split_unquoted(value, ':');
+        let parts = parts.into_iter().map(|part| self.expand_string(part)).collect::<Result<Vec<_>, _>>()?;
+        Ok(parts.join(":"))
+    }
+
+    /// Expand `word` into a single string, without word splitting or
+    /// pathname expansion, as a case word or redirection target is
+    fn expand_string(&mut self, word: &str) -> Result<String, String> {
+        Ok(to_text(&self.expand_quoted(word, false)?))
+    }
+
+    /// Expand `word` into a pattern, in which what was quoted only matches
+    /// itself
+    fn expand_pattern(&mut self, word: &str) -> Result<String, String> {
+        Ok(to_pattern(&self.expand_quoted(word, false)?, self.extglob))
+    }
+
+    /// Replace a leading `~` with $HOME, or `~user` with that user's home
+    /// directory. An unknown user is left as it is.
+    fn expand_tilde(&self, word: &str) -> String {
+        let Some(rest) = word.strip_prefix('~') else {
+            return word.to_string();
+        };
+        let (user, path) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+        let home = if user.is_empty() { Some(self.get_variable("HOME")) } else { home_dir(user) };
+        match home {
+            Some(home) => format!("{}{}", home, path),
+            None => word.to_string(),
+        }
+    }
+
+    /// Expand `text` with its quotes: tilde expansion at its start unless
+    /// `quoted`, i.e. inside double quotes, then the `$` expansions outside
+    /// single quotes, with a backslash escaping the char after it. Each char
+    /// of the result is marked quoted if it came from inside quotes.
+    fn expand_quoted(&mut self, text: &str, quoted: bool) -> Result<Marked, String> {
+        let mut result = Marked::new();
+        let mut index = 0;
+        let prefix = &text[..text.find('/').unwrap_or(text.len())];
+        if !quoted && prefix.starts_with('~') && !prefix.contains(['\'', '"', '\\', '$']) {
+            result.extend(mark(&self.expand_tilde(prefix), true));
+            index = prefix.len();
+        }
+
+        while let Some(ch) = text[index..].chars().next() {
+            let rest = &text[index..];
+            match ch {
+                '\'' if !quoted => {
+                    let len = lexer::unit_len(rest);
+                    let inner = rest[1..len].strip_suffix('\'').unwrap_or(&rest[1..len]);
+                    result.extend(mark(inner, true));
+                    if inner.is_empty() {
+                        result.push(QUOTED_NULL);
+                    }
+                    index += len;
+                }
+                // Only `${x:-"..."}` can hold one inside double quotes
+                '"' => {
+                    let len = lexer::unit_len(rest);
+                    let inner = rest[1..len].strip_suffix('"').unwrap_or(&rest[1..len]);
+                    let expanded = self.expand_quoted(inner, true)?;
+                    // `""` makes a field even if empty, but `"$@"` without
+                    // positional parameters none
+                    if expanded.is_empty() && !is_only_at(inner) {
+                        result.push(QUOTED_NULL);
+                    }
+                    result.extend(expanded);
+                    index += len;
+                }
+                '\\' => match rest[1..].chars().next() {
+                    Some('\n') => index += 2,
+                    Some(next) if !quoted || matches!(next, '$' | '`' | '"' | '\\') => {
+                        result.push((next, true));
+                        index += 1 + next.len_utf8();
+                    }
+                    _ => {
+                        result.push((ch, quoted));
+                        index += 1;
+                    }
+                },
+                '$' => {
+                    let (expanded, len) = self.expand_dollar(&rest[1..], quoted)?;
+                    result.extend(expanded);
+                    index += 1 + len;
+                }
+                _ => {
+                    result.push((ch, quoted));
+                    index += ch.len_utf8();
+                }
+            }
+        }
+        Ok(result)
+    }
+
+    /// Expand the body of a here-document with an unquoted delimiter: its
+    /// `$` expansions and `` `...` `` substitutions. A backslash only escapes
+    /// `$`, `` ` `` and `\`, or joins a line to the next.
+    fn expand_heredoc(&mut self, body: &str) -> Result<String, String> {
+        let mut result = String::new();
+        let mut index = 0;
+        while let Some(offset) = body[index..].find(['$', '`', '\\']) {
+            result.push_str(&body[index..index + offset]);
+            index += offset;
+            let rest = &body[index + 1..];
+            match &body[index..index + 1] {
+                "$" => {
+                    let (expanded, len) = self.expand_dollar(rest, true)?;
+                    result.push_str(&to_text(&expanded));
+                    index += 1 + len;
+                }
+                "`" => {
+                    let (source, len) = backquoted(rest);
+                    result.push_str(&self.command_substitution(&source));
+                    index += 1 + len;
+                }
+                _ => match rest.chars().next() {
+                    Some('\n') => index += 2,
+                    Some(ch @ ('$' | '`' | '\\')) => {
+                        result.push(ch);
+                        index += 2;
+                    }
+                    _ => {
+                        result.push('\\');
+                        index += 1;
+                    }
+                },
+            }
+        }
+        result.push_str(&body[index..]);
+        Ok(result)
+    }
+
+    /// Expand the `$` expansions in `input`, taking everything else as it
+    /// is, as in an arithmetic expression
+    fn expand_variables(&mut self, input: &str) -> Result<String, String> {
+        let mut result = String::new();
+        let mut index = 0;
+        while let Some(offset) = input[index..].find('$') {
+            result.push_str(&input[index..index + offset]);
+            let (expanded, len) = self.expand_dollar(&input[index + offset + 1..], true)?;
+            result.push_str(&to_text(&expanded));
+            index += offset + 1 + len;
+        }
+        result.push_str(&input[index..]);
+        Ok(result)
+    }
+
+    /// The expansion that `text`, after a `$`, starts with, and its length.
+    /// A `$` that starts none is kept as it is.
+    fn expand_dollar(&mut self, text: &str, quoted: bool) -> Result<(Marked, usize), String> {
+        if text.starts_with('(')
+            && let Some(len) = lexer::substitution_len(text.chars())
+        {
+            let end = text.char_indices().nth(len).map_or(text.len(), |(index, _)| index);
+            return Ok((mark(&self.command_substitution(&text[1..end - 1]), quoted), end));
+        }
+        if text.starts_with('{') {
+            // ${...}, through the matching brace or else the end
+            let len = parameter::expansion_len(text).unwrap_or(text.len());
+            let inner = text[1..len].strip_suffix('}').unwrap_or(&text[1..len]);
+            return Ok((self.expand_parameter(inner, quoted)?, len));
+        }
+        match text.chars().next() {
+            Some('@') => Ok((self.positional_fields(quoted), 1)),
+            Some('*') if !quoted => Ok((self.positional_fields(quoted), 1)),
+            // Positional and special parameters are a single character
+            Some(c) if c.is_ascii_digit() || matches!(c, '#' | '*' | '?' | '!' | '-' | '$') => {
+                Ok((mark(&self.get_variable(&c.to_string()), quoted), 1))
+            }
+            _ => {
+                let len = text.find(|c: char| !(c.is_alphanumeric() || c == '_')).unwrap_or(text.len());
+                if len == 0 {
+                    return Ok((mark("$", quoted), 0));
+                }
+                Ok((mark(&self.get_variable(&text[..len]), quoted), len))
+            }
+        }
+    }
+
+    /// `${...}`: the value of the parameter `inner` names, or what its
+    /// operator makes of it, marked `quoted` but for the quoting of a
+    /// substituted word
+    fn expand_parameter(&mut self, inner: &str, quoted: bool) -> Result<Marked, String> {
+        let expansion = parameter::parse(inner)?;
+        let name = expansion.name;
+        let value = self.get_variable(name);
+        let value = match expansion.operation {
+            Operation::Value if name == "@" || name == "*" && !quoted => return Ok(self.positional_fields(quoted)),
+            Operation::Substring { offset, length } if name == "@" || name == "*" => {
+                // Counted from $0, so that `${@:1}` is all of them
+                let values: Vec<String> = std::iter::once(self.shell_name.clone()).chain(self.positional.iter().cloned()).collect();
+                let range = self.substring_range(values.len(), offset, length)?;
+                if name == "*" && quoted {
+                    return Ok(mark(&values[range].join(&self.ifs_separator()), quoted));
+                }
+                return Ok(fields(&values[range], quoted));
+            }
+            Operation::Value => value,
+            Operation::Length => {
+                let elements = name.strip_suffix("[@]").or_else(|| name.strip_suffix("[*]")).and_then(|name| self.array(name));
+                elements.map_or(value.chars().count(), |elements| elements.len()).to_string()
+            }
+            Operation::Substring { offset, length } => self.substring(&value, offset, length)?,
+            Operation::Replace { anchor, pattern, replacement } => {
+                let pattern = self.expand_pattern(pattern)?;
+                let replacement = to_text(&self.expand_quoted(replacement, quoted)?);
+                pattern::replace(&pattern, &value, &replacement, anchor)
+            }
+            Operation::Case { upper, all, pattern } => {
+                let pattern = self.expand_pattern(pattern)?;
+                parameter::change_case(&value, upper, all, &pattern)
+            }
+            Operation::Remove { suffix, longest, pattern } => {
+                let pattern = self.expand_pattern(pattern)?;
+                pattern::remove(&pattern, &value, suffix, longest)
+            }
+            Operation::Substitute { kind, colon, word } => {
+                return self.substitute_parameter(name, value, kind, colon, word, quoted);
+            }
+        };
+        Ok(mark(&value, quoted))
+    }
+
+    /// `$@`, and `$*` unquoted: the positional parameters, each a field of
+    /// its own, even if empty when `quoted`
+    fn positional_fields(&self, quoted: bool) -> Marked {
+        fields(&self.positional, quoted)
+    }
+
+    /// What `"$*"` joins the positional parameters with: the first
+    /// character of IFS, or a space when IFS is unset
+    fn ifs_separator(&self) -> String {
+        if !self.is_set("IFS") {
+            return " ".to_string();
+        }
+        self.get_variable("IFS").chars().next().map(String::from).unwrap_or_default()
+    }
+
+    /// `${name-word}` and the like: `word` expanded in place of `value`, or
+    /// assigned to `name`, or reported as an error, when `name` is unset or
+    /// with `colon` empty
+    fn substitute_parameter(
+        &mut self,
+        name: &str,
+        value: String,
+        kind: Substitution,
+        colon: bool,
+        word: &str,
+        quoted: bool,
+    ) -> Result<Marked, String> {
+        let unset = !self.is_set(name) || (colon && value.is_empty());
+        match (kind, unset) {
+            (Substitution::Default, true) | (Substitution::Alternative, false) => self.expand_quoted(word, quoted),
+            (Substitution::Alternative, true) => Ok(Marked::new()),
+            (Substitution::Assign, true) => {
+                if !parameter::is_assignable(name) {
+                    return Err(tr!("${}: cannot assign in this way", name));
+                }
+                let value = self.expand_quoted(word, quoted)?;
+                self.set_variable(name, &to_text(&value));
+                Ok(value)
+            }
+            (Substitution::Error, true) => {
+                let message = match to_text(&self.expand_quoted(word, quoted)?) {
+                    message if message.is_empty() => tr!("parameter null or not set"),
+                    message => message,
+                };
+                // Fatal to a script, as in POSIX shells
+                if !self.interactive {
+                    eprintln!("clam: {}", tr!("{}: {}", name, message));
+                    self.clean_up_on_exit();
+                    std::process::exit(1);
+                }
+                Err(format!("{}: {}", name, message))
+            }
+            (_, false) => Ok(mark(&value, quoted)),
+        }
+    }
+
+    /// `${name:offset:length}`: the characters of `value` from `offset`, to
+    /// the end or `length` of them. Either counts from the end when negative.
+    fn substring(&mut self, value: &str, offset: &str, length: Option<&str>) -> Result<String, String> {
+        let chars: Vec<char> = value.chars().collect();
+        let range = self.substring_range(chars.len(), offset, length)?;
+        Ok(chars[range].iter().collect())
+    }
+
+    /// The part of `len` items that `offset` and `length` take, as
+    /// `${name:offset:length}` does of characters and `${@:offset:length}`
+    /// of positional parameters
+    fn substring_range(&mut self, len: usize, offset: &str, length: Option<&str>) -> Result<std::ops::Range<usize>, String> {
+        let mut evaluate = |expression: &str| {
+            let expanded = self.expand_variables(expression)?;
+            arith::evaluate(&expanded, self).map_err(|e| format!("{}: {}", expression.trim(), e))
+        };
+        let len = len as i64;
+        let offset = evaluate(offset)?;
+        let start = if offset < 0 { len.saturating_add(offset) } else { offset };
+        if !(0..=len).contains(&start) {
+            return Ok(0..0);
+        }
+        let end = match length {
+            None => len,
+            Some(expression) => match evaluate(expression)? {
+                length if length < 0 && len + length < start => {
+                    return Err(tr!("{}: substring expression < 0", expression.trim()));
+                }
+                length if length < 0 => len + length,
+                length => start.saturating_add(length).min(len),
+            },
+        };
+        Ok(start as usize..end as usize)
+    }
+
+    /// Whether the parameter `name` is set, even if empty
+    fn is_set(&self, name: &str) -> bool {
+        match name {
+            "#" | "?" | "0" | "$" | "-" | "_" => return true,
+            "@" | "*" => return !self.positional.is_empty(),
+            "!" => return self.last_background_pid.is_some(),
+            _ => {}
+        }
+        if let Ok(index) = name.parse::<usize>() {
+            return index <= self.positional.len();
+        }
+        if let Some((name, subscript)) = name.strip_suffix(']').and_then(|name| name.split_once('[')) {
+            let len = self.array(name).map_or(usize::from(self.is_set(name)), |values| values.len());
+            return subscript.parse::<usize>().map_or(len > 0, |index| index < len);
+        }
+        self.array(name).is_some()
+            || self.dynamic.get(name).is_some()
+            || self.env_vars.contains_key(name)
+            || std::env::var_os(name).is_some()
+    }
+
+    /// `$(...)`: what `source` writes to stdout when run in a subshell,
+    /// less trailing newlines. Its status becomes `$?`.
+    fn command_substitution(&mut self, source: &str) -> String {
+        let commands = match script::parse(source) {
+            Ok(commands) => commands,
+            Err(error) => {
+                eprintln!("clam: {}", tr!("command substitution: syntax error: {}", error.message));
+                self.last_exit_status = script::SYNTAX_ERROR_STATUS;
+                self.substitution_status = Some(script::SYNTAX_ERROR_STATUS);
+                return String::new();
+            }
+        };
+        let run = |executor: &mut Self| commands.iter().try_fold(0, |_, command| executor.execute(command));
+        let (output, status) = self.capture_output(&format!("$({})", source), run).unwrap_or_else(|e| {
+            eprintln!("clam: {}", e);
+            (String::new(), 1)
+        });
+        self.last_exit_status = status;
+        self.substitution_status = Some(status);
+        output
+    }
+
+    pub fn get_variable(&self, name: &str) -> String {
+        match name {
+            "#" => return self.positional.len().to_string(),
+            "@" => return self.positional.join(" "),
+            "*" => return self.positional.join(&self.ifs_separator()),
+            "?" => return self.last_exit_status.to_string(),
+            "0" => return self.shell_name.clone(),
+            "!" => return self.last_background_pid.map(|pid| pid.to_string()).unwrap_or_default(),
+            "$" => return self.shell_pid.to_string(),
+            "_" => return self.last_argument.clone(),
+            "-" => return self.option_flags(),
+            _ => {}
+        }
+        if let Ok(index) = name.parse::<usize>()
+            && index > 0
+        {
+            return self.positional.get(index - 1).cloned().unwrap_or_default();
+        }
+        // `NAME[index]` or `NAME[@]`; a plain variable is an array of one
+        if let Some((name, subscript)) = name.strip_suffix(']').and_then(|name| name.split_once('[')) {
+            let values = self.array(name).unwrap_or_else(|| vec![self.get_variable(name)]);
+            return match subscript {
+                "@" | "*" => values.join(" "),
+                index => index.parse::<usize>().ok().and_then(|i| values.get(i).cloned()).unwrap_or_default(),
+            };
+        }
+        if let Some(values) = self.array(name) {
+            return values.into_iter().next().unwrap_or_default();
+        }
+        if let Some(value) = self.dynamic.get(name) {
+            return value;
+        }
+
+        // Check shell variables first
+        if let Some(value) = self.env_vars.get(name) {
+            return value.clone();
+        }
+
+        // Then check environment variables
+        std::env::var(name).unwrap_or_default()
+    }
+
+    /// Array variables, all of them maintained by the shell itself so far
+    fn array(&self, name: &str) -> Option<Vec<String>> {
+        match name {
+            "PIPESTATUS" => Some(self.pipe_status.iter().map(|status| status.to_string()).collect()),
+            _ => None,
+        }
+    }
+}
+
+impl Default for Executor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl arith::Variables for Executor {
+    fn get(&self, name: &str) -> String {
+        self.get_variable(name)
+    }
+
+    fn set(&mut self, name: &str, value: &str) {
+        self.set_variable(name, value);
+    }
+}
+
+/// Expanded text, each char marked with whether it was quoted, which keeps
+/// it from word splitting and pathname expansion
+type Marked = Vec<(char, bool)>;
+/// Left by a quoted empty string, as `""` or `"$EMPTY"`, so that the field
+/// it is in is kept even if empty. A NUL can't come from the text itself.
+const QUOTED_NULL: (char, bool) = ('\0', true);
+/// Ends a field, quoted or not, between the positional parameters of `$@`;
+/// a space where the text isn't split
+const FIELD_BREAK: (char, bool) = ('\0', false);
+
+/// `text` marked; a NUL, which no argument can hold, is dropped as bash does
+fn mark(text: &str, quoted: bool) -> Marked {
+    text.chars().filter(|&ch| ch != '\0').map(|ch| (ch, quoted)).collect()
+}
+
+/// `values` as fields of their own, even if empty when `quoted`
+fn fields(values: &[String], quoted: bool) -> Marked {
+    let mut result = Marked::new();
+    for (index, value) in values.iter().enumerate() {
+        if index > 0 {
+            result.push(FIELD_BREAK);
+        }
+        result.extend(mark(value, quoted));
+        if quoted {
+            result.push(QUOTED_NULL);
+        }
+    }
+    result
+}
+
+fn to_text(marked: &[(char, bool)]) -> String {
+    marked
+        .iter()
+        .filter(|&&unit| unit != QUOTED_NULL)
+        .map(|&unit| if unit == FIELD_BREAK { ' ' } else { unit.0 })
+        .collect()
+}
+
+/// The source of the `` `...` `` substitution whose opening backquote
+/// `text` follows, with its escaped `$`, `` ` `` and `\` unescaped, and its
+/// length through the closing backquote, or to the end if there is none
+fn backquoted(text: &str) -> (String, usize) {
+    let mut source = String::new();
+    let mut chars = text.char_indices();
+    while let Some((index, ch)) = chars.next() {
+        match ch {
+            '`' => return (source, index + 1),
+            '\\' => match chars.next() {
+                Some((_, escaped @ ('$' | '`' | '\\'))) => source.push(escaped),
+                Some((_, other)) => {
+                    source.push('\\');
+                    source.push(other);
+                }
+                None => source.push('\\'),
+            },
+            _ => source.push(ch),
+        }
+    }
+    (source, text.len())
+}
+
+/// `marked` as a pattern, with the quoted pattern characters escaped.
+/// Without `extglob`, every `(` is, so none starts an extended pattern.
+fn to_pattern(marked: &[(char, bool)], extglob: bool) -> String {
+    let mut pattern = String::new();
+    for &(ch, quoted) in marked.iter().filter(|&&unit| unit != QUOTED_NULL) {
+        if quoted && matches!(ch, '*' | '?' | '[' | '\\' | '(' | '|' | ')') || !extglob && ch == '(' {
+            pattern.push('\\');
+        }
+        pattern.push(if (ch, quoted) == FIELD_BREAK { ' ' } else { ch });
+    }
+    pattern
+}
+
+/// Whether `text` is nothing but `$@`, which makes no field at all without
+/// positional parameters, even quoted
+fn is_only_at(text: &str) -> bool {
+    !text.is_empty() && text.replace("${@}", "").replace("$@", "").is_empty()
+}
+
+/// Word splitting: the fields between runs of unquoted whitespace, which
+/// only an expansion can have left, and between the parameters of `$@`.
+/// Empty fields are dropped unless they hold a quoted empty string.
+fn word_split(marked: &[(char, bool)]) -> Vec<&[(char, bool)]> {
+    marked
+        .split(|&(ch, quoted)| (ch, quoted) == FIELD_BREAK || !quoted && ch.is_whitespace())
+        .filter(|field| !field.is_empty())
+        .collect()
+}
+
+/// Write `value` to stdout as one line of JSON, for the `--json` forms of
+/// builtins that tools and prompts read
+fn print_json(value: &impl serde::Serialize) {
+    match serde_json::to_string(value) {
+        Ok(json) => {
+            let _ = writeln!(std::io::stdout().lock(), "{}", json);
+        }
+        Err(e) => eprintln!("clam: {}", e),
+    }
+}
+
+/// `value` with the characters special between double quotes escaped
+fn escape_double_quoted(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for ch in value.chars() {
+        if matches!(ch, '"' | '\\' | '$' | '`') {
+            escaped.push('\\');
+        }
+        escaped.push(ch);
+    }
+    escaped
+}
+
+/// `kill -l`: list all signal names, or the names of the given signal
+/// numbers or exit statuses of signalled commands
+fn list_signals(args: &[String]) -> i32 {
+    let mut stdout = std::io::stdout();
+    if args.is_empty() {
+        for (number, name) in signals::names() {
+            let _ = writeln!(stdout, "{:2}) SIG{}", number, name);
+        }
+        return 0;
+    }
+
+    let mut status = 0;
+    for arg in args {
+        // Numbers (or statuses) translate to names, names to numbers
+        let translated = match arg.parse::<i32>() {
+            Ok(number) if number > 128 => signals::name(number - 128).map(str::to_string),
+            Ok(number) => signals::name(number).map(str::to_string),
+            Err(_) => signals::parse(arg).map(|number| number.to_string()),
+        };
+        match translated {
+            Some(text) => {
+                let _ = writeln!(stdout, "{}", text);
+            }
+            None => {
+                eprintln!("clam: {}", tr!("kill: {}: invalid signal specification", arg));
+                status = 1;
+            }
+        }
+    }
+    status
+}
+
+/// Read one line from fd 0 a byte at a time, so that whatever follows it
+/// is left for the next reader (which may be a child process sharing the
+/// same stdin). Returns the line and whether it ended with a newline.
+/// Find the program `name` would run: the path itself if it has a slash,
+/// otherwise the first executable file named `name` in `path_var`
+fn find_executable(name: &str, path_var: &str) -> Option<PathBuf> {
+    let is_executable = |path: &Path| {
+        let Ok(c_path) = std::ffi::CString::new(path.as_os_str().as_encoded_bytes()) else {
+            return false;
+        };
+        path.is_file() && unsafe { libc::access(c_path.as_ptr(), libc::X_OK) } == 0
+    };
+    if name.contains('/') {
+        let path = PathBuf::from(name);
+        return is_executable(&path).then_some(path);
+    }
+    path_var
+        .split(':')
+        .map(|dir| Path::new(if dir.is_empty() { "." } else { dir }).join(name))
+        .find(|path| is_executable(path))
+}
+
+/// The home directory of `user`, from the password database
+fn home_dir(user: &str) -> Option<String> {
+    let name = std::ffi::CString::new(user).ok()?;
+    let entry = unsafe { libc::getpwnam(name.as_ptr()) };
+    if entry.is_null() {
+        return None;
+    }
+    let dir = unsafe { std::ffi::CStr::from_ptr((*entry).pw_dir) };
+    Some(dir.to_string_lossy().into_owned())
+}
+
+/// User and system CPU time used so far by the shell and the children it
+/// waited for
+fn cpu_times() -> (Duration, Duration) {
+    let mut user = Duration::ZERO;
+    let mut system = Duration::ZERO;
+    for who in [libc::RUSAGE_SELF, libc::RUSAGE_CHILDREN] {
+        let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+        if unsafe { libc::getrusage(who, &mut usage) } == 0 {
+            user += timeval_duration(usage.ru_utime);
+            system += timeval_duration(usage.ru_stime);
+        }
+    }
+    (user, system)
+}
+
+fn timeval_duration(time: libc::timeval) -> Duration {
+    Duration::new(time.tv_sec as u64, time.tv_usec as u32 * 1000)
+}
+
+/// A duration the way bash's `time` prints it, e.g. `1m2.345s`
+fn format_minutes(time: Duration) -> String {
+    let millis = time.as_millis();
+    format!("{}m{}.{:03}s", millis / 60_000, millis / 1000 % 60, millis % 1000)
+}
+
+/// A `sleep` interval such as `0.5`, `10s`, `1.5m`, `2h` or `1d`
+fn parse_interval(text: &str) -> Option<Duration> {
+    let (number, unit) = match text.strip_suffix(['s', 'm', 'h', 'd']) {
+        Some(number) => (number, &text[number.len()..]),
+        None => (text, "s"),
+    };
+    let scale = match unit {
+        "s" => 1.0,
+        "m" => 60.0,
+        "h" => 3600.0,
+        _ => 86400.0,
+    };
+    // Guard against what f64 parses but an interval isn't, e.g. `inf`
+    if !number.starts_with(|c: char| c.is_ascii_digit() || c == '.') {
+        return None;
+    }
+    Duration::try_from_secs_f64(number.parse::<f64>().ok()? * scale).ok()
+}
+
+/// What makes running `words` alarming enough for `set -o globguard` to
+/// ask first, if they are a recursive `rm`: one word that expanded to more
+/// than `max` paths, or a path that is `/`, a directory right under it, or
+/// `home`
+fn removal_risk(words: &[String], widest: usize, max: usize, home: &str) -> Option<String> {
+    if Path::new(&words[0]).file_name().is_none_or(|name| name != "rm") {
+        return None;
+    }
+    let mut options = words[1..].iter().take_while(|word| word.as_str() != "--").filter(|word| word.starts_with('-'));
+    if !options.any(|option| option == "--recursive" || !option.starts_with("--") && option.contains(['r', 'R'])) {
+        return None;
+    }
+    if widest > max {
+        return Some(tr!("{}: a word expanded to {} paths", words[0], widest));
+    }
+    words[1..]
+        .iter()
+        .filter(|word| !word.starts_with('-'))
+        .find(|word| {
+            let path = word.trim_end_matches('/');
+            word.starts_with('/') && path.is_empty() || path.rfind('/') == Some(0) || !home.is_empty() && path == home.trim_end_matches('/')
+        })
+        .map(|word| tr!("{}: {} is a top-level or home directory", words[0], word))
+}
+
+/// A line read from `fd` a byte at a time, so nothing after it is
+/// consumed, and whether it ended with a newline rather than end of file.
+/// Without `raw`, a backslash escapes the next byte.
+fn read_line_from(fd: RawFd, raw: bool) -> (String, bool) {
+    let mut bytes = Vec::new();
+    let mut escaped = false;
+    let complete = loop {
+        let mut byte = 0u8;
+        let n = unsafe { libc::read(fd, &mut byte as *mut u8 as *mut libc::c_void, 1) };
+        if n < 0
+            && std::io::Error::last_os_error().kind() == std::io::ErrorKind::Interrupted
+            && !signals::interrupt_pending()
+        {
+            continue;
+        }
+        if n <= 0 {
+            break false;
+        }
+
+        if escaped {
+            // A backslash-newline continues the line
+            if byte != b'\n' {
+                bytes.push(byte);
+            }
+            escaped = false;
+        } else if byte == b'\\' && !raw {
+            escaped = true;
+        } else if byte == b'\n' {
+            break true;
+        } else {
+            bytes.push(byte);
+        }
+    };
+    (String::from_utf8_lossy(&bytes).into_owned(), complete)
+}
+
+/// Split on whitespace into at most `count` fields, the last keeping the
+/// remainder of the line
+fn split_fields(line: &str, count: usize) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut rest = line.trim_matches([' ', '\t']);
+    while fields.len() + 1 < count && !rest.is_empty() {
+        let end = rest.find([' ', '\t']).unwrap_or(rest.len());
+        fields.push(rest[..end].to_string());
+        rest = rest[end..].trim_start_matches([' ', '\t']);
+    }
+    if !rest.is_empty() {
+        fields.push(rest.to_string());
+    }
+    fields
+}
+
+/// Locate a file for `source`: names containing a slash are used as is,
+/// others are looked up in PATH and then in the current directory
+fn find_source_file(name: &str, path_var: &str) -> Option<PathBuf> {
+    if name.contains('/') {
+        return Some(PathBuf::from(name));
+    }
+
+    path_var
+        .split(':')
+        .filter(|dir| !dir.is_empty())
+        .map(|dir| Path::new(dir).join(name))
+        .find(|candidate| candidate.is_file())
+        .or_else(|| Path::new(name).is_file().then(|| PathBuf::from(name)))
+}
+
+/// A pipe holding `body`, or None if it doesn't fit the pipe buffer
+fn heredoc_pipe(body: &str) -> Result<Option<RawFd>, String> {
+    let (read_end, write_end) = create_pipe()?;
+    unsafe { libc::fcntl(write_end, libc::F_SETFL, libc::O_NONBLOCK) };
+
+    let mut remaining = body.as_bytes();
+    while !remaining.is_empty() {
+        let n = unsafe { libc::write(write_end, remaining.as_ptr() as *const libc::c_void, remaining.len()) };
+        if n < 0 {
+            let error = std::io::Error::last_os_error();
+            if error.kind() == std::io::ErrorKind::Interrupted {
+                continue;
+            }
+            unsafe {
+                libc::close(read_end);
+                libc::close(write_end);
+            }
+            if error.kind() == std::io::ErrorKind::WouldBlock {
+                return Ok(None);
+            }
+            return Err(tr!("here-document: {}", error));
+        }
+        remaining = &remaining[n as usize..];
+    }
+
+    unsafe { libc::close(write_end) };
+    Ok(Some(read_end))
+}
+
+/// An unlinked temp file in `dir` holding `body`, positioned at its start
+fn heredoc_temp_file(body: &str, dir: &Path) -> Result<RawFd, String> {
+    let mut file = unlinked_temp_file(dir, "heredoc").map_err(|e| tr!("here-document: {}", e))?;
+    file.write_all(body.as_bytes())
+        .and_then(|()| file.seek(SeekFrom::Start(0)).map(|_| ()))
+        .map_err(|e| tr!("here-document: {}", e))?;
+    Ok(file.into_raw_fd())
+}
+
+/// Copy what was written to `file` to `out`
+fn replay_output(file: &mut std::fs::File, out: &mut impl Write) {
+    let _ = file.seek(SeekFrom::Start(0)).and_then(|_| std::io::copy(file, out));
+    let _ = out.flush();
+}
+
+/// A new file in `dir` for the shell's own use, readable and writable by
+/// the user only. It is unlinked at once, so it goes away when closed.
+fn unlinked_temp_file(dir: &Path, kind: &str) -> Result<std::fs::File, String> {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+    let path = dir.join(format!(
+        "clam-{}-{}-{}",
+        kind,
+        std::process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(&path)
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+    let _ = std::fs::remove_file(&path);
+    Ok(file)
+}
+
+/// A new directory under `dir` that only the shell's user can enter, named
+/// after the process and a number that no existing directory has
+fn private_temp_dir(dir: &Path, kind: &str) -> Result<PathBuf, String> {
+    let mut attempt = 0;
+    loop {
+        let path = dir.join(format!("clam-{}-{}-{}", kind, std::process::id(), attempt));
+        match std::fs::DirBuilder::new().mode(0o700).create(&path) {
+            Ok(()) => return Ok(path),
+            // Left over from an earlier shell with the same process ID
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists && attempt < 100 => attempt += 1,
+            Err(e) => return Err(format!("{}: {}", path.display(), e)),
+        }
+    }
+}
+
+/// Both ends of the FIFO at `path`, as (read end, write end) like
+/// `create_pipe` makes. The read end is opened first and neither blocks,
+/// so there is no waiting for the other side.
+fn open_fifo(path: &Path) -> Result<(RawFd, RawFd), String> {
+    let open = |options: &mut OpenOptions| {
+        let file = options.custom_flags(libc::O_NONBLOCK).open(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let fd = file.into_raw_fd();
+        unsafe { libc::fcntl(fd, libc::F_SETFL, libc::fcntl(fd, libc::F_GETFL) & !libc::O_NONBLOCK) };
+        Ok::<_, String>(fd)
+    };
+    let read_end = open(OpenOptions::new().read(true))?;
+    match open(OpenOptions::new().write(true)) {
+        Ok(write_end) => Ok((read_end, write_end)),
+        Err(e) => {
+            unsafe { libc::close(read_end) };
+            Err(e)
+        }
+    }
+}
+
+fn remove_fifo(fifo: &Option<PathBuf>) {
+    if let Some(fifo) = fifo {
+        let _ = std::fs::remove_file(fifo);
+    }
+}
+
+/// A pipe as (read end, write end), both close-on-exec so that commands
+/// only inherit the ends dup'ed onto their stdin/stdout
+fn create_pipe() -> Result<(RawFd, RawFd), String> {
+    let mut fds = [0; 2];
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } < 0 {
+        return Err(format!("pipe: {}", std::io::Error::last_os_error()));
+    }
+    Ok((fds[0], fds[1]))
+}
+
+/// Wait for a child process and convert its termination into a shell
+/// exit status (128 + signal number when killed by a signal)
+fn wait_for_pid(pid: libc::pid_t) -> Result<i32, String> {
+    let mut status = 0;
+    loop {
+        if unsafe { libc::waitpid(pid, &mut status, 0) } >= 0 {
+            break;
+        }
+        let error = std::io::Error::last_os_error();
+        if error.kind() != std::io::ErrorKind::Interrupted {
+            return Err(format!("waitpid: {}", error));
+        }
+    }
+
+    Ok(jobs::exit_status(status))
+}
+
+/// The helper of a `<(...)` or `>(...)`, and the shell's end of the pipe
+/// to it
+struct ProcessSubstitution {
+    pid: libc::pid_t,
+    /// None for a `<(...)` FIFO, which only the helper opens
+    fd: Option<RawFd>,
+    /// `<(...)`: the helper writes what the command reads
+    feeds_command: bool,
+    /// The FIFO the pipe is, without /dev/fd
+    fifo: Option<PathBuf>,
+}
+
+/// Executor state to return to when a command is aborted by an error.
+/// Redirected fds and temporary assignments are restored where they are
+/// made; this covers what an error can leave behind at the top level.
+struct Checkpoint {
+    positional: Vec<String>,
+    function_depth: usize,
+    cwd: Option<PathBuf>,
+    /// `set -o` options, such as pipefail and noclobber, with whether each
+    /// was on
+    options: Vec<(&'static str, bool)>,
+    shopt_options: Vec<(&'static str, bool)>,
+}
+
+/// Original file descriptors saved while redirections are in effect.
+/// Each entry holds the redirected fd and a duplicate of what it pointed to
+/// before (None if the fd was not open).
+struct SavedFds {
+    saved: Vec<(RawFd, Option<RawFd>)>,
+}
+
+impl SavedFds {
+    fn new() -> Self {
+        Self { saved: Vec::new() }
+    }
+
+    /// Remember the current state of `fd` the first time it is touched
+    fn save(&mut self, fd: RawFd) {
+        if self.saved.iter().any(|(saved_fd, _)| *saved_fd == fd) {
+            return;
+        }
+        // Flush buffered output so it lands on the original destination
+        let _ = std::io::stdout().flush();
+        let _ = std::io::stderr().flush();
+        let copy = unsafe { libc::fcntl(fd, libc::F_DUPFD_CLOEXEC, 10) };
+        self.saved.push((fd, if copy >= 0 { Some(copy) } else { None }));
+    }
+
+    /// Point `fd` at the freshly opened `file`, consuming it
+    fn redirect(&mut self, fd: RawFd, file: RawFd) -> Result<(), String> {
+        if file == fd {
+            // The fd was closed before and open() reused it, close-on-exec
+            // as the shell opens everything
+            if !self.saved.iter().any(|(saved_fd, _)| *saved_fd == fd) {
+                self.saved.push((fd, None));
+            }
+            unsafe { libc::fcntl(fd, libc::F_SETFD, 0) };
+            return Ok(());
+        }
+        let result = self.duplicate(file, fd);
+        unsafe { libc::close(file) };
+        result
+    }
+
+    /// Point both stdout and stderr at `file`, consuming it
+    fn redirect_both(&mut self, file: RawFd) -> Result<(), String> {
+        let result = self.duplicate(file, 1).and_then(|_| self.duplicate(file, 2));
+        unsafe { libc::close(file) };
+        result
+    }
+
+    fn duplicate(&mut self, source: RawFd, fd: RawFd) -> Result<(), String> {
+        self.save(fd);
+        if unsafe { libc::dup2(source, fd) } < 0 {
+            return Err(format!("{}: {}", source, std::io::Error::last_os_error()));
+        }
+        Ok(())
+    }
+
+    fn close(&mut self, fd: RawFd) -> Result<(), String> {
+        self.save(fd);
+        unsafe { libc::close(fd) };
+        Ok(())
+    }
+
+    /// Leave the redirections in effect, forgetting the original fds
+    fn keep(mut self) {
+        for (_, original) in self.saved.drain(..) {
+            if let Some(copy) = original {
+                unsafe { libc::close(copy) };
+            }
+        }
+    }
+
+}
+
+/// Dropping SavedFds puts the original fds back, so they are restored on
+/// every path out of a redirected command, including errors
+impl Drop for SavedFds {
+    fn drop(&mut self) {
+        let _ = std::io::stdout().flush();
+        let _ = std::io::stderr().flush();
+        for (fd, original) in self.saved.drain(..).rev() {
+            unsafe {
+                match original {
+                    Some(copy) => {
+                        libc::dup2(copy, fd);
+                        libc::close(copy);
+                    }
+                    None => {
+                        libc::close(fd);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+    use std::sync::Mutex;
+
+    // Redirections rewire the process-wide fds, so tests touching them must not overlap
+    pub(crate) static FD_LOCK: Mutex<()> = Mutex::new(());
+
+    pub(crate) fn run(executor: &mut Executor, input: &str) -> Result<i32, String> {
+        let tokens = Lexer::new(input).tokenize()?;
+        let commands = Parser::new(tokens).parse()?;
+        let mut status = 0;
+        for command in &commands {
+            status = executor.execute(command)?;
+        }
+        Ok(status)
+    }
+
+    fn parse_one(input: &str) -> Command {
+        let tokens = Lexer::new(input).tokenize().unwrap();
+        Parser::new(tokens).parse().unwrap().remove(0)
+    }
+
+    pub(crate) fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("clam-test-{}-{}", std::process::id(), name))
+    }
+
+    #[test]
+    fn test_append_both_redirection() {
+        let _guard = FD_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let path = temp_path("append-both");
+        let script = temp_path("append-both.sh");
+        let _ = std::fs::remove_file(&path);
+        std::fs::write(&script, "echo out\necho err >&2\n").unwrap();
+
+        let mut executor = Executor::new();
+        executor.env_vars.insert("LOG".to_string(), path.display().to_string());
+        executor.env_vars.insert("SCRIPT".to_string(), script.display().to_string());
+        run(&mut executor, "sh $SCRIPT &>> $LOG").unwrap();
+        run(&mut executor, "echo again &>> $LOG").unwrap();
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(contents, "out\nerr\nagain\n");
+        let _ = std::fs::remove_file(&path);
+        let _ = std::fs::remove_file(&script);
+    }
+
+    #[test]
+    fn test_pipe_with_stderr() {
+        let _guard = FD_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let path = temp_path("pipe-stderr");
+        let script = temp_path("pipe-stderr.sh");
+        std::fs::write(&script, "echo out\necho err >&2\n").unwrap();
+
+        let mut executor = Executor::new();
+        executor.env_vars.insert("LOG".to_string(), path.display().to_string());
+        executor.env_vars.insert("SCRIPT".to_string(), script.display().to_string());
+        run(&mut executor, "sh $SCRIPT |& sort -r > $LOG").unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "out\nerr\n");
+        let _ = std::fs::remove_file(&path);
+        let _ = std::fs::remove_file(&script);
+    }
+
+    #[test]
+    fn test_subshell_isolates_variables() {
+        let mut executor = Executor::new();
+        run(&mut executor, "FOO=outer").unwrap();
+
+        let status = run(&mut executor, "(FOO=inner; false)").unwrap();
+
+        assert_eq!(status, 1);
+        assert_eq!(executor.get_variable("FOO"), "outer");
+    }
+
+    #[test]
+    fn test_subshell_isolates_cwd() {
+        let _guard = FD_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let path = temp_path("subshell-cwd");
+        let cwd = std::env::current_dir().unwrap();
+        let mut executor = Executor::new();
+        executor.env_vars.insert("OUT".to_string(), path.display().to_string());
+
+        // There is no cd builtin yet; pushd changes directory the same way
+        assert_eq!(run(&mut executor, "(pushd /tmp > /dev/null; pwd > $OUT)").unwrap(), 0);
+        let inside = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(Path::new(inside.trim_end()), std::fs::canonicalize("/tmp").unwrap());
+        assert_eq!(std::env::current_dir().unwrap(), cwd);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_group_runs_in_current_shell() {
+        let _guard = FD_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let path = temp_path("group");
+        let _ = std::fs::remove_file(&path);
+
+        let mut executor = Executor::new();
+        executor.env_vars.insert("OUT".to_string(), path.display().to_string());
+        let status = run(&mut executor, "{ FOO=set; echo a; echo b; false; } > $OUT").unwrap();
+
+        assert_eq!(status, 1);
+        assert_eq!(executor.get_variable("FOO"), "set");
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "a\nb\n");
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_case_glob_patterns() {
+        let mut executor = Executor::new();
+        let script = "case $NAME in\n  *.txt) KIND=text ;;\n  [0-9]*) KIND=number ;;\n  *) KIND=other ;;\nesac";
+
+        executor.env_vars.insert("NAME".to_string(), "notes.txt".to_string());
+        run(&mut executor, script).unwrap();
+        assert_eq!(executor.get_variable("KIND"), "text");
+
+        executor.env_vars.insert("NAME".to_string(), "42abc".to_string());
+        run(&mut executor, script).unwrap();
+        assert_eq!(executor.get_variable("KIND"), "number");
+
+        executor.env_vars.insert("NAME".to_string(), "README".to_string());
+        run(&mut executor, script).unwrap();
+        assert_eq!(executor.get_variable("KIND"), "other");
+    }
+
+    #[test]
+    fn test_output_redirection_truncates() {
+        let _guard = FD_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let path = temp_path("output");
+        std::fs::write(&path, "old contents\n").unwrap();
+
+        let mut executor = Executor::new();
+        executor.env_vars.insert("OUT".to_string(), path.display().to_string());
+        run(&mut executor, "echo new > $OUT").unwrap();
+
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "new\n");
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_function_positional_parameters() {
+        let mut executor = Executor::new();
+        run(&mut executor, "function greet() { COUNT=$#; FIRST=$1; ALL=$@; }").unwrap();
+
+        run(&mut executor, "greet hello big world").unwrap();
+
+        assert_eq!(executor.get_variable("COUNT"), "3");
+        assert_eq!(executor.get_variable("FIRST"), "hello");
+        assert_eq!(executor.get_variable("ALL"), "hello big world");
+        assert_eq!(executor.get_variable("1"), "");
+    }
+
+    #[test]
+    fn test_set_and_shift_positional_parameters() {
+        let mut executor = Executor::new();
+        run(&mut executor, "set -- a 'b c' -x; N=$#; THIRD=$3").unwrap();
+        assert_eq!(executor.get_variable("N"), "3");
+        assert_eq!(executor.get_variable("THIRD"), "-x");
+        assert_eq!(run(&mut executor, "shift 4").unwrap(), 1);
+        assert_eq!(run(&mut executor, "shift; FIRST=$1; shift 2; N=$#").unwrap(), 0);
+        assert_eq!(executor.get_variable("FIRST"), "b c");
+        assert_eq!(executor.get_variable("N"), "0");
+        assert_eq!(run(&mut executor, "shift x 2> /dev/null").unwrap(), 1);
+
+        // Inside a function, only its own parameters change
+        run(&mut executor, "set one two; f() { set -- in; shift; INNER=$#; }; f x; OUTER=$*").unwrap();
+        assert_eq!(executor.get_variable("INNER"), "0");
+        assert_eq!(executor.get_variable("OUTER"), "one two");
+
+        run(&mut executor, "set -- 1 2 3 4; IFS=-; JOINED=\"$*\"; IFS=; GLUED=\"${*:2:2}\"; LAST=${@: -1}").unwrap();
+        assert_eq!(executor.get_variable("JOINED"), "1-2-3-4");
+        assert_eq!(executor.get_variable("GLUED"), "23");
+        assert_eq!(executor.get_variable("LAST"), "4");
+        let mut words = Vec::new();
+        for word in ["\"${@:3}\"", "\"${@:0:2}\"", "${*:9}"] {
+            words.push(executor.expand_word(word).unwrap());
+        }
+        assert_eq!(words, [vec!["3", "4"], vec!["clam", "1"], vec![]]);
+
+        run(&mut executor, "set -- a,b c; IFS=,; N=$#; FIRST=$1; JOINED=\"$*\"").unwrap();
+        assert_eq!(executor.get_variable("N"), "2");
+        assert_eq!(executor.get_variable("FIRST"), "a,b");
+        assert_eq!(executor.get_variable("JOINED"), "a,b,c");
+    }
+
+    #[test]
+    fn test_function_return() {
+        let mut executor = Executor::new();
+        run(&mut executor, "function check { for x in $@; do if test $x = stop; then return 3; fi; SEEN=$x; done; true; }").unwrap();
+
+        assert_eq!(run(&mut executor, "check a stop b").unwrap(), 3);
+        assert_eq!(executor.get_variable("SEEN"), "a");
+        assert_eq!(executor.get_variable("?"), "3");
+        assert_eq!(run(&mut executor, "check a b").unwrap(), 0);
+        assert!(run(&mut executor, "return 1").is_err());
+    }
+
+    fn fd_identity(fd: RawFd) -> (u64, u64) {
+        let mut stat: libc::stat = unsafe { std::mem::zeroed() };
+        assert_eq!(unsafe { libc::fstat(fd, &mut stat) }, 0);
+        (stat.st_dev as u64, stat.st_ino as u64)
+    }
+
+    #[test]
+    fn test_failed_redirection_restores_fds() {
+        let _guard = FD_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let path = temp_path("failed-redirection");
+        let stdout_before = fd_identity(1);
+        let stderr_before = fd_identity(2);
+
+        let mut executor = Executor::new();
+        executor.env_vars.insert("OUT".to_string(), path.display().to_string());
+        let result = executor.execute_isolated(&parse_one("echo lost > $OUT 2> /nonexistent-dir/err"));
+
+        assert_eq!(result, Ok(1));
+        assert_eq!(executor.get_last_exit_status(), 1);
+        assert_eq!(fd_identity(1), stdout_before);
+        assert_eq!(fd_identity(2), stderr_before);
+
+        // The rest of the line still runs
+        let result = executor.execute_isolated(&parse_one("echo lost 2> /nonexistent-dir/err > $OUT; echo next > $OUT"));
+        assert_eq!(result, Ok(0));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "next\n");
+        assert_eq!(fd_identity(2), stderr_before);
+
+        run(&mut executor, "echo ok > $OUT").unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "ok\n");
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_aborted_function_restores_state() {
+        let mut executor = Executor::new();
+        executor.set_positional(vec!["top".to_string()]);
+        run(&mut executor, "function bad { INNER=$1; return oops; }").unwrap();
+
+        let result = executor.execute_isolated(&parse_one("CLAM_SCOPED=temporary bad arg"));
+
+        assert!(result.is_err());
+        assert_eq!(executor.get_variable("INNER"), "arg");
+        assert_eq!(executor.get_variable("CLAM_SCOPED"), "");
+        assert_eq!(executor.get_variable("1"), "top");
+        assert_eq!(executor.function_depth, 0);
+        assert!(!executor.returning);
+        assert!(run(&mut executor, "return 0").is_err());
+        assert_eq!(run(&mut executor, "true; A=after").unwrap(), 0);
+        assert_eq!(executor.get_variable("A"), "after");
+    }
+
+    #[test]
+    fn test_aborted_command_restores_options() {
+        let mut executor = Executor::new();
+        run(&mut executor, "set -T").unwrap();
+
+        let result = executor.execute_isolated(&parse_one("set -o pipefail -C +T; shopt -s nullglob; return oops"));
+
+        assert!(result.is_err());
+        assert!(!executor.pipefail && !executor.noclobber && !executor.nullglob);
+        assert!(executor.functrace);
+        run(&mut executor, "set -o pipefail -C +T; shopt -s nullglob").unwrap();
+        assert!(executor.pipefail && executor.noclobber && executor.nullglob);
+        assert!(!executor.functrace);
+    }
+
+    #[test]
+    fn test_missing_command_does_not_abort_list() {
+        let mut executor = Executor::new();
+
+        let status = run(&mut executor, "clam-no-such-command || FALLBACK=yes").unwrap();
+
+        assert_eq!(status, 0);
+        assert_eq!(executor.get_variable("FALLBACK"), "yes");
+        assert_eq!(run(&mut executor, "clam-no-such-command").unwrap(), 127);
+    }
+
+    #[test]
+    fn test_pipeline_negation() {
+        let mut executor = Executor::new();
+
+        assert_eq!(run(&mut executor, "! false").unwrap(), 0);
+        assert_eq!(run(&mut executor, "! true").unwrap(), 1);
+        assert_eq!(run(&mut executor, "! clam-no-such-command").unwrap(), 0);
+        run(&mut executor, "if ! false; then NEGATED=yes; fi").unwrap();
+        assert_eq!(executor.get_variable("NEGATED"), "yes");
+        run(&mut executor, "! true && NEVER=set").unwrap();
+        assert_eq!(executor.get_variable("NEVER"), "");
+    }
+
+    #[test]
+    fn test_pipeline_connects_commands() {
+        let _guard = FD_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let path = temp_path("pipeline");
+        let _ = std::fs::remove_file(&path);
+
+        let mut executor = Executor::new();
+        executor.env_vars.insert("OUT".to_string(), path.display().to_string());
+        assert_eq!(run(&mut executor, "echo hello | tr a-z A-Z | cat > $OUT").unwrap(), 0);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "HELLO\n");
+
+        assert_eq!(run(&mut executor, "echo hello | grep -q nope").unwrap(), 1);
+        assert_eq!(run(&mut executor, "! echo hello | grep -q nope").unwrap(), 0);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_background_does_not_block() {
+        let mut executor = Executor::new();
+        let started = std::time::Instant::now();
+
+        let status = run(&mut executor, "sleep 5 & CHILD_ONLY=1 &\nAFTER=yes").unwrap();
+
+        assert_eq!(status, 0);
+        assert!(started.elapsed() < std::time::Duration::from_secs(2));
+        assert_eq!(executor.get_variable("AFTER"), "yes");
+        assert_eq!(executor.get_variable("CHILD_ONLY"), "");
+        let jobs = executor.jobs.jobs();
+        assert_eq!(jobs.iter().map(|job| job.id).collect::<Vec<_>>(), vec![1, 2]);
+        assert_eq!(jobs[0].command, "sleep 5");
+        assert_eq!(jobs[1].command, "CHILD_ONLY=1");
+
+        for job in jobs {
+            unsafe { libc::kill(job.pid, libc::SIGKILL) };
+            wait_for_pid(job.pid).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_and_or_chains() {
+        let mut executor = Executor::new();
+
+        run(&mut executor, "false && A=1 || B=2; C=3").unwrap();
+
+        assert_eq!(executor.get_variable("A"), "");
+        assert_eq!(executor.get_variable("B"), "2");
+        assert_eq!(executor.get_variable("C"), "3");
+    }
+
+    #[test]
+    fn test_source_searches_path() {
+        let dir = temp_path("source-path");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("lib.sh"), "LOADED=$1\nreturn 4\nNOT_REACHED=1\n").unwrap();
+
+        let mut executor = Executor::new();
+        executor.env_vars.insert("PATH".to_string(), format!("/nonexistent:{}", dir.display()));
+        executor.set_positional(vec!["outer".to_string()]);
+
+        assert_eq!(run(&mut executor, "source lib.sh inner").unwrap(), 4);
+        assert_eq!(executor.get_variable("LOADED"), "inner");
+        assert_eq!(executor.get_variable("NOT_REACHED"), "");
+        assert_eq!(executor.get_variable("1"), "outer");
+        assert_eq!(run(&mut executor, ". missing-clam-file.sh").unwrap(), 1);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_source_detects_recursion() {
+        let dir = temp_path("source-cycle");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("a.sh"), "A=1\nsource $DIR/b.sh\n").unwrap();
+        std::fs::write(dir.join("b.sh"), "B=1\nsource $DIR/a.sh\n").unwrap();
+
+        let mut executor = Executor::new();
+        executor.env_vars.insert("DIR".to_string(), dir.display().to_string());
+
+        assert_eq!(run(&mut executor, "source $DIR/a.sh").unwrap(), 1);
+        assert_eq!(executor.get_variable("B"), "1");
+        assert!(executor.source_stack.is_empty());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_jobs_builtin_lists_background_jobs() {
+        let _guard = FD_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let path = temp_path("jobs");
+        let mut executor = Executor::new();
+        executor.env_vars.insert("OUT".to_string(), path.display().to_string());
+
+        run(&mut executor, "sleep 5 &").unwrap();
+        let pid = executor.jobs.jobs()[0].pid;
+        run(&mut executor, "jobs -l > $OUT").unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            format!("[1]+ {} Running                 sleep 5 &\n", pid)
+        );
+        run(&mut executor, "jobs --json > $OUT").unwrap();
+        let json: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(json[0]["pid"], pid);
+        assert_eq!(json[0]["state"], "running");
+        assert_eq!(json[0]["current"], "+");
+
+        unsafe { libc::kill(pid, libc::SIGKILL) };
+        wait_for_pid(pid).unwrap();
+        run(&mut executor, "jobs -p > $OUT").unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), format!("{}\n", pid));
+        assert_eq!(run(&mut executor, "jobs -x").unwrap(), 2);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_fg_and_bg_resume_stopped_jobs() {
+        let _guard = FD_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let path = temp_path("fg_bg");
+        let mut executor = Executor::new();
+        executor.env_vars.insert("OUT".to_string(), path.display().to_string());
+
+        run(&mut executor, "sleep 5 &").unwrap();
+        let pid = executor.jobs.jobs()[0].pid;
+        unsafe { libc::kill(pid, libc::SIGSTOP) };
+        while executor.jobs.jobs()[0].state != JobState::Stopped {
+            executor.reap_jobs();
+        }
+
+        assert_eq!(run(&mut executor, "bg %sleep > $OUT").unwrap(), 0);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "[1]+ sleep 5 &\n");
+        assert_eq!(executor.jobs.jobs()[0].state, JobState::Running);
+
+        // Terminated only once fg is waiting for it
+        let killer = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(100));
+            unsafe { libc::kill(pid, libc::SIGTERM) };
+        });
+        assert_eq!(run(&mut executor, "fg %1 > $OUT").unwrap(), 128 + libc::SIGTERM);
+        killer.join().unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "sleep 5\n");
+        assert!(executor.jobs.jobs().is_empty());
+        assert_eq!(run(&mut executor, "fg").unwrap(), 1);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_shell_name_unchanged_in_functions_and_sourced_files() {
+        let path = temp_path("name.sh");
+        std::fs::write(&path, "SOURCED=$0\n").unwrap();
+        let mut executor = Executor::new();
+        executor.set_shell_name("-clam");
+        executor.env_vars.insert("FILE".to_string(), path.display().to_string());
+
+        run(&mut executor, "f() { IN_FUNCTION=$0; }\nf one\n. $FILE two").unwrap();
+
+        assert_eq!(executor.get_variable("IN_FUNCTION"), "-clam");
+        assert_eq!(executor.get_variable("SOURCED"), "-clam");
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_pid_and_last_argument_parameters() {
+        let mut executor = Executor::new();
+        executor.set_shell_name("/bin/clam");
+        run(&mut executor, "START=$_; f() { IN=$_; true; }; f a 'b c'; AFTER=$_; PID=$$; SUB=$(true; echo ${$} $_)").unwrap();
+
+        let pid = std::process::id().to_string();
+        assert_eq!(executor.get_variable("START"), "/bin/clam");
+        assert_eq!(executor.get_variable("IN"), "/bin/clam");
+        assert_eq!(executor.get_variable("AFTER"), "b c");
+        assert_eq!(executor.get_variable("PID"), pid);
+        // Subshells keep the shell's pid and see the commands they ran
+        assert_eq!(executor.get_variable("SUB"), format!("{} true", pid));
+        // Assignments alone aren't commands
+        assert_eq!(executor.get_variable("_"), "b c");
+    }
+
+    #[test]
+    fn test_child_killed_by_sigint_abandons_command_line() {
+        let path = temp_path("interrupt.sh");
+        std::fs::write(&path, "#!/bin/sh\nkill -INT $$\n").unwrap();
+        std::fs::set_permissions(&path, std::os::unix::fs::PermissionsExt::from_mode(0o755)).unwrap();
+        let mut executor = Executor::new();
+        executor.env_vars.insert("SCRIPT".to_string(), path.display().to_string());
+
+        let command = parse_one("for i in 1 2 3; do COUNT=$i; $SCRIPT; done; AFTER=1");
+        assert_eq!(executor.execute_isolated(&command), Ok(INTERRUPTED_STATUS));
+
+        assert_eq!(executor.get_variable("COUNT"), "1");
+        assert_eq!(executor.get_variable("AFTER"), "");
+        assert_eq!(executor.get_last_exit_status(), INTERRUPTED_STATUS);
+        assert_eq!(run(&mut executor, "NEXT=1").unwrap(), 0);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_pipeline_stage_dies_when_reader_exits() {
+        let _guard = FD_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let mut executor = Executor::new();
+        run(&mut executor, "sleep 5 &").unwrap();
+        let pid = executor.jobs.jobs()[0].pid;
+
+        // `jobs` is a builtin, so only SIGPIPE can stop the loop
+        let status = run(&mut executor, "while true; do jobs; done | head -n 1 > /dev/null").unwrap();
+        assert_eq!(status, 0);
+
+        unsafe { libc::kill(pid, libc::SIGKILL) };
+        wait_for_pid(pid).unwrap();
+    }
+
+    #[test]
+    fn test_wait_builtin() {
+        let mut executor = Executor::new();
+
+        run(&mut executor, "sleep 0.2 & sleep 0.1 &").unwrap();
+        assert_eq!(run(&mut executor, "wait").unwrap(), 0);
+        assert!(executor.jobs.jobs().is_empty());
+
+        run(&mut executor, "sleep 5 & false &").unwrap();
+        let started = std::time::Instant::now();
+        assert_eq!(run(&mut executor, "wait -n").unwrap(), 1);
+        assert!(started.elapsed() < std::time::Duration::from_secs(2));
+        assert_eq!(executor.jobs.jobs().len(), 1);
+
+        let pid = executor.jobs.jobs()[0].pid;
+        unsafe { libc::kill(pid, libc::SIGTERM) };
+        assert_eq!(run(&mut executor, "wait %1").unwrap(), 128 + libc::SIGTERM);
+        assert_eq!(run(&mut executor, "wait 999999").unwrap(), 127);
+        assert_eq!(run(&mut executor, "wait abc").unwrap(), 2);
+    }
+
+    #[test]
+    fn test_wait_for_reported_job_by_pid() {
+        let mut executor = Executor::new();
+
+        run(&mut executor, "false &").unwrap();
+        let pid = executor.jobs.jobs()[0].pid;
+        assert_eq!(executor.get_variable("!"), pid.to_string());
+        // Reported as done before a prompt, which removes it from the table
+        while !executor.jobs.jobs().is_empty() {
+            executor.reap_jobs();
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+
+        assert_eq!(run(&mut executor, "wait $!").unwrap(), 1);
+        // The status is consumed by the first wait
+        assert_eq!(run(&mut executor, "wait $!").unwrap(), 127);
+    }
+
+    #[test]
+    fn test_kill_builtin() {
+        let mut executor = Executor::new();
+
+        run(&mut executor, "sleep 5 &").unwrap();
+        assert_eq!(run(&mut executor, "kill -s HUP %1").unwrap(), 0);
+        assert_eq!(run(&mut executor, "wait %1").unwrap(), 128 + libc::SIGHUP);
+
+        run(&mut executor, "sleep 5 &").unwrap();
+        assert_eq!(run(&mut executor, "kill -9 $!").unwrap(), 0);
+        assert_eq!(run(&mut executor, "wait $!").unwrap(), 128 + libc::SIGKILL);
+
+        // A stopped job is continued so it can act on SIGTERM
+        run(&mut executor, "sleep 5 &").unwrap();
+        run(&mut executor, "kill -STOP %1").unwrap();
+        let pid = executor.jobs.jobs()[0].pid;
+        while executor.jobs.jobs()[0].state != JobState::Stopped {
+            executor.jobs.update();
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        assert_eq!(run(&mut executor, "kill %1").unwrap(), 0);
+        assert_eq!(run(&mut executor, &format!("wait {}", pid)).unwrap(), 128 + libc::SIGTERM);
+
+        assert_eq!(run(&mut executor, "kill -BOGUS 1").unwrap(), 1);
+        assert_eq!(run(&mut executor, "kill %9").unwrap(), 1);
+        assert_eq!(run(&mut executor, "kill").unwrap(), 2);
+        assert_eq!(run(&mut executor, "kill -l 143 TERM").unwrap(), 0);
+        assert_eq!(run(&mut executor, "kill -l 999").unwrap(), 1);
+    }
+
+    #[test]
+    fn test_disown_and_hang_up_jobs() {
+        let mut executor = Executor::new();
+
+        run(&mut executor, "sleep 5 & sleep 5 &").unwrap();
+        let disowned = executor.jobs.jobs()[0].pid;
+        assert_eq!(run(&mut executor, "disown %1").unwrap(), 0);
+        assert_eq!(executor.jobs.jobs().len(), 1);
+        assert_eq!(run(&mut executor, "disown %1").unwrap(), 1);
+
+        // Without CLAM_HUPONEXIT jobs are left alone
+        executor.hang_up_jobs();
+        assert_eq!(executor.jobs.jobs()[0].state, JobState::Running);
+
+        executor.env_vars.insert(HUPONEXIT_VAR.to_string(), "1".to_string());
+        executor.hang_up_jobs();
+        assert_eq!(run(&mut executor, "wait %2").unwrap(), 128 + libc::SIGHUP);
+
+        // The disowned job was not hung up
+        assert_eq!(unsafe { libc::kill(disowned, 0) }, 0);
+        unsafe { libc::kill(disowned, libc::SIGKILL) };
+        assert_eq!(wait_for_pid(disowned).unwrap(), 128 + libc::SIGKILL);
+    }
+
+    #[test]
+    fn test_stopped_foreground_command_returns_without_job_control() {
+        let script = temp_path("stop-self.sh");
+        std::fs::write(&script, "kill -STOP $$\n").unwrap();
+        let mut executor = Executor::new();
+        executor.env_vars.insert("SCRIPT".to_string(), script.display().to_string());
+
+        assert_eq!(run(&mut executor, "sh $SCRIPT").unwrap(), 128 + libc::SIGTSTP);
+        assert_eq!(executor.jobs.jobs().len(), 1);
+        assert_eq!(executor.jobs.jobs()[0].state, JobState::Stopped);
+
+        assert_eq!(run(&mut executor, "kill %1").unwrap(), 0);
+        assert_eq!(run(&mut executor, "wait %1").unwrap(), 128 + libc::SIGTERM);
+        let _ = std::fs::remove_file(&script);
+    }
+
+    #[test]
+    fn test_trap_builtin() {
+        let mut executor = Executor::new();
+        run(&mut executor, "on_usr1() { TRAPPED=yes; }").unwrap();
+
+        run(&mut executor, "trap on_usr1 USR1").unwrap();
+        unsafe { libc::raise(libc::SIGUSR1) };
+        assert_eq!(run(&mut executor, "false").unwrap(), 1);
+        assert_eq!(executor.get_variable("TRAPPED"), "yes");
+        // The trap leaves $? alone
+        assert_eq!(executor.get_last_exit_status(), 1);
+
+        // A trapped signal ends `wait` early, then the trap runs
+        executor.env_vars.remove("TRAPPED");
+        run(&mut executor, "sleep 5 &").unwrap();
+        let waiter = unsafe { libc::pthread_self() };
+        let signaller = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(100));
+            unsafe { libc::pthread_kill(waiter, libc::SIGUSR1) };
+        });
+        assert_eq!(run(&mut executor, "wait").unwrap(), 128 + libc::SIGUSR1);
+        signaller.join().unwrap();
+        assert_eq!(executor.get_variable("TRAPPED"), "yes");
+        run(&mut executor, "kill %1; wait").unwrap();
+
+        assert_eq!(run(&mut executor, "trap - USR1").unwrap(), 0);
+        assert_eq!(executor.traps.get(Condition::Signal(libc::SIGUSR1)), None);
+
+        // An empty action ignores the signal
+        executor.traps.set(Condition::Signal(libc::SIGUSR2), "");
+        executor.apply_trap(libc::SIGUSR2).unwrap();
+        unsafe { libc::raise(libc::SIGUSR2) };
+        run(&mut executor, "trap USR2").unwrap();
+
+        assert_eq!(run(&mut executor, "trap 'true' BOGUS").unwrap(), 1);
+    }
+
+    #[test]
+    fn test_debug_err_and_return_traps() {
+        let mut executor = Executor::new();
+        run(&mut executor, "count() { COUNT=x$COUNT; }; seen() { SEEN=$CLAM_COMMAND; }").unwrap();
+
+        run(&mut executor, "trap count ERR").unwrap();
+        run(&mut executor, "false; true").unwrap();
+        assert_eq!(executor.get_variable("COUNT"), "x");
+        // Failures whose status is being tested don't count
+        run(&mut executor, "if false; then true; fi; false && true; false || true; ! true").unwrap();
+        run(&mut executor, "while false; do true; done; false | true").unwrap();
+        assert_eq!(executor.get_variable("COUNT"), "x");
+        run(&mut executor, "true && false; (false); true | false").unwrap();
+        assert_eq!(executor.get_variable("COUNT"), "xxxx");
+        run(&mut executor, "trap - ERR").unwrap();
+
+        run(&mut executor, "trap seen DEBUG").unwrap();
+        run(&mut executor, "echo hi > /dev/null").unwrap();
+        assert_eq!(executor.get_variable("SEEN"), "echo hi > /dev/null");
+        run(&mut executor, "trap - DEBUG").unwrap();
+
+        executor.env_vars.remove("COUNT");
+        run(&mut executor, "trap count RETURN; f() { return 3; }; f; f").unwrap();
+        assert_eq!(executor.get_variable("COUNT"), "");
+        assert_eq!(executor.get_last_exit_status(), 3);
+        run(&mut executor, "set -T; f; set +T").unwrap();
+        assert_eq!(executor.get_variable("COUNT"), "x");
+        // A RETURN trap the function sets runs as it returns
+        run(&mut executor, "trap - RETURN; g() { trap count RETURN; }; g").unwrap();
+        assert_eq!(executor.get_variable("COUNT"), "xx");
+        run(&mut executor, "trap - RETURN").unwrap();
+    }
+
+    #[test]
+    fn test_err_trap_in_functions() {
+        let mut executor = Executor::new();
+        run(&mut executor, "count() { COUNT=x$COUNT; }; f() { false; }").unwrap();
+
+        // Only the call fails with the trap set, not the command inside
+        run(&mut executor, "trap count ERR; f").unwrap();
+        assert_eq!(executor.get_variable("COUNT"), "x");
+        run(&mut executor, "set -E; f; set +E").unwrap();
+        assert_eq!(executor.get_variable("COUNT"), "xxx");
+        assert_eq!(executor.traps.get(Condition::Err), Some("count"));
+    }
+
+    #[test]
+    fn test_exit_trap() {
+        let _guard = FD_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let output = temp_path("exit-trap");
+        let _ = std::fs::remove_file(&output);
+        let mut executor = Executor::new();
+        executor.env_vars.insert("OUT".to_string(), output.display().to_string());
+
+        run(&mut executor, "parent() { echo parent >> $OUT; }; child() { echo child >> $OUT; }").unwrap();
+
+        run(&mut executor, "trap parent EXIT").unwrap();
+        // Subshells run their own EXIT trap, but not the parent's
+        run(&mut executor, "(trap child EXIT; true)").unwrap();
+        run(&mut executor, "(true)").unwrap();
+        assert_eq!(std::fs::read_to_string(&output).unwrap(), "child\n");
+
+        executor.run_exit_trap();
+        executor.run_exit_trap();
+        assert_eq!(std::fs::read_to_string(&output).unwrap(), "child\nparent\n");
+        let _ = std::fs::remove_file(&output);
+    }
+
+    #[test]
+    fn test_exit_signal_runs_exit_trap_in_interactive_shell() {
+        let _guard = FD_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let output = temp_path("exit-signal-trap");
+        let _ = std::fs::remove_file(&output);
+        let mut executor = Executor::new();
+        executor.env_vars.insert("OUT".to_string(), output.display().to_string());
+        let (ready, ready_write) = create_pipe().unwrap();
+
+        let pid = executor.fork().unwrap();
+        if pid == 0 {
+            // As the interactive shell sets itself up, without taking the terminal
+            executor.interactive = true;
+            signals::catch_interrupts();
+            signals::on_exit_signal(|_| {});
+            let _ = run(&mut executor, "trap 'echo bye > $OUT' EXIT");
+            unsafe { libc::write(ready_write, b"x".as_ptr().cast(), 1) };
+            // The line editor returns when interrupted
+            while !signals::interrupt_pending() {
+                std::thread::sleep(Duration::from_millis(10));
+            }
+            if let Some(signal) = signals::take_exit_signal() {
+                executor.die_from_signal(signal);
+            }
+            Executor::exit_child(0);
+        }
+        unsafe {
+            libc::close(ready_write);
+            libc::read(ready, [0u8; 1].as_mut_ptr().cast(), 1);
+            libc::close(ready);
+            libc::kill(pid, libc::SIGHUP);
+        }
+        let mut status = 0;
+        unsafe { libc::waitpid(pid, &mut status, 0) };
+        assert!(libc::WIFSIGNALED(status));
+        assert_eq!(libc::WTERMSIG(status), libc::SIGHUP);
+        assert_eq!(std::fs::read_to_string(&output).unwrap(), "bye\n");
+        let _ = std::fs::remove_file(&output);
+    }
+
+    #[test]
+    fn test_assertion_builtins() {
+        let mut executor = Executor::new();
+        // Only available in test mode
+        assert_eq!(run(&mut executor, "assert_eq a a").unwrap(), 127);
+
+        executor.enable_assertions();
+        run(&mut executor, "greet() { echo hello; echo; }").unwrap();
+        assert_eq!(run(&mut executor, "assert_eq a a").unwrap(), 0);
+        assert_eq!(run(&mut executor, "assert_eq a b message").unwrap(), 1);
+        assert_eq!(run(&mut executor, "assert_status 1 false").unwrap(), 0);
+        assert_eq!(run(&mut executor, "assert_status 0 false").unwrap(), 1);
+        assert_eq!(run(&mut executor, "assert_output hello greet").unwrap(), 0);
+        assert_eq!(run(&mut executor, "assert_output bye echo hi").unwrap(), 1);
+        assert_eq!(run(&mut executor, "assert_status").unwrap(), 2);
+
+        assert_eq!(executor.assertions(), Some(Assertions { passed: 3, failed: 4 }));
+    }
+
+    #[test]
+    fn test_read_builtin_splits_fields() {
+        assert_eq!(split_fields("  a  b c d ", 2), vec!["a", "b c d"]);
+        assert_eq!(split_fields("a b", 3), vec!["a", "b"]);
+        assert_eq!(split_fields("", 1), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_loop_shares_redirected_stdin() {
+        let _guard = FD_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let input = temp_path("loop-input");
+        let output = temp_path("loop-output");
+        std::fs::write(&input, "one 1\ntwo 2\nthree 3\n").unwrap();
+        let _ = std::fs::remove_file(&output);
+
+        let mut executor = Executor::new();
+        executor.env_vars.insert("IN".to_string(), input.display().to_string());
+        executor.env_vars.insert("OUT".to_string(), output.display().to_string());
+        run(&mut executor, "while read word number; do echo $number $word >> $OUT; done < $IN").unwrap();
+        assert_eq!(std::fs::read_to_string(&output).unwrap(), "1 one\n2 two\n3 three\n");
+
+        // A command in the body reading stdin consumes the loop's input
+        std::fs::remove_file(&output).unwrap();
+        run(&mut executor, "while read line; do echo $line >> $OUT; cat > /dev/null; done < $IN").unwrap();
+        assert_eq!(std::fs::read_to_string(&output).unwrap(), "one 1\n");
+        let _ = std::fs::remove_file(&input);
+        let _ = std::fs::remove_file(&output);
+    }
+
+    #[test]
+    fn test_heredoc_through_pipe_and_temp_file() {
+        let _guard = FD_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let path = temp_path("heredoc");
+        let mut executor = Executor::new();
+        executor.env_vars.insert("OUT".to_string(), path.display().to_string());
+        executor.env_vars.insert("NAME".to_string(), "world".to_string());
+
+        for pipe_max in [DEFAULT_HEREDOC_PIPE_MAX, 0] {
+            executor.heredoc_pipe_max = pipe_max;
+            run(&mut executor, "cat <<EOF > $OUT\nhello $NAME\n  second\nEOF").unwrap();
+            assert_eq!(std::fs::read_to_string(&path).unwrap(), "hello world\n  second\n");
+        }
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_heredocs_on_one_line() {
+        let _guard = FD_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let path = temp_path("heredocs-on-one-line");
+        let mut executor = Executor::new();
+        executor.env_vars.insert("OUT".to_string(), path.display().to_string());
+
+        // The last one for an fd wins, but each body is read in turn
+        run(&mut executor, "cat <<A <<B > $OUT\nfirst\nA\nsecond\nB").unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "second\n");
+        run(&mut executor, "cat <<A | cat - /dev/fd/3 3<<B > $OUT\none\nA\ntwo\nB").unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "one\ntwo\n");
+        run(&mut executor, "tr a-z A-Z <<A |\nbody\nA\ncat > $OUT").unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "BODY\n");
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_heredoc_expansion_follows_delimiter_quoting() {
+        let _guard = FD_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let path = temp_path("heredoc-quoting");
+        let mut executor = Executor::new();
+        executor.env_vars.insert("OUT".to_string(), path.display().to_string());
+        executor.set_variable("X", "value");
+
+        run(&mut executor, "cat <<EOF > $OUT\n$X $(echo sub) `echo back` \\$X \\\\ \\a 'q' \"q\"\njoined \\\nline\nEOF").unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "value sub back $X \\ \\a 'q' \"q\"\njoined line\n");
+        let literal = "$X $(echo sub) `echo back` \\$X \\\n";
+        for delimiter in ["'EOF'", "\"EOF\"", "\\EOF", "E\"O\"F"] {
+            run(&mut executor, &format!("cat <<{} >| $OUT\n{}EOF", delimiter, literal)).unwrap();
+            assert_eq!(std::fs::read_to_string(&path).unwrap(), literal, "<<{}", delimiter);
+        }
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_here_string() {
+        let _guard = FD_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let path = temp_path("here-string");
+        let mut executor = Executor::new();
+        executor.env_vars.insert("OUT".to_string(), path.display().to_string());
+        executor.env_vars.insert("LINES".to_string(), "one foo\ntwo  bar".to_string());
+
+        run(&mut executor, "grep foo <<< \"$LINES\" > $OUT").unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "one foo\n");
+        run(&mut executor, "cat <<< $LINES > $OUT").unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "one foo\ntwo  bar\n");
+        assert_eq!(parse_one("cat 3<<< word").to_string(), "cat 3<<< word");
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_large_heredoc_does_not_deadlock() {
+        let _guard = FD_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let path = temp_path("large-heredoc");
+        let line = "x".repeat(99) + "\n";
+        let body = line.repeat(20_000);
+
+        let mut executor = Executor::new();
+        executor.env_vars.insert("OUT".to_string(), path.display().to_string());
+        // Even with a limit above the pipe buffer, a full pipe falls back to a file
+        for pipe_max in [DEFAULT_HEREDOC_PIPE_MAX, usize::MAX] {
+            executor.heredoc_pipe_max = pipe_max;
+            run(&mut executor, &format!("wc -c <<EOF > $OUT\n{}EOF", body)).unwrap();
+            assert_eq!(std::fs::read_to_string(&path).unwrap().trim(), body.len().to_string());
+        }
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_time_keyword() {
+        let _guard = FD_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let path = temp_path("time");
+        let mut executor = Executor::new();
+        executor.env_vars.insert("LOG".to_string(), path.display().to_string());
+
+        assert_eq!(run(&mut executor, "{ time -p sleep 0.1 | false; } 2> $LOG").unwrap(), 1);
+        let report = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = report.lines().collect();
+        assert_eq!(lines.len(), 3);
+        let real: f64 = lines[0].strip_prefix("real ").unwrap().parse().unwrap();
+        assert!(real >= 0.1, "{}", report);
+        assert!(lines[1].starts_with("user ") && lines[2].starts_with("sys "));
+
+        assert_eq!(run(&mut executor, "{ time ! false; } 2> $LOG").unwrap(), 0);
+        let report = std::fs::read_to_string(&path).unwrap();
+        assert!(report.starts_with("\nreal\t0m0.0"), "{}", report);
+        assert_eq!(format_minutes(Duration::from_millis(62_345)), "1m2.345s");
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_pipefail_and_pipestatus() {
+        let mut executor = Executor::new();
+        assert_eq!(run(&mut executor, "false | true | false | true").unwrap(), 0);
+        assert_eq!(executor.get_variable("PIPESTATUS[@]"), "1 0 1 0");
+        assert_eq!(executor.get_variable("PIPESTATUS[2]"), "1");
+        assert_eq!(executor.get_variable("PIPESTATUS"), "1");
+        assert_eq!(executor.get_variable("PIPESTATUS[9]"), "");
+
+        run(&mut executor, "set -o pipefail").unwrap();
+        assert_eq!(run(&mut executor, "false | true").unwrap(), 1);
+        assert_eq!(run(&mut executor, "! false | true").unwrap(), 0);
+        assert_eq!(run(&mut executor, "true | true").unwrap(), 0);
+
+        // A single command, negated or not, records its own status
+        assert_eq!(run(&mut executor, "! false").unwrap(), 0);
+        assert_eq!(executor.get_variable("PIPESTATUS[@]"), "1");
+
+        run(&mut executor, "set +o pipefail").unwrap();
+        assert_eq!(run(&mut executor, "false | true").unwrap(), 0);
+        assert_eq!(run(&mut executor, "set -o nosuchoption").unwrap(), 1);
+    }
+
+    #[test]
+    fn test_shopt_glob_options() {
+        let _guard = FD_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let dir = temp_path("shopt");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        for file in ["a.txt", ".hidden", "sub/.b.txt"] {
+            std::fs::write(dir.join(file), "").unwrap();
+        }
+        let out = temp_path("shopt-out");
+        let mut executor = Executor::new();
+        executor.set_variable("DIR", &dir.display().to_string());
+        executor.set_variable("OUT", &out.display().to_string());
+        let names = |executor: &mut Executor, word: &str| -> Vec<String> {
+            let base = dir.display().to_string();
+            executor.expand_word(&format!("$DIR/{}", word)).unwrap().iter().map(|path| path.replacen(&base, "", 1)).collect()
+        };
+
+        assert_eq!(names(&mut executor, "*.none"), ["/*.none"]);
+        assert_eq!(run(&mut executor, "shopt -s nullglob").unwrap(), 0);
+        assert!(names(&mut executor, "*.none").is_empty());
+        assert_eq!(names(&mut executor, "*"), ["/a.txt", "/sub"]);
+        run(&mut executor, "shopt -s dotglob globstar").unwrap();
+        assert_eq!(names(&mut executor, "*"), ["/.hidden", "/a.txt", "/sub"]);
+        assert_eq!(names(&mut executor, "**/*.txt"), ["/a.txt", "/sub/.b.txt"]);
+
+        run(&mut executor, "shopt -s failglob").unwrap();
+        assert_eq!(executor.expand_word("$DIR/*.none").unwrap_err(), format!("no match: {}/*.none", dir.display()));
+        // Quoted, it isn't a pattern to fail
+        assert_eq!(executor.expand_word("\"$DIR/*.none\"").unwrap().len(), 1);
+        run(&mut executor, "shopt -u failglob; shopt -s nocaseglob").unwrap();
+        assert_eq!(names(&mut executor, "A.*"), ["/a.txt"]);
+        run(&mut executor, "shopt -s nocasematch; case README in read*) C=yes ;; esac").unwrap();
+        assert_eq!(executor.get_variable("C"), "yes");
+        run(&mut executor, "shopt -u nocaseglob nocasematch; case README in read*) D=yes ;; esac").unwrap();
+        assert_eq!(executor.get_variable("D"), "");
+
+        run(&mut executor, "shopt -u dotglob failglob; shopt -p > $OUT; shopt failglob nullglob >> $OUT").unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&out).unwrap(),
+            "shopt -u dotglob\nshopt -u extglob\nshopt -u failglob\nshopt -s globstar\nshopt -u nocaseglob\nshopt -u nocasematch\nshopt -s nullglob\nfailglob       \toff\nnullglob       \ton\n"
+        );
+        assert_eq!(run(&mut executor, "shopt -q nullglob globstar").unwrap(), 0);
+        assert_eq!(run(&mut executor, "shopt -q nullglob dotglob").unwrap(), 1);
+        assert_eq!(run(&mut executor, "shopt -s nosuchoption").unwrap(), 1);
+        assert_eq!(run(&mut executor, "shopt -x 2> /dev/null").unwrap(), 2);
+        run(&mut executor, "shopt -o -s pipefail").unwrap();
+        assert!(executor.pipefail);
+        let _ = std::fs::remove_dir_all(&dir);
+        let _ = std::fs::remove_file(&out);
+    }
+
+    #[test]
+    fn test_extglob() {
+        let dir = temp_path("extglob");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        for file in ["a.jpg", "b.png", "c.gif", ".d.png"] {
+            std::fs::write(dir.join(file), "").unwrap();
+        }
+        let mut executor = Executor::new();
+        executor.set_variable("DIR", &dir.display().to_string());
+        let names = |executor: &mut Executor, word: &str| -> Vec<String> {
+            let base = dir.display().to_string();
+            executor.expand_word(&format!("$DIR/{}", word)).unwrap().iter().map(|path| path.replacen(&base, "", 1)).collect()
+        };
+
+        // Off, the group is literal
+        assert_eq!(names(&mut executor, "*.@(jpg|png)"), ["/*.@(jpg|png)"]);
+        run(&mut executor, "shopt -s extglob").unwrap();
+        assert_eq!(names(&mut executor, "*.@(jpg|png)"), ["/a.jpg", "/b.png"]);
+        assert_eq!(names(&mut executor, "!(*.png)"), ["/a.jpg", "/c.gif"]);
+        assert_eq!(names(&mut executor, "\"*.@(jpg|png)\""), ["/*.@(jpg|png)"]);
+
+        let script = "case $NAME in +([0-9])) KIND=number ;; @(*.tar|*.zip)?(.gz)) KIND=archive ;; *) KIND=other ;; esac";
+        for (name, kind) in [("42", "number"), ("a.tar.gz", "archive"), ("a.zip", "archive"), ("4a", "other")] {
+            executor.set_variable("NAME", name);
+            run(&mut executor, script).unwrap();
+            assert_eq!(executor.get_variable("KIND"), kind);
+        }
+
+        run(&mut executor, "F=photo.jpeg; A=${F%.@(jpg|jpeg)}; B=${F##*([a-z])}; P='+(o)'; C=${F//$P/0}").unwrap();
+        assert_eq!(executor.get_variable("A"), "photo");
+        assert_eq!(executor.get_variable("B"), ".jpeg");
+        assert_eq!(executor.get_variable("C"), "ph0t0.jpeg");
+        // Quoted, the pattern characters match themselves
+        run(&mut executor, "G='@(x|y)'; case $G in \"@(x|y)\") H=literal ;; esac").unwrap();
+        assert_eq!(executor.get_variable("H"), "literal");
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_removal_risk() {
+        let words = |line: &str| line.split(' ').map(String::from).collect::<Vec<_>>();
+        assert_eq!(removal_risk(&words("rm -rf a b"), 2, 100, "/home/me"), None);
+        assert_eq!(removal_risk(&words("rm -rf a"), 101, 100, "/home/me").unwrap(), "rm: a word expanded to 101 paths");
+        assert_eq!(removal_risk(&words("/bin/rm -f -R /usr/"), 1, 100, "").unwrap(), "/bin/rm: /usr/ is a top-level or home directory");
+        assert!(removal_risk(&words("rm --recursive /"), 1, 100, "").is_some());
+        assert!(removal_risk(&words("rm -r /home/me/"), 1, 100, "/home/me").is_some());
+        // Not recursive, or not `rm` at all
+        assert_eq!(removal_risk(&words("rm -f /etc"), 500, 100, ""), None);
+        assert_eq!(removal_risk(&words("rm -- -r /etc"), 1, 100, ""), None);
+        assert_eq!(removal_risk(&words("cp -r /etc x"), 1, 100, ""), None);
+        assert_eq!(removal_risk(&words("rm -r /etc/x"), 1, 100, "/etc"), None);
+    }
+
+    #[test]
+    fn test_option_listing_and_flags() {
+        let _guard = FD_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let path = temp_path("set-o");
+        let mut executor = Executor::new();
+        executor.env_vars.insert("OUT".to_string(), path.display().to_string());
+        assert_eq!(executor.get_variable("-"), "");
+
+        run(&mut executor, "set -C -o pipefail; set +o > $OUT; F=$-").unwrap();
+        let listing = std::fs::read_to_string(&path).unwrap();
+        assert!(listing.contains("set -o noclobber\n"));
+        assert!(listing.contains("set -o pipefail\n"));
+        assert!(listing.contains("set +o globstar\n"));
+        assert!(listing.contains("set +o globguard\n"));
+        assert_eq!(executor.get_variable("F"), "C");
+        run(&mut executor, "set -o >| $OUT").unwrap();
+        assert!(std::fs::read_to_string(&path).unwrap().contains("noclobber      \ton\n"));
+
+        // What `set +o` prints puts the options back as they were
+        let mut other = Executor::new();
+        other.interactive = true;
+        std::fs::write(&path, listing).unwrap();
+        assert_eq!(other.source(&path.display().to_string()).ok(), Some(0));
+        assert!(other.noclobber && other.pipefail);
+        assert_eq!(other.get_variable("-"), "Ci");
+        run(&mut other, "case $- in *i*) I=yes;; esac; set +C").unwrap();
+        assert_eq!(other.get_variable("I"), "yes");
+        assert_eq!(other.expand_string("${-}").unwrap(), "i");
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_structured_pipelines() {
+        let _guard = FD_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let path = temp_path("structured");
+        std::fs::write(&path, r#"[{"name": "a", "size": 3}, {"name": "b", "size": 30}, {"name": "c", "size": 300}]"#).unwrap();
+        let mut executor = Executor::new();
+        executor.env_vars.insert("IN".to_string(), path.display().to_string());
+        executor.env_vars.insert("OUT".to_string(), temp_path("structured-out").display().to_string());
+
+        // Only there once the option is on, so `where` may still be a program
+        assert_eq!(run(&mut executor, "from-json < $IN 2> /dev/null").unwrap(), 127);
+        run(&mut executor, "set -o structured").unwrap();
+        run(&mut executor, "from-json < $IN | where size -ge 30 | select-field name | to-json > $OUT").unwrap();
+        assert_eq!(std::fs::read_to_string(temp_path("structured-out")).unwrap(), "[{\"name\":\"b\"},{\"name\":\"c\"}]\n");
+
+        assert_eq!(run(&mut executor, "where size 2> /dev/null").unwrap(), 2);
+        assert_eq!(run(&mut executor, "echo nope | to-json 2> /dev/null").unwrap(), 1);
+        let _ = std::fs::remove_file(&path);
+        let _ = std::fs::remove_file(temp_path("structured-out"));
+    }
+
+    #[test]
+    fn test_set_options_as_json() {
+        let _guard = FD_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let path = temp_path("set_json");
+        let mut executor = Executor::new();
+        executor.env_vars.insert("OUT".to_string(), path.display().to_string());
+
+        run(&mut executor, "set -o pipefail; set -o --json > $OUT").unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "{\"errtrace\":false,\"functrace\":false,\"globguard\":false,\"globstar\":false,\"noclobber\":false,\"pipefail\":true,\"structured\":false}\n");
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_declare_prints_variables() {
+        let _guard = FD_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let path = temp_path("declare");
+        let mut executor = Executor::new();
+        executor.env_vars.insert("OUT".to_string(), path.display().to_string());
+
+        run(&mut executor, "x='a \"b\" $c'").unwrap();
+        assert_eq!(run(&mut executor, "declare -p x unset_here > $OUT").unwrap(), 1);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "declare -- x=\"a \\\"b\\\" \\$c\"\n");
+        run(&mut executor, "declare --json x > $OUT").unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "{\"x\":\"a \\\"b\\\" $c\"}\n");
+        run(&mut executor, "declare > $OUT").unwrap();
+        assert!(std::fs::read_to_string(&path).unwrap().lines().any(|line| line.starts_with("declare -- OUT=")));
+        assert_eq!(run(&mut executor, "declare -x y").unwrap(), 2);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_directory_stack() {
+        let _guard = FD_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let original = std::env::current_dir().unwrap();
+        let dir = temp_path("dir-stack");
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        let dir = dir.canonicalize().unwrap();
+        let path = temp_path("dirs");
+        let mut executor = Executor::new();
+        executor.env_vars.insert("OUT".to_string(), path.display().to_string());
+        executor.env_vars.insert("HOME".to_string(), dir.display().to_string());
+        let output = || std::fs::read_to_string(&path).unwrap();
+
+        run(&mut executor, "pushd $HOME > $OUT").unwrap();
+        run(&mut executor, "pushd sub > $OUT").unwrap();
+        assert_eq!(output(), format!("~/sub ~ {}\n", original.display()));
+        run(&mut executor, "dirs -v > $OUT").unwrap();
+        assert_eq!(output(), format!(" 0  ~/sub\n 1  ~\n 2  {}\n", original.display()));
+        run(&mut executor, "dirs -lp > $OUT").unwrap();
+        assert_eq!(output(), format!("{}\n{}\n{}\n", dir.join("sub").display(), dir.display(), original.display()));
+        run(&mut executor, "dirs --json > $OUT").unwrap();
+        let json: serde_json::Value = serde_json::from_str(&output()).unwrap();
+        assert_eq!(json, serde_json::json!([dir.join("sub"), dir, original]));
+
+        // Without a directory, pushd swaps the top two
+        run(&mut executor, "pushd > $OUT").unwrap();
+        assert_eq!(std::env::current_dir().unwrap(), dir);
+        run(&mut executor, "popd > $OUT").unwrap();
+        assert_eq!(std::env::current_dir().unwrap(), dir.join("sub"));
+        run(&mut executor, "popd > $OUT").unwrap();
+        assert_eq!(std::env::current_dir().unwrap(), original);
+        assert_eq!(run(&mut executor, "popd 2> /dev/null").unwrap(), 1);
+        assert_eq!(run(&mut executor, "pushd 2> /dev/null").unwrap(), 1);
+        assert_eq!(run(&mut executor, "pushd /nonexistent 2> /dev/null").unwrap(), 1);
+        assert_eq!(run(&mut executor, "dirs -x 2> /dev/null").unwrap(), 2);
+
+        std::env::set_current_dir(&original).unwrap();
+        let _ = std::fs::remove_file(&path);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_expand_word() {
+        let dir = temp_path("expand");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        for file in ["a.conf", "b.conf", "c.txt"] {
+            std::fs::write(dir.join(file), "").unwrap();
+        }
+        let dir = dir.display().to_string();
+
+        let mut executor = Executor::new();
+        executor.set_variable("HOME", &dir);
+        executor.set_variable("NAMES", "one two");
+        assert_eq!(executor.expand_word("~/*.conf").unwrap(), [format!("{}/a.conf", dir), format!("{}/b.conf", dir)]);
+        assert_eq!(executor.expand_word("$NAMES").unwrap(), ["one", "two"]);
+        assert_eq!(executor.expand_word("{a,b}$NAMES").unwrap(), ["aone", "two", "bone", "two"]);
+        assert_eq!(executor.expand_word("~/{a,c}.*").unwrap(), [format!("{}/a.conf", dir), format!("{}/c.txt", dir)]);
+        assert_eq!(executor.expand_word("~/*.none").unwrap(), [format!("{}/*.none", dir)]);
+        assert_eq!(executor.expand_word("~root/x").unwrap()[0], format!("{}/x", home_dir("root").unwrap()));
+        assert_eq!(executor.expand_word("~nosuchuser/x").unwrap(), ["~nosuchuser/x"]);
+        assert_eq!(executor.expand_assignment("~/bin:~/sbin").unwrap(), format!("{0}/bin:{0}/sbin", dir));
+
+        executor.set_variable("DIR", &dir);
+        run(&mut executor, "for f in $DIR/*.txt; do FOUND=$f; done").unwrap();
+        assert_eq!(executor.get_variable("FOUND"), format!("{}/c.txt", dir));
+
+        executor.set_variable("CLAM_MAX_EXPANSION", "2");
+        assert!(executor.expand_word("$DIR/*").is_err());
+        assert!(run(&mut executor, "echo $DIR/*").is_err());
+        executor.set_variable("CLAM_MAX_EXPANSION", "3");
+        assert!(executor.expand_word("{1..4}").is_err());
+        executor.set_variable("CLAM_GLOB_MAX_ENTRIES", "2");
+        assert!(executor.expand_word("$DIR/*").is_err());
+        assert_eq!(executor.expand_word("$DIR/a.conf").unwrap().len(), 1);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_quoting() {
+        let dir = temp_path("quoting");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("a.txt"), "").unwrap();
+        let dir = dir.display().to_string();
+
+        let mut executor = Executor::new();
+        executor.set_variable("HOME", &dir);
+        executor.set_variable("X", "1   2");
+        let expand = |executor: &mut Executor, word: &str| executor.expand_word(word).unwrap();
+        assert_eq!(expand(&mut executor, "\"$HOME dir\""), [format!("{} dir", dir)]);
+        assert_eq!(expand(&mut executor, "'$HOME ~'"), ["$HOME ~"]);
+        assert_eq!(expand(&mut executor, "$X"), ["1", "2"]);
+        assert_eq!(expand(&mut executor, "\"$X\""), ["1   2"]);
+        assert_eq!(expand(&mut executor, "\"~/*.txt\""), ["~/*.txt"]);
+        assert_eq!(expand(&mut executor, "~/\\*.txt"), [format!("{}/*.txt", dir)]);
+        assert_eq!(expand(&mut executor, "\"\\$X \\a\""), ["$X \\a"]);
+        assert_eq!(expand(&mut executor, "${UNSET:-\"a  b\"}"), ["a  b"]);
+        assert_eq!(expand(&mut executor, "\"{a,b}\""), ["{a,b}"]);
+        assert_eq!(expand(&mut executor, "''"), [""]);
+        assert_eq!(expand(&mut executor, "\"$UNSET\""), [""]);
+        assert!(expand(&mut executor, "$UNSET").is_empty());
+        assert_eq!(expand(&mut executor, "${UNSET:-\"\"}x\"\""), ["x"]);
+
+        executor.set_positional(vec!["a".to_string(), "b  c".to_string(), String::new()]);
+        assert_eq!(expand(&mut executor, "\"$@\""), ["a", "b  c", ""]);
+        assert_eq!(expand(&mut executor, "\"<${@}>\""), ["<a", "b  c", ">"]);
+        assert_eq!(expand(&mut executor, "$@"), ["a", "b", "c"]);
+        assert_eq!(expand(&mut executor, "\"$*\""), ["a b  c "]);
+        assert_eq!(executor.expand_string("\"$@\"").unwrap(), "a b  c ");
+        executor.set_positional(Vec::new());
+        assert!(expand(&mut executor, "\"$@\"").is_empty());
+        assert_eq!(expand(&mut executor, "\"$*\""), [""]);
+        assert_eq!(expand(&mut executor, "x\"$@\""), ["x"]);
+        assert_eq!(executor.expand_assignment("\"$X\":'$X'").unwrap(), "1   2:$X");
+
+        run(&mut executor, "for f in \"$HOME\"/*.txt '*'; do LAST=$f; done").unwrap();
+        assert_eq!(executor.get_variable("LAST"), "*");
+        run(&mut executor, "case \"$X\" in '1*') M=no;; \"1   2\") M=yes;; esac").unwrap();
+        assert_eq!(executor.get_variable("M"), "yes");
+        run(&mut executor, "V=\"$X\"; W=${V#'1'}").unwrap();
+        assert_eq!(executor.get_variable("W"), "   2");
+        run(&mut executor, "J=a\"$X\"'$X'; for f in \"$HOME\"/'a'.*; do K=$f; done").unwrap();
+        assert_eq!(executor.get_variable("J"), "a1   2$X");
+        assert_eq!(executor.get_variable("K"), format!("{}/a.txt", dir));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_parameter_substitution() {
+        let mut executor = Executor::new();
+        executor.interactive = true;
+        executor.set_variable("EMPTY", "");
+        executor.set_variable("SET", "value");
+        let expand = |executor: &mut Executor, word: &str| executor.expand_variables(word).unwrap();
+
+        assert_eq!(expand(&mut executor, "${UNSET_VAR:-a b}/${EMPTY:-x}/${EMPTY-x}/${SET:-x}"), "a b/x//value");
+        assert_eq!(expand(&mut executor, "${UNSET_VAR:-${SET:-no}}"), "value");
+        assert_eq!(expand(&mut executor, "${SET:+alt}/${EMPTY:+alt}/${EMPTY+alt}/${UNSET_VAR+alt}"), "alt//alt/");
+        assert_eq!(expand(&mut executor, "${1:-none} ${#:-x}"), "none 0");
+
+        assert_eq!(expand(&mut executor, "${ASSIGNED:=$SET}"), "value");
+        assert_eq!(executor.get_variable("ASSIGNED"), "value");
+        assert_eq!(expand(&mut executor, "${EMPTY=kept}"), "");
+        assert!(executor.expand_variables("${1:=x}").is_err());
+
+        assert_eq!(expand(&mut executor, "${SET:?unused}"), "value");
+        assert_eq!(executor.expand_variables("${EMPTY:?is empty}"), Err("EMPTY: is empty".to_string()));
+        assert_eq!(executor.expand_variables("${UNSET_VAR?}"), Err("UNSET_VAR: parameter null or not set".to_string()));
+        assert!(executor.expand_variables("${SET;x}").is_err());
+
+        executor.set_variable("WORD", "héllo world");
+        executor.set_variable("N", "2");
+        assert_eq!(expand(&mut executor, "${#WORD} ${#UNSET_VAR} ${#} ${#PIPESTATUS[@]}"), "11 0 0 0");
+        assert_eq!(expand(&mut executor, "${WORD:6}|${WORD:1:4}|${WORD: -5}|${WORD:N*3:-2}"), "world|éllo|world|wor");
+        assert_eq!(expand(&mut executor, "${WORD: -50}|${WORD:50}|${WORD:0:0}|${WORD:(-5):2}"), "|||wo");
+        assert!(executor.expand_variables("${WORD:8:-5}").is_err());
+        assert!(executor.expand_variables("${WORD:1 +}").is_err());
+
+        executor.set_variable("FILE", "/srv/www/index.tar.gz");
+        executor.set_variable("DIR", "/srv");
+        assert_eq!(expand(&mut executor, "${FILE##*/} ${FILE%/*} ${FILE%.*} ${FILE%%.*}"), "index.tar.gz /srv/www /srv/www/index.tar /srv/www/index");
+        assert_eq!(expand(&mut executor, "${FILE#$DIR/} ${FILE#*[.]} ${FILE#nope} ${UNSET_VAR%x}"), "www/index.tar.gz tar.gz /srv/www/index.tar.gz ");
+
+        executor.set_variable("OLD", "www");
+        assert_eq!(expand(&mut executor, "${FILE/$OLD/web} ${FILE//[.]/_} ${FILE/#\\/srv/~} ${FILE/%.gz} ${FILE/q/y}"), "/srv/web/index.tar.gz /srv/www/index_tar_gz ~/www/index.tar.gz /srv/www/index.tar /srv/www/index.tar.gz");
+
+        executor.set_variable("ANSWER", "yes please");
+        assert_eq!(expand(&mut executor, "${ANSWER^} ${ANSWER^^} ${ANSWER^^[ep]} ${SET,,} ${NAME,}"), "Yes please YES PLEASE yEs PlEasE value ");
+        executor.set_variable("NAME", "ADA");
+        assert_eq!(expand(&mut executor, "${NAME,} ${NAME,,[!D]}"), "aDA aDa");
+
+        // The error abandons the command
+        assert!(run(&mut executor, "echo ${UNSET_VAR:?}; RAN=1").is_err());
+        assert_eq!(executor.get_variable("RAN"), "");
+    }
+
+    #[test]
+    fn test_with_builtin() {
+        let _guard = FD_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let path = temp_path("with");
+        let mut executor = Executor::new();
+        executor.env_vars.insert("FILE".to_string(), path.display().to_string());
+        executor.env_vars.insert("GREETING".to_string(), "hello".to_string());
+        let cwd = std::env::current_dir().unwrap();
+
+        run(&mut executor, "with --cwd / --env GREETING=hi --env EXTRA=1 --umask 027 -- sh -c 'pwd; echo $GREETING $EXTRA; umask' > $FILE").unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "/\nhi 1\n0027\n");
+        assert_eq!(std::env::current_dir().unwrap(), cwd);
+        assert_eq!(executor.get_variable("GREETING"), "hello");
+        assert_eq!(run(&mut executor, "with sh -c 'exit 3'").unwrap(), 3);
+
+        assert_eq!(run(&mut executor, "with --cwd /no/such/dir true 2> /dev/null").unwrap(), 1);
+        assert_eq!(run(&mut executor, "with --env 1=x true 2> /dev/null").unwrap(), 1);
+        assert_eq!(run(&mut executor, "with --umask 999 true 2> /dev/null").unwrap(), 1);
+        assert_eq!(run(&mut executor, "with --cwd 2> /dev/null").unwrap(), 2);
+        assert_eq!(run(&mut executor, "with --nice 5 true 2> /dev/null").unwrap(), 2);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_exec() {
+        let _guard = FD_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let path = temp_path("exec");
+        let script = temp_path("exec.sh");
+        std::fs::write(&script, "echo replaced\nexit 7\n").unwrap();
+
+        let mut executor = Executor::new();
+        executor.env_vars.insert("LOG".to_string(), path.display().to_string());
+        executor.env_vars.insert("SCRIPT".to_string(), script.display().to_string());
+
+        // Without a command the redirections outlive the line
+        run(&mut executor, "exec 7> $LOG").unwrap();
+        run(&mut executor, "echo kept >&7").unwrap();
+        run(&mut executor, "exec 7>&-").unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "kept\n");
+        assert_eq!(unsafe { libc::fcntl(7, libc::F_GETFD) }, -1);
+
+        // The subshell becomes the script, so nothing after exec runs
+        assert_eq!(run(&mut executor, "(exec sh $SCRIPT > $LOG; echo after > $LOG)").unwrap(), 7);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "replaced\n");
+        assert_eq!(run(&mut executor, "(exec -c env > $LOG)").unwrap(), 0);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "");
+        assert_eq!(run(&mut executor, "(exec nosuchcommand; true) 2> /dev/null").unwrap(), 127);
+        let _ = std::fs::remove_file(&path);
+        let _ = std::fs::remove_file(&script);
+    }
+
+    #[test]
+    fn test_dynamic_variables() {
+        let mut executor = Executor::new();
+        let mut other = Executor::new();
+        run(&mut executor, "RANDOM=7").unwrap();
+        other.set_variable("RANDOM", "7");
+        let numbers = executor.get_variable("RANDOM") + " " + &executor.get_variable("RANDOM");
+        assert_eq!(numbers, other.get_variable("RANDOM") + " " + &other.get_variable("RANDOM"));
+        assert_eq!(executor.env_vars.get("RANDOM"), None);
+
+        run(&mut executor, "SECONDS=100").unwrap();
+        assert_eq!(executor.get_variable("SECONDS"), "100");
+
+        // Children don't repeat each other's numbers, reproducibly so in
+        // deterministic mode
+        // Two numbers each, so that a chance match is all but impossible
+        let substitutions = "A=$(echo $RANDOM$RANDOM) B=$(echo $RANDOM$RANDOM) C=$RANDOM$RANDOM";
+        let numbers = |executor: &Executor| ["A", "B", "C"].map(|name| executor.get_variable(name));
+        run(&mut executor, substitutions).unwrap();
+        let [a, b, c] = numbers(&executor);
+        assert!(a != b && b != c && a != c);
+        executor.dynamic = DynamicVariables::deterministic(7, Duration::ZERO);
+        other.dynamic = DynamicVariables::deterministic(7, Duration::ZERO);
+        run(&mut executor, substitutions).unwrap();
+        run(&mut other, substitutions).unwrap();
+        let [a, b, c] = numbers(&executor);
+        assert!(a != b && b != c && a != c);
+        assert_eq!(numbers(&other), [a, b, c]);
+
+        executor.set_variable("TMPDIR", "/var/tmp/clam");
+        assert_eq!(executor.temp_dir(), Path::new("/var/tmp/clam"));
+    }
+
+    #[test]
+    fn test_noclobber() {
+        let _guard = FD_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let path = temp_path("noclobber");
+        std::fs::write(&path, "original\n").unwrap();
+
+        let mut executor = Executor::new();
+        executor.env_vars.insert("FILE".to_string(), path.display().to_string());
+        run(&mut executor, "set -C").unwrap();
+        assert_eq!(run(&mut executor, "echo replaced 2> /dev/null > $FILE").unwrap(), 1);
+        assert_eq!(run(&mut executor, "{ echo replaced &> $FILE; } 2> /dev/null").unwrap(), 1);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "original\n");
+
+        // Only the command with the refused redirection fails
+        run(&mut executor, "set -C; echo a 2> /dev/null > $FILE || STATUS=refused; NEXT=ran").unwrap();
+        assert_eq!(executor.get_variable("STATUS"), "refused");
+        assert_eq!(executor.get_variable("NEXT"), "ran");
+        run(&mut executor, "if echo a 2> /dev/null > $FILE; then BRANCH=then; else BRANCH=else; fi").unwrap();
+        assert_eq!(executor.get_variable("BRANCH"), "else");
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "original\n");
+        assert_eq!(run(&mut executor, "echo appended >> $FILE").unwrap(), 0);
+        assert_eq!(run(&mut executor, "echo discarded > /dev/null").unwrap(), 0);
+
+        assert_eq!(run(&mut executor, "echo forced >| $FILE").unwrap(), 0);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "forced\n");
+
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(run(&mut executor, "echo created > $FILE").unwrap(), 0);
+        run(&mut executor, "set +o noclobber").unwrap();
+        assert_eq!(run(&mut executor, "echo again > $FILE").unwrap(), 0);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "again\n");
+        assert_eq!(run(&mut executor, "set -Z").unwrap(), 2);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_command_substitution() {
+        let _guard = FD_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let path = temp_path("substitution");
+        let mut executor = Executor::new();
+        executor.env_vars.insert("OUT".to_string(), path.display().to_string());
+
+        run(&mut executor, "NOW=$(printf 'two\\n\\n')").unwrap();
+        assert_eq!(executor.get_variable("NOW"), "two");
+        run(&mut executor, "NESTED=x$(echo $(echo inner) outer)y").unwrap();
+        assert_eq!(executor.get_variable("NESTED"), "xinner outery");
+        run(&mut executor, "echo \"today is $(echo Monday)\" > $OUT").unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "today is Monday\n");
+
+        // The subshell's variables stay there
+        run(&mut executor, "SEEN=$(INSIDE=1; echo $INSIDE)").unwrap();
+        assert_eq!(executor.get_variable("SEEN"), "1");
+        assert_eq!(executor.get_variable("INSIDE"), "");
+
+        assert_eq!(run(&mut executor, "STATUS=$(false)").unwrap(), 1);
+        assert_eq!(run(&mut executor, "$(true)").unwrap(), 0);
+        assert_eq!(run(&mut executor, "for word in $(echo a b c); do LAST=$word; done").unwrap(), 0);
+        assert_eq!(executor.get_variable("LAST"), "c");
+        assert!(run(&mut executor, "echo $(if)").is_err());
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_command_substitution_environment() {
+        let _guard = FD_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let mut executor = Executor::new();
+        run(&mut executor, "GREETING=hello").unwrap();
+        run(&mut executor, "FIRST=$(printenv GREETING); SECOND=$(printenv GREETING)").unwrap();
+        assert_eq!(executor.get_variable("FIRST"), "hello");
+        assert_eq!(executor.get_variable("SECOND"), "hello");
+
+        // A change in between, in the shell or the child, is seen
+        run(&mut executor, "GREETING=bye; THIRD=$(printenv GREETING)").unwrap();
+        assert_eq!(executor.get_variable("THIRD"), "bye");
+        run(&mut executor, "FOURTH=$(GREETING=inner; printenv GREETING)").unwrap();
+        assert_eq!(executor.get_variable("FOURTH"), "inner");
+        run(&mut executor, "FIFTH=$(GREETING=once printenv GREETING; printenv GREETING)").unwrap();
+        assert_eq!(executor.get_variable("FIFTH"), "once\nbye");
+        assert!(std::env::var_os("GREETING").is_none());
+    }
+
+    #[test]
+    fn test_process_substitution() {
+        let _guard = FD_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let path = temp_path("process-substitution");
+        let mut executor = Executor::new();
+        executor.env_vars.insert("OUT".to_string(), path.display().to_string());
+
+        run(&mut executor, "cat <(echo one) <(printf 'two\\n') > $OUT").unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "one\ntwo\n");
+        assert_eq!(run(&mut executor, "cmp -s <(echo {a,b}) <(echo a b)").unwrap(), 0);
+
+        // The shell itself reads it when it is redirected from
+        run(&mut executor, "n=0; while read line; do (( ++n )); done < <(printf 'a\\nb\\nc\\n')").unwrap();
+        assert_eq!(executor.get_variable("n"), "3");
+
+        // Writing to it is done by the time the command is
+        run(&mut executor, "echo hello > >(tr a-z A-Z > $OUT)").unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "HELLO\n");
+
+        // Helpers that never finish on their own don't hold the shell up
+        assert_eq!(run(&mut executor, "true <(sleep 10)").unwrap(), 0);
+        assert!(executor.process_substitutions.is_empty());
+        assert!(run(&mut executor, "cat <(if)").is_err());
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_process_substitution_fifo() {
+        let _guard = FD_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let path = temp_path("process-substitution-fifo");
+        let mut executor = Executor::new();
+        executor.has_dev_fd = false;
+        executor.env_vars.insert("OUT".to_string(), path.display().to_string());
+
+        run(&mut executor, "cat <(echo one) <(printf 'two\\n') > $OUT").unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "one\ntwo\n");
+        run(&mut executor, "n=0; while read line; do (( ++n )); done < <(printf 'a\\nb\\n')").unwrap();
+        assert_eq!(executor.get_variable("n"), "2");
+        run(&mut executor, "echo hello > >(tr a-z A-Z > $OUT)").unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "HELLO\n");
+        // Nor does a FIFO the command never opens hold the shell up
+        assert_eq!(run(&mut executor, "true <(sleep 10) >(cat)").unwrap(), 0);
+
+        // The FIFOs go as their commands finish, and their directory as the
+        // shell exits
+        let dir = executor.fifo_dir.clone().unwrap();
+        assert_eq!(std::os::unix::fs::PermissionsExt::mode(&std::fs::metadata(&dir).unwrap().permissions()) & 0o777, 0o700);
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+        run(&mut executor, "echo <(true) > $OUT").unwrap();
+        assert!(std::fs::read_to_string(&path).unwrap().starts_with(&dir.display().to_string()));
+
+        executor.env_vars.insert(FIFO_MAX_VAR.to_string(), "1".to_string());
+        assert_eq!(run(&mut executor, "cat <(true)").unwrap(), 0);
+        assert!(run(&mut executor, "cat <(true) <(true)").is_err());
+        assert!(executor.process_substitutions.is_empty());
+        executor.clean_up_on_exit();
+        assert!(!dir.exists());
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_fifo_dir_removed_on_fatal_signal() {
+        let _guard = FD_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let path = temp_path("fifo-fatal-signal");
+        let mut executor = Executor::new();
+        executor.has_dev_fd = false;
+        executor.env_vars.insert("OUT".to_string(), path.display().to_string());
+
+        // A script without an EXIT trap, killed after making a FIFO
+        let pid = executor.fork().unwrap();
+        if pid == 0 {
+            let _ = run(&mut executor, "echo <(true) > $OUT");
+            unsafe { libc::kill(libc::getpid(), libc::SIGTERM) };
+            let _ = run(&mut executor, "true");
+            Executor::exit_child(0);
+        }
+        let mut status = 0;
+        unsafe { libc::waitpid(pid, &mut status, 0) };
+        assert!(libc::WIFSIGNALED(status));
+        assert_eq!(libc::WTERMSIG(status), libc::SIGTERM);
+        let fifo = std::fs::read_to_string(&path).unwrap();
+        assert!(!std::path::Path::new(fifo.trim_end()).parent().unwrap().exists());
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_arithmetic_command() {
+        let _guard = FD_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let mut executor = Executor::new();
+        executor.env_vars.insert("i".to_string(), "1".to_string());
+        assert_eq!(run(&mut executor, "(( i++ ))").unwrap(), 0);
+        assert_eq!(executor.get_variable("i"), "2");
+        assert_eq!(run(&mut executor, "(( i -= 2 ))").unwrap(), 1);
+        assert_eq!(executor.get_variable("i"), "0");
+
+        run(&mut executor, "x=12; if (( x > 10 )); then SIZE=big; fi").unwrap();
+        assert_eq!(executor.get_variable("SIZE"), "big");
+        run(&mut executor, "n=0; while (( n < 5 )); do (( n += 2 )); done").unwrap();
+        assert_eq!(executor.get_variable("n"), "6");
+        assert_eq!(run(&mut executor, "(( $x == $(echo 12) ))").unwrap(), 0);
+        assert_eq!(run(&mut executor, "(( 1 / 0 ))").unwrap(), 1);
+        assert_eq!(run(&mut executor, "(( ))").unwrap(), 1);
+    }
+
+    #[test]
+    fn test_let_builtin() {
+        let mut executor = Executor::new();
+        assert_eq!(run(&mut executor, "let x=4 y=x+1 y*=2").unwrap(), 0);
+        assert_eq!(executor.get_variable("x"), "4");
+        assert_eq!(executor.get_variable("y"), "10");
+        assert_eq!(run(&mut executor, "let x=x-4").unwrap(), 1);
+        assert_eq!(executor.get_variable("x"), "0");
+        assert_eq!(run(&mut executor, "let 0 1").unwrap(), 0);
+        assert_eq!(run(&mut executor, "let y/x y=1").unwrap(), 1);
+        assert_eq!(executor.get_variable("y"), "10");
+        assert_eq!(run(&mut executor, "let").unwrap(), 2);
+    }
+
+    #[test]
+    fn test_repeat_builtin() {
+        let mut executor = Executor::new();
+        run(&mut executor, "n=0; count() { (( n++ )); }").unwrap();
+        assert_eq!(run(&mut executor, "repeat -n 0 -t 3 count").unwrap(), 0);
+        assert_eq!(executor.get_variable("n"), "3");
+
+        // -e stops at the first failure, with its status
+        run(&mut executor, "n=0; below() { (( n++ < $1 )); }").unwrap();
+        assert_eq!(run(&mut executor, "repeat -n 0.001 -e -- below 2").unwrap(), 1);
+        assert_eq!(executor.get_variable("n"), "3");
+
+        assert_eq!(run(&mut executor, "repeat -n -1 count").unwrap(), 2);
+        assert_eq!(run(&mut executor, "repeat -t 2").unwrap(), 2);
+    }
+
+    #[test]
+    fn test_parallel_for() {
+        let _guard = FD_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let path = temp_path("parallel_for");
+        let mut executor = Executor::new();
+        executor.env_vars.insert("OUT".to_string(), path.display().to_string());
+
+        // Output comes in the order of the words, whichever finishes first
+        let status = run(&mut executor, "for -P 3 n in 3 1 2; do sleep 0.0$n; echo $n; test $n -ne 1; done > $OUT");
+        assert_eq!(status.unwrap(), 1);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "3\n1\n2\n");
+        assert_eq!(executor.get_variable("PIPESTATUS[@]"), "0 1 0");
+        // Each iteration runs in a subshell
+        assert_eq!(executor.get_variable("n"), "");
+        assert!(executor.jobs.jobs().is_empty());
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_calc_builtin() {
+        let _guard = FD_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let path = temp_path("calc");
+        let mut executor = Executor::new();
+        executor.env_vars.insert("OUT".to_string(), path.display().to_string());
+        executor.env_vars.insert("price".to_string(), "19.99".to_string());
+
+        run(&mut executor, "calc -v total -f %.2f price + price / 10").unwrap();
+        assert_eq!(executor.get_variable("total"), "21.99");
+        run(&mut executor, "calc 1 / 3 + -0.5 > $OUT").unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "-0.166667\n");
+
+        assert_eq!(run(&mut executor, "calc -f %d 1 2> /dev/null").unwrap(), 1);
+        assert_eq!(run(&mut executor, "calc 1 / 0 2> /dev/null").unwrap(), 1);
+        assert_eq!(run(&mut executor, "calc -v x 2> /dev/null").unwrap(), 2);
+        assert_eq!(run(&mut executor, "calc 2> /dev/null").unwrap(), 2);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_str_builtin() {
+        let _guard = FD_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let path = temp_path("str");
+        let mut executor = Executor::new();
+        executor.env_vars.insert("FILE".to_string(), path.display().to_string());
+        executor.env_vars.insert("NAME".to_string(), "Clam Shell".to_string());
+
+        run(&mut executor, "str -v upper upper $NAME").unwrap();
+        assert_eq!(executor.get_variable("upper"), "CLAM SHELL");
+        run(&mut executor, "str -v joined join - a b c").unwrap();
+        assert_eq!(executor.get_variable("joined"), "a-b-c");
+        run(&mut executor, "str replace / . usr/local/bin > $FILE").unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "usr.local.bin\n");
+
+        // Without operands, each line of stdin
+        std::fs::write(&path, "  one \ntwo\n").unwrap();
+        run(&mut executor, "str -v trimmed trim < $FILE").unwrap();
+        assert_eq!(executor.get_variable("trimmed"), "one\ntwo");
+        run(&mut executor, "str -v line join + < $FILE").unwrap();
+        assert_eq!(executor.get_variable("line"), "  one +two");
+
+        assert_eq!(run(&mut executor, "str 2> /dev/null").unwrap(), 2);
+        assert_eq!(run(&mut executor, "str reverse x 2> /dev/null").unwrap(), 2);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_path_builtin() {
+        let _guard = FD_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let path = temp_path("path");
+        let mut executor = Executor::new();
+        executor.env_vars.insert("FILE".to_string(), path.display().to_string());
+
+        run(&mut executor, "path -v base basename /srv/www/index.html").unwrap();
+        assert_eq!(executor.get_variable("base"), "index.html");
+        run(&mut executor, "path -v rel relative /srv/www/img /srv/cgi-bin/").unwrap();
+        assert_eq!(executor.get_variable("rel"), "../www/img");
+        run(&mut executor, "path dirname /srv/www/ lib > $FILE").unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "/srv\n.\n");
+        run(&mut executor, "path normalize /x/../y a//b/ > $FILE").unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "/y\na/b\n");
+        run(&mut executor, "path -v joined join /srv www/ ../index.html").unwrap();
+        assert_eq!(executor.get_variable("joined"), "/srv/www/../index.html");
+
+        assert_eq!(run(&mut executor, "path ext 2> /dev/null").unwrap(), 2);
+        assert_eq!(run(&mut executor, "path stem x 2> /dev/null").unwrap(), 2);
+        assert_eq!(run(&mut executor, "path relative a b c 2> /dev/null").unwrap(), 2);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_sleep_builtin() {
+        assert_eq!(parse_interval("0.25"), Some(Duration::from_millis(250)));
+        assert_eq!(parse_interval("1.5m"), Some(Duration::from_secs(90)));
+        assert_eq!(parse_interval("2h"), Some(Duration::from_secs(7200)));
+        assert_eq!(parse_interval("1d"), Some(Duration::from_secs(86400)));
+        assert_eq!(parse_interval(".5s"), Some(Duration::from_millis(500)));
+        for invalid in ["", "s", "-1", "1x", "inf", "nan", "1e400"] {
+            assert_eq!(parse_interval(invalid), None, "{}", invalid);
+        }
+
+        let mut executor = Executor::new();
+        let start = Instant::now();
+        assert_eq!(run(&mut executor, "sleep 0.05 0.05s").unwrap(), 0);
+        assert!(start.elapsed() >= Duration::from_millis(100));
+        assert_eq!(run(&mut executor, "sleep 2> /dev/null").unwrap(), 2);
+        assert_eq!(run(&mut executor, "sleep 1 x 2> /dev/null").unwrap(), 1);
+    }
+
+    #[test]
+    fn test_datetime_builtin() {
+        let _guard = FD_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let path = temp_path("datetime");
+        let mut executor = Executor::new();
+        executor.env_vars.insert("FILE".to_string(), path.display().to_string());
+
+        run(&mut executor, "datetime --epoch 1700000000 -u +%F_%H:%M > $FILE").unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "2023-11-14_22:13\n");
+        run(&mut executor, "datetime +%Y > $FILE").unwrap();
+        assert!(std::fs::read_to_string(&path).unwrap().trim().parse::<i32>().unwrap() >= 2023);
+
+        assert_eq!(run(&mut executor, "datetime --epoch 2> /dev/null").unwrap(), 2);
+        assert_eq!(run(&mut executor, "datetime -x 2> /dev/null").unwrap(), 2);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_loadenv_builtin() {
+        let _guard = FD_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let env = temp_path("loadenv.env");
+        let path = temp_path("loadenv");
+        std::fs::write(&env, "# app\nexport HOST=example.com\nGREETING='hi $(rm -rf x)'\nKEY=\"a\nb\"\n").unwrap();
+        let mut executor = Executor::new();
+        executor.env_vars.insert("ENV".to_string(), env.display().to_string());
+        executor.env_vars.insert("FILE".to_string(), path.display().to_string());
+        executor.set_variable("HOST", "example.com");
+
+        run(&mut executor, "loadenv -x --print $ENV > $FILE").unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "export GREETING='hi $(rm -rf x)'\nexport KEY='a\nb'\n");
+        assert!(!executor.is_set("GREETING"));
+
+        assert_eq!(run(&mut executor, "loadenv $ENV").unwrap(), 0);
+        assert_eq!(executor.get_variable("GREETING"), "hi $(rm -rf x)");
+        assert_eq!(executor.get_variable("KEY"), "a\nb");
+        run(&mut executor, "loadenv --print $ENV > $FILE").unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "");
+
+        std::fs::write(&env, "not an assignment\n").unwrap();
+        assert_eq!(run(&mut executor, "loadenv $ENV 2> /dev/null").unwrap(), 1);
+        assert_eq!(run(&mut executor, "loadenv 2> /dev/null").unwrap(), 2);
+        assert_eq!(run(&mut executor, "loadenv -q $ENV 2> /dev/null").unwrap(), 2);
+        let _ = std::fs::remove_file(&env);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_retry_builtin() {
+        let mut executor = Executor::new();
+        run(&mut executor, "n=0; flaky() { (( ++n >= $1 )); }").unwrap();
+        assert_eq!(run(&mut executor, "retry -n 5 -d 0 -- flaky 3").unwrap(), 0);
+        assert_eq!(executor.get_variable("n"), "3");
+
+        // The status of the last attempt is returned once they run out
+        run(&mut executor, "n=0").unwrap();
+        let start = Instant::now();
+        assert_eq!(run(&mut executor, "retry -n 3 -d 0.01 -m 0.015 flaky 9").unwrap(), 1);
+        assert_eq!(executor.get_variable("n"), "3");
+        assert!(start.elapsed() >= Duration::from_millis(25));
+
+        assert_eq!(run(&mut executor, "retry -n 0 flaky 1").unwrap(), 2);
+        assert_eq!(run(&mut executor, "retry -d").unwrap(), 2);
+    }
+
+    #[test]
+    fn test_history_builtin() {
+        use crate::history::{HistoryBackend, HistoryEntry};
+        use crate::history_sqlite::SqliteBackend;
+        use std::sync::{Arc, Mutex};
+
+        let _guard = FD_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let path = temp_path("history-out");
+        let database = temp_path("history.db");
+        let _ = std::fs::remove_file(&database);
+        let mut backend = SqliteBackend::open(&database).unwrap();
+        for (command, cwd, status) in [("make", "/src", 2), ("make", "/tmp", 1), ("ls", "/src", 0)] {
+            let entry = HistoryEntry { command: command.to_string(), cwd: Some(cwd.into()), ..HistoryEntry::default() };
+            backend.add(&entry, None).unwrap();
+            backend.finish(&HistoryEntry { exit_status: Some(status), ..entry }).unwrap();
+        }
+
+        let mut executor = Executor::new();
+        executor.env_vars.insert("OUT".to_string(), path.display().to_string());
+        assert_eq!(run(&mut executor, "history > $OUT").unwrap(), 0);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "");
+
+        executor.set_history(Arc::new(Mutex::new(Box::new(backend))));
+        run(&mut executor, "history --cwd /src --failed > $OUT").unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "make\n");
+        run(&mut executor, "history -n 2 > $OUT").unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "make\nls\n");
+        run(&mut executor, "history --stats -n 1 > $OUT").unwrap();
+        let table = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(table.lines().nth(1).unwrap().split_whitespace().collect::<Vec<_>>(), ["2", "100%", "-", "make"]);
+        assert_eq!(table.lines().count(), 2);
+        assert_eq!(run(&mut executor, "history -n").unwrap(), 2);
+        let _ = std::fs::remove_file(&path);
+        let _ = std::fs::remove_file(&database);
+    }
+
+    #[test]
+    fn test_session_builtin() {
+        let _guard = FD_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let path = temp_path("session-out");
+        let dir = temp_path("sessions");
+        let mut executor = Executor::new();
+        executor.env_vars.insert(session::SESSION_DIR_VAR.to_string(), dir.display().to_string());
+        run(&mut executor, "EDITOR=vi; greet() { echo hello $1; }; set -o pipefail").unwrap();
+        assert_eq!(run(&mut executor, "session save work").unwrap(), 0);
+
+        let mut restored = Executor::new();
+        restored.env_vars.insert(session::SESSION_DIR_VAR.to_string(), dir.display().to_string());
+        restored.env_vars.insert("OUT".to_string(), path.display().to_string());
+        assert_eq!(run(&mut restored, "session restore missing").unwrap(), 1);
+        assert_eq!(run(&mut restored, "session restore work").unwrap(), 0);
+        assert_eq!(restored.get_variable("EDITOR"), "vi");
+        assert!(restored.pipefail);
+        run(&mut restored, "greet you > $OUT").unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "hello you\n");
+        run(&mut restored, "session list > $OUT").unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "work\n");
+        assert_eq!(run(&mut restored, "session save").unwrap(), 2);
+        let _ = std::fs::remove_file(&path);
+        let _ = std::fs::remove_dir_all(&dir);
     }
 
-    fn word_split(&self, input: &str) -> Vec<String> {
-        // Split on whitespace (spaces, tabs, newlines)
-        // This is a simplified version - real bash uses IFS variable
-        input
-            .split_whitespace()
-            .map(|s| s.to_string())
-            .collect()
+    #[test]
+    fn test_backquote_substitution() {
+        let mut executor = Executor::new();
+        run(&mut executor, "OLD=`echo a \\`echo b\\``").unwrap();
+        assert_eq!(executor.get_variable("OLD"), "a b");
+        run(&mut executor, "QUOTED=\"<`echo \\\"x\\\" y`>\"").unwrap();
+        assert_eq!(executor.get_variable("QUOTED"), "<x y>");
+        assert_eq!(run(&mut executor, "STATUS=`false`").unwrap(), 1);
     }
 }
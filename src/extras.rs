@@ -0,0 +1,108 @@
+use crate::messages::tr;
+use chacha20poly1305::aead::OsRng;
+use chacha20poly1305::aead::rand_core::RngCore;
+use sha2::{Digest, Sha224, Sha256, Sha384, Sha512};
+use std::io::Read;
+
+/// The algorithms `hash-file` knows
+pub const ALGORITHMS: &[&str] = &["sha224", "sha256", "sha384", "sha512"];
+
+/// The digest of everything `input` holds, in lower-case hex
+pub fn hash(algorithm: &str, input: impl Read) -> Result<String, String> {
+    match algorithm {
+        "sha224" => digest::<Sha224>(input),
+        "sha256" => digest::<Sha256>(input),
+        "sha384" => digest::<Sha384>(input),
+        "sha512" => digest::<Sha512>(input),
+        _ => Err(tr!("{}: unknown algorithm (one of {})", algorithm, ALGORITHMS.join(", "))),
+    }
+}
+
+fn digest<D: Digest + std::io::Write>(mut input: impl Read) -> Result<String, String> {
+    let mut hasher = D::new();
+    std::io::copy(&mut input, &mut hasher).map_err(|e| e.to_string())?;
+    Ok(hex(&hasher.finalize()))
+}
+
+/// `count` bytes from the operating system's random source
+pub fn random_bytes(count: usize) -> Vec<u8> {
+    let mut bytes = vec![0; count];
+    OsRng.fill_bytes(&mut bytes);
+    bytes
+}
+
+/// A random (version 4) UUID, as uuidgen(1) prints one
+pub fn uuid() -> String {
+    let mut bytes = [0; 16];
+    OsRng.fill_bytes(&mut bytes);
+    format_uuid(bytes)
+}
+
+fn format_uuid(mut bytes: [u8; 16]) -> String {
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    let hex = hex(&bytes);
+    format!("{}-{}-{}-{}-{}", &hex[..8], &hex[8..12], &hex[12..16], &hex[16..20], &hex[20..])
+}
+
+/// `bytes` as text in `encoding`, `hex` or `base64`
+pub fn encode(bytes: &[u8], encoding: &str) -> Result<String, String> {
+    match encoding {
+        "hex" => Ok(hex(bytes)),
+        "base64" => Ok(base64(bytes)),
+        _ => Err(tr!("{}: unknown encoding (hex or base64)", encoding)),
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::new();
+    for chunk in bytes.chunks(3) {
+        let group = chunk.iter().enumerate().fold(0u32, |group, (index, &byte)| group | u32::from(byte) << (16 - 8 * index));
+        for index in 0..4 {
+            if index <= chunk.len() {
+                encoded.push(char::from(ALPHABET[(group >> (18 - 6 * index) & 0x3f) as usize]));
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hash() {
+        assert_eq!(hash("sha256", "abc".as_bytes()).unwrap(), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+        assert_eq!(hash("sha224", "".as_bytes()).unwrap(), "d14a028c2a3a2bc9476102bb288234c415a2b01f828ea62ac5b3e42f");
+        assert_eq!(hash("sha512", "".as_bytes()).unwrap().len(), 128);
+        assert!(hash("md4", "".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_uuid() {
+        assert_eq!(format_uuid([0xff; 16]), "ffffffff-ffff-4fff-bfff-ffffffffffff");
+        assert_eq!(format_uuid([0; 16]), "00000000-0000-4000-8000-000000000000");
+        let (first, second) = (uuid(), uuid());
+        assert_eq!(first.len(), 36);
+        assert_ne!(first, second);
+    }
+
+    #[test]
+    fn test_encode() {
+        assert_eq!(encode(&[0, 0xab, 0x10], "hex").unwrap(), "00ab10");
+        assert_eq!(encode(b"Man", "base64").unwrap(), "TWFu");
+        assert_eq!(encode(b"Ma", "base64").unwrap(), "TWE=");
+        assert_eq!(encode(b"M", "base64").unwrap(), "TQ==");
+        assert_eq!(encode(b"", "base64").unwrap(), "");
+        assert!(encode(b"", "z85").is_err());
+        assert_eq!(random_bytes(32).len(), 32);
+    }
+}
@@ -0,0 +1,158 @@
+use crate::messages::tr;
+use std::io::Read;
+use std::time::Duration;
+
+/// How long to wait for a connection before giving up
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A `fetch URL [-o file] [-H header]...` command line
+#[derive(Debug, PartialEq)]
+pub struct Request {
+    pub url: String,
+    /// Where to save the body; stdout if None
+    pub output: Option<String>,
+    pub headers: Vec<(String, String)>,
+}
+
+impl Request {
+    /// The options may come before or after the URL
+    pub fn parse(args: &[String]) -> Result<Self, String> {
+        let mut url = None;
+        let mut output = None;
+        let mut headers = Vec::new();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-o" | "-H" => {
+                    let value = args.next().ok_or_else(|| tr!("fetch: {}: option requires an argument", arg))?;
+                    if arg == "-o" {
+                        output = Some(value.clone());
+                    } else {
+                        let (name, value) = value.split_once(':').ok_or_else(|| tr!("fetch: {}: invalid header", value))?;
+                        headers.push((name.trim().to_string(), value.trim().to_string()));
+                    }
+                }
+                _ if arg.starts_with('-') => return Err(tr!("fetch: {}: invalid option", arg)),
+                _ if url.is_some() => return Err(tr!("fetch: {}: only one URL at a time", arg)),
+                _ => url = Some(arg.clone()),
+            }
+        }
+        let url = url.ok_or_else(|| tr!("fetch: missing URL"))?;
+        Ok(Self { url, output, headers })
+    }
+
+    /// Send the request, through `proxy` if there is one, and return the
+    /// body to read. A status of 400 or above is an error.
+    pub fn send(&self, proxy: Option<&str>) -> Result<Box<dyn Read + Send + Sync>, String> {
+        let mut agent = ureq::AgentBuilder::new().timeout_connect(CONNECT_TIMEOUT);
+        if let Some(proxy) = proxy {
+            agent = agent.proxy(ureq::Proxy::new(proxy).map_err(|e| format!("{}: {}", proxy, e))?);
+        }
+        let mut request = agent.build().get(&self.url);
+        for (name, value) in &self.headers {
+            request = request.set(name, value);
+        }
+        match request.call() {
+            Ok(response) => Ok(response.into_reader()),
+            Err(ureq::Error::Status(status, response)) => Err(format!("{} {}", status, response.status_text())),
+            Err(ureq::Error::Transport(e)) => Err(e.to_string()),
+        }
+    }
+}
+
+/// The proxy to reach `url` through, from the variables `var` looks up:
+/// `http_proxy` or `https_proxy` by scheme, else `all_proxy`, each also in
+/// upper case, unless the host is in `no_proxy`
+pub fn proxy_for(url: &str, var: impl Fn(&str) -> Option<String>) -> Option<String> {
+    let lookup = |name: &str| var(name).or_else(|| var(&name.to_uppercase())).filter(|value| !value.is_empty());
+    let (scheme, rest) = url.split_once("://")?;
+    let authority = rest.split(['/', '?', '#']).next().unwrap_or_default();
+    let host_port = authority.rsplit_once('@').map_or(authority, |(_, host)| host);
+    let host = match host_port.strip_prefix('[') {
+        Some(bracketed) => bracketed.split(']').next().unwrap_or_default(),
+        None => host_port.split(':').next().unwrap_or_default(),
+    };
+    let bypassed = lookup("no_proxy").is_some_and(|hosts| {
+        hosts.split(',').map(str::trim).filter(|entry| !entry.is_empty()).any(|entry| {
+            let domain = entry.trim_start_matches('.');
+            entry == "*" || host.eq_ignore_ascii_case(domain) || host.to_ascii_lowercase().ends_with(&format!(".{}", domain.to_ascii_lowercase()))
+        })
+    });
+    if bypassed {
+        return None;
+    }
+    lookup(&format!("{}_proxy", scheme.to_ascii_lowercase())).or_else(|| lookup("all_proxy"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+
+    fn parse(args: &[&str]) -> Result<Request, String> {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        Request::parse(&args)
+    }
+
+    #[test]
+    fn test_parse() {
+        let request = parse(&["http://example.com/a", "-o", "a.txt", "-H", "Accept: text/plain"]).unwrap();
+        assert_eq!(request.url, "http://example.com/a");
+        assert_eq!(request.output.as_deref(), Some("a.txt"));
+        assert_eq!(request.headers, [("Accept".to_string(), "text/plain".to_string())]);
+        assert_eq!(parse(&["-o", "b", "http://example.com/b"]).unwrap().output.as_deref(), Some("b"));
+
+        assert!(parse(&[]).is_err());
+        assert!(parse(&["http://a", "-o"]).is_err());
+        assert!(parse(&["http://a", "-H", "no colon"]).is_err());
+        assert!(parse(&["http://a", "http://b"]).is_err());
+        assert!(parse(&["-x", "http://a"]).is_err());
+    }
+
+    #[test]
+    fn test_proxy_for() {
+        let vars = HashMap::from([
+            ("http_proxy", "http://plain:3128"),
+            ("HTTPS_PROXY", "http://secure:3128"),
+            ("no_proxy", "localhost, .internal"),
+        ]);
+        let proxy = |url: &str| proxy_for(url, |name| vars.get(name).map(|value| value.to_string()));
+        assert_eq!(proxy("http://example.com/").as_deref(), Some("http://plain:3128"));
+        assert_eq!(proxy("https://user@example.com:8443/x").as_deref(), Some("http://secure:3128"));
+        assert_eq!(proxy("http://localhost:8080/"), None);
+        assert_eq!(proxy("https://git.internal/repo"), None);
+        assert_eq!(proxy("ftp://example.com/"), None);
+    }
+
+    #[test]
+    fn test_send() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = std::thread::spawn(move || {
+            let mut requests = Vec::new();
+            // The statuses come first, or zip would wait for a third connection
+            for (status, stream) in ["200 OK", "404 Not Found"].into_iter().zip(listener.incoming()) {
+                let mut stream = stream.unwrap();
+                let mut request = String::new();
+                let mut reader = BufReader::new(&stream);
+                while reader.read_line(&mut request).unwrap() > 2 {}
+                requests.push(request);
+                write!(stream, "HTTP/1.1 {}\r\nContent-Length: 5\r\nConnection: close\r\n\r\nhello", status).unwrap();
+            }
+            requests
+        });
+
+        let url = format!("http://{}/file.txt", address);
+        let request = parse(&[&url, "-H", "X-Token: secret"]).unwrap();
+        let mut body = String::new();
+        request.send(None).unwrap().read_to_string(&mut body).unwrap();
+        assert_eq!(body, "hello");
+        assert_eq!(request.send(None).err().as_deref(), Some("404 Not Found"));
+
+        let requests = server.join().unwrap();
+        assert!(requests[0].starts_with("GET /file.txt HTTP/1.1\r\n"));
+        assert!(requests[0].contains("X-Token: secret\r\n"));
+    }
+}
@@ -0,0 +1,286 @@
+use crate::messages::tr;
+use crate::pattern;
+use std::collections::HashSet;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Most threads reading directories at once
+const MAX_WORKERS: usize = 8;
+
+/// Whether `word` has unescaped pattern characters, making it subject to
+/// pathname expansion. `@(`, `+(` and `!(` start extended patterns.
+pub fn is_pattern(word: &str) -> bool {
+    let mut chars = word.chars().peekable();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '\\' => {
+                chars.next();
+            }
+            '*' | '?' | '[' => return true,
+            '@' | '+' | '!' if chars.peek() == Some(&'(') => return true,
+            _ => {}
+        }
+    }
+    false
+}
+
+/// How pathname expansion behaves
+#[derive(Debug, Clone, Copy)]
+pub struct Options {
+    /// Give up once more than this many directory entries have been read
+    pub max_entries: usize,
+    /// `set -o globstar`: a `**` component matches any number of directories
+    pub globstar: bool,
+    /// `shopt -s dotglob`: names starting with `.` match like any other
+    pub dotglob: bool,
+    /// `shopt -s nocaseglob`: patterns match names in either case
+    pub nocaseglob: bool,
+}
+
+/// Pathname expansion: the existing paths matching `pattern`, sorted. Each
+/// `/`-separated component is matched against the entries of the
+/// directories matched so far, and a name starting with `.` only matches a
+/// component that starts with `.` too, unless `dotglob` is on. Empty when nothing matches, and an
+/// error once more than `max_entries` directory entries have been read.
+pub fn expand(pattern: &str, options: Options) -> Result<Vec<String>, String> {
+    let max_entries = options.max_entries;
+    let too_many = || tr!("{}: more than {} directory entries to search", pattern, max_entries);
+    let (mut paths, rest) = match pattern.strip_prefix('/') {
+        Some(rest) => (vec!["/".to_string()], rest),
+        None => (vec![String::new()], pattern),
+    };
+
+    let entries_read = AtomicUsize::new(0);
+    let components: Vec<&str> = rest.split('/').collect();
+    for (index, component) in components.iter().enumerate() {
+        if options.globstar && *component == "**" {
+            // At the end, `**` matches the files too
+            let files = index + 1 == components.len();
+            paths = descend(paths, files, options.dotglob, &entries_read, max_entries).ok_or_else(too_many)?;
+            continue;
+        }
+        if !is_pattern(component) {
+            paths = paths.iter().map(|base| join(base, &unescape(component))).collect();
+            continue;
+        }
+        let dirs: Vec<&str> = paths.iter().map(|base| if base.is_empty() { "." } else { base.as_str() }).collect();
+        let listings = read_dirs(&dirs, &entries_read, max_entries).ok_or_else(too_many)?;
+        let matches = if options.nocaseglob { pattern::matches_ignoring_case } else { pattern::matches };
+        let mut next = Vec::new();
+        for (base, names) in paths.iter().zip(listings) {
+            next.extend(
+                names
+                    .into_iter()
+                    .filter(|name| (!name.starts_with('.') || component.starts_with('.') || options.dotglob) && matches(component, name))
+                    .map(|name| join(base, &name)),
+            );
+        }
+        paths = next;
+    }
+
+    // Literal components were taken on trust until now
+    paths.retain(|path| Path::new(path).symlink_metadata().is_ok());
+    paths.sort();
+    Ok(paths)
+}
+
+/// For `**`: `bases` and every directory beneath them, or with `files`
+/// everything beneath them. The tree is read a level at a time, skipping
+/// hidden entries unless `dotglob`. Symbolic links aren't followed and no directory is read
+/// twice, so links and bind mounts can't make it loop.
+fn descend(bases: Vec<String>, files: bool, dotglob: bool, entries_read: &AtomicUsize, max_entries: usize) -> Option<Vec<String>> {
+    let mut seen = HashSet::new();
+    let mut found = if files { Vec::new() } else { bases.clone() };
+    let mut level = bases;
+    while !level.is_empty() {
+        let dirs: Vec<&str> = level.iter().map(|base| if base.is_empty() { "." } else { base.as_str() }).collect();
+        let listings = read_dirs(&dirs, entries_read, max_entries)?;
+        let mut next = Vec::new();
+        for (base, names) in level.iter().zip(listings) {
+            for name in names.into_iter().filter(|name| dotglob || !name.starts_with('.')) {
+                let path = join(base, &name);
+                let Ok(metadata) = Path::new(&path).symlink_metadata() else {
+                    continue;
+                };
+                let new_dir = metadata.is_dir() && seen.insert((metadata.dev(), metadata.ino()));
+                if files || new_dir {
+                    found.push(path.clone());
+                }
+                if new_dir {
+                    next.push(path);
+                }
+            }
+        }
+        level = next;
+    }
+    Some(found)
+}
+
+/// The entry names of each of `dirs`, none for one that can't be read.
+/// Several directories are read by a bounded pool of threads, so a slow or
+/// large tree doesn't hold up the rest. None once `entries_read` goes over
+/// `max_entries`.
+fn read_dirs(dirs: &[&str], entries_read: &AtomicUsize, max_entries: usize) -> Option<Vec<Vec<String>>> {
+    let read = |dir: &str| -> Option<Vec<String>> {
+        let mut names = Vec::new();
+        for entry in std::fs::read_dir(dir).into_iter().flatten() {
+            if entries_read.fetch_add(1, Ordering::Relaxed) >= max_entries {
+                return None;
+            }
+            names.extend(entry.ok().and_then(|entry| entry.file_name().into_string().ok()));
+        }
+        Some(names)
+    };
+
+    let workers = std::thread::available_parallelism().map_or(1, |n| n.get()).min(MAX_WORKERS).min(dirs.len());
+    if workers <= 1 {
+        return dirs.iter().map(|dir| read(dir)).collect();
+    }
+    let next = AtomicUsize::new(0);
+    let listings = Mutex::new(vec![Some(Vec::new()); dirs.len()]);
+    std::thread::scope(|scope| {
+        for _ in 0..workers {
+            scope.spawn(|| loop {
+                let index = next.fetch_add(1, Ordering::Relaxed);
+                let Some(dir) = dirs.get(index) else {
+                    break;
+                };
+                let names = read(dir);
+                let over_limit = names.is_none();
+                listings.lock().unwrap_or_else(|e| e.into_inner())[index] = names;
+                if over_limit {
+                    break;
+                }
+            });
+        }
+    });
+    listings.into_inner().unwrap_or_else(|e| e.into_inner()).into_iter().collect()
+}
+
+fn join(base: &str, name: &str) -> String {
+    if base.is_empty() || base.ends_with('/') {
+        format!("{}{}", base, name)
+    } else {
+        format!("{}/{}", base, name)
+    }
+}
+
+fn unescape(component: &str) -> String {
+    let mut result = String::with_capacity(component.len());
+    let mut chars = component.chars();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '\\' => result.extend(chars.next()),
+            _ => result.push(ch),
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(max_entries: usize) -> Options {
+        Options { max_entries, globstar: false, dotglob: false, nocaseglob: false }
+    }
+
+    #[test]
+    fn test_expand_paths() {
+        let root = std::env::temp_dir().join(format!("clam-test-{}-glob", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        for dir in ["src", "docs", ".git"] {
+            std::fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        for file in ["src/main.rs", "src/lib.rs", "docs/notes.md", "README", ".hidden"] {
+            std::fs::write(root.join(file), "").unwrap();
+        }
+        let base = root.display().to_string();
+        let expand = |pattern: &str| -> Vec<String> {
+            expand(&format!("{}/{}", base, pattern), options(100))
+                .unwrap()
+                .into_iter()
+                .map(|path| path[base.len() + 1..].to_string())
+                .collect()
+        };
+
+        assert_eq!(expand("*"), ["README", "docs", "src"]);
+        assert_eq!(expand(".*"), [".git", ".hidden"]);
+        let dotglob = Options { dotglob: true, ..options(100) };
+        assert_eq!(super::expand(&format!("{}/*", base), dotglob).unwrap().len(), 5);
+        let nocaseglob = Options { nocaseglob: true, ..options(100) };
+        assert_eq!(super::expand(&format!("{}/read*", base), nocaseglob).unwrap(), [format!("{}/README", base)]);
+        assert!(expand("read*").is_empty());
+        assert_eq!(expand("*/*.rs"), ["src/lib.rs", "src/main.rs"]);
+        assert_eq!(expand("*/"), ["docs/", "src/"]);
+        assert_eq!(expand("[ds]*/n?tes.md"), ["docs/notes.md"]);
+        assert_eq!(expand("src/main.rs"), ["src/main.rs"]);
+        assert!(expand("*.txt").is_empty());
+        assert!(expand("missing/*").is_empty());
+        // 5 entries in the root, then 1 in docs and 2 in src
+        assert!(super::expand(&format!("{}/*/*", base), options(8)).is_ok());
+        assert!(super::expand(&format!("{}/*/*", base), options(7)).is_err());
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn test_expand_many_directories() {
+        let root = std::env::temp_dir().join(format!("clam-test-{}-glob-many", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        for index in 0..40 {
+            let dir = root.join(format!("crate{:02}", index));
+            std::fs::create_dir_all(&dir).unwrap();
+            std::fs::write(dir.join("lib.rs"), "").unwrap();
+            std::fs::write(dir.join("README"), "").unwrap();
+        }
+        let base = root.display().to_string();
+
+        let paths = expand(&format!("{}/*/*.rs", base), options(1000)).unwrap();
+        assert_eq!(paths.len(), 40);
+        assert!(paths.is_sorted());
+        assert_eq!(paths[39], format!("{}/crate39/lib.rs", base));
+        assert!(expand(&format!("{}/*/*.rs", base), options(100)).is_err());
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn test_globstar() {
+        let root = std::env::temp_dir().join(format!("clam-test-{}-globstar", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        for dir in ["src/parser/ast", "docs", ".git/objects"] {
+            std::fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        for file in ["main.rs", "src/lib.rs", "src/parser/ast/node.rs", "docs/notes.md", ".git/objects/x.rs"] {
+            std::fs::write(root.join(file), "").unwrap();
+        }
+        // A link back up the tree must not send the walk round in circles
+        std::os::unix::fs::symlink(&root, root.join("src/parser/loop")).unwrap();
+        let base = root.display().to_string();
+        let expand = |pattern: &str, globstar: bool| -> Vec<String> {
+            expand(&format!("{}/{}", base, pattern), Options { max_entries: 100, globstar, dotglob: false, nocaseglob: false })
+                .unwrap()
+                .into_iter()
+                .map(|path| path[base.len() + 1..].to_string())
+                .collect()
+        };
+
+        assert_eq!(expand("**/*.rs", true), ["main.rs", "src/lib.rs", "src/parser/ast/node.rs"]);
+        assert_eq!(expand("src/**/", true), ["src/", "src/parser/", "src/parser/ast/"]);
+        assert_eq!(expand("docs/**", true), ["docs/notes.md"]);
+        // Without the option, `**` is just `*`
+        assert_eq!(expand("**/*.rs", false), ["src/lib.rs"]);
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn test_is_pattern() {
+        assert!(is_pattern("*.rs"));
+        assert!(is_pattern("file[12]"));
+        assert!(!is_pattern("plain.txt"));
+        assert!(!is_pattern("\\*literal"));
+        assert!(is_pattern("+(a|b).rs"));
+        assert!(!is_pattern("user@\\(host)"));
+    }
+}
@@ -0,0 +1,302 @@
+/// A byte range of the source
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+/// What a span of source is, for choosing its colour
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenClass {
+    /// A reserved word where it starts or ends a compound command
+    Keyword,
+    /// Quoted text and here-document bodies
+    String,
+    /// A `$` expansion, or the name an assignment sets
+    Variable,
+    /// Control and redirection operators
+    Operator,
+    Comment,
+}
+
+const KEYWORDS: &[&str] = &[
+    "if", "then", "else", "elif", "fi", "case", "esac", "for", "select", "while", "until", "do", "done",
+    "function", "time", "!",
+];
+
+/// Operators, longest first so `&&` wins over `&`
+const OPERATORS: &[&str] = &[
+    "<<<", "<<-", "&>>", "&&", "||", "|&", ";;", ">>", "<<", "<&", ">&", ">|", "&>", "<>", "|", "&", ";", ">", "<", "(", ")",
+    "{", "}",
+];
+
+/// Classify the spans of `source` worth colouring, in order and without
+/// overlapping. Unlike the lexer this never fails: an unterminated quote or
+/// expansion runs to the end, as it does while still being typed. Text
+/// that is none of these, like command names and arguments, is left out.
+pub fn highlight_spans(source: &str) -> Vec<(Span, TokenClass)> {
+    Scanner { source, position: 0, spans: Vec::new() }.scan()
+}
+
+struct Scanner<'a> {
+    source: &'a str,
+    position: usize,
+    spans: Vec<(Span, TokenClass)>,
+}
+
+impl Scanner<'_> {
+    fn scan(mut self) -> Vec<(Span, TokenClass)> {
+        // Whether the next word starts a command, and so may be a keyword
+        let mut command_position = true;
+        // Words left before `in` is a keyword again, after `for`, `case`
+        // or `select` and their name
+        let mut awaiting_in = 0;
+        let mut heredocs: Vec<(String, bool)> = Vec::new();
+        // Set after a redirection operator, whose target word is not
+        // classified; `Some(tabs)` for a here-document delimiter
+        let mut redirection: Option<Option<bool>> = None;
+
+        while let Some(ch) = self.peek() {
+            let start = self.position;
+            if ch == '\n' {
+                self.position += 1;
+                command_position = true;
+                for (delimiter, strip_tabs) in heredocs.drain(..) {
+                    self.heredoc_body(&delimiter, strip_tabs);
+                }
+            } else if ch.is_whitespace() {
+                self.position += ch.len_utf8();
+            } else if ch == '#' {
+                let end = self.source[start..].find('\n').map_or(self.source.len(), |i| start + i);
+                self.position = end;
+                self.push(start, TokenClass::Comment);
+            } else if let Some(operator) = self.operator() {
+                self.position += operator.len();
+                let braces = matches!(operator, "{" | "}");
+                if braces && !command_position {
+                    // Part of a word, like a brace expansion
+                    self.position = start;
+                    self.word();
+                    continue;
+                }
+                self.push(start, if braces { TokenClass::Keyword } else { TokenClass::Operator });
+                redirection = match operator {
+                    "<<" => Some(Some(false)),
+                    "<<-" => Some(Some(true)),
+                    ">" | "<" | ">>" | "<<<" | "<&" | ">&" | ">|" | "&>" | "&>>" | "<>" => Some(None),
+                    _ => {
+                        command_position = true;
+                        None
+                    }
+                };
+            } else {
+                let word = self.word();
+                if let Some(heredoc) = redirection.take() {
+                    if let Some(strip_tabs) = heredoc {
+                        heredocs.push((word.replace(['\'', '"', '\\'], ""), strip_tabs));
+                    }
+                    continue;
+                }
+                awaiting_in = if awaiting_in > 0 { awaiting_in - 1 } else { 0 };
+                let keyword = (command_position && KEYWORDS.contains(&word.as_str()))
+                    || (awaiting_in == 1 && word == "in");
+                if keyword {
+                    self.push(start, TokenClass::Keyword);
+                    if matches!(word.as_str(), "for" | "case" | "select") {
+                        awaiting_in = 3;
+                    }
+                    command_position = !matches!(word.as_str(), "for" | "case" | "select" | "function" | "in");
+                } else if command_position && let Some(name) = assigned_name(&word) {
+                    self.spans.insert(
+                        self.spans.partition_point(|(span, _)| span.start < start),
+                        (Span { start, end: start + name.len() }, TokenClass::Variable),
+                    );
+                } else {
+                    command_position = false;
+                }
+            }
+        }
+        self.spans
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.source[self.position..].chars().next()
+    }
+
+    fn push(&mut self, start: usize, class: TokenClass) {
+        if self.position > start {
+            self.spans.push((Span { start, end: self.position }, class));
+        }
+    }
+
+    fn operator(&self) -> Option<&'static str> {
+        let rest = &self.source[self.position..];
+        OPERATORS.iter().copied().find(|operator| rest.starts_with(operator))
+    }
+
+    /// Scan one word, classifying the quotes and expansions within it, and
+    /// return its text
+    fn word(&mut self) -> String {
+        let start = self.position;
+        while let Some(ch) = self.peek() {
+            if ch.is_whitespace() || (self.position > start && self.operator().is_some()) {
+                break;
+            }
+            match ch {
+                '\\' => {
+                    self.position += 1;
+                    self.position += self.peek().map_or(0, char::len_utf8);
+                }
+                '\'' => {
+                    let quote = self.position;
+                    self.position += 1;
+                    self.position = self.source[self.position..]
+                        .find('\'')
+                        .map_or(self.source.len(), |i| self.position + i + 1);
+                    self.push(quote, TokenClass::String);
+                }
+                '"' => self.double_quoted(),
+                '$' => self.expansion(),
+                _ => self.position += ch.len_utf8(),
+            }
+        }
+        self.source[start..self.position].to_string()
+    }
+
+    /// A double-quoted string, split around the expansions inside it
+    fn double_quoted(&mut self) {
+        let mut start = self.position;
+        self.position += 1;
+        while let Some(ch) = self.peek() {
+            match ch {
+                '"' => {
+                    self.position += 1;
+                    break;
+                }
+                '\\' => {
+                    self.position += 1;
+                    self.position += self.peek().map_or(0, char::len_utf8);
+                }
+                '$' if self.expansion_length() > 0 => {
+                    self.push(start, TokenClass::String);
+                    self.expansion();
+                    start = self.position;
+                }
+                _ => self.position += ch.len_utf8(),
+            }
+        }
+        self.push(start, TokenClass::String);
+    }
+
+    /// `${...}`, `$NAME`, or a special parameter like `$?`. A `$` with
+    /// none of these after it is plain text.
+    fn expansion(&mut self) {
+        let start = self.position;
+        let length = self.expansion_length();
+        self.position += 1 + length;
+        if length > 0 {
+            self.push(start, TokenClass::Variable);
+        }
+    }
+
+    /// The length of the expansion after the `$` at the current position
+    fn expansion_length(&self) -> usize {
+        let rest = &self.source[self.position + 1..];
+        match rest.chars().next() {
+            Some('{') => rest.find('}').map_or(rest.len(), |i| i + 1),
+            Some(ch) if ch.is_ascii_digit() || "#@*?$!-".contains(ch) => 1,
+            _ => rest.find(|c: char| !(c.is_alphanumeric() || c == '_')).unwrap_or(rest.len()),
+        }
+    }
+
+    /// A here-document body: the lines up to its delimiter
+    fn heredoc_body(&mut self, delimiter: &str, strip_tabs: bool) {
+        let start = self.position;
+        while self.position < self.source.len() {
+            let line_end = self.source[self.position..].find('\n').map_or(self.source.len(), |i| self.position + i);
+            let line = &self.source[self.position..line_end];
+            let line = if strip_tabs { line.trim_start_matches('\t') } else { line };
+            if line == delimiter {
+                let body_end = self.position;
+                self.position = line_end;
+                if body_end > start {
+                    self.spans.push((Span { start, end: body_end }, TokenClass::String));
+                }
+                return;
+            }
+            self.position = (line_end + 1).min(self.source.len());
+        }
+        self.push(start, TokenClass::String);
+    }
+}
+
+/// The variable name of an assignment word like `NAME=value`
+fn assigned_name(word: &str) -> Option<&str> {
+    let (name, _) = word.split_once('=')?;
+    let mut chars = name.chars();
+    let valid = chars.next().is_some_and(|c| c.is_alphabetic() || c == '_') && chars.all(|c| c.is_alphanumeric() || c == '_');
+    valid.then_some(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn classes(source: &str) -> Vec<(&str, TokenClass)> {
+        highlight_spans(source)
+            .into_iter()
+            .map(|(span, class)| (&source[span.start..span.end], class))
+            .collect()
+    }
+
+    #[test]
+    fn test_classify_spans() {
+        use TokenClass::*;
+        assert_eq!(
+            classes("if true; then echo \"hi $USER\" 'a$b' >> log; fi # done"),
+            vec![
+                ("if", Keyword),
+                (";", Operator),
+                ("then", Keyword),
+                ("\"hi ", String),
+                ("$USER", Variable),
+                ("\"", String),
+                ("'a$b'", String),
+                (">>", Operator),
+                (";", Operator),
+                ("fi", Keyword),
+                ("# done", Comment),
+            ]
+        );
+        assert_eq!(
+            classes("for x in a done; do X=${y}1 echo in; done"),
+            vec![
+                ("for", Keyword),
+                ("in", Keyword),
+                (";", Operator),
+                ("do", Keyword),
+                ("X", Variable),
+                ("${y}", Variable),
+                (";", Operator),
+                ("done", Keyword),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unterminated_and_heredoc() {
+        use TokenClass::*;
+        assert_eq!(classes("echo \"open $"), vec![("\"open $", String)]);
+        assert_eq!(
+            classes("cat <<-EOF|wc\n\tbody $x\n\tEOF\n{ echo a#b; }"),
+            vec![
+                ("<<-", Operator),
+                ("|", Operator),
+                ("\tbody $x\n", String),
+                ("{", Keyword),
+                (";", Operator),
+                ("}", Keyword),
+            ]
+        );
+    }
+}
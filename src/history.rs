@@ -0,0 +1,722 @@
+use crate::cipher::HistoryCipher;
+use crate::history_command::CommandBackend;
+use crate::history_sqlite::SqliteBackend;
+use crate::history_stats::{Frecency, SharedFrecency};
+use crate::messages::tr;
+use crate::pattern;
+use crate::selector::SharedHistory;
+use crate::terminal;
+use regex::Regex;
+use rustyline::history::FileHistory;
+use rustyline::{Config, Editor, Helper};
+use serde::{Deserialize, Serialize};
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command as ProcessCommand, Stdio};
+use std::sync::{Arc, Mutex};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+/// Where the flat-file backend keeps the history
+pub const HISTORY_FILE: &str = ".clam_history";
+/// Which backend keeps the history: `file` (the default), `sqlite`, or
+/// `command` to hand it to a program that may keep it anywhere
+const BACKEND_VAR: &str = "CLAM_HISTORY_BACKEND";
+/// Enables per-project history files
+const PROJECT_HISTORY_VAR: &str = "CLAM_PROJECT_HISTORY";
+/// Colon-separated list of files/directories marking a project root
+const PROJECT_MARKERS_VAR: &str = "CLAM_PROJECT_MARKERS";
+const DEFAULT_PROJECT_MARKERS: &str = ".git";
+/// File with one regex per line; matching lines are kept out of history
+const SECRETS_FILE_VAR: &str = "CLAM_HISTORY_SECRETS_FILE";
+const DEFAULT_SECRETS_FILE: &str = ".clam_history_secrets";
+/// Store matching lines with the secret masked instead of dropping them
+const REDACT_VAR: &str = "CLAM_HISTORY_REDACT";
+const REDACTED: &str = "***";
+/// Encrypts the history files at rest
+const ENCRYPT_VAR: &str = "CLAM_HISTORY_ENCRYPT";
+/// Command printing the passphrase, instead of asking the system keyring
+const KEY_COMMAND_VAR: &str = "CLAM_HISTORY_KEY_COMMAND";
+const KEYRING_SERVICE: &str = "clam-history";
+/// Header of rustyline's history file format
+const FILE_VERSION_V2: &str = "#V2";
+
+/// A command line in the history, with what is known about its run.
+/// The flat-file backend only keeps the command.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HistoryEntry {
+    pub command: String,
+    /// The directory it was run in
+    pub cwd: Option<PathBuf>,
+    /// When it started, in seconds since the epoch
+    pub started: u64,
+    pub duration_ms: Option<u64>,
+    pub exit_status: Option<i32>,
+}
+
+/// Which entries to search the history for. Entries must match every
+/// condition that is set.
+#[derive(Debug, Default, Serialize)]
+pub struct HistoryQuery {
+    /// Commands containing this text
+    pub text: Option<String>,
+    /// Commands run in exactly this directory
+    pub cwd: Option<PathBuf>,
+    /// Commands that exited with a non-zero status
+    pub failed: bool,
+    /// Only the most recent entries, at most this many
+    pub limit: Option<usize>,
+}
+
+/// Storage for the history, picked with `CLAM_HISTORY_BACKEND`
+pub trait HistoryBackend: Send {
+    /// The commands to preload into the line editor, oldest first. Inside
+    /// `project`, its own commands come last so that searches find them
+    /// first.
+    fn load(&mut self, project: Option<&Path>) -> Result<Vec<String>, String>;
+
+    /// Store a command as it starts to run, in `project` if its
+    /// directory is in one
+    fn add(&mut self, entry: &HistoryEntry, project: Option<&Path>) -> Result<(), String>;
+
+    /// Record how the command added last went, now that `entry` has its
+    /// exit status and duration
+    fn finish(&mut self, entry: &HistoryEntry) -> Result<(), String>;
+
+    /// The stored entries matching `query`, oldest first
+    fn search(&mut self, query: &HistoryQuery) -> Result<Vec<HistoryEntry>, String>;
+}
+
+/// The history backend, shared with the `history` builtin
+pub type SharedBackend = Arc<Mutex<Box<dyn HistoryBackend>>>;
+
+/// The backend `CLAM_HISTORY_BACKEND` picks, or `default` without it.
+/// Only the flat files can be encrypted, so asking for encryption with
+/// another backend is an error rather than a reason to store the history
+/// in the clear.
+pub fn open_backend(global_file: &str, default: &str) -> Result<Box<dyn HistoryBackend>, String> {
+    let name = std::env::var(BACKEND_VAR).unwrap_or_else(|_| default.to_string());
+    if env_flag(ENCRYPT_VAR) && !matches!(name.as_str(), "" | "file") {
+        return Err(tr!("{}: history backend can't be encrypted; history will not be saved", name));
+    }
+    match name.as_str() {
+        "" | "file" => Ok(Box::new(FileBackend::from_env(global_file)?)),
+        "sqlite" => Ok(Box::new(SqliteBackend::open(&SqliteBackend::database_path())?)),
+        "command" => Ok(Box::new(CommandBackend::from_env()?)),
+        _ => Err(tr!("{}: unknown history backend; history will not be saved", name)),
+    }
+}
+
+/// Feeds the line editor from the history backend and stores the lines
+/// typed into it. With per-project history, the editor sees the global
+/// history with the project's entries merged in as the most recent ones,
+/// so searches and arrow keys prefer them.
+pub struct HistoryStore {
+    project_markers: Option<Vec<String>>,
+    shared: SharedHistory,
+    frecency: SharedFrecency,
+    /// None when the history can't be saved
+    backend: Option<SharedBackend>,
+    filter: HistoryFilter,
+    /// The line running now, stored with its outcome when it finishes
+    running: Option<(HistoryEntry, Instant)>,
+}
+
+impl HistoryStore {
+    pub fn new(global_file: &str, default_backend: &str) -> Self {
+        let project_markers = env_flag(PROJECT_HISTORY_VAR).then(|| {
+            std::env::var(PROJECT_MARKERS_VAR)
+                .unwrap_or_else(|_| DEFAULT_PROJECT_MARKERS.to_string())
+                .split(':')
+                .filter(|marker| !marker.is_empty())
+                .map(|marker| marker.to_string())
+                .collect()
+        });
+
+        let backend = match open_backend(global_file, default_backend) {
+            Ok(backend) => Some(Arc::new(Mutex::new(backend))),
+            Err(e) => {
+                eprintln!("clam: {}", e);
+                None
+            }
+        };
+
+        Self {
+            project_markers,
+            shared: Arc::new(Mutex::new(Vec::new())),
+            frecency: Arc::new(Mutex::new(Frecency::default())),
+            backend,
+            filter: HistoryFilter::from_env(),
+            running: None,
+        }
+    }
+
+    /// History entries shared with the interactive widgets
+    pub fn shared(&self) -> SharedHistory {
+        Arc::clone(&self.shared)
+    }
+
+    /// The ranking of the commands, for completion and autosuggestions
+    pub fn frecency(&self) -> SharedFrecency {
+        Arc::clone(&self.frecency)
+    }
+
+    /// The backend, for the `history` builtin to search
+    pub fn backend(&self) -> Option<SharedBackend> {
+        self.backend.clone()
+    }
+
+    pub fn load<H: Helper>(&mut self, rl: &mut Editor<H, FileHistory>) {
+        let Some(backend) = &self.backend else {
+            return;
+        };
+
+        let project = self.current_project();
+        let mut backend = backend.lock().unwrap();
+        match backend.load(project.as_deref()) {
+            Ok(entries) => {
+                let _ = rl.clear_history();
+                for entry in entries {
+                    let _ = rl.add_history_entry(entry);
+                }
+            }
+            Err(e) => eprintln!("clam: {}", tr!("history: {}", e)),
+        }
+
+        let query = HistoryQuery { limit: Some(Config::default().max_history_size()), ..HistoryQuery::default() };
+        if let Ok(entries) = backend.search(&query) {
+            *self.frecency.lock().unwrap() = Frecency::new(&entries, now());
+        }
+
+        self.shared.lock().unwrap().extend(rl.history().iter().cloned());
+    }
+
+    /// Add a line typed by the user, unless HISTIGNORE or the secret
+    /// patterns exclude it. This is the only way lines enter the history,
+    /// so filtered lines never reach the backend. The line is stored
+    /// right away, so nothing is lost if the shell is killed while it
+    /// runs.
+    pub fn record<H: Helper>(&mut self, rl: &mut Editor<H, FileHistory>, line: &str, histignore: &str) {
+        self.running = None;
+        let previous = rl.history().iter().next_back().cloned();
+        if is_ignored(histignore, line, previous.as_deref()) {
+            return;
+        }
+
+        let Some(line) = self.filter.apply(line) else {
+            return;
+        };
+
+        let _ = rl.add_history_entry(line.as_str());
+        self.shared.lock().unwrap().push(line.clone());
+
+        let entry = HistoryEntry {
+            command: line,
+            cwd: std::env::current_dir().ok(),
+            started: now(),
+            ..HistoryEntry::default()
+        };
+        self.frecency.lock().unwrap().add(&entry, entry.started);
+        if let Some(backend) = &self.backend
+            && let Err(e) = backend.lock().unwrap().add(&entry, self.current_project().as_deref())
+        {
+            eprintln!("clam: {}", tr!("history: {}", e));
+        }
+        self.running = Some((entry, Instant::now()));
+    }
+
+    /// Store the exit status and duration of the line recorded last
+    pub fn finish(&mut self, status: i32) {
+        let Some((mut entry, start)) = self.running.take() else {
+            return;
+        };
+        entry.exit_status = Some(status);
+        entry.duration_ms = Some(u64::try_from(start.elapsed().as_millis()).unwrap_or(u64::MAX));
+        if let Some(backend) = &self.backend
+            && let Err(e) = backend.lock().unwrap().finish(&entry)
+        {
+            eprintln!("clam: {}", tr!("history: {}", e));
+        }
+    }
+
+    fn current_project(&self) -> Option<PathBuf> {
+        let markers = self.project_markers.as_ref()?;
+        let cwd = std::env::current_dir().ok()?;
+        find_project_root(&cwd, markers)
+    }
+}
+
+/// History in rustyline's file format: one global file plus a file per
+/// project, optionally encrypted at rest. Only the commands are kept.
+pub struct FileBackend {
+    global_file: PathBuf,
+    cipher: Option<HistoryCipher>,
+    // Cleared when a file can't be decrypted, so it is never overwritten
+    persist: bool,
+}
+
+impl FileBackend {
+    pub fn new(global_file: &Path, cipher: Option<HistoryCipher>) -> Self {
+        Self { global_file: global_file.to_path_buf(), cipher, persist: true }
+    }
+
+    fn from_env(global_file: &str) -> Result<Self, String> {
+        let cipher = if env_flag(ENCRYPT_VAR) {
+            let passphrase = history_passphrase()
+                .ok_or_else(|| tr!("no history passphrase available; history will not be saved"))?;
+            Some(HistoryCipher::new(passphrase))
+        } else {
+            None
+        };
+        Ok(Self::new(Path::new(global_file), cipher))
+    }
+
+    fn read_entries(&mut self, path: &Path) -> Vec<String> {
+        let Ok(data) = std::fs::read(path) else {
+            return Vec::new();
+        };
+
+        // Plain files are still readable, and get encrypted on the next save
+        let text = match &mut self.cipher {
+            Some(cipher) if HistoryCipher::is_encrypted(&data) => match cipher.decrypt(&data) {
+                Ok(plaintext) => plaintext,
+                Err(e) => {
+                    eprintln!("clam: {}", tr!("{}: {}; history will not be saved", path.display(), e));
+                    self.persist = false;
+                    return Vec::new();
+                }
+            },
+            _ => data,
+        };
+
+        parse_entries(&String::from_utf8_lossy(&text))
+    }
+
+    fn append_entries<'a>(&mut self, path: &Path, lines: impl Iterator<Item = &'a String>) -> Result<(), String> {
+        let mut entries = self.read_entries(path);
+        if !self.persist {
+            return Ok(());
+        }
+
+        for line in lines {
+            if entries.last() != Some(line) {
+                entries.push(line.clone());
+            }
+        }
+        let max_len = Config::default().max_history_size();
+        if entries.len() > max_len {
+            entries.drain(..entries.len() - max_len);
+        }
+
+        let mut data = serialize_entries(&entries).into_bytes();
+        if let Some(cipher) = &mut self.cipher {
+            data = cipher.encrypt(&data)?;
+        }
+
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(path)
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+        file.write_all(&data).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+}
+
+impl HistoryBackend for FileBackend {
+    fn load(&mut self, project: Option<&Path>) -> Result<Vec<String>, String> {
+        let global_file = self.global_file.clone();
+        let global = self.read_entries(&global_file);
+        let project = match project {
+            Some(root) => self.read_entries(&project_history_file(root)),
+            None => Vec::new(),
+        };
+        Ok(merge_entries(&global, &project))
+    }
+
+    fn add(&mut self, entry: &HistoryEntry, project: Option<&Path>) -> Result<(), String> {
+        let global_file = self.global_file.clone();
+        self.append_entries(&global_file, std::iter::once(&entry.command))?;
+        if let Some(root) = project {
+            let file = project_history_file(root);
+            if let Some(parent) = file.parent() {
+                let _ = std::fs::create_dir_all(parent);
+            }
+            self.append_entries(&file, std::iter::once(&entry.command))?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self, _entry: &HistoryEntry) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn search(&mut self, query: &HistoryQuery) -> Result<Vec<HistoryEntry>, String> {
+        if query.cwd.is_some() || query.failed {
+            return Err(tr!("the file backend keeps no directories or exit statuses"));
+        }
+        let global_file = self.global_file.clone();
+        let mut entries: Vec<HistoryEntry> = self
+            .read_entries(&global_file)
+            .into_iter()
+            .filter(|command| query.text.as_ref().is_none_or(|text| command.contains(text.as_str())))
+            .map(|command| HistoryEntry { command, ..HistoryEntry::default() })
+            .collect();
+        if let Some(limit) = query.limit {
+            entries.drain(..entries.len().saturating_sub(limit));
+        }
+        Ok(entries)
+    }
+}
+
+/// Seconds since the epoch
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()
+}
+
+fn env_flag(name: &str) -> bool {
+    std::env::var(name)
+        .map(|value| !value.is_empty() && value != "0")
+        .unwrap_or(false)
+}
+
+/// The history passphrase: from CLAM_HISTORY_KEY_COMMAND if set, else from
+/// the system keyring, else prompted for on the terminal
+fn history_passphrase() -> Option<String> {
+    let from_command = match std::env::var(KEY_COMMAND_VAR) {
+        Ok(command) => run_key_command("sh", &["-c", &command]),
+        Err(_) if cfg!(target_os = "macos") => {
+            run_key_command("security", &["find-generic-password", "-s", KEYRING_SERVICE, "-w"])
+        }
+        Err(_) => run_key_command("secret-tool", &["lookup", "service", KEYRING_SERVICE]),
+    };
+
+    from_command.or_else(|| {
+        terminal::read_password("History passphrase: ")
+            .ok()
+            .filter(|passphrase| !passphrase.is_empty())
+    })
+}
+
+fn run_key_command(program: &str, args: &[&str]) -> Option<String> {
+    let output = ProcessCommand::new(program)
+        .args(args)
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+
+    let passphrase = String::from_utf8(output.stdout).ok()?;
+    let passphrase = passphrase.trim_end_matches(['\r', '\n']);
+    (!passphrase.is_empty()).then(|| passphrase.to_string())
+}
+
+/// Lines matching these patterns are not stored, or stored with the
+/// matched text masked when redaction is enabled
+struct HistoryFilter {
+    secrets: Vec<Regex>,
+    redact: bool,
+}
+
+impl HistoryFilter {
+    fn from_env() -> Self {
+        let path = std::env::var(SECRETS_FILE_VAR).map(PathBuf::from).unwrap_or_else(|_| {
+            let home = std::env::var("HOME").unwrap_or_else(|_| ".".to_string());
+            Path::new(&home).join(DEFAULT_SECRETS_FILE)
+        });
+        let contents = std::fs::read_to_string(&path).unwrap_or_default();
+
+        Self {
+            secrets: parse_secret_patterns(&contents),
+            redact: env_flag(REDACT_VAR),
+        }
+    }
+
+    /// The line to store, or None if it must not be stored at all
+    fn apply(&self, line: &str) -> Option<String> {
+        let matching: Vec<&Regex> = self.secrets.iter().filter(|re| re.is_match(line)).collect();
+        if matching.is_empty() {
+            return Some(line.to_string());
+        }
+        if !self.redact {
+            return None;
+        }
+
+        let mut redacted = line.to_string();
+        for re in matching {
+            redacted = redact(re, &redacted);
+        }
+        Some(redacted)
+    }
+}
+
+/// One regex per line; blank lines and `#` comments are skipped, and
+/// invalid patterns are reported once and ignored
+fn parse_secret_patterns(contents: &str) -> Vec<Regex> {
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| match Regex::new(line) {
+            Ok(re) => Some(re),
+            Err(e) => {
+                eprintln!("clam: {}", tr!("invalid history secret pattern '{}': {}", line, e));
+                None
+            }
+        })
+        .collect()
+}
+
+/// Mask what `re` matched: only its capture groups if it has any,
+/// otherwise the whole match
+fn redact(re: &Regex, line: &str) -> String {
+    let mut result = String::new();
+    let mut last = 0;
+
+    for captures in re.captures_iter(line) {
+        let groups: Vec<_> = captures.iter().skip(1).flatten().collect();
+        let spans: Vec<(usize, usize)> = if groups.is_empty() {
+            captures.get(0).map(|m| vec![(m.start(), m.end())]).unwrap_or_default()
+        } else {
+            groups.iter().map(|m| (m.start(), m.end())).collect()
+        };
+
+        for (start, end) in spans {
+            if start < last {
+                continue;
+            }
+            result.push_str(&line[last..start]);
+            result.push_str(REDACTED);
+            last = end;
+        }
+    }
+
+    result.push_str(&line[last..]);
+    result
+}
+
+/// bash-style HISTIGNORE: colon-separated patterns matched against the
+/// whole line, where `&` stands for the previous history entry
+fn is_ignored(histignore: &str, line: &str, previous: Option<&str>) -> bool {
+    split_histignore(histignore).iter().any(|p| {
+        if p == "&" {
+            previous == Some(line)
+        } else {
+            pattern::matches(p, line)
+        }
+    })
+}
+
+fn split_histignore(histignore: &str) -> Vec<String> {
+    let mut patterns = Vec::new();
+    let mut current = String::new();
+    let mut chars = histignore.chars();
+
+    while let Some(ch) = chars.next() {
+        match ch {
+            '\\' if chars.as_str().starts_with(':') => {
+                chars.next();
+                current.push(':');
+            }
+            ':' => patterns.push(std::mem::take(&mut current)),
+            _ => current.push(ch),
+        }
+    }
+    patterns.push(current);
+
+    patterns.into_iter().filter(|p| !p.is_empty()).collect()
+}
+
+/// Walk up from `start` to the nearest directory containing one of `markers`
+pub fn find_project_root(start: &Path, markers: &[String]) -> Option<PathBuf> {
+    start
+        .ancestors()
+        .find(|dir| markers.iter().any(|marker| dir.join(marker).exists()))
+        .map(|dir| dir.to_path_buf())
+}
+
+/// Where the history of the project rooted at `root` is kept
+pub fn project_history_file(root: &Path) -> PathBuf {
+    let name = root.to_string_lossy().replace('%', "%25").replace('/', "%");
+    data_dir().join("clam/projects").join(name)
+}
+
+/// `$XDG_DATA_HOME`, by default `~/.local/share`
+pub fn data_dir() -> PathBuf {
+    std::env::var("XDG_DATA_HOME").map(PathBuf::from).unwrap_or_else(|_| {
+        let home = std::env::var("HOME").unwrap_or_else(|_| ".".to_string());
+        Path::new(&home).join(".local/share")
+    })
+}
+
+/// Global entries followed by the project's entries; global entries that the
+/// project also has are dropped so they only appear at the project position
+pub fn merge_entries(global: &[String], project: &[String]) -> Vec<String> {
+    global
+        .iter()
+        .filter(|entry| !project.contains(entry))
+        .chain(project.iter())
+        .cloned()
+        .collect()
+}
+
+/// Parse a history file in rustyline's format: an optional `#V2` header,
+/// then one entry per line with `\n` and `\\` escaped in V2 files
+fn parse_entries(text: &str) -> Vec<String> {
+    let mut lines = text.lines().peekable();
+    let v2 = lines.peek() == Some(&FILE_VERSION_V2);
+    if v2 {
+        lines.next();
+    }
+
+    lines
+        .filter(|line| !line.is_empty())
+        .map(|line| if v2 { unescape_entry(line) } else { line.to_string() })
+        .collect()
+}
+
+fn serialize_entries(entries: &[String]) -> String {
+    let mut text = format!("{}\n", FILE_VERSION_V2);
+    for entry in entries {
+        text.push_str(&entry.replace('\\', "\\\\").replace('\n', "\\n"));
+        text.push('\n');
+    }
+    text
+}
+
+fn unescape_entry(line: &str) -> String {
+    let mut entry = String::new();
+    let mut chars = line.chars();
+    while let Some(ch) = chars.next() {
+        match (ch, chars.clone().next()) {
+            ('\\', Some('n')) => {
+                chars.next();
+                entry.push('\n');
+            }
+            ('\\', Some('\\')) => {
+                chars.next();
+                entry.push('\\');
+            }
+            _ => entry.push(ch),
+        }
+    }
+    entry
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_project_root() {
+        let root = std::env::temp_dir().join(format!("clam-test-{}-project", std::process::id()));
+        let nested = root.join("src/deep");
+        std::fs::create_dir_all(&nested).unwrap();
+        std::fs::create_dir_all(root.join(".git")).unwrap();
+
+        let markers = vec![".git".to_string()];
+        assert_eq!(find_project_root(&nested, &markers), Some(root.clone()));
+        assert_eq!(find_project_root(&nested, &["no-such-marker".to_string()]), None);
+
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn test_project_history_file_is_unique_per_root() {
+        let a = project_history_file(Path::new("/home/me/a"));
+        let b = project_history_file(Path::new("/home/me/a/b"));
+        assert_ne!(a, b);
+        assert!(a.ends_with("clam/projects/%home%me%a"));
+    }
+
+    #[test]
+    fn test_histignore() {
+        assert!(is_ignored("ls:cd *", "cd /tmp", None));
+        assert!(!is_ignored("ls:cd *", "ls -la", None));
+        assert!(is_ignored("&", "make", Some("make")));
+        assert!(!is_ignored("&", "make", Some("make test")));
+        assert!(is_ignored("echo a\\:b", "echo a:b", None));
+        assert!(!is_ignored("", "anything", None));
+    }
+
+    #[test]
+    fn test_secret_filter_skips_matching_lines() {
+        let filter = HistoryFilter {
+            secrets: parse_secret_patterns("# comment\n.*PASSWORD=.*\ncurl .*Authorization.*\n"),
+            redact: false,
+        };
+        assert_eq!(filter.apply("DB_PASSWORD=hunter2 ./migrate"), None);
+        assert_eq!(filter.apply("curl -H 'Authorization: x' url"), None);
+        assert_eq!(filter.apply("ls -la"), Some("ls -la".to_string()));
+    }
+
+    #[test]
+    fn test_secret_filter_redacts_capture_groups() {
+        let filter = HistoryFilter {
+            secrets: parse_secret_patterns("TOKEN=(\\S+)\n"),
+            redact: true,
+        };
+        assert_eq!(
+            filter.apply("TOKEN=abc123 deploy"),
+            Some("TOKEN=*** deploy".to_string())
+        );
+    }
+
+    #[test]
+    fn test_entries_round_trip() {
+        let entries = vec!["echo a\\b".to_string(), "for x in 1\ndo echo $x\ndone".to_string()];
+        assert_eq!(parse_entries(&serialize_entries(&entries)), entries);
+        assert_eq!(parse_entries("ls\npwd\n"), vec!["ls", "pwd"]);
+    }
+
+    #[test]
+    fn test_encrypted_history_file() {
+        let path = std::env::temp_dir().join(format!("clam-test-{}-encrypted", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let mut backend = FileBackend::new(&path, Some(HistoryCipher::new("passphrase".to_string())));
+        let lines = vec!["echo top secret".to_string()];
+        backend.append_entries(&path, lines.iter()).unwrap();
+
+        let data = std::fs::read(&path).unwrap();
+        assert!(HistoryCipher::is_encrypted(&data));
+        assert_eq!(backend.read_entries(&path), lines);
+
+        backend.cipher = Some(HistoryCipher::new("wrong".to_string()));
+        assert!(backend.read_entries(&path).is_empty());
+        assert!(!backend.persist);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_file_backend_search() {
+        let path = std::env::temp_dir().join(format!("clam-test-{}-file-backend", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let mut backend = FileBackend::new(&path, None);
+        for command in ["make", "ls", "make test"] {
+            let entry = HistoryEntry { command: command.to_string(), ..HistoryEntry::default() };
+            backend.add(&entry, None).unwrap();
+        }
+        assert_eq!(backend.load(None).unwrap(), ["make", "ls", "make test"]);
+
+        let query = HistoryQuery { text: Some("make".to_string()), limit: Some(1), ..HistoryQuery::default() };
+        let found = backend.search(&query).unwrap();
+        assert_eq!(found.iter().map(|entry| entry.command.as_str()).collect::<Vec<_>>(), ["make test"]);
+        assert!(backend.search(&HistoryQuery { failed: true, ..HistoryQuery::default() }).is_err());
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_merge_prefers_project_entries() {
+        let global = vec!["ls".to_string(), "make".to_string(), "cd ..".to_string()];
+        let project = vec!["make".to_string(), "cargo test".to_string()];
+        assert_eq!(merge_entries(&global, &project), vec!["ls", "cd ..", "make", "cargo test"]);
+    }
+}
@@ -0,0 +1,105 @@
+use crate::history::{HistoryBackend, HistoryEntry, HistoryQuery};
+use crate::messages::tr;
+use serde::Serialize;
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command as ProcessCommand, Stdio};
+
+/// The program behind the `command` backend, run with `sh -c`
+const COMMAND_VAR: &str = "CLAM_HISTORY_COMMAND";
+
+/// History kept by a program of the user's, which can store it anywhere,
+/// such as on a server shared by several machines. The program gets the
+/// operation as `$1` and a JSON request on stdin:
+///
+/// - `load`: `{"project": DIR}`; prints the commands to preload
+/// - `add`: the entry of a command starting to run
+/// - `finish`: the same entry with its exit status and duration
+/// - `search`: the query; prints the matching entries
+///
+/// Entries are printed one JSON object per line, oldest first.
+pub struct CommandBackend {
+    command: String,
+}
+
+impl CommandBackend {
+    pub fn new(command: &str) -> Self {
+        Self { command: command.to_string() }
+    }
+
+    pub fn from_env() -> Result<Self, String> {
+        match std::env::var(COMMAND_VAR) {
+            Ok(command) if !command.is_empty() => Ok(Self::new(&command)),
+            _ => Err(tr!("{} is not set; history will not be saved", COMMAND_VAR)),
+        }
+    }
+
+    fn run(&self, operation: &str, request: &impl Serialize) -> Result<Vec<HistoryEntry>, String> {
+        let request = serde_json::to_string(request).map_err(|e| e.to_string())?;
+        let mut child = ProcessCommand::new("sh")
+            .args(["-c", &self.command, "clam-history", operation])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("{}: {}", self.command, e))?;
+        if let Some(mut stdin) = child.stdin.take() {
+            // The program may not care to read it
+            let _ = writeln!(stdin, "{}", request);
+        }
+        let output = child.wait_with_output().map_err(|e| format!("{}: {}", self.command, e))?;
+        if !output.status.success() {
+            return Err(tr!("{}: {} failed: {}", self.command, operation, output.status));
+        }
+
+        String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| {
+                serde_json::from_str(line)
+                    .map_err(|e| tr!("{}: {}: invalid entry: {}", self.command, operation, e))
+            })
+            .collect()
+    }
+}
+
+impl HistoryBackend for CommandBackend {
+    fn load(&mut self, project: Option<&Path>) -> Result<Vec<String>, String> {
+        let entries = self.run("load", &serde_json::json!({ "project": project }))?;
+        Ok(entries.into_iter().map(|entry| entry.command).collect())
+    }
+
+    fn add(&mut self, entry: &HistoryEntry, _project: Option<&Path>) -> Result<(), String> {
+        self.run("add", entry).map(|_| ())
+    }
+
+    fn finish(&mut self, entry: &HistoryEntry) -> Result<(), String> {
+        self.run("finish", entry).map(|_| ())
+    }
+
+    fn search(&mut self, query: &HistoryQuery) -> Result<Vec<HistoryEntry>, String> {
+        self.run("search", query)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_command_backend() {
+        let path = std::env::temp_dir().join(format!("clam-test-{}-history-command", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let script = format!(
+            "case $1 in add) cat >> {0} ;; load|search) cat > /dev/null; cat {0} ;; *) exit 3 ;; esac",
+            path.display()
+        );
+        let mut backend = CommandBackend::new(&script);
+
+        let entry = HistoryEntry { command: "make".to_string(), started: 7, ..HistoryEntry::default() };
+        backend.add(&entry, None).unwrap();
+        assert_eq!(backend.load(None).unwrap(), ["make"]);
+        assert_eq!(backend.search(&HistoryQuery::default()).unwrap(), std::slice::from_ref(&entry));
+        assert!(backend.finish(&entry).is_err());
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,200 @@
+use crate::history::{self, HistoryBackend, HistoryEntry, HistoryQuery};
+use crate::messages::tr;
+use rusqlite::types::Value;
+use rusqlite::{Connection, params, params_from_iter};
+use rustyline::Config;
+use std::fs::OpenOptions;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// The database of the SQLite backend, by default `clam/history.db` in
+/// `$XDG_DATA_HOME`
+const DATABASE_VAR: &str = "CLAM_HISTORY_DB";
+/// How long to wait for another shell writing to the same database
+const BUSY_TIMEOUT: Duration = Duration::from_secs(2);
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS history (
+        id INTEGER PRIMARY KEY,
+        command TEXT NOT NULL,
+        cwd TEXT,
+        started INTEGER NOT NULL,
+        duration_ms INTEGER,
+        exit_status INTEGER
+    );
+    CREATE INDEX IF NOT EXISTS history_cwd ON history (cwd);
+";
+
+/// History in an SQLite database, shared by all the shells of the user.
+/// Each command is kept with the directory it ran in, its exit status and
+/// how long it took, so the history can be searched by them.
+pub struct SqliteBackend {
+    connection: Connection,
+    /// The row of the command added last, which `finish` completes
+    last_row: Option<i64>,
+}
+
+impl SqliteBackend {
+    pub fn open(path: &Path) -> Result<Self, String> {
+        let error = |e: &dyn std::fmt::Display| tr!("{}: {}; history will not be saved", path.display(), e);
+        if let Some(parent) = path.parent() {
+            let _ = std::fs::create_dir_all(parent);
+        }
+        // Create it readable by the user only before SQLite does
+        OpenOptions::new()
+            .append(true)
+            .create(true)
+            .mode(0o600)
+            .open(path)
+            .map_err(|e| error(&e))?;
+        let connection = Connection::open(path).map_err(|e| error(&e))?;
+        Self::with_connection(connection).map_err(|e| error(&e))
+    }
+
+    fn with_connection(connection: Connection) -> rusqlite::Result<Self> {
+        connection.busy_timeout(BUSY_TIMEOUT)?;
+        connection.execute_batch(SCHEMA)?;
+        Ok(Self { connection, last_row: None })
+    }
+
+    pub fn database_path() -> PathBuf {
+        std::env::var(DATABASE_VAR)
+            .map(PathBuf::from)
+            .unwrap_or_else(|_| history::data_dir().join("clam/history.db"))
+    }
+
+    /// The last `limit` commands matching `condition`, oldest first
+    fn recent_commands(&self, condition: &str, args: &[Value], limit: usize) -> rusqlite::Result<Vec<String>> {
+        let sql = format!(
+            "SELECT command FROM (SELECT id, command FROM history WHERE {} ORDER BY id DESC LIMIT {}) ORDER BY id",
+            condition, limit
+        );
+        let mut statement = self.connection.prepare(&sql)?;
+        let rows = statement.query_map(params_from_iter(args), |row| row.get(0))?;
+        rows.collect()
+    }
+}
+
+impl HistoryBackend for SqliteBackend {
+    fn load(&mut self, project: Option<&Path>) -> Result<Vec<String>, String> {
+        let max_len = Config::default().max_history_size();
+        let global = self.recent_commands("1", &[], max_len).map_err(|e| e.to_string())?;
+        let project = match project {
+            Some(root) => {
+                let root = Value::Text(root.to_string_lossy().into_owned());
+                let condition = "cwd = ?1 OR substr(cwd, 1, length(?1) + 1) = ?1 || '/'";
+                self.recent_commands(condition, &[root], max_len).map_err(|e| e.to_string())?
+            }
+            None => Vec::new(),
+        };
+        Ok(history::merge_entries(&global, &project))
+    }
+
+    fn add(&mut self, entry: &HistoryEntry, _project: Option<&Path>) -> Result<(), String> {
+        let cwd = entry.cwd.as_ref().map(|cwd| cwd.to_string_lossy().into_owned());
+        self.connection
+            .execute(
+                "INSERT INTO history (command, cwd, started) VALUES (?1, ?2, ?3)",
+                params![entry.command, cwd, entry.started as i64],
+            )
+            .map_err(|e| e.to_string())?;
+        self.last_row = Some(self.connection.last_insert_rowid());
+        Ok(())
+    }
+
+    fn finish(&mut self, entry: &HistoryEntry) -> Result<(), String> {
+        let Some(row) = self.last_row.take() else {
+            return Ok(());
+        };
+        let duration_ms = entry.duration_ms.map(|ms| ms as i64);
+        self.connection
+            .execute(
+                "UPDATE history SET exit_status = ?1, duration_ms = ?2 WHERE id = ?3",
+                params![entry.exit_status, duration_ms, row],
+            )
+            .map_err(|e| e.to_string())?;
+        Ok(())
+    }
+
+    fn search(&mut self, query: &HistoryQuery) -> Result<Vec<HistoryEntry>, String> {
+        let mut conditions = vec!["1"];
+        let mut args = Vec::new();
+        if let Some(text) = &query.text {
+            conditions.push("instr(command, ?) > 0");
+            args.push(Value::Text(text.clone()));
+        }
+        if let Some(cwd) = &query.cwd {
+            conditions.push("cwd = ?");
+            args.push(Value::Text(cwd.to_string_lossy().into_owned()));
+        }
+        if query.failed {
+            conditions.push("exit_status != 0");
+        }
+        // A negative LIMIT is no limit
+        let limit = query.limit.map_or(-1, |limit| limit as i64);
+        let sql = format!(
+            "SELECT command, cwd, started, duration_ms, exit_status FROM \
+             (SELECT * FROM history WHERE {} ORDER BY id DESC LIMIT {}) ORDER BY id",
+            conditions.join(" AND "),
+            limit
+        );
+
+        let mut statement = self.connection.prepare(&sql).map_err(|e| e.to_string())?;
+        let rows = statement
+            .query_map(params_from_iter(args), |row| {
+                Ok(HistoryEntry {
+                    command: row.get(0)?,
+                    cwd: row.get::<_, Option<String>>(1)?.map(PathBuf::from),
+                    started: row.get::<_, i64>(2)? as u64,
+                    duration_ms: row.get::<_, Option<i64>>(3)?.map(|ms| ms as u64),
+                    exit_status: row.get(4)?,
+                })
+            })
+            .map_err(|e| e.to_string())?;
+        rows.collect::<rusqlite::Result<_>>().map_err(|e| e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(command: &str, cwd: &str) -> HistoryEntry {
+        HistoryEntry { command: command.to_string(), cwd: Some(PathBuf::from(cwd)), ..HistoryEntry::default() }
+    }
+
+    #[test]
+    fn test_sqlite_backend() {
+        let mut backend = SqliteBackend::with_connection(Connection::open_in_memory().unwrap()).unwrap();
+        for (command, cwd, status) in [
+            ("make", "/src/app", 2),
+            ("ls", "/home", 0),
+            ("cargo test", "/src/app/core", 101),
+            ("make", "/src/app", 0),
+            ("git log", "/src/application", 0),
+        ] {
+            backend.add(&entry(command, cwd), None).unwrap();
+            let finished = HistoryEntry { exit_status: Some(status), duration_ms: Some(5), ..entry(command, cwd) };
+            backend.finish(&finished).unwrap();
+        }
+
+        let query = HistoryQuery { cwd: Some(PathBuf::from("/src/app")), failed: true, ..HistoryQuery::default() };
+        let failed = backend.search(&query).unwrap();
+        assert_eq!(failed.len(), 1);
+        assert_eq!(failed[0].command, "make");
+        assert_eq!(failed[0].exit_status, Some(2));
+        assert_eq!(failed[0].duration_ms, Some(5));
+
+        let query = HistoryQuery { text: Some("a".to_string()), limit: Some(2), ..HistoryQuery::default() };
+        let commands: Vec<String> = backend.search(&query).unwrap().into_iter().map(|entry| entry.command).collect();
+        assert_eq!(commands, ["cargo test", "make"]);
+
+        // The project's commands come last, but not those of a sibling
+        // directory that merely shares the prefix
+        assert_eq!(
+            backend.load(Some(Path::new("/src/app"))).unwrap(),
+            ["ls", "git log", "make", "cargo test", "make"]
+        );
+    }
+}
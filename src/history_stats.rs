@@ -0,0 +1,203 @@
+use crate::history::HistoryEntry;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// How often and how recently a command line was run, for ranking it
+#[derive(Clone, Copy, Default)]
+struct Score {
+    weight: f64,
+    /// Position of its last run, to break ties by recency
+    last: usize,
+}
+
+/// Command lines ranked by frecency: each run counts for more the more
+/// recent it is, so what was run often lately comes first. Runs whose time
+/// is unknown count the same, which ranks by frequency alone.
+#[derive(Default)]
+pub struct Frecency {
+    scores: HashMap<String, Score>,
+    ranking: Vec<String>,
+    runs: usize,
+}
+
+/// The frecency ranking, shared with completion and autosuggestions
+pub type SharedFrecency = Arc<Mutex<Frecency>>;
+
+impl Frecency {
+    /// The ranking of `entries`, oldest first, as of `now` in seconds since
+    /// the epoch
+    pub fn new(entries: &[HistoryEntry], now: u64) -> Self {
+        let mut frecency = Self::default();
+        for entry in entries {
+            frecency.count(entry, now);
+        }
+        frecency.rank();
+        frecency
+    }
+
+    /// Count a new run of a command
+    pub fn add(&mut self, entry: &HistoryEntry, now: u64) {
+        self.count(entry, now);
+        self.rank();
+    }
+
+    /// The command lines, best first
+    pub fn ranking(&self) -> &[String] {
+        &self.ranking
+    }
+
+    /// The best-ranked command line that `prefix` is the start of
+    pub fn suggest(&self, prefix: &str) -> Option<&str> {
+        self.ranking
+            .iter()
+            .find(|command| command.len() > prefix.len() && command.starts_with(prefix))
+            .map(String::as_str)
+    }
+
+    fn count(&mut self, entry: &HistoryEntry, now: u64) {
+        let score = self.scores.entry(entry.command.clone()).or_default();
+        score.weight += recency_weight(entry.started, now);
+        score.last = self.runs;
+        self.runs += 1;
+    }
+
+    fn rank(&mut self) {
+        let mut ranking: Vec<(&String, &Score)> = self.scores.iter().collect();
+        ranking.sort_by(|(_, a), (_, b)| b.weight.total_cmp(&a.weight).then(b.last.cmp(&a.last)));
+        self.ranking = ranking.into_iter().map(|(command, _)| command.clone()).collect();
+    }
+}
+
+/// What a run `started` at that time counts for at `now`; 0 is unknown
+fn recency_weight(started: u64, now: u64) -> f64 {
+    const HOUR: u64 = 60 * 60;
+    const DAY: u64 = 24 * HOUR;
+    if started == 0 {
+        return 1.0;
+    }
+    match now.saturating_sub(started) {
+        age if age < HOUR => 4.0,
+        age if age < DAY => 2.0,
+        age if age < 7 * DAY => 0.5,
+        _ => 0.25,
+    }
+}
+
+/// What the history says about one command, i.e. the first word of the
+/// lines that ran it
+#[derive(Debug, Default, PartialEq)]
+pub struct CommandStats {
+    pub name: String,
+    pub runs: usize,
+    /// Of the runs whose exit status is known, those that failed
+    pub failures: usize,
+    pub with_status: usize,
+    /// Total duration of the runs whose duration is known
+    pub total_ms: u64,
+    pub timed: usize,
+}
+
+impl CommandStats {
+    pub fn average_ms(&self) -> Option<u64> {
+        (self.timed > 0).then(|| self.total_ms / self.timed as u64)
+    }
+
+    /// Percentage of the runs that failed
+    pub fn failure_rate(&self) -> Option<usize> {
+        (self.with_status > 0).then(|| self.failures * 100 / self.with_status)
+    }
+}
+
+/// Statistics per command, the most used first
+pub fn command_stats(entries: &[HistoryEntry]) -> Vec<CommandStats> {
+    let mut stats: HashMap<&str, CommandStats> = HashMap::new();
+    for entry in entries {
+        let Some(name) = entry.command.split_whitespace().find(|word| !word.contains('=')) else {
+            continue;
+        };
+        let command = stats.entry(name).or_insert_with(|| CommandStats { name: name.to_string(), ..CommandStats::default() });
+        command.runs += 1;
+        if let Some(status) = entry.exit_status {
+            command.with_status += 1;
+            command.failures += usize::from(status != 0);
+        }
+        if let Some(ms) = entry.duration_ms {
+            command.timed += 1;
+            command.total_ms += ms;
+        }
+    }
+
+    let mut stats: Vec<CommandStats> = stats.into_values().collect();
+    stats.sort_by(|a, b| b.runs.cmp(&a.runs).then_with(|| a.name.cmp(&b.name)));
+    stats
+}
+
+/// A duration the way `history --stats` shows it
+pub fn format_duration(ms: u64) -> String {
+    match ms {
+        0..1_000 => format!("{}ms", ms),
+        1_000..60_000 => format!("{:.1}s", ms as f64 / 1000.0),
+        _ => format!("{}m{:02}s", ms / 60_000, ms % 60_000 / 1000),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(command: &str, started: u64, status: Option<i32>, duration_ms: Option<u64>) -> HistoryEntry {
+        HistoryEntry {
+            command: command.to_string(),
+            started,
+            exit_status: status,
+            duration_ms,
+            ..HistoryEntry::default()
+        }
+    }
+
+    #[test]
+    fn test_frecency() {
+        let now = 1_700_000_000;
+        let entries = [
+            entry("make all", now - 30 * 86400, None, None),
+            entry("make all", now - 20 * 86400, None, None),
+            entry("make all", now - 10 * 86400, None, None),
+            entry("make test", now - 60, None, None),
+            entry("ls", now - 3 * 86400, None, None),
+        ];
+        let mut frecency = Frecency::new(&entries, now);
+        assert_eq!(frecency.ranking(), ["make test", "make all", "ls"]);
+        assert_eq!(frecency.suggest("make"), Some("make test"));
+        assert_eq!(frecency.suggest("make test"), None);
+        assert_eq!(frecency.suggest("git"), None);
+
+        frecency.add(&entry("make all", now, None, None), now);
+        assert_eq!(frecency.suggest("make"), Some("make all"));
+
+        // Without times, the most frequent and then the latest win
+        let frecency = Frecency::new(&[entry("a", 0, None, None), entry("b", 0, None, None)], now);
+        assert_eq!(frecency.ranking(), ["b", "a"]);
+    }
+
+    #[test]
+    fn test_command_stats() {
+        let entries = [
+            entry("make", 0, Some(0), Some(1000)),
+            entry("CC=clang make -j4", 0, Some(2), Some(3000)),
+            entry("ls -l", 0, Some(0), None),
+            entry("make test", 0, None, None),
+        ];
+        let stats = command_stats(&entries);
+        assert_eq!(stats.len(), 2);
+        assert_eq!(stats[0].name, "make");
+        assert_eq!(stats[0].runs, 3);
+        assert_eq!(stats[0].failure_rate(), Some(50));
+        assert_eq!(stats[0].average_ms(), Some(2000));
+        assert_eq!(stats[1].name, "ls");
+        assert_eq!(stats[1].average_ms(), None);
+
+        assert_eq!(format_duration(250), "250ms");
+        assert_eq!(format_duration(1500), "1.5s");
+        assert_eq!(format_duration(125_000), "2m05s");
+    }
+}
@@ -0,0 +1,561 @@
+use crate::messages::tr;
+use crate::terminal::TerminalModes;
+use serde::Serialize;
+
+/// What a job is currently doing
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JobState {
+    Running,
+    Stopped,
+    /// Finished, with its exit status
+    Done(i32),
+}
+
+/// How many statuses of forgotten jobs are kept for `wait`, as POSIX asks
+/// for at least CHILD_MAX of them
+const RETAINED_STATUSES: usize = 1024;
+
+/// Signals that stop a process from the terminal, which a shell doing job
+/// control ignores itself and restores for its jobs
+const STOP_SIGNALS: [libc::c_int; 3] = [libc::SIGTSTP, libc::SIGTTIN, libc::SIGTTOU];
+
+/// A command started in the background, or a foreground one that was stopped
+#[derive(Debug, Clone)]
+pub struct Job {
+    pub id: usize,
+    /// The first process, which leads the process group if the job has one
+    pub pid: libc::pid_t,
+    /// Set when the job runs in a process group of its own
+    pub pgid: Option<libc::pid_t>,
+    /// The command as typed, without the trailing `&`
+    pub command: String,
+    pub state: JobState,
+    /// The terminal modes the job left behind when it stopped, to give back
+    /// to it when it is resumed in the foreground
+    pub modes: Option<TerminalModes>,
+    /// Each process of a pipeline with its own state
+    processes: Vec<(libc::pid_t, JobState)>,
+    /// Finished or stopped in the background and not yet reported
+    changed: bool,
+    /// When the job was last started, stopped or resumed, to find the
+    /// current and previous jobs
+    active_at: u64,
+}
+
+impl Job {
+    /// The exit status of each process, 0 for those still running
+    pub fn statuses(&self) -> Vec<i32> {
+        self.processes
+            .iter()
+            .map(|(_, state)| match state {
+                JobState::Done(status) => *status,
+                _ => 0,
+            })
+            .collect()
+    }
+}
+
+/// A job as `jobs --json` describes it
+#[derive(Debug, PartialEq, Serialize)]
+pub struct JobInfo {
+    pub id: usize,
+    pub pid: libc::pid_t,
+    pub pgid: Option<libc::pid_t>,
+    pub command: String,
+    /// `running`, `stopped` or `done`
+    pub state: &'static str,
+    pub exit_status: Option<i32>,
+    /// The job's marker in `jobs`: `+`, `-` or none
+    pub current: Option<char>,
+}
+
+/// The shell's jobs, ordered by job number. The most recently active job
+/// is the current job (`+`), the one before it the previous job (`-`).
+pub struct JobTable {
+    jobs: Vec<Job>,
+    /// Pids and exit statuses of finished jobs that were reported and
+    /// removed, oldest first, until `wait` asks for them
+    retained: Vec<(libc::pid_t, i32)>,
+    clock: u64,
+}
+
+impl JobTable {
+    pub fn new() -> Self {
+        Self {
+            jobs: Vec::new(),
+            retained: Vec::new(),
+            clock: 0,
+        }
+    }
+
+    /// Record a new running job made of `pids` and return its job number
+    pub fn add(&mut self, pids: &[libc::pid_t], pgid: Option<libc::pid_t>, command: String) -> usize {
+        // Numbers are reused once the highest jobs are gone, like bash
+        let id = self.jobs.iter().map(|job| job.id).max().unwrap_or(0) + 1;
+        self.clock += 1;
+        self.jobs.push(Job {
+            id,
+            pid: pids[0],
+            pgid,
+            command,
+            state: JobState::Running,
+            modes: None,
+            processes: pids.iter().map(|&pid| (pid, JobState::Running)).collect(),
+            changed: false,
+            active_at: self.clock,
+        });
+        id
+    }
+
+    /// Make the job at `index` the current job
+    pub fn touch(&mut self, index: usize) {
+        self.clock += 1;
+        self.jobs[index].active_at = self.clock;
+    }
+
+    /// Mark the job and its unfinished processes as running or stopped
+    pub fn set_state(&mut self, index: usize, state: JobState) {
+        let job = &mut self.jobs[index];
+        for (_, process_state) in job.processes.iter_mut() {
+            if !matches!(process_state, JobState::Done(_)) {
+                *process_state = state;
+            }
+        }
+        job.state = state;
+    }
+
+    /// Remember the terminal modes the job stopped with
+    pub fn save_modes(&mut self, index: usize, modes: TerminalModes) {
+        self.jobs[index].modes = Some(modes);
+    }
+
+    /// Send `signal` to the job's process group, or to each of its
+    /// processes when it has none
+    pub fn signal(&self, index: usize, signal: libc::c_int) -> std::io::Result<()> {
+        let job = &self.jobs[index];
+        let targets = match job.pgid {
+            Some(pgid) => vec![-pgid],
+            None => job.processes.iter().map(|&(pid, _)| pid).collect(),
+        };
+        for target in targets {
+            if unsafe { libc::kill(target, signal) } < 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+        }
+        Ok(())
+    }
+
+    /// Like `signal`, but a stopped job is also continued after SIGTERM or
+    /// SIGHUP, as it could not act on them otherwise
+    pub fn terminate(&self, index: usize, signal: libc::c_int) -> std::io::Result<()> {
+        self.signal(index, signal)?;
+        if self.jobs[index].state == JobState::Stopped && matches!(signal, libc::SIGTERM | libc::SIGHUP) {
+            self.signal(index, libc::SIGCONT)?;
+        }
+        Ok(())
+    }
+
+    pub fn remove(&mut self, index: usize) -> Job {
+        self.jobs.remove(index)
+    }
+
+    /// Resolve a job specifier to an index: `%N`, `%+`/`%%`/`%` (current),
+    /// `%-` (previous), `%string` (command starts with string) or
+    /// `%?string` (command contains string). None means the current job.
+    pub fn find(&self, spec: Option<&str>) -> Result<usize, String> {
+        let Some(spec) = spec else {
+            return self.by_recency(0).ok_or_else(|| tr!("current: no such job"));
+        };
+        let no_such_job = || tr!("{}: no such job", spec);
+        let Some(name) = spec.strip_prefix('%') else {
+            return Err(no_such_job());
+        };
+
+        match name {
+            "" | "+" | "%" => self.by_recency(0).ok_or_else(no_such_job),
+            "-" => self.by_recency(1).or_else(|| self.by_recency(0)).ok_or_else(no_such_job),
+            _ if name.chars().all(|c| c.is_ascii_digit()) => {
+                let id: usize = name.parse().map_err(|_| no_such_job())?;
+                self.jobs.iter().position(|job| job.id == id).ok_or_else(no_such_job)
+            }
+            _ => {
+                let matching: Vec<usize> = match name.strip_prefix('?') {
+                    Some(needle) => self.matching(|job| job.command.contains(needle)),
+                    None => self.matching(|job| job.command.starts_with(name)),
+                };
+                match matching.as_slice() {
+                    [index] => Ok(*index),
+                    [] => Err(no_such_job()),
+                    _ => Err(tr!("{}: ambiguous job spec", spec)),
+                }
+            }
+        }
+    }
+
+    /// Index of the job containing process `pid`
+    pub fn find_pid(&self, pid: libc::pid_t) -> Option<usize> {
+        self.jobs.iter().position(|job| job.processes.iter().any(|&(p, _)| p == pid))
+    }
+
+    fn matching(&self, predicate: impl Fn(&Job) -> bool) -> Vec<usize> {
+        (0..self.jobs.len()).filter(|&i| predicate(&self.jobs[i])).collect()
+    }
+
+    /// Index of the nth most recently active job
+    fn by_recency(&self, nth: usize) -> Option<usize> {
+        let mut indices: Vec<usize> = (0..self.jobs.len()).collect();
+        indices.sort_by_key(|&i| std::cmp::Reverse(self.jobs[i].active_at));
+        indices.get(nth).copied()
+    }
+
+    pub fn jobs(&self) -> &[Job] {
+        &self.jobs
+    }
+
+    /// Poll jobs without blocking and record the ones that stopped,
+    /// continued or finished
+    pub fn update(&mut self) {
+        let flags = libc::WNOHANG | libc::WUNTRACED | libc::WCONTINUED;
+        for job in self.jobs.iter_mut() {
+            if let JobState::Done(_) = job.state {
+                continue;
+            }
+            for (pid, state) in job.processes.iter_mut() {
+                if let JobState::Done(_) = state {
+                    continue;
+                }
+                let mut status = 0;
+                if unsafe { libc::waitpid(*pid, &mut status, flags) } != *pid {
+                    continue;
+                }
+                *state = if libc::WIFSTOPPED(status) {
+                    JobState::Stopped
+                } else if libc::WIFCONTINUED(status) {
+                    JobState::Running
+                } else {
+                    JobState::Done(exit_status(status))
+                };
+            }
+            let state = overall_state(&job.processes);
+            if state != job.state && state != JobState::Running {
+                job.changed = true;
+            }
+            job.state = state;
+        }
+    }
+
+    /// Lines reporting the jobs that finished or stopped since the last
+    /// call, as printed before a prompt. Finished jobs are then forgotten.
+    pub fn take_notifications(&mut self) -> Vec<String> {
+        let changed: Vec<usize> = (0..self.jobs.len()).filter(|&i| self.jobs[i].changed).collect();
+        let lines = changed.iter().map(|&i| self.format(i, false)).collect();
+        for &index in &changed {
+            self.jobs[index].changed = false;
+        }
+        self.remove_done();
+        lines
+    }
+
+    /// Mark the job at `index` as reported, e.g. by `jobs`
+    pub fn mark_reported(&mut self, index: usize) {
+        self.jobs[index].changed = false;
+    }
+
+    /// Block until the job at `index` stops or all of its processes
+    /// finish, and record which
+    pub fn wait(&mut self, index: usize) -> Result<JobState, String> {
+        let job = &mut self.jobs[index];
+        for (pid, state) in job.processes.iter_mut() {
+            if let JobState::Done(_) = state {
+                continue;
+            }
+            *state = wait_stoppable(*pid)?;
+            if *state == JobState::Stopped {
+                break;
+            }
+        }
+
+        let state = if job.processes.iter().any(|(_, state)| *state == JobState::Stopped) {
+            JobState::Stopped
+        } else {
+            overall_state(&job.processes)
+        };
+        if state == JobState::Stopped {
+            self.touch(index);
+        }
+        self.set_state(index, state);
+        Ok(state)
+    }
+
+    /// Forget jobs that have finished, once they've been reported. Their
+    /// statuses are kept until `take_retained` consumes them.
+    pub fn remove_done(&mut self) {
+        for job in &self.jobs {
+            if let JobState::Done(status) = job.state {
+                self.retained.push((job.pid, status));
+            }
+        }
+        if self.retained.len() > RETAINED_STATUSES {
+            self.retained.drain(..self.retained.len() - RETAINED_STATUSES);
+        }
+        self.jobs.retain(|job| !matches!(job.state, JobState::Done(_)));
+    }
+
+    /// The status of a finished job that has already been removed
+    pub fn take_retained(&mut self, pid: libc::pid_t) -> Option<i32> {
+        let index = self.retained.iter().rposition(|&(retained_pid, _)| retained_pid == pid)?;
+        Some(self.retained.remove(index).1)
+    }
+
+    /// `+` for the current job, `-` for the previous one, else a space
+    pub fn marker(&self, index: usize) -> char {
+        if self.by_recency(0) == Some(index) {
+            '+'
+        } else if self.by_recency(1) == Some(index) {
+            '-'
+        } else {
+            ' '
+        }
+    }
+
+    /// A line of `jobs` output, e.g. `[1]+  Running    sleep 10 &`.
+    /// The long form includes the pid.
+    pub fn format(&self, index: usize, long: bool) -> String {
+        let job = &self.jobs[index];
+        let state = match job.state {
+            JobState::Running => tr!("Running"),
+            JobState::Stopped => tr!("Stopped"),
+            JobState::Done(0) => tr!("Done"),
+            JobState::Done(status) => tr!("Exit {}", status),
+        };
+        let suffix = if job.state == JobState::Running { " &" } else { "" };
+        let pid = if long { format!(" {}", job.pid) } else { " ".to_string() };
+        format!("[{}]{}{} {:<24}{}{}", job.id, self.marker(index), pid, state, job.command, suffix)
+    }
+
+    pub fn info(&self, index: usize) -> JobInfo {
+        let job = &self.jobs[index];
+        let (state, exit_status) = match job.state {
+            JobState::Running => ("running", None),
+            JobState::Stopped => ("stopped", None),
+            JobState::Done(status) => ("done", Some(status)),
+        };
+        JobInfo {
+            id: job.id,
+            pid: job.pid,
+            pgid: job.pgid,
+            command: job.command.clone(),
+            state,
+            exit_status,
+            current: Some(self.marker(index)).filter(|marker| *marker != ' '),
+        }
+    }
+}
+
+impl Default for JobTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A job is done once all its processes are, with the status of the last
+/// one, and stopped once none of them is still running
+fn overall_state(processes: &[(libc::pid_t, JobState)]) -> JobState {
+    if processes.iter().all(|(_, state)| matches!(state, JobState::Done(_))) {
+        processes.last().map_or(JobState::Done(0), |&(_, state)| state)
+    } else if processes.iter().any(|(_, state)| *state == JobState::Running) {
+        JobState::Running
+    } else {
+        JobState::Stopped
+    }
+}
+
+/// Make the shell immune to terminal stop signals, so that Ctrl-Z stops
+/// only the foreground job
+pub fn ignore_stop_signals() {
+    for signal in STOP_SIGNALS {
+        unsafe { libc::signal(signal, libc::SIG_IGN) };
+    }
+}
+
+/// Set up a forked job process: join process group `pgid` (0 for a new
+/// group led by itself), take the `terminal` if the job runs in the
+/// foreground, and restore the stop signals the shell ignores. The parent
+/// calls setpgid too, so neither has to wait for the other. Only makes
+/// async-signal-safe calls, so it can run between fork and exec.
+pub fn enter_job(terminal: libc::c_int, pgid: libc::pid_t, foreground: bool) {
+    unsafe {
+        libc::setpgid(0, pgid);
+        if foreground {
+            libc::tcsetpgrp(terminal, libc::getpgrp());
+        }
+    }
+    restore_stop_signals();
+}
+
+/// Undo `ignore_stop_signals`, for a process about to exec a command
+pub fn restore_stop_signals() {
+    for signal in STOP_SIGNALS {
+        unsafe { libc::signal(signal, libc::SIG_DFL) };
+    }
+}
+
+/// Block until some child process has exited or stopped, without reaping
+/// it, so that `update` can still record it in its job. Returns false if a
+/// signal interrupted the wait.
+pub fn wait_for_change() -> Result<bool, String> {
+    let mut info: libc::siginfo_t = unsafe { std::mem::zeroed() };
+    let flags = libc::WEXITED | libc::WSTOPPED | libc::WNOWAIT;
+    if unsafe { libc::waitid(libc::P_ALL, 0, &mut info, flags) } == 0 {
+        return Ok(true);
+    }
+    let error = std::io::Error::last_os_error();
+    if error.kind() == std::io::ErrorKind::Interrupted {
+        return Ok(false);
+    }
+    Err(format!("waitid: {}", error))
+}
+
+/// Wait for `pid` to finish or be stopped by a signal
+pub fn wait_stoppable(pid: libc::pid_t) -> Result<JobState, String> {
+    let mut status = 0;
+    loop {
+        if unsafe { libc::waitpid(pid, &mut status, libc::WUNTRACED) } >= 0 {
+            break;
+        }
+        let error = std::io::Error::last_os_error();
+        if error.kind() != std::io::ErrorKind::Interrupted {
+            return Err(format!("waitpid: {}", error));
+        }
+    }
+
+    if libc::WIFSTOPPED(status) {
+        Ok(JobState::Stopped)
+    } else {
+        Ok(JobState::Done(exit_status(status)))
+    }
+}
+
+/// Convert a waitpid status into a shell exit status (128 + signal number
+/// when killed by a signal)
+pub fn exit_status(status: libc::c_int) -> i32 {
+    if libc::WIFEXITED(status) {
+        libc::WEXITSTATUS(status)
+    } else if libc::WIFSIGNALED(status) {
+        128 + libc::WTERMSIG(status)
+    } else {
+        1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_job_numbers_and_markers() {
+        let mut table = JobTable::new();
+        assert_eq!(table.add(&[100], None, "sleep 10".to_string()), 1);
+        assert_eq!(table.add(&[101], None, "make".to_string()), 2);
+        assert_eq!(table.add(&[102], None, "tail -f log".to_string()), 3);
+
+        assert_eq!(table.marker(0), ' ');
+        assert_eq!(table.marker(1), '-');
+        assert_eq!(table.marker(2), '+');
+
+        table.jobs[2].state = JobState::Done(0);
+        table.jobs[1].state = JobState::Done(2);
+        assert_eq!(table.format(0, false), "[1]   Running                 sleep 10 &");
+        assert_eq!(table.format(1, true), "[2]- 101 Exit 2                  make");
+
+        table.remove_done();
+        assert_eq!(table.jobs().len(), 1);
+        assert_eq!(table.add(&[103], None, "top".to_string()), 2);
+    }
+
+    #[test]
+    fn test_find_job_specs() {
+        let mut table = JobTable::new();
+        table.add(&[100], None, "sleep 10".to_string());
+        table.add(&[101], None, "make all".to_string());
+        table.add(&[102], None, "sleep 20".to_string());
+
+        assert_eq!(table.find(None), Ok(2));
+        assert_eq!(table.find(Some("%+")), Ok(2));
+        assert_eq!(table.find(Some("%%")), Ok(2));
+        assert_eq!(table.find(Some("%-")), Ok(1));
+        assert_eq!(table.find(Some("%1")), Ok(0));
+        assert_eq!(table.find(Some("%make")), Ok(1));
+        assert_eq!(table.find(Some("%?20")), Ok(2));
+        assert_eq!(table.find(Some("%sleep")), Err("%sleep: ambiguous job spec".to_string()));
+        assert_eq!(table.find(Some("%7")), Err("%7: no such job".to_string()));
+        assert!(table.find(Some("1")).is_err());
+
+        table.touch(0);
+        assert_eq!(table.find(Some("%+")), Ok(0));
+        assert_eq!(table.find(Some("%-")), Ok(2));
+        assert_eq!(table.marker(0), '+');
+        assert_eq!(table.marker(1), ' ');
+    }
+
+    #[test]
+    fn test_update_tracks_stopped_and_finished_jobs() {
+        let mut child = std::process::Command::new("sleep").arg("5").spawn().unwrap();
+        let pid = child.id() as libc::pid_t;
+        let mut table = JobTable::new();
+        table.add(&[pid], None, "sleep 5".to_string());
+
+        unsafe { libc::kill(pid, libc::SIGSTOP) };
+        wait_for_state(&mut table, JobState::Stopped);
+        assert_eq!(table.format(0, false), "[1]+  Stopped                 sleep 5");
+
+        unsafe { libc::kill(pid, libc::SIGKILL) };
+        wait_for_state(&mut table, JobState::Done(128 + libc::SIGKILL));
+        // Already reaped by update(), this only satisfies Child
+        let _ = child.wait();
+    }
+
+    #[test]
+    fn test_wait_returns_when_a_process_stops() {
+        let mut first = std::process::Command::new("sleep").arg("5").spawn().unwrap();
+        let mut second = std::process::Command::new("sleep").arg("5").spawn().unwrap();
+        let pids = [first.id() as libc::pid_t, second.id() as libc::pid_t];
+        let mut table = JobTable::new();
+        table.add(&pids, None, "sleep 5 | sleep 5".to_string());
+
+        unsafe { libc::kill(pids[0], libc::SIGSTOP) };
+        assert_eq!(table.wait(0), Ok(JobState::Stopped));
+        assert_eq!(table.jobs()[0].state, JobState::Stopped);
+
+        table.signal(0, libc::SIGKILL).unwrap();
+        assert_eq!(table.wait(0), Ok(JobState::Done(128 + libc::SIGKILL)));
+        let _ = first.wait();
+        let _ = second.wait();
+    }
+
+    #[test]
+    fn test_notifications_report_each_change_once() {
+        let mut child = std::process::Command::new("true").spawn().unwrap();
+        let mut table = JobTable::new();
+        table.add(&[child.id() as libc::pid_t], None, "true".to_string());
+
+        wait_for_state(&mut table, JobState::Done(0));
+        assert_eq!(table.take_notifications(), vec!["[1]+  Done                    true"]);
+        assert!(table.jobs().is_empty());
+        assert!(table.take_notifications().is_empty());
+        assert_eq!(table.take_retained(child.id() as libc::pid_t), Some(0));
+        assert_eq!(table.take_retained(child.id() as libc::pid_t), None);
+        let _ = child.wait();
+    }
+
+    fn wait_for_state(table: &mut JobTable, state: JobState) {
+        for _ in 0..500 {
+            table.update();
+            if table.jobs()[0].state == state {
+                return;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        panic!("job never reached {:?}", state);
+    }
+}
@@ -1,3 +1,5 @@
+use crate::messages::tr;
+use crate::parameter;
 use crate::token::{Position, Token, TokenKind};
 
 pub struct Lexer {
@@ -5,6 +7,9 @@ pub struct Lexer {
     position: usize,
     line: usize,
     column: usize,
+    /// Where lexing continues after the next newline when here-document
+    /// bodies following it have already been read
+    heredoc_resume: Option<usize>,
 }
 
 impl Lexer {
@@ -14,6 +19,7 @@ impl Lexer {
             position: 0,
             line: 1,
             column: 1,
+            heredoc_resume: None,
         }
     }
 
@@ -27,7 +33,20 @@ impl Lexer {
             }
 
             let token = self.next_token()?;
+            let heredoc = match tokens.last().map(|t: &Token| &t.kind) {
+                Some(TokenKind::LessLess) if token.kind == TokenKind::Word => Some(false),
+                Some(TokenKind::LessLessDash) if token.kind == TokenKind::Word => Some(true),
+                _ => None,
+            };
+            let (delimiter, pos) = (unquote(&token.value), token.position);
             tokens.push(token);
+
+            // The body follows the current line, but is attached right
+            // after its delimiter so the parser sees it with the redirection
+            if let Some(strip_tabs) = heredoc {
+                let body = self.read_heredoc_body(&delimiter, strip_tabs)?;
+                tokens.push(Token::new(TokenKind::HeredocBody, body, pos));
+            }
         }
 
         tokens.push(Token::new(
@@ -46,6 +65,12 @@ impl Lexer {
         match ch {
             '\n' => {
                 self.advance();
+                if let Some(resume) = self.heredoc_resume.take() {
+                    // Skip the here-document bodies already read
+                    while self.position < resume {
+                        self.advance();
+                    }
+                }
                 Ok(Token::new(TokenKind::Newline, "\n".to_string(), pos))
             }
             ';' => {
@@ -62,6 +87,9 @@ impl Lexer {
                 if self.current_char() == '|' {
                     self.advance();
                     Ok(Token::new(TokenKind::Or, "||".to_string(), pos))
+                } else if self.current_char() == '&' {
+                    self.advance();
+                    Ok(Token::new(TokenKind::PipeAnd, "|&".to_string(), pos))
                 } else {
                     Ok(Token::new(TokenKind::Pipe, "|".to_string(), pos))
                 }
@@ -73,11 +101,19 @@ impl Lexer {
                     Ok(Token::new(TokenKind::And, "&&".to_string(), pos))
                 } else if self.current_char() == '>' {
                     self.advance();
-                    Ok(Token::new(TokenKind::AndGreat, "&>".to_string(), pos))
+                    if self.current_char() == '>' {
+                        self.advance();
+                        Ok(Token::new(TokenKind::AndGreatGreat, "&>>".to_string(), pos))
+                    } else {
+                        Ok(Token::new(TokenKind::AndGreat, "&>".to_string(), pos))
+                    }
                 } else {
                     Ok(Token::new(TokenKind::Ampersand, "&".to_string(), pos))
                 }
             }
+            // `<(...)` and `>(...)` are process substitutions, words
+            // rather than redirections
+            '<' | '>' if self.input.get(self.position + 1) == Some(&'(') => self.read_word(pos),
             '>' => {
                 self.advance();
                 if self.current_char() == '>' {
@@ -100,6 +136,9 @@ impl Lexer {
                     if self.current_char() == '-' {
                         self.advance();
                         Ok(Token::new(TokenKind::LessLessDash, "<<-".to_string(), pos))
+                    } else if self.current_char() == '<' {
+                        self.advance();
+                        Ok(Token::new(TokenKind::LessLessLess, "<<<".to_string(), pos))
                     } else {
                         Ok(Token::new(TokenKind::LessLess, "<<".to_string(), pos))
                     }
@@ -113,11 +152,16 @@ impl Lexer {
                     Ok(Token::new(TokenKind::Less, "<".to_string(), pos))
                 }
             }
+            // `!(...)` is an extended pattern
+            '!' if self.input.get(self.position + 1) == Some(&'(') => self.read_word(pos),
             '!' => {
                 self.advance();
                 Ok(Token::new(TokenKind::Not, "!".to_string(), pos))
             }
             '(' => {
+                if let Some(expression) = self.arithmetic_command() {
+                    return Ok(Token::new(TokenKind::Arithmetic, expression, pos));
+                }
                 self.advance();
                 Ok(Token::new(TokenKind::LeftParen, "(".to_string(), pos))
             }
@@ -125,14 +169,17 @@ impl Lexer {
                 self.advance();
                 Ok(Token::new(TokenKind::RightParen, ")".to_string(), pos))
             }
-            '{' => {
+            // Braces are reserved words, but only on their own, as
+            // `{a,b}` is a word to brace-expand
+            '{' if self.at_word_end(1) => {
                 self.advance();
                 Ok(Token::new(TokenKind::LeftBrace, "{".to_string(), pos))
             }
-            '}' => {
+            '}' if self.at_word_end(1) => {
                 self.advance();
                 Ok(Token::new(TokenKind::RightBrace, "}".to_string(), pos))
             }
+            '{' | '}' => self.read_word(pos),
             '-' if self.is_standalone_dash() => {
                 self.advance();
                 Ok(Token::new(TokenKind::Dash, "-".to_string(), pos))
@@ -144,15 +191,12 @@ impl Lexer {
                 }
                 self.next_token()
             }
-            '"' => self.read_quoted_string('"'),
-            '\'' => self.read_quoted_string('\''),
-            '$' => self.read_variable_or_word(pos),
+            '"' | '\'' => self.read_word(pos),
+            '$' | '`' => self.read_variable_or_word(pos),
+            '\\' => self.read_word(pos),
             _ if ch.is_ascii_digit() => self.read_number_or_word(pos),
             _ if self.is_word_start(ch) => self.read_word(pos),
-            _ => Err(format!(
-                "Unexpected character '{}' at {}:{}",
-                ch, self.line, self.column
-            )),
+            _ => Err(tr!("Unexpected character '{}' at {}:{}", ch, self.line, self.column)),
         }
     }
 
@@ -169,39 +213,22 @@ impl Lexer {
 
             if ch == '$' {
                 // Variable expansion
-                word.push(self.current_char());
-                self.advance();
-
-                // Check for ${VAR} syntax
-                if !self.is_eof() && self.current_char() == '{' {
-                    word.push(self.current_char());
-                    self.advance();
-
-                    while !self.is_eof() && self.current_char() != '}' {
-                        word.push(self.current_char());
-                        self.advance();
-                    }
-
-                    if self.is_eof() {
-                        return Err("Unclosed variable expansion".to_string());
-                    }
-
-                    word.push(self.current_char()); // closing }
-                    self.advance();
-                } else {
-                    // $VAR syntax - read variable name
-                    while !self.is_eof() && (self.current_char().is_alphanumeric() || self.current_char() == '_') {
-                        word.push(self.current_char());
-                        self.advance();
-                    }
-                }
+                self.read_expansion(&mut word)?;
+            } else if ch == '`' {
+                self.read_backquote(&mut word, false)?;
+            } else if ch == '\\' {
+                self.read_escape(&mut word);
+            } else if ch == '\'' || ch == '"' {
+                // A quoted part, joined to the rest of the word
+                self.read_quoted(&mut word)?;
+            } else if ch == '(' && self.at_extended_pattern(&word) {
+                self.read_parenthesized(&mut word);
             } else if self.is_word_char(ch) {
                 // Regular word character
                 word.push(self.current_char());
                 self.advance();
             } else if ch.is_whitespace() || ch == ';' || ch == '&' || ch == '|'
-                    || ch == '>' || ch == '<' || ch == '(' || ch == ')'
-                    || ch == '{' || ch == '}' || ch == '\'' || ch == '"' {
+                    || ch == '>' || ch == '<' || ch == '(' || ch == ')' {
                 // Word boundary
                 break;
             } else {
@@ -227,38 +254,28 @@ impl Lexer {
 
             if ch == '$' {
                 // Variable expansion within the word
-                word.push(self.current_char());
+                self.read_expansion(&mut word)?;
+            } else if ch == '`' {
+                self.read_backquote(&mut word, false)?;
+            } else if ch == '\\' {
+                self.read_escape(&mut word);
+            } else if matches!(ch, '<' | '>') && word.is_empty() && self.input.get(self.position + 1) == Some(&'(') {
+                let pos = Position::new(self.line, self.column);
+                word.push(ch);
                 self.advance();
-
-                // Check for ${VAR} syntax
-                if !self.is_eof() && self.current_char() == '{' {
-                    word.push(self.current_char());
-                    self.advance();
-
-                    while !self.is_eof() && self.current_char() != '}' {
-                        word.push(self.current_char());
-                        self.advance();
-                    }
-
-                    if self.is_eof() {
-                        return Err("Unclosed variable expansion".to_string());
-                    }
-
-                    word.push(self.current_char()); // closing }
-                    self.advance();
-                } else {
-                    // $VAR syntax - read variable name
-                    while !self.is_eof() && (self.current_char().is_alphanumeric() || self.current_char() == '_') {
-                        word.push(self.current_char());
-                        self.advance();
-                    }
+                if !self.read_parenthesized(&mut word) {
+                    return Err(tr!("Unterminated process substitution at {}:{}", pos.line, pos.column));
                 }
+            } else if ch == '\'' || ch == '"' {
+                self.read_quoted(&mut word)?;
+            } else if ch == '(' && self.at_extended_pattern(&word) {
+                self.read_parenthesized(&mut word);
             } else if self.is_word_char(ch) {
                 word.push(self.current_char());
                 self.advance();
             } else if ch.is_whitespace() || ch == ';' || ch == '&' || ch == '|'
                     || ch == '>' || ch == '<' || ch == '(' || ch == ')'
-                    || ch == '{' || ch == '}' || ch == '\'' || ch == '"' || ch == '=' {
+                    || (ch == '=' && is_name(&word)) {
                 // Word boundary
                 break;
             } else {
@@ -273,33 +290,15 @@ impl Lexer {
             word.push('=');
             self.advance();
 
-            // Read the value part (which might be quoted)
-            if !self.is_eof() {
-                if self.current_char() == '"' || self.current_char() == '\'' {
-                    // Read quoted value
-                    let quote = self.current_char();
-                    self.advance(); // Skip opening quote
-
-                    while !self.is_eof() && self.current_char() != quote {
-                        if self.current_char() == '\\' && quote == '"' {
-                            self.advance();
-                            if !self.is_eof() {
-                                word.push(self.current_char());
-                                self.advance();
-                            }
-                        } else {
-                            word.push(self.current_char());
-                            self.advance();
-                        }
-                    }
-
-                    if !self.is_eof() {
-                        self.advance(); // Skip closing quote
-                    }
-                } else {
-                    // Read unquoted value
-                    while !self.is_eof() && self.is_word_char(self.current_char()) {
-                        word.push(self.current_char());
+            // Read the value, which may join quoted and unquoted parts
+            while !self.is_eof() && (self.is_word_char(self.current_char()) || matches!(self.current_char(), '`' | '\\' | '\'' | '"')) {
+                match self.current_char() {
+                    '$' => self.read_expansion(&mut word)?,
+                    '`' => self.read_backquote(&mut word, false)?,
+                    '\\' => self.read_escape(&mut word),
+                    '\'' | '"' => self.read_quoted(&mut word)?,
+                    ch => {
+                        word.push(ch);
                         self.advance();
                     }
                 }
@@ -332,6 +331,147 @@ impl Lexer {
         Ok(Token::new(kind, word, pos))
     }
 
+    /// Read a `$` expansion into `word`: `${...}`, `$(...)`, a variable
+    /// name, or a single-character special parameter like `$#` or `$1`
+    fn read_expansion(&mut self, word: &mut String) -> Result<(), String> {
+        word.push(self.current_char()); // '$'
+        self.advance();
+
+        if self.is_eof() {
+            return Ok(());
+        }
+
+        let ch = self.current_char();
+        if ch == '(' {
+            let pos = Position::new(self.line, self.column);
+            if !self.read_parenthesized(word) {
+                return Err(tr!("Unterminated command substitution at {}:{}", pos.line, pos.column));
+            }
+        } else if ch == '{' {
+            // Through the matching brace, as the word of `${x:-${y}}` may
+            // hold expansions of its own
+            let mut depth = 0;
+            loop {
+                if self.is_eof() {
+                    return Err(tr!("Unclosed variable expansion"));
+                }
+                let ch = self.current_char();
+                word.push(ch);
+                self.advance();
+                match ch {
+                    '{' => depth += 1,
+                    '}' if depth == 1 => break,
+                    '}' => depth -= 1,
+                    _ => {}
+                }
+            }
+        } else if ch.is_ascii_digit() || matches!(ch, '#' | '@' | '*' | '?' | '$' | '!' | '-') {
+            word.push(ch);
+            self.advance();
+        } else {
+            // $VAR syntax - read variable name
+            while !self.is_eof() && (self.current_char().is_alphanumeric() || self.current_char() == '_') {
+                word.push(self.current_char());
+                self.advance();
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Read the `(...)` of a command or process substitution into `word`.
+    /// False if it is never closed, leaving the lexer at EOF so the input
+    /// counts as incomplete.
+    fn read_parenthesized(&mut self, word: &mut String) -> bool {
+        let Some(len) = substitution_len(self.input[self.position..].iter().copied()) else {
+            while !self.is_eof() {
+                self.advance();
+            }
+            return false;
+        };
+        for _ in 0..len {
+            word.push(self.current_char());
+            self.advance();
+        }
+        true
+    }
+
+    /// Read a `` `...` `` command substitution into `word`, rewritten as the
+    /// `$(...)` it stands for. Inside, a backslash only escapes `$`, `` ` ``
+    /// and `\`, and `"` too within double quotes; before anything else it
+    /// is kept as it is.
+    fn read_backquote(&mut self, word: &mut String, in_double_quotes: bool) -> Result<(), String> {
+        let pos = Position::new(self.line, self.column);
+        self.advance(); // Skip opening backquote
+
+        let mut source = String::new();
+        while !self.is_eof() && self.current_char() != '`' {
+            if self.current_char() == '\\' {
+                self.advance();
+                let ch = self.current_char();
+                if !(matches!(ch, '$' | '`' | '\\') || (in_double_quotes && ch == '"')) {
+                    source.push('\\');
+                }
+            }
+            if !self.is_eof() {
+                source.push(self.current_char());
+                self.advance();
+            }
+        }
+
+        if self.is_eof() {
+            return Err(tr!("Unterminated command substitution at {}:{}", pos.line, pos.column));
+        }
+        self.advance(); // Skip closing backquote
+
+        let substitution = format!("({})", source);
+        if substitution_len(substitution.chars()) != Some(substitution.chars().count()) {
+            return Err(tr!("Unbalanced parentheses in command substitution at {}:{}", pos.line, pos.column));
+        }
+        word.push('$');
+        word.push_str(&substitution);
+        Ok(())
+    }
+
+    /// Read a here-document body: the lines after the current one (or after
+    /// the previous body on this line) up to `delimiter`. With `<<-`,
+    /// leading tabs are stripped from the body and the delimiter line.
+    fn read_heredoc_body(&mut self, delimiter: &str, strip_tabs: bool) -> Result<String, String> {
+        let start = match self.heredoc_resume {
+            Some(resume) => resume,
+            None => match self.input[self.position..].iter().position(|&c| c == '\n') {
+                Some(offset) => self.position + offset + 1,
+                None => self.input.len(),
+            },
+        };
+
+        let mut body = String::new();
+        let mut line_start = start;
+        while line_start < self.input.len() {
+            let line_end = self.input[line_start..]
+                .iter()
+                .position(|&c| c == '\n')
+                .map_or(self.input.len(), |offset| line_start + offset);
+            let line: String = self.input[line_start..line_end].iter().collect();
+            let line = if strip_tabs { line.trim_start_matches('\t') } else { line.as_str() };
+
+            let next = (line_end + 1).min(self.input.len());
+            if line == delimiter {
+                self.heredoc_resume = Some(next);
+                return Ok(body);
+            }
+            body.push_str(line);
+            body.push('\n');
+            line_start = next;
+        }
+
+        // Leave the lexer at EOF so the input counts as incomplete
+        while !self.is_eof() {
+            self.advance();
+        }
+        Err(tr!("here-document delimited by end-of-file (wanted `{}')", delimiter))
+    }
+
     fn read_number_or_word(&mut self, pos: Position) -> Result<Token, String> {
         let mut value = String::new();
 
@@ -346,60 +486,82 @@ impl Lexer {
             return Ok(Token::new(TokenKind::Number, value, pos));
         }
 
-        // Otherwise, continue reading as a word
-        while !self.is_eof() && self.is_word_char(self.current_char()) {
-            value.push(self.current_char());
-            self.advance();
-        }
-
-        // Check for assignment
-        if !self.is_eof() && self.current_char() == '=' {
-            value.push('=');
-            self.advance();
-            while !self.is_eof() && self.is_word_char(self.current_char()) {
-                value.push(self.current_char());
-                self.advance();
-            }
-            return Ok(Token::new(TokenKind::AssignmentWord, value, pos));
-        }
-
-        Ok(Token::new(TokenKind::Word, value, pos))
+        // Otherwise, read it again as a word; the digits span no newline
+        self.position -= value.len();
+        self.column -= value.len();
+        self.read_word(pos)
     }
 
-    fn read_quoted_string(&mut self, quote: char) -> Result<Token, String> {
+    /// Read a quoted string into `word`, quotes and escapes included, as
+    /// expansion needs them to know what not to split or expand
+    fn read_quoted(&mut self, word: &mut String) -> Result<(), String> {
         let pos = Position::new(self.line, self.column);
-        let mut value = String::new();
+        let quote = self.current_char();
+        word.push(quote);
         self.advance(); // Skip opening quote
 
         while !self.is_eof() && self.current_char() != quote {
             if self.current_char() == '\\' && quote == '"' {
-                // Handle escape sequences in double quotes
-                self.advance();
-                if !self.is_eof() {
-                    value.push(self.current_char());
-                    self.advance();
-                }
+                self.read_escape(word);
+            } else if quote == '"' && self.at_substitution() {
+                self.read_expansion(word)?;
+            } else if quote == '"' && self.current_char() == '`' {
+                self.read_backquote(word, true)?;
             } else {
-                value.push(self.current_char());
+                word.push(self.current_char());
                 self.advance();
             }
         }
 
         if self.is_eof() {
-            return Err(format!("Unterminated string at {}:{}", pos.line, pos.column));
+            return Err(tr!("Unterminated string at {}:{}", pos.line, pos.column));
         }
 
+        word.push(quote);
         self.advance(); // Skip closing quote
+        Ok(())
+    }
 
-        Ok(Token::new(TokenKind::Word, value, pos))
+    /// Read a backslash into `word` together with the char it escapes
+    fn read_escape(&mut self, word: &mut String) {
+        word.push('\\');
+        self.advance();
+        if !self.is_eof() {
+            word.push(self.current_char());
+            self.advance();
+        }
     }
 
     fn is_word_start(&self, ch: char) -> bool {
-        ch.is_alphabetic() || ch == '_' || ch == '-' || ch == '.' || ch == '/'
+        ch.is_alphabetic() || matches!(ch, '_' | '-' | '.' | '/' | '=' | '%' | '+' | '@' | ',') || self.is_glob_char(ch)
+    }
+
+    /// Whether the `(` here opens the group of an extended pattern, like
+    /// `@(a|b)`: it follows an unescaped `?`, `*`, `+`, `@` or `!` and is
+    /// closed. The group, `|`s and all, belongs to the word.
+    fn at_extended_pattern(&self, word: &str) -> bool {
+        let escapes = word.chars().rev().skip(1).take_while(|&ch| ch == '\\').count();
+        word.ends_with(['?', '*', '+', '@', '!'])
+            && escapes % 2 == 0
+            && substitution_len(self.input[self.position..].iter().copied()).is_some()
     }
 
     fn is_word_char(&self, ch: char) -> bool {
-        ch.is_alphanumeric() || ch == '_' || ch == '-' || ch == '.' || ch == '/' || ch == '$'
+        ch.is_alphanumeric()
+            || matches!(ch, '_' | '-' | '.' | '/' | '$' | '%' | '+' | ',')
+            || self.is_glob_char(ch)
+    }
+
+    fn is_glob_char(&self, ch: char) -> bool {
+        ch == '*' || ch == '?' || ch == '[' || ch == ']'
+    }
+
+    /// Whether the word ends `offset` chars on, where a blank, an operator
+    /// or the end of the input follows
+    fn at_word_end(&self, offset: usize) -> bool {
+        self.input
+            .get(self.position + offset)
+            .is_none_or(|&next| next.is_whitespace() || matches!(next, ';' | '&' | '|' | '<' | '>' | '(' | ')'))
     }
 
     fn is_standalone_dash(&self) -> bool {
@@ -422,6 +584,37 @@ impl Lexer {
         }
     }
 
+    /// At `((`, the expression of an arithmetic command through its `))`,
+    /// if it has one. Otherwise the parentheses open nested subshells, as
+    /// in `((cd src; make) )`.
+    fn arithmetic_command(&mut self) -> Option<String> {
+        if self.input.get(self.position + 1) != Some(&'(') {
+            return None;
+        }
+        let mut depth = 0;
+        let mut end = self.position + 2;
+        loop {
+            match self.input.get(end)? {
+                '(' => depth += 1,
+                ')' if depth > 0 => depth -= 1,
+                ')' if self.input.get(end + 1) == Some(&')') => break,
+                ')' => return None,
+                _ => {}
+            }
+            end += 1;
+        }
+        let expression: String = self.input[self.position + 2..end].iter().collect();
+        while self.position < end + 2 {
+            self.advance();
+        }
+        Some(expression)
+    }
+
+    /// At the `$(` or `${` of an expansion, whose text may hold quotes
+    fn at_substitution(&self) -> bool {
+        self.current_char() == '$' && matches!(self.input.get(self.position + 1), Some('(' | '{'))
+    }
+
     fn current_char(&self) -> char {
         if self.is_eof() {
             '\0'
@@ -442,9 +635,187 @@ impl Lexer {
         }
     }
 
-    fn is_eof(&self) -> bool {
+    pub fn is_eof(&self) -> bool {
         self.position >= self.input.len()
     }
+
+    /// Line the lexer stopped at, for locating errors
+    pub fn line(&self) -> usize {
+        self.line
+    }
+}
+
+/// Whether `word` is a valid variable name, i.e. may start an assignment
+fn is_name(word: &str) -> bool {
+    let mut chars = word.chars();
+    chars.next().is_some_and(|c| c.is_alphabetic() || c == '_') && chars.all(|c| c.is_alphanumeric() || c == '_')
+}
+
+/// Quote `word` so that the shell reads it back as that one word, leaving
+/// it as it is when nothing in it is special
+pub fn quote(word: &str) -> String {
+    let is_plain = !word.is_empty()
+        && word
+            .chars()
+            .all(|c| c.is_alphanumeric() || matches!(c, '/' | '.' | '_' | '-' | '+' | ',' | ':' | '@'));
+    if is_plain {
+        word.to_string()
+    } else {
+        format!("'{}'", word.replace('\'', "'\\''"))
+    }
+}
+
+/// `word` with its quotes and escapes removed but nothing expanded, as the
+/// delimiter of a here-document is matched
+pub fn unquote(word: &str) -> String {
+    let mut result = String::new();
+    let mut index = 0;
+    while let Some(ch) = word[index..].chars().next() {
+        let len = unit_len(&word[index..]);
+        let unit = &word[index..index + len];
+        match ch {
+            '\'' => result.push_str(unit[1..].strip_suffix('\'').unwrap_or(&unit[1..])),
+            '"' => {
+                let mut chars = unit[1..].strip_suffix('"').unwrap_or(&unit[1..]).chars().peekable();
+                while let Some(ch) = chars.next() {
+                    match chars.next_if(|next| ch == '\\' && matches!(next, '$' | '`' | '"' | '\\')) {
+                        Some(escaped) => result.push(escaped),
+                        None => result.push(ch),
+                    }
+                }
+            }
+            '\\' => result.push_str(&unit[1..]),
+            _ => result.push_str(unit),
+        }
+        index += len;
+    }
+    result
+}
+
+/// The length in bytes of the first unit of the word `text`: a quoted
+/// string, a backslash with the char it escapes, a `$(...)`, `<(...)` or
+/// `${...}` as a whole, or else one char. One never closed runs to the end.
+pub fn unit_len(text: &str) -> usize {
+    let mut chars = text.chars();
+    let Some(first) = chars.next() else {
+        return 0;
+    };
+    let rest = &text[first.len_utf8()..];
+    match first {
+        '\\' => 1 + chars.next().map_or(0, char::len_utf8),
+        '\'' => rest.find('\'').map_or(text.len(), |end| end + 2),
+        '"' => {
+            let mut index = 1;
+            while let Some(ch) = text[index..].chars().next() {
+                match ch {
+                    '"' => return index + 1,
+                    '\\' | '$' => index += unit_len(&text[index..]),
+                    _ => index += ch.len_utf8(),
+                }
+            }
+            text.len()
+        }
+        '$' | '<' | '>' if rest.starts_with('(') => match substitution_len(rest.chars()) {
+            Some(len) => 1 + rest.chars().take(len).map(char::len_utf8).sum::<usize>(),
+            None => text.len(),
+        },
+        '$' if rest.starts_with('{') => parameter::expansion_len(rest).map_or(text.len(), |len| 1 + len),
+        _ => first.len_utf8(),
+    }
+}
+
+/// `text` split at each `separator` outside quotes and expansions
+pub fn split_unquoted(text: &str, separator: char) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut start = 0;
+    let mut index = 0;
+    while let Some(ch) = text[index..].chars().next() {
+        if ch == separator {
+            parts.push(&text[start..index]);
+            start = index + ch.len_utf8();
+        }
+        index += unit_len(&text[index..]);
+    }
+    parts.push(&text[start..]);
+    parts
+}
+
+/// The length in chars of the `(...)` that `chars` starts with, through
+/// its matching `)`, as in a `$(...)` command substitution. Quoted or
+/// escaped parentheses don't count. None if it is never closed.
+pub fn substitution_len(chars: impl Iterator<Item = char>) -> Option<usize> {
+    let mut depth = 0;
+    let mut quote = None;
+    let mut escaped = false;
+    for (index, ch) in chars.enumerate() {
+        if escaped {
+            escaped = false;
+            continue;
+        }
+        match (quote, ch) {
+            (Some('\''), '\'') => quote = None,
+            (Some('\''), _) => {}
+            (_, '\\') => escaped = true,
+            (Some(_), '"') => quote = None,
+            (Some(_), _) => {}
+            (None, '\'' | '"') => quote = Some(ch),
+            (None, '(') => depth += 1,
+            (None, ')') => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(index + 1);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// The `<(...)` or `>(...)` process substitution that `word` starts with,
+/// as its `<` or `>`, its source and the rest of the word
+pub fn process_substitution(word: &str) -> Option<(char, &str, &str)> {
+    let direction = word.chars().next().filter(|&ch| ch == '<' || ch == '>')?;
+    if !word[1..].starts_with('(') {
+        return None;
+    }
+    let len = substitution_len(word[1..].chars())?;
+    let end = 1 + word[1..].chars().take(len).map(char::len_utf8).sum::<usize>();
+    Some((direction, &word[2..end - 1], &word[end..]))
+}
+
+/// The source of each command substitution in `word`, in order: a leading
+/// process substitution, then each `$(...)` outside single quotes
+pub fn command_substitutions(word: &str) -> Vec<&str> {
+    let mut sources = Vec::new();
+    let mut rest = word;
+    if let Some((_, source, after)) = process_substitution(word) {
+        sources.push(source);
+        rest = after;
+    }
+    let mut in_double_quotes = false;
+    let mut index = 0;
+    while let Some(ch) = rest[index..].chars().next() {
+        let tail = &rest[index..];
+        index += match ch {
+            '$' if tail[1..].starts_with('(') => {
+                let Some(len) = substitution_len(tail[1..].chars()) else {
+                    break;
+                };
+                let end = 1 + tail[1..].chars().take(len).map(char::len_utf8).sum::<usize>();
+                sources.push(&tail[2..end - 1]);
+                end
+            }
+            '\'' if !in_double_quotes => unit_len(tail),
+            '\\' => unit_len(tail),
+            '"' => {
+                in_double_quotes = !in_double_quotes;
+                1
+            }
+            _ => ch.len_utf8(),
+        };
+    }
+    sources
 }
 
 #[cfg(test)]
@@ -476,6 +847,30 @@ mod tests {
         assert!(tokens.iter().any(|t| t.kind == TokenKind::Greater));
     }
 
+    #[test]
+    fn test_append_both_redirection() {
+        let mut lexer = Lexer::new("make &>> build.log");
+        let tokens = lexer.tokenize().unwrap();
+        assert_eq!(tokens[1].kind, TokenKind::AndGreatGreat);
+        assert_eq!(tokens[2].value, "build.log");
+    }
+
+    #[test]
+    fn test_glob_words() {
+        let mut lexer = Lexer::new("ls *.rs [ab]? foo*");
+        let tokens = lexer.tokenize().unwrap();
+        let words: Vec<&str> = tokens.iter().map(|t| t.value.as_str()).collect();
+        assert_eq!(words, vec!["ls", "*.rs", "[ab]?", "foo*", ""]);
+    }
+
+    #[test]
+    fn test_job_spec_words() {
+        let mut lexer = Lexer::new("fg %+ %- %% %1 %?vim");
+        let tokens = lexer.tokenize().unwrap();
+        let words: Vec<&str> = tokens.iter().map(|t| t.value.as_str()).collect();
+        assert_eq!(words, vec!["fg", "%+", "%-", "%%", "%1", "%?vim", ""]);
+    }
+
     #[test]
     fn test_assignment() {
         let mut lexer = Lexer::new("FOO=bar");
@@ -483,4 +878,208 @@ mod tests {
         assert_eq!(tokens[0].kind, TokenKind::AssignmentWord);
         assert_eq!(tokens[0].value, "FOO=bar");
     }
+
+    #[test]
+    fn test_special_parameters_and_equals() {
+        let mut lexer = Lexer::new("COUNT=$# test $1 = --color=auto $@");
+        let tokens = lexer.tokenize().unwrap();
+        assert_eq!(tokens[0].kind, TokenKind::AssignmentWord);
+        assert_eq!(tokens[0].value, "COUNT=$#");
+        let words: Vec<&str> = tokens[1..].iter().map(|t| t.value.as_str()).collect();
+        assert_eq!(words, vec!["test", "$1", "=", "--color=auto", "$@", ""]);
+        assert_eq!(tokens[4].kind, TokenKind::Word);
+    }
+
+    #[test]
+    fn test_heredoc_bodies() {
+        let mut lexer = Lexer::new("cat <<EOF; cat <<-END\nhello $USER\nEOF\n\tindented\n\tEND\necho done");
+        let tokens = lexer.tokenize().unwrap();
+        let kinds: Vec<&TokenKind> = tokens.iter().map(|t| &t.kind).collect();
+        assert_eq!(kinds[..4], [&TokenKind::Word, &TokenKind::LessLess, &TokenKind::Word, &TokenKind::HeredocBody]);
+        assert_eq!(tokens[3].value, "hello $USER\n");
+        assert_eq!(tokens[8].value, "indented\n");
+        assert_eq!(tokens[9].kind, TokenKind::Newline);
+        assert_eq!(tokens[10].value, "echo");
+        assert_eq!(tokens[10].position.line, 6);
+    }
+
+    #[test]
+    fn test_heredocs_in_a_pipeline() {
+        let tokens = Lexer::new("cat <<A <<-B | cat - 3<<C\na\nA\n\tb\nB\nc\nC\nnext").tokenize().unwrap();
+        let bodies: Vec<&str> = tokens.iter().filter(|t| t.kind == TokenKind::HeredocBody).map(|t| t.value.as_str()).collect();
+        assert_eq!(bodies, ["a\n", "b\n", "c\n"]);
+        assert_eq!(tokens[5].value, "B");
+        assert_eq!(tokens[6].kind, TokenKind::HeredocBody);
+        assert_eq!(tokens[tokens.len() - 2].value, "next");
+        assert_eq!(tokens[tokens.len() - 2].position.line, 8);
+    }
+
+    #[test]
+    fn test_here_string() {
+        let tokens = Lexer::new("grep foo <<< \"$var\"").tokenize().unwrap();
+        assert_eq!(tokens[2].kind, TokenKind::LessLessLess);
+        assert_eq!(tokens[3].kind, TokenKind::Word);
+        assert_eq!(tokens[3].value, "\"$var\"");
+    }
+
+    #[test]
+    fn test_braces_in_words() {
+        let tokens = Lexer::new("echo {a,b} x{1..3} }; { true;}").tokenize().unwrap();
+        let kinds: Vec<&TokenKind> = tokens.iter().map(|token| &token.kind).collect();
+        assert_eq!(tokens[1].value, "{a,b}");
+        assert_eq!(tokens[2].value, "x{1..3}");
+        assert_eq!(
+            kinds[3..],
+            [&TokenKind::RightBrace, &TokenKind::Semicolon, &TokenKind::LeftBrace, &TokenKind::Word, &TokenKind::Semicolon, &TokenKind::RightBrace, &TokenKind::Eof]
+        );
+    }
+
+    #[test]
+    fn test_quote() {
+        assert_eq!(quote("src/main.rs"), "src/main.rs");
+        assert_eq!(quote("my file"), "'my file'");
+        assert_eq!(quote("it's"), "'it'\\''s'");
+        assert_eq!(quote(""), "''");
+    }
+
+    #[test]
+    fn test_command_substitution() {
+        let tokens = Lexer::new("echo \"today is $(date +%A)\" x$(a $(b) \")\")y").tokenize().unwrap();
+        assert_eq!(tokens[1].value, "\"today is $(date +%A)\"");
+        assert_eq!(tokens[2].value, "x$(a $(b) \")\")y");
+        assert_eq!(command_substitutions(&tokens[2].value), ["a $(b) \")\""]);
+        assert_eq!(command_substitutions("$(one)-$(two)"), ["one", "two"]);
+        assert_eq!(command_substitutions("'$(no)'\"$(yes)\"\\$(no)"), ["yes"]);
+
+        let mut lexer = Lexer::new("echo $(date");
+        assert!(lexer.tokenize().is_err());
+        assert!(lexer.is_eof());
+    }
+
+    #[test]
+    fn test_process_substitution() {
+        let mut lexer = Lexer::new("diff <(sort a) >(tee \"b)\") < <(ls)");
+        let tokens = lexer.tokenize().unwrap();
+        let kinds: Vec<_> = tokens.iter().map(|token| (&token.kind, token.value.as_str())).collect();
+        assert_eq!(
+            kinds,
+            [
+                (&TokenKind::Word, "diff"),
+                (&TokenKind::Word, "<(sort a)"),
+                (&TokenKind::Word, ">(tee \"b)\")"),
+                (&TokenKind::Less, "<"),
+                (&TokenKind::Word, "<(ls)"),
+                (&TokenKind::Eof, ""),
+            ]
+        );
+        assert!(Lexer::new("cat <(ls").tokenize().is_err());
+
+        assert_eq!(process_substitution("<(sort a)"), Some(('<', "sort a", "")));
+        assert_eq!(process_substitution(">(cat)x"), Some(('>', "cat", "x")));
+        assert_eq!(process_substitution("a<(b)"), None);
+        assert_eq!(command_substitutions("<(echo $(pwd))$(date)"), ["echo $(pwd)", "date"]);
+    }
+
+    #[test]
+    fn test_backquote_substitution() {
+        let tokens = Lexer::new("echo `date +%A` x`a \\`b\\` \\$c \\d`y \"[`echo \\\"q\\\"`]\" V=`pwd`")
+            .tokenize()
+            .unwrap();
+        assert_eq!(tokens[1].value, "$(date +%A)");
+        assert_eq!(tokens[2].value, "x$(a `b` $c \\d)y");
+        assert_eq!(tokens[3].value, "\"[$(echo \"q\")]\"");
+        assert_eq!(tokens[4].kind, TokenKind::AssignmentWord);
+        assert_eq!(tokens[4].value, "V=$(pwd)");
+
+        let mut lexer = Lexer::new("echo `date");
+        assert!(lexer.tokenize().is_err());
+        assert!(lexer.is_eof());
+        assert!(Lexer::new("echo `echo )`").tokenize().is_err());
+    }
+
+    #[test]
+    fn test_quotes_kept() {
+        let tokens = Lexer::new("echo \"$HOME dir\" '$HOME' a\\ b X=\"1 2\" <<'E\"OF'\nbody\nE\"OF").tokenize().unwrap();
+        let values: Vec<&str> = tokens.iter().map(|token| token.value.as_str()).collect();
+        assert_eq!(values[..6], ["echo", "\"$HOME dir\"", "'$HOME'", "a\\ b", "X=\"1 2\"", "<<"]);
+        assert_eq!(tokens[4].kind, TokenKind::AssignmentWord);
+        assert_eq!(tokens[7].value, "body\n");
+
+        assert_eq!(unquote("'a b'\"c\\\"\\d\"\\ e"), "a bc\"\\d e");
+        assert_eq!(split_unquoted("~/a:'b:c':\"$(d:e)\"", ':'), ["~/a", "'b:c'", "\"$(d:e)\""]);
+        assert_eq!(unit_len("\"a\\\"${b:-\"}\"c"), 12);
+    }
+
+    #[test]
+    fn test_joined_quotes() {
+        let tokens = Lexer::new("echo foo\"bar\"'baz'$X 'a'\\ b V=\"a\"b'c' 1\"2\" 3>out").tokenize().unwrap();
+        let words: Vec<(&TokenKind, &str)> = tokens.iter().map(|token| (&token.kind, token.value.as_str())).collect();
+        assert_eq!(
+            words,
+            [
+                (&TokenKind::Word, "echo"),
+                (&TokenKind::Word, "foo\"bar\"'baz'$X"),
+                (&TokenKind::Word, "'a'\\ b"),
+                (&TokenKind::AssignmentWord, "V=\"a\"b'c'"),
+                (&TokenKind::Word, "1\"2\""),
+                (&TokenKind::Number, "3"),
+                (&TokenKind::Greater, ">"),
+                (&TokenKind::Word, "out"),
+                (&TokenKind::Eof, ""),
+            ]
+        );
+        assert_eq!(Lexer::new("\"if\" x").tokenize().unwrap()[0].kind, TokenKind::Word);
+    }
+
+    #[test]
+    fn test_commas_in_words() {
+        let tokens = Lexer::new("IFS=, A=x,y str split , \"$s\" a,b ,c").tokenize().unwrap();
+        let words: Vec<(&TokenKind, &str)> = tokens.iter().map(|token| (&token.kind, token.value.as_str())).collect();
+        assert_eq!(
+            words,
+            [
+                (&TokenKind::AssignmentWord, "IFS=,"),
+                (&TokenKind::AssignmentWord, "A=x,y"),
+                (&TokenKind::Word, "str"),
+                (&TokenKind::Word, "split"),
+                (&TokenKind::Word, ","),
+                (&TokenKind::Word, "\"$s\""),
+                (&TokenKind::Word, "a,b"),
+                (&TokenKind::Word, ",c"),
+                (&TokenKind::Eof, ""),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unterminated_heredoc() {
+        let mut lexer = Lexer::new("cat <<EOF\nno end");
+        assert!(lexer.tokenize().is_err());
+        assert!(lexer.is_eof());
+    }
+
+    #[test]
+    fn test_extended_patterns() {
+        let tokens = Lexer::new("ls *.@(jpg|png) !(a) $d/+(x) a\\?(b) f (x)").tokenize().unwrap();
+        let words: Vec<(&TokenKind, &str)> = tokens.iter().map(|token| (&token.kind, token.value.as_str())).collect();
+        assert_eq!(
+            words,
+            [
+                (&TokenKind::Word, "ls"),
+                (&TokenKind::Word, "*.@(jpg|png)"),
+                (&TokenKind::Word, "!(a)"),
+                (&TokenKind::Word, "$d/+(x)"),
+                (&TokenKind::Word, "a\\?"),
+                (&TokenKind::LeftParen, "("),
+                (&TokenKind::Word, "b"),
+                (&TokenKind::RightParen, ")"),
+                (&TokenKind::Word, "f"),
+                (&TokenKind::LeftParen, "("),
+                (&TokenKind::Word, "x"),
+                (&TokenKind::RightParen, ")"),
+                (&TokenKind::Eof, ""),
+            ]
+        );
+        assert_eq!(Lexer::new("! (x)").tokenize().unwrap()[0].kind, TokenKind::Not);
+    }
 }
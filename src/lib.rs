@@ -0,0 +1,48 @@
+#![recursion_limit = "512"]
+
+mod arith;
+mod ast;
+mod brace;
+mod calc;
+mod cipher;
+pub mod completion;
+pub mod config;
+pub mod datetime;
+mod dotenv;
+pub mod dynamic;
+mod environment;
+pub mod executor;
+#[cfg(feature = "extras")]
+mod extras;
+#[cfg(feature = "fetch")]
+mod fetch;
+mod glob;
+mod highlight;
+pub mod history;
+mod history_command;
+mod history_sqlite;
+mod history_stats;
+mod jobs;
+pub mod lexer;
+mod lint;
+pub mod messages;
+mod parameter;
+pub mod parse_service;
+pub mod parser;
+mod pathname;
+mod pattern;
+pub mod prompt;
+pub mod remote;
+pub mod script;
+pub mod selector;
+mod session;
+pub mod signals;
+pub mod spawner;
+mod structured;
+pub mod terminal;
+pub mod testing;
+mod text;
+pub mod token;
+mod traps;
+mod tui;
+mod visit;
@@ -0,0 +1,130 @@
+use crate::ast::*;
+use crate::lexer;
+use crate::messages::tr;
+use crate::visit::{Visitor, walk_command};
+
+/// Commands that read stdin even when given other input, with the option
+/// that stops them from doing so
+const STDIN_CONSUMERS: &[(&str, &str)] = &[
+    ("ssh", "-n"),
+    ("rsh", "-n"),
+    ("ffmpeg", "-nostdin"),
+];
+
+/// Warnings about legal but likely unintended constructs in `command`.
+///
+/// Currently this catches the classic loop pitfall: in
+/// `while read line; do ssh host cmd; done < file` every command in the
+/// loop inherits the loop's stdin, so ssh swallows the rest of the file
+/// and the loop stops after one iteration. Such commands need their own
+/// input (`ssh -n`, or `< /dev/null`).
+pub fn check(command: &Command) -> Vec<String> {
+    let mut lint = StdinLint { shared_stdin: false, warnings: Vec::new() };
+    lint.visit_command(command);
+    lint.warnings
+}
+
+struct StdinLint {
+    /// Inside a loop reading redirected or piped input
+    shared_stdin: bool,
+    warnings: Vec<String>,
+}
+
+impl StdinLint {
+    fn visit_with_stdin(&mut self, command: &Command, shared_stdin: bool) {
+        let outer = std::mem::replace(&mut self.shared_stdin, shared_stdin);
+        self.visit_command(command);
+        self.shared_stdin = outer;
+    }
+}
+
+impl Visitor for StdinLint {
+    fn visit_command(&mut self, command: &Command) {
+        match command {
+            Command::Pipeline(pipeline) => {
+                for (index, stage) in pipeline.commands.iter().enumerate() {
+                    // Later stages read the pipe, not the loop's input
+                    let piped = index > 0;
+                    self.visit_with_stdin(stage, (self.shared_stdin && index == 0) || (piped && is_loop(stage)));
+                }
+            }
+            Command::FunctionDef(def) => self.visit_with_stdin(&def.body, false),
+            Command::Redirected(redirected) => {
+                let own_stdin = redirects_stdin(&redirected.redirections);
+                let loop_input = own_stdin && is_loop(&redirected.command);
+                self.visit_with_stdin(&redirected.command, loop_input || (self.shared_stdin && !own_stdin));
+            }
+            _ => walk_command(self, command),
+        }
+    }
+
+    fn visit_simple_command(&mut self, cmd: &SimpleCommand) {
+        if self.shared_stdin && !redirects_stdin(&cmd.redirections) {
+            check_consumer(cmd, &mut self.warnings);
+        }
+    }
+}
+
+fn check_consumer(cmd: &SimpleCommand, warnings: &mut Vec<String>) {
+    let Some(name) = cmd.words.first().map(|word| lexer::unquote(&word.value)) else {
+        return;
+    };
+    let program = name.rsplit('/').next().unwrap_or(&name);
+
+    if let Some((_, option)) = STDIN_CONSUMERS.iter().find(|(consumer, _)| *consumer == program)
+        && !cmd.words.iter().any(|word| lexer::unquote(&word.value) == *option)
+    {
+        warnings.push(tr!(
+            "`{}` inside a loop reading stdin will consume the loop's input; use `{} {}` or `< /dev/null`",
+            cmd, program, option
+        ));
+    }
+}
+
+fn is_loop(command: &Command) -> bool {
+    matches!(command, Command::While(_) | Command::Until(_))
+}
+
+fn redirects_stdin(redirections: &[Redirection]) -> bool {
+    redirections.iter().any(|r| {
+        matches!(
+            r.kind,
+            RedirectionKind::Input
+                | RedirectionKind::Heredoc
+                | RedirectionKind::HeredocStrip
+                | RedirectionKind::HereString
+                | RedirectionKind::InputDup
+                | RedirectionKind::InputOutput
+        ) && r.fd.unwrap_or(0) == 0
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    fn lint(input: &str) -> Vec<String> {
+        let tokens = Lexer::new(input).tokenize().unwrap();
+        let commands = Parser::new(tokens).parse().unwrap();
+        commands.iter().flat_map(check).collect()
+    }
+
+    #[test]
+    fn test_warns_about_ssh_in_read_loop() {
+        let warnings = lint("while read host; do ssh $host uptime; done < hosts");
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].starts_with("`ssh $host uptime` inside a loop"));
+
+        assert_eq!(lint("cat hosts | while read host; do ssh $host uptime; done").len(), 1);
+    }
+
+    #[test]
+    fn test_no_warning_when_stdin_is_handled() {
+        assert!(lint("while read host; do ssh -n $host uptime; done < hosts").is_empty());
+        assert!(lint("while read host; do ssh $host uptime < /dev/null; done < hosts").is_empty());
+        assert!(lint("while true; do ssh host uptime; done").is_empty());
+        assert!(lint("ssh host uptime < hosts").is_empty());
+    }
+}
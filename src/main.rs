@@ -1,56 +1,191 @@
-#![recursion_limit = "512"]
-
-mod ast;
-mod executor;
-mod lexer;
-mod parser;
-mod token;
-
-use executor::Executor;
-use lexer::Lexer;
-use parser::Parser;
+use clam_shell::completion::ShellHelper;
+use clam_shell::config::{self, Config};
+use clam_shell::executor::Executor;
+use clam_shell::history::{self, HistoryStore};
+use clam_shell::lexer::Lexer;
+use clam_shell::parser::Parser;
+use clam_shell::prompt::{self, Prompt, RenderedPrompt, ShellState};
+use clam_shell::selector::{FileWidget, HistoryWidget, SharedHistory};
+use clam_shell::terminal::TerminalModes;
+use clam_shell::{datetime, dynamic, parse_service, remote, script, signals, testing, token, tr};
 use rustyline::error::ReadlineError;
 use rustyline::history::FileHistory;
-use rustyline::{Editor, Result};
+use rustyline::{Editor, EventHandler, Result};
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+type ShellEditor = Editor<ShellHelper, FileHistory>;
+
+/// Read by login shells, after the environment login(1) sets up
+const LOGIN_PROFILE: &str = ".clam_profile";
 
 fn main() -> Result<()> {
-    let mut rl: Editor<(), FileHistory> = Editor::new()?;
     let mut executor = Executor::new();
-    let history_file = ".clam_history";
 
-    load_history(&mut rl, history_file);
-    run_repl(&mut rl, &mut executor)?;
-    save_history(&mut rl, history_file)?;
+    let mut args = std::env::args();
+    let argv0 = args.next().unwrap_or_else(|| "clam".to_string());
+    let mut args: Vec<String> = args.collect();
+    if args.first().is_some_and(|arg| arg == "--structured") {
+        args.remove(0);
+        executor.enable_structured();
+    }
+    // login(1) and sshd start login shells with a '-' before the name
+    let login = argv0.starts_with('-');
+    set_shell_variable(&mut executor, login);
+    executor.set_variable("HOSTNAME", &dynamic::hostname());
 
-    Ok(())
+    match args.split_first().map(|(first, rest)| (first.as_str(), rest)) {
+        Some(("--test", [])) => {
+            eprintln!("clam: {}", tr!("--test: no test scripts given"));
+            std::process::exit(2);
+        }
+        Some(("--test", paths)) => std::process::exit(testing::run_tests(paths)),
+        Some(("--parse-json", _)) => {
+            if let Err(e) = parse_service::serve(std::io::stdin().lock(), std::io::stdout().lock()) {
+                eprintln!("clam: {}", tr!("--parse-json: {}", e));
+                std::process::exit(1);
+            }
+            std::process::exit(0);
+        }
+        Some(("--ssh", [host, path, script_args @ ..])) => {
+            std::process::exit(remote::run_over_ssh(host, path, script_args));
+        }
+        Some(("config", rest)) if rest.first().is_some_and(|action| matches!(action.as_str(), "get" | "set" | "path")) => {
+            std::process::exit(config::run(rest));
+        }
+        Some(("--ssh", _)) => {
+            eprintln!("clam: {}", tr!("--ssh: usage: clam --ssh HOST SCRIPT [ARGS...]"));
+            std::process::exit(2);
+        }
+        _ => {}
+    }
+    if let Some((path, script_args)) = args.split_first() {
+        std::process::exit(script::run_script(&mut executor, path, script_args));
+    }
+    executor.set_shell_name(&argv0);
+
+    install_crash_handlers();
+    let config = load_config();
+    let mut rl: ShellEditor = Editor::new()?;
+    rl.set_helper(Some(ShellHelper::new(&config)));
+    let mut history = HistoryStore::new(history::HISTORY_FILE, &config.history.backend);
+    if let Some(backend) = history.backend() {
+        executor.set_history(backend);
+    }
+    if let Some(helper) = rl.helper_mut() {
+        helper.set_frecency(history.frecency());
+    }
+
+    executor.set_interactive(true);
+    if login {
+        run_login_profile(&mut executor);
+    }
+    history.load(&mut rl);
+    bind_widgets(&mut rl, history.shared(), &config);
+    run_repl(&mut rl, &mut executor, &mut history)?;
+    if let Some(signal) = signals::take_exit_signal() {
+        executor.die_from_signal(signal);
+    }
+    executor.clean_up_on_exit();
+    executor.hang_up_jobs();
+
+    std::process::exit(executor.get_last_exit_status());
 }
 
-fn load_history(rl: &mut Editor<(), FileHistory>, history_file: &str) {
-    let _ = rl.load_history(history_file);
+/// Put the terminal back the way it was found if the shell is killed or
+/// panics. A SIGTERM or SIGHUP also ends `run_repl`, so the EXIT trap still
+/// runs. History needs no flushing, as each line is saved when entered.
+fn install_crash_handlers() {
+    let modes = TerminalModes::save(0);
+    let panic_modes = modes.clone();
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        panic_modes.restore();
+        default_hook(info);
+    }));
+    signals::on_exit_signal(move |_| modes.restore());
 }
 
-fn save_history(rl: &mut Editor<(), FileHistory>, history_file: &str) -> Result<()> {
-    rl.save_history(history_file)?;
-    Ok(())
+/// Point $SHELL at clam when nothing set it, and always for a login shell,
+/// whose SHELL may come from a different user's session
+fn set_shell_variable(executor: &mut Executor, login: bool) {
+    if !login && std::env::var_os("SHELL").is_some() {
+        return;
+    }
+    if let Ok(path) = std::env::current_exe() {
+        executor.set_variable("SHELL", &path.display().to_string());
+    }
 }
 
-fn run_repl(rl: &mut Editor<(), FileHistory>, executor: &mut Executor) -> Result<()> {
-    loop {
-        match rl.readline("$ ") {
+/// The settings in the config file, or the defaults if it can't be read
+fn load_config() -> Config {
+    let Some(path) = config::path() else {
+        return Config::default();
+    };
+    Config::load(&path).unwrap_or_else(|e| {
+        eprintln!("clam: {}", tr!("{}: {}", path.display(), e));
+        Config::default()
+    })
+}
+
+fn run_login_profile(executor: &mut Executor) {
+    let Some(home) = std::env::var_os("HOME") else {
+        return;
+    };
+    let profile = std::path::Path::new(&home).join(LOGIN_PROFILE);
+    if !profile.is_file() {
+        return;
+    }
+    if let Err(e) = executor.source(&profile.display().to_string()) {
+        eprintln!("clam: {}", tr!("{}: {}", profile.display(), e));
+    }
+}
+
+fn bind_widgets(rl: &mut ShellEditor, history: SharedHistory, config: &Config) {
+    if let Some(key) = config.key("history-search") {
+        rl.bind_sequence(key, EventHandler::Conditional(Box::new(HistoryWidget::new(history))));
+    }
+    if let Some(key) = config.key("file-search") {
+        rl.bind_sequence(key, EventHandler::Conditional(Box::new(FileWidget)));
+    }
+}
+
+fn run_repl(
+    rl: &mut ShellEditor,
+    executor: &mut Executor,
+    history: &mut HistoryStore,
+) -> Result<()> {
+    let prompt = Prompt::new();
+    let mut duration = None;
+    while !signals::exit_requested() {
+        executor.reap_jobs();
+        let rendered = render_prompt(&prompt, executor, duration);
+        let plain = rendered.plain.clone();
+        if let Some(helper) = rl.helper_mut() {
+            helper.set_prompt(rendered);
+        }
+        match rl.readline(&plain) {
             Ok(line) => {
-                if !handle_input(rl, executor, &line) {
+                let started = Instant::now();
+                if !handle_input(rl, executor, history, &line) {
                     continue;
                 }
+                duration = Some(started.elapsed());
+            }
+            // Written without println!, which panics if stdout is a closed pipe
+            Err(ReadlineError::Interrupted) if signals::exit_requested() => {
+                let _ = writeln!(std::io::stdout());
             }
             Err(ReadlineError::Interrupted) => {
-                println!("^C");
+                let _ = writeln!(std::io::stdout(), "^C");
             }
             Err(ReadlineError::Eof) => {
-                println!();
+                let _ = writeln!(std::io::stdout());
                 break;
             }
             Err(err) => {
-                eprintln!("Error: {:?}", err);
+                eprintln!("{}", tr!("Error: {}", err));
                 break;
             }
         }
@@ -58,14 +193,35 @@ fn run_repl(rl: &mut Editor<(), FileHistory>, executor: &mut Executor) -> Result
     Ok(())
 }
 
-fn handle_input(rl: &mut Editor<(), FileHistory>, executor: &mut Executor, line: &str) -> bool {
+/// `PS1` with the segments it names filled in from the shell's state
+fn render_prompt(prompt: &Prompt, executor: &Executor, duration: Option<Duration>) -> RenderedPrompt {
+    let ps1 = executor.get_variable("PS1");
+    let home = executor.get_variable("HOME");
+    let state = ShellState {
+        cwd: std::env::current_dir().unwrap_or_else(|_| PathBuf::from(executor.get_variable("PWD"))),
+        home: (!home.is_empty()).then(|| PathBuf::from(home)),
+        status: executor.get_last_exit_status(),
+        jobs: executor.job_count(),
+        duration,
+        time: datetime::now(),
+    };
+    prompt.render(if ps1.is_empty() { prompt::DEFAULT_PS1 } else { &ps1 }, &state)
+}
+
+fn handle_input(
+    rl: &mut ShellEditor,
+    executor: &mut Executor,
+    history: &mut HistoryStore,
+    line: &str,
+) -> bool {
     let trimmed = line.trim();
     if trimmed.is_empty() {
         return false;
     }
 
-    let _ = rl.add_history_entry(line);
+    history.record(rl, line, &executor.get_variable("HISTIGNORE"));
     process_command(executor, trimmed);
+    history.finish(executor.get_last_exit_status());
     true
 }
 
@@ -76,7 +232,7 @@ fn process_command(executor: &mut Executor, input: &str) {
             parse_and_execute(executor, tokens);
         }
         Err(e) => {
-            eprintln!("Lexer error: {}", e);
+            eprintln!("{}", tr!("Lexer error: {}", e));
         }
     }
 }
@@ -86,18 +242,18 @@ fn parse_and_execute(executor: &mut Executor, tokens: Vec<token::Token>) {
     match parser.parse() {
         Ok(commands) => {
             for command in commands {
-                match executor.execute(&command) {
+                match executor.execute_isolated(&command) {
                     Ok(_exit_status) => {
                         // Command executed successfully
                     }
                     Err(e) => {
-                        eprintln!("Execution error: {}", e);
+                        eprintln!("{}", tr!("Execution error: {}", e));
                     }
                 }
             }
         }
         Err(e) => {
-            eprintln!("Parse error: {}", e);
+            eprintln!("{}", tr!("Parse error: {}", e));
         }
     }
 }
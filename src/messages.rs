@@ -0,0 +1,162 @@
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+/// Directory holding the message catalogs, overriding the one installed
+/// next to the binary in `<prefix>/share/clam/locale`
+const LOCALE_DIR_VAR: &str = "CLAM_LOCALEDIR";
+
+/// A message for the user, translated for the locale when its catalog has
+/// it. The English text is the key, with `{}` for each argument in turn; a
+/// translation may take them in another order with `{0}`, `{1}`...
+#[macro_export]
+macro_rules! tr {
+    ($msgid:literal $(, $arg:expr)* $(,)?) => {
+        $crate::messages::format($msgid, &[$(&$arg as &dyn std::fmt::Display),*])
+    };
+}
+pub use crate::tr;
+
+pub fn format(msgid: &str, args: &[&dyn Display]) -> String {
+    let template = catalog().get(msgid).map_or(msgid, String::as_str);
+    substitute(template, args)
+}
+
+/// Translations of the messages for the user's locale: `<locale>.json`
+/// in the catalog directory, a JSON object from each English message to
+/// its translation. Empty for English, or when there is no catalog.
+fn catalog() -> &'static HashMap<String, String> {
+    static CATALOG: OnceLock<HashMap<String, String>> = OnceLock::new();
+    CATALOG.get_or_init(|| {
+        let Some(dir) = catalog_dir() else {
+            return HashMap::new();
+        };
+        for name in catalog_names(&locale()) {
+            let path = dir.join(format!("{}.json", name));
+            let Ok(text) = std::fs::read_to_string(&path) else {
+                continue;
+            };
+            match serde_json::from_str(&text) {
+                Ok(catalog) => return catalog,
+                Err(e) => eprintln!("clam: {}: {}", path.display(), e),
+            }
+        }
+        HashMap::new()
+    })
+}
+
+fn catalog_dir() -> Option<PathBuf> {
+    if let Some(dir) = std::env::var_os(LOCALE_DIR_VAR) {
+        return Some(dir.into());
+    }
+    let exe = std::env::current_exe().ok()?;
+    Some(exe.parent()?.parent()?.join("share/clam/locale"))
+}
+
+/// The locale for messages, from the first of `LC_ALL`, `LC_MESSAGES` and
+/// `LANG` that is set
+fn locale() -> String {
+    ["LC_ALL", "LC_MESSAGES", "LANG"]
+        .iter()
+        .filter_map(|name| std::env::var(name).ok())
+        .find(|value| !value.is_empty())
+        .unwrap_or_default()
+}
+
+/// The catalogs that could serve `locale`, most specific first: `ja_JP`
+/// and then `ja` for `ja_JP.UTF-8`. None for the C locale.
+fn catalog_names(locale: &str) -> Vec<String> {
+    let name = locale.split(['.', '@']).next().unwrap_or_default();
+    if name.is_empty() || name == "C" || name == "POSIX" {
+        return Vec::new();
+    }
+    let mut names = vec![name.to_string()];
+    if let Some((language, _)) = name.split_once('_') {
+        names.push(language.to_string());
+    }
+    names
+}
+
+/// Fill the `{}` and `{N}` placeholders of `template` with `args`
+fn substitute(template: &str, args: &[&dyn Display]) -> String {
+    let mut result = String::with_capacity(template.len());
+    let mut next = 0;
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        result.push_str(&rest[..start]);
+        let placeholder = rest[start + 1..].find('}').map(|end| &rest[start + 1..start + 1 + end]);
+        let index = match placeholder {
+            Some("") => Some(next),
+            Some(digits) => digits.parse().ok(),
+            None => None,
+        };
+        match (placeholder, index.and_then(|index| args.get(index))) {
+            (Some(placeholder), Some(arg)) => {
+                result.push_str(&arg.to_string());
+                next = index.unwrap_or(next) + 1;
+                rest = &rest[start + placeholder.len() + 2..];
+            }
+            _ => {
+                result.push('{');
+                rest = &rest[start + 1..];
+            }
+        }
+    }
+    result.push_str(rest);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_substitute() {
+        assert_eq!(substitute("{}: {}", &[&"ls", &"not found"]), "ls: not found");
+        assert_eq!(substitute("{1} ({0})", &[&1, &"job"]), "job (1)");
+        assert_eq!(substitute("{x} {5} {", &[&1]), "{x} {5} {");
+        assert_eq!(tr!("{}: command not found", "nosuch"), "nosuch: command not found");
+    }
+
+    #[test]
+    fn test_catalog_names() {
+        assert_eq!(catalog_names("ja_JP.UTF-8"), ["ja_JP", "ja"]);
+        assert_eq!(catalog_names("de@euro"), ["de"]);
+        assert!(catalog_names("C.UTF-8").is_empty());
+        assert!(catalog_names("").is_empty());
+    }
+
+    /// Every translation must use the arguments its message has
+    #[test]
+    fn test_shipped_catalogs() {
+        let placeholders = |text: &str| {
+            let mut used: Vec<char> = substitute(text, &[&'\u{1}', &'\u{2}', &'\u{3}', &'\u{4}'])
+                .chars()
+                .filter(|ch| ('\u{1}'..='\u{4}').contains(ch))
+                .collect();
+            used.sort();
+            used
+        };
+        let catalog: HashMap<String, String> = serde_json::from_str(include_str!("../locale/ja.json")).unwrap();
+        for (msgid, translation) in &catalog {
+            assert_eq!(placeholders(msgid), placeholders(translation), "{}", msgid);
+        }
+    }
+
+    /// Every message the source passes to `tr!` must have a translation
+    #[test]
+    fn test_catalogs_are_complete() {
+        let catalog: HashMap<String, String> = serde_json::from_str(include_str!("../locale/ja.json")).unwrap();
+        let msgid = regex::Regex::new(r#"\btr!\(\s*"((?:[^"\\]|\\.)*)""#).unwrap();
+        let dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("src");
+        for entry in std::fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            let source = std::fs::read_to_string(&path).unwrap();
+            for captures in msgid.captures_iter(&source) {
+                let text = captures[1].replace("\\\"", "\"").replace("\\\\", "\\");
+                assert!(catalog.contains_key(&text), "{}: {:?} has no translation", path.display(), text);
+            }
+        }
+    }
+}
@@ -0,0 +1,257 @@
+use crate::messages::tr;
+use crate::pattern::{self, Anchor};
+
+/// What a `${...}` parameter expansion holds: the parameter and what to
+/// do with it
+#[derive(Debug, PartialEq)]
+pub struct Expansion<'a> {
+    pub name: &'a str,
+    pub operation: Operation<'a>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Operation<'a> {
+    /// `${name}`
+    Value,
+    /// `${name-word}` and the like; with `:`, as in `${name:-word}`, an
+    /// empty parameter counts as unset too
+    Substitute { kind: Substitution, colon: bool, word: &'a str },
+    /// `${#name}`: the length in characters, or the number of elements of
+    /// `name[@]`
+    Length,
+    /// `${name#pattern}` and `${name##pattern}`, the shortest and longest
+    /// matching prefix removed, or the suffix with `%` and `%%`
+    Remove { suffix: bool, longest: bool, pattern: &'a str },
+    /// `${name/pattern/string}` and its `//`, `/#` and `/%` forms; without
+    /// the string, the matches are removed
+    Replace { anchor: Anchor, pattern: &'a str, replacement: &'a str },
+    /// `${name^pattern}` and `${name,pattern}`, the first character made
+    /// upper or lower case if it matches, or every one that does with `^^`
+    /// and `,,`. An empty pattern matches any character.
+    Case { upper: bool, all: bool, pattern: &'a str },
+    /// `${name:offset}` and `${name:offset:length}`, both arithmetic
+    /// expressions, which count from the end when negative
+    Substring { offset: &'a str, length: Option<&'a str> },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Substitution {
+    /// `-`: the word if the parameter is unset
+    Default,
+    /// `=`: the word, also assigned to the parameter, if it is unset
+    Assign,
+    /// `+`: the word if the parameter is set, else nothing
+    Alternative,
+    /// `?`: an error with the word as its message if the parameter is unset
+    Error,
+}
+
+/// Parse `inner`, the text between `${` and `}`
+pub fn parse(inner: &str) -> Result<Expansion<'_>, String> {
+    let bad_substitution = || tr!("{}: bad substitution", format!("${{{}}}", inner));
+    // `${#}` and `${#:-word}` are about `$#` itself, not a length
+    if let Some(name) = inner.strip_prefix('#').filter(|name| !name.is_empty() && name_len(name) == name.len()) {
+        return Ok(Expansion { name, operation: Operation::Length });
+    }
+    let (name, rest) = inner.split_at(name_len(inner));
+    if name.is_empty() {
+        return Err(bad_substitution());
+    }
+    if let Some(marker @ ('#' | '%')) = rest.chars().next() {
+        let pattern = &rest[1..];
+        let (longest, pattern) = match pattern.strip_prefix(marker) {
+            Some(pattern) => (true, pattern),
+            None => (false, pattern),
+        };
+        return Ok(Expansion { name, operation: Operation::Remove { suffix: marker == '%', longest, pattern } });
+    }
+    if let Some(marker @ ('^' | ',')) = rest.chars().next() {
+        let pattern = &rest[1..];
+        let (all, pattern) = match pattern.strip_prefix(marker) {
+            Some(pattern) => (true, pattern),
+            None => (false, pattern),
+        };
+        return Ok(Expansion { name, operation: Operation::Case { upper: marker == '^', all, pattern } });
+    }
+    if let Some(rest) = rest.strip_prefix('/') {
+        let (anchor, rest) = match rest.chars().next() {
+            Some('/') => (Anchor::All, &rest[1..]),
+            Some('#') => (Anchor::Start, &rest[1..]),
+            Some('%') => (Anchor::End, &rest[1..]),
+            _ => (Anchor::First, rest),
+        };
+        let (pattern, replacement) = split_unescaped(rest, '/');
+        return Ok(Expansion { name, operation: Operation::Replace { anchor, pattern, replacement } });
+    }
+    let (colon, rest) = match rest.strip_prefix(':') {
+        Some(rest) => (true, rest),
+        None => (false, rest),
+    };
+    let mut chars = rest.chars();
+    let kind = match chars.next() {
+        None if !colon => return Ok(Expansion { name, operation: Operation::Value }),
+        Some('-') => Substitution::Default,
+        Some('=') => Substitution::Assign,
+        Some('+') => Substitution::Alternative,
+        Some('?') => Substitution::Error,
+        // `${name: -1}` needs the blank, or it would be a default value
+        Some(_) if colon => {
+            let (offset, length) = match rest.split_once(':') {
+                Some((offset, length)) => (offset, Some(length)),
+                None => (rest, None),
+            };
+            return Ok(Expansion { name, operation: Operation::Substring { offset, length } });
+        }
+        _ => return Err(bad_substitution()),
+    };
+    Ok(Expansion { name, operation: Operation::Substitute { kind, colon, word: chars.as_str() } })
+}
+
+/// The length of the parameter name `inner` starts with: a variable name
+/// with an optional `[subscript]`, the digits of a positional parameter or
+/// a special parameter's single character
+fn name_len(inner: &str) -> usize {
+    let Some(first) = inner.chars().next() else {
+        return 0;
+    };
+    if first.is_ascii_digit() {
+        return inner.find(|c: char| !c.is_ascii_digit()).unwrap_or(inner.len());
+    }
+    if matches!(first, '#' | '?' | '@' | '*' | '!' | '$' | '-') {
+        return 1;
+    }
+    if !(first.is_alphabetic() || first == '_') {
+        return 0;
+    }
+    let len = inner.find(|c: char| !(c.is_alphanumeric() || c == '_')).unwrap_or(inner.len());
+    match inner[len..].strip_prefix('[').and_then(|rest| rest.find(']')) {
+        Some(close) => len + close + 2,
+        None => len,
+    }
+}
+
+/// `text` with the case of its characters changed as `Operation::Case`
+/// says
+pub fn change_case(text: &str, upper: bool, all: bool, pattern: &str) -> String {
+    let mut changed = String::new();
+    for (index, ch) in text.chars().enumerate() {
+        if (all || index == 0) && (pattern.is_empty() || pattern::matches(pattern, ch.encode_utf8(&mut [0; 4]))) {
+            if upper {
+                changed.extend(ch.to_uppercase());
+            } else {
+                changed.extend(ch.to_lowercase());
+            }
+        } else {
+            changed.push(ch);
+        }
+    }
+    changed
+}
+
+/// `text` split at the first `separator` not escaped with a backslash,
+/// or all of it and nothing if there is none
+fn split_unescaped(text: &str, separator: char) -> (&str, &str) {
+    let mut chars = text.char_indices();
+    while let Some((index, ch)) = chars.next() {
+        if ch == '\\' {
+            chars.next();
+        } else if ch == separator {
+            return (&text[..index], &text[index + ch.len_utf8()..]);
+        }
+    }
+    (text, "")
+}
+
+/// Whether `name` is a variable that can be assigned, rather than a
+/// positional or special parameter or an array element
+pub fn is_assignable(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_alphabetic() || c == '_') && chars.all(|c| c.is_alphanumeric() || c == '_')
+}
+
+/// The length of the `${...}` that `text` starts with, through the `}`
+/// matching its `{`; None if it is never closed
+pub fn expansion_len(text: &str) -> Option<usize> {
+    let mut depth = 0;
+    for (index, ch) in text.char_indices() {
+        match ch {
+            '{' => depth += 1,
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(index + 1);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn substitute(kind: Substitution, colon: bool, word: &str) -> Operation<'_> {
+        Operation::Substitute { kind, colon, word }
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse("HOME").unwrap(), Expansion { name: "HOME", operation: Operation::Value });
+        assert_eq!(parse("10").unwrap().name, "10");
+        assert_eq!(parse("#").unwrap().name, "#");
+        assert_eq!(parse("PIPESTATUS[1]").unwrap().name, "PIPESTATUS[1]");
+        assert_eq!(parse("x:-a b").unwrap().operation, substitute(Substitution::Default, true, "a b"));
+        assert_eq!(parse("x-").unwrap().operation, substitute(Substitution::Default, false, ""));
+        assert_eq!(parse("x:=${y}").unwrap().operation, substitute(Substitution::Assign, true, "${y}"));
+        assert_eq!(parse("1+set").unwrap().operation, substitute(Substitution::Alternative, false, "set"));
+        assert_eq!(parse("x:?no x").unwrap().operation, substitute(Substitution::Error, true, "no x"));
+
+        assert_eq!(parse("#x").unwrap(), Expansion { name: "x", operation: Operation::Length });
+        assert_eq!(parse("#PIPESTATUS[@]").unwrap().operation, Operation::Length);
+        assert_eq!(parse("x:1").unwrap().operation, Operation::Substring { offset: "1", length: None });
+        assert_eq!(parse("x: -3:n+1").unwrap().operation, Operation::Substring { offset: " -3", length: Some("n+1") });
+
+        assert_eq!(parse("path##*/").unwrap().operation, Operation::Remove { suffix: false, longest: true, pattern: "*/" });
+        assert_eq!(parse("file%.txt").unwrap().operation, Operation::Remove { suffix: true, longest: false, pattern: ".txt" });
+        assert_eq!(parse("1%%").unwrap().operation, Operation::Remove { suffix: true, longest: true, pattern: "" });
+        assert_eq!(parse("##").unwrap(), Expansion { name: "#", operation: Operation::Length });
+
+        let replace = |anchor, pattern, replacement| Operation::Replace { anchor, pattern, replacement };
+        assert_eq!(parse("x/a/b").unwrap().operation, replace(Anchor::First, "a", "b"));
+        assert_eq!(parse("x//a\\/b/c/d").unwrap().operation, replace(Anchor::All, "a\\/b", "c/d"));
+        assert_eq!(parse("x/#a").unwrap().operation, replace(Anchor::Start, "a", ""));
+        assert_eq!(parse("x/%").unwrap().operation, replace(Anchor::End, "", ""));
+
+        assert_eq!(parse("x^^").unwrap().operation, Operation::Case { upper: true, all: true, pattern: "" });
+        assert_eq!(parse("x,[A-M]").unwrap().operation, Operation::Case { upper: false, all: false, pattern: "[A-M]" });
+
+        assert!(parse("").is_err());
+        assert!(parse("#x-").is_err());
+        assert!(parse("x:").is_err());
+        assert!(parse("x y").is_err());
+        assert!(parse("%x").is_err());
+    }
+
+    #[test]
+    fn test_change_case() {
+        assert_eq!(change_case("hello world", true, false, ""), "Hello world");
+        assert_eq!(change_case("hello world", true, true, ""), "HELLO WORLD");
+        assert_eq!(change_case("hello world", true, true, "[a-m]"), "HELLo worLD");
+        assert_eq!(change_case("YES", false, false, ""), "yES");
+        assert_eq!(change_case("YES", false, true, "[!S]"), "yeS");
+        assert_eq!(change_case("Yes", false, false, "N"), "Yes");
+        assert_eq!(change_case("straße", true, true, ""), "STRASSE");
+    }
+
+    #[test]
+    fn test_expansion_len() {
+        assert_eq!(expansion_len("{x}rest"), Some(3));
+        assert_eq!(expansion_len("{x:-${y:-z}}."), Some(12));
+        assert_eq!(expansion_len("{x"), None);
+        assert!(is_assignable("_x1"));
+        assert!(!is_assignable("1"));
+        assert!(!is_assignable("a[0]"));
+    }
+}
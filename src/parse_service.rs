@@ -0,0 +1,137 @@
+use crate::ast::Command;
+use crate::lint;
+use crate::messages::tr;
+use crate::script;
+use serde::Serialize;
+use std::io::{self, BufRead, Write};
+
+/// A reply to one request, written as a single line of JSON
+#[derive(Serialize)]
+struct Response<'a> {
+    ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    commands: Option<&'a [Command]>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+#[derive(Serialize)]
+struct Diagnostic {
+    severity: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    line: Option<usize>,
+    message: String,
+    /// The source ended in the middle of a command, so more input could
+    /// make it valid
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    incomplete: bool,
+}
+
+impl Diagnostic {
+    fn error(line: Option<usize>, message: String) -> Self {
+        Diagnostic { severity: "error", line, message, incomplete: false }
+    }
+}
+
+/// `clam --parse-json`: parse source snippets for editors and language
+/// servers without starting a process for each. Every request is a line
+/// holding the length of the source in bytes, followed by the source
+/// itself; every reply is one line of JSON with either the commands parsed
+/// and lint warnings, or the syntax error. Blank lines between requests
+/// are skipped. Runs until the input ends; a malformed length is replied
+/// to and then ends the session, as the rest of the stream can no longer
+/// be framed.
+pub fn serve(mut input: impl BufRead, mut output: impl Write) -> io::Result<()> {
+    let mut header = String::new();
+    loop {
+        header.clear();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(());
+        }
+        if header.trim().is_empty() {
+            continue;
+        }
+        let Ok(length) = header.trim().parse::<usize>() else {
+            let message = tr!("invalid length header: {}", format!("{:?}", header.trim_end()));
+            reply(&mut output, None, vec![Diagnostic::error(None, message.clone())])?;
+            return Err(io::Error::new(io::ErrorKind::InvalidData, message));
+        };
+
+        let mut source = vec![0; length];
+        input.read_exact(&mut source)?;
+        match String::from_utf8(source) {
+            Ok(source) => respond(&mut output, &source)?,
+            Err(_) => reply(&mut output, None, vec![Diagnostic::error(None, tr!("source is not valid UTF-8"))])?,
+        }
+    }
+}
+
+fn respond(output: &mut impl Write, source: &str) -> io::Result<()> {
+    match script::parse(source) {
+        Ok(commands) => {
+            let diagnostics = commands
+                .iter()
+                .flat_map(lint::check)
+                .map(|message| Diagnostic { severity: "warning", line: None, message, incomplete: false })
+                .collect();
+            reply(output, Some(&commands), diagnostics)
+        }
+        Err(error) => {
+            let diagnostic = Diagnostic {
+                incomplete: error.incomplete,
+                ..Diagnostic::error(Some(error.line), error.message)
+            };
+            reply(output, None, vec![diagnostic])
+        }
+    }
+}
+
+fn reply(output: &mut impl Write, commands: Option<&[Command]>, diagnostics: Vec<Diagnostic>) -> io::Result<()> {
+    let response = Response { ok: commands.is_some(), commands, diagnostics };
+    serde_json::to_writer(&mut *output, &response)?;
+    output.write_all(b"\n")?;
+    output.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(source: &str) -> String {
+        format!("{}\n{}", source.len(), source)
+    }
+
+    fn replies(input: &str) -> (Vec<serde_json::Value>, io::Result<()>) {
+        let mut output = Vec::new();
+        let result = serve(input.as_bytes(), &mut output);
+        let lines = String::from_utf8(output)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        (lines, result)
+    }
+
+    #[test]
+    fn test_replies_with_ast_and_errors() {
+        let input = request("echo hi | cat\n") + "\n" + &request("if true; then") + &request("");
+        let (replies, result) = replies(&input);
+        assert!(result.is_ok());
+        assert_eq!(replies.len(), 3);
+
+        assert_eq!(replies[0]["ok"], true);
+        assert!(replies[0]["commands"][0]["Pipeline"].is_object());
+        assert_eq!(replies[1]["ok"], false);
+        assert_eq!(replies[1]["diagnostics"][0]["severity"], "error");
+        assert_eq!(replies[1]["diagnostics"][0]["incomplete"], true);
+        assert_eq!(replies[2]["commands"], serde_json::json!([]));
+    }
+
+    #[test]
+    fn test_bad_header_ends_session() {
+        let input = "abc\n".to_string() + &request("true");
+        let (replies, result) = replies(&input);
+        assert!(result.is_err());
+        assert_eq!(replies.len(), 1);
+        assert_eq!(replies[0]["ok"], false);
+    }
+}
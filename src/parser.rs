@@ -1,4 +1,6 @@
 use crate::ast::*;
+use crate::lexer::{self, Lexer};
+use crate::messages::tr;
 use crate::token::{Token, TokenKind};
 
 pub struct Parser {
@@ -98,11 +100,7 @@ impl Parser {
             self.advance();
             self.skip_newlines();
             Some(Separator::Background)
-        } else if self.check(&TokenKind::Semicolon) {
-            self.advance();
-            self.skip_newlines();
-            Some(Separator::Sequential)
-        } else if self.check(&TokenKind::Newline) {
+        } else if self.check(&TokenKind::Semicolon) || self.check(&TokenKind::Newline) {
             self.advance();
             self.skip_newlines();
             Some(Separator::Sequential)
@@ -110,18 +108,21 @@ impl Parser {
             None
         };
 
-        // If no separator, just return the single command
-        if first_sep.is_none() {
+        // If no separator, or only a trailing one, just return the single command
+        let Some(first_sep) = first_sep else {
+            return Ok(first_cmd);
+        };
+        if first_sep == Separator::Sequential && self.is_at_end() {
             return Ok(first_cmd);
         }
 
         items.push(ListItem {
             command: first_cmd,
-            separator: first_sep.unwrap(),
+            separator: first_sep,
         });
 
-        // Parse remaining commands
-        loop {
+        // Parse remaining commands, allowing a trailing separator
+        while !self.is_at_end() {
             let cmd = self.parse_pipeline_command()?;
 
             let separator = if self.check(&TokenKind::And) {
@@ -136,11 +137,7 @@ impl Parser {
                 self.advance();
                 self.skip_newlines();
                 Separator::Background
-            } else if self.check(&TokenKind::Semicolon) {
-                self.advance();
-                self.skip_newlines();
-                Separator::Sequential
-            } else if self.check(&TokenKind::Newline) {
+            } else if self.check(&TokenKind::Semicolon) || self.check(&TokenKind::Newline) {
                 self.advance();
                 self.skip_newlines();
                 Separator::Sequential
@@ -164,29 +161,48 @@ impl Parser {
 
     // <PIPELINE-COMMAND> ::= <PIPELINE>
     //                     | '!' <PIPELINE>
+    //                     | <TIMESPEC> <PIPELINE>
+    //                     | <TIMESPEC> '!' <PIPELINE>
+    //                     | '!' <TIMESPEC> <PIPELINE>
     fn parse_pipeline_command(&mut self) -> Result<Command, String> {
-        let negated = if self.check(&TokenKind::Not) {
+        let negated = self.check(&TokenKind::Not);
+        if negated {
             self.advance();
-            true
+        }
+
+        let pipeline = if self.check(&TokenKind::Time) {
+            self.parse_timed_pipeline()?
         } else {
-            false
+            self.parse_pipeline()?
         };
 
-        let pipeline = self.parse_pipeline()?;
+        Ok(if negated { pipeline.negate() } else { pipeline })
+    }
+
+    // <TIMESPEC> ::= 'time' | 'time' '-p'
+    fn parse_timed_pipeline(&mut self) -> Result<Command, String> {
+        self.advance(); // time
+        let posix = self.check(&TokenKind::Word) && self.current().value == "-p";
+        if posix {
+            self.advance();
+        }
 
+        let negated = self.check(&TokenKind::Not);
         if negated {
-            if let Command::Pipeline(mut p) = pipeline {
-                p.negated = true;
-                Ok(Command::Pipeline(p))
-            } else {
-                Ok(Command::Pipeline(Pipeline {
-                    negated: true,
-                    commands: vec![pipeline],
-                }))
-            }
-        } else {
-            Ok(pipeline)
+            self.advance();
         }
+        // `time` alone times nothing, reporting zeros
+        let ends = [TokenKind::Semicolon, TokenKind::Newline, TokenKind::Ampersand, TokenKind::And, TokenKind::Or];
+        let pipeline = if !negated && (self.is_at_end() || ends.iter().any(|kind| self.check(kind))) {
+            Command::Simple(SimpleCommand::new())
+        } else {
+            self.parse_pipeline()?
+        };
+
+        Ok(Command::Timed(TimedCommand {
+            posix,
+            command: Box::new(if negated { pipeline.negate() } else { pipeline }),
+        }))
     }
 
     // <PIPELINE> ::= <PIPELINE> '|' <NEWLINE-LIST> <PIPELINE>
@@ -194,8 +210,13 @@ impl Parser {
     fn parse_pipeline(&mut self) -> Result<Command, String> {
         let mut commands = vec![self.parse_command()?];
 
-        while self.check(&TokenKind::Pipe) {
-            self.advance();
+        while self.check(&TokenKind::Pipe) || self.check(&TokenKind::PipeAnd) {
+            // `a |& b` is `a 2>&1 | b`, the 2>&1 going after a's own redirections
+            if self.advance().kind == TokenKind::PipeAnd {
+                let stage = commands.pop().unwrap();
+                let stderr = Redirection::new(RedirectionKind::OutputDup, RedirectionTarget::Fd(1)).fd(2);
+                commands.push(stage.redirect(stderr));
+            }
             self.skip_newlines();
             commands.push(self.parse_command()?);
         }
@@ -226,9 +247,11 @@ impl Parser {
             self.parse_case_command()?
         } else if self.check(&TokenKind::LeftParen) {
             self.parse_subshell()?
+        } else if self.check(&TokenKind::Arithmetic) {
+            Command::Arithmetic(self.advance().value.clone())
         } else if self.check(&TokenKind::LeftBrace) {
             self.parse_group_command()?
-        } else if self.check(&TokenKind::Function) {
+        } else if self.check(&TokenKind::Function) || self.is_posix_function_def() {
             self.parse_function_def()?
         } else {
             return self.parse_simple_command();
@@ -236,14 +259,7 @@ impl Parser {
 
         // Check for redirection list after shell command
         let redirections = self.parse_redirection_list()?;
-        if !redirections.is_empty() {
-            Ok(Command::Redirected(RedirectedCommand {
-                command: Box::new(cmd),
-                redirections,
-            }))
-        } else {
-            Ok(cmd)
-        }
+        Ok(cmd.redirect_all(redirections))
     }
 
     fn parse_simple_command(&mut self) -> Result<Command, String> {
@@ -253,24 +269,24 @@ impl Parser {
         loop {
             let old_pos = self.position;
 
-            if self.check(&TokenKind::AssignmentWord) {
+            // After the command name, `name=value` is just an argument
+            if self.check(&TokenKind::AssignmentWord) && cmd.words.is_empty() {
                 let token = self.advance();
                 if let Some((name, value)) = token.value.split_once('=') {
-                    cmd.assignments.push(Assignment {
-                        name: name.to_string(),
-                        value: value.to_string(),
-                    });
+                    cmd.assignments.push(Assignment::new(name, value));
                 }
                 made_progress = true;
             } else if self.is_redirection() {
                 cmd.redirections.push(self.parse_redirection()?);
                 made_progress = true;
-            } else if self.is_word_or_keyword() {
+            } else if self.is_word_or_keyword()
+                || self.check(&TokenKind::AssignmentWord)
+                // Braces are reserved only where a command starts
+                || (!cmd.words.is_empty() && (self.check(&TokenKind::LeftBrace) || self.check(&TokenKind::RightBrace)))
+            {
                 // Accept both Word tokens and reserved words as arguments
-                let token = self.advance();
-                cmd.words.push(Word {
-                    value: token.value.clone(),
-                });
+                let value = self.advance().value.clone();
+                cmd.words.push(parse_word(value)?);
                 made_progress = true;
             } else {
                 break;
@@ -282,8 +298,8 @@ impl Parser {
             }
         }
 
-        if !made_progress && cmd.assignments.is_empty() && cmd.words.is_empty() && cmd.redirections.is_empty() {
-            return Err("Expected command".to_string());
+        if !made_progress && cmd.is_empty() {
+            return Err(tr!("Expected command"));
         }
 
         Ok(Command::Simple(cmd))
@@ -293,6 +309,7 @@ impl Parser {
         matches!(
             self.current().kind,
             TokenKind::Word
+                | TokenKind::Dash
                 | TokenKind::Done
                 | TokenKind::Time
                 | TokenKind::In
@@ -316,12 +333,14 @@ impl Parser {
             TokenKind::GreatGreat => RedirectionKind::Append,
             TokenKind::LessLess => RedirectionKind::Heredoc,
             TokenKind::LessLessDash => RedirectionKind::HeredocStrip,
+            TokenKind::LessLessLess => RedirectionKind::HereString,
             TokenKind::LessAnd => RedirectionKind::InputDup,
             TokenKind::GreatAnd => RedirectionKind::OutputDup,
             TokenKind::LessGreat => RedirectionKind::InputOutput,
             TokenKind::GreatPipe => RedirectionKind::Clobber,
             TokenKind::AndGreat => RedirectionKind::OutputBoth,
-            _ => return Err(format!("Expected redirection operator, got {:?}", kind_token)),
+            TokenKind::AndGreatGreat => RedirectionKind::AppendBoth,
+            _ => return Err(tr!("Expected redirection operator, got {}", format!("{:?}", kind_token))),
         };
 
         self.advance();
@@ -334,9 +353,16 @@ impl Parser {
             RedirectionTarget::Fd(token.value.parse::<i32>().unwrap())
         } else if self.check(&TokenKind::Word) {
             let token = self.advance();
-            RedirectionTarget::File(token.value.clone())
+            let word = token.value.clone();
+            if self.check(&TokenKind::HeredocBody) {
+                let body = self.advance().value.clone();
+                let quoted = word.contains(['\'', '"', '\\']);
+                RedirectionTarget::Heredoc { delimiter: word, body, quoted }
+            } else {
+                RedirectionTarget::File(word)
+            }
         } else {
-            return Err("Expected redirection target".to_string());
+            return Err(tr!("Expected redirection target"));
         };
 
         Ok(Redirection { kind, fd, target })
@@ -423,11 +449,7 @@ impl Parser {
                 self.advance();
                 self.skip_newlines();
                 Separator::Background
-            } else if self.check(&TokenKind::Semicolon) {
-                self.advance();
-                self.skip_newlines();
-                Separator::Sequential
-            } else if self.check(&TokenKind::Newline) {
+            } else if self.check(&TokenKind::Semicolon) || self.check(&TokenKind::Newline) {
                 self.advance();
                 self.skip_newlines();
                 Separator::Sequential
@@ -496,6 +518,24 @@ impl Parser {
     fn parse_for_command(&mut self) -> Result<Command, String> {
         self.expect(&TokenKind::For)?;
 
+        let parallel = if self.check(&TokenKind::Word) && self.current().value == "-P" {
+            self.advance();
+            let token = self.advance();
+            match token.value.parse::<usize>() {
+                Ok(jobs) if jobs > 0 => Some(jobs),
+                _ => {
+                    return Err(tr!(
+                        "Expected a number of jobs after -P, got '{}' at {}:{}",
+                        token.value,
+                        token.position.line,
+                        token.position.column
+                    ));
+                }
+            }
+        } else {
+            None
+        };
+
         let var_token = self.expect(&TokenKind::Word)?;
         let variable = var_token.value.clone();
 
@@ -528,6 +568,7 @@ impl Parser {
             variable,
             words,
             body,
+            parallel,
         }))
     }
 
@@ -564,7 +605,11 @@ impl Parser {
             self.expect(&TokenKind::RightParen)?;
             self.skip_newlines();
 
-            let body = Box::new(self.parse_list()?);
+            let body = if self.check(&TokenKind::DoubleSemicolon) || self.check(&TokenKind::Esac) {
+                Box::new(Command::Simple(SimpleCommand::new()))
+            } else {
+                Box::new(self.parse_compound_list(&[TokenKind::DoubleSemicolon, TokenKind::Esac])?)
+            };
 
             cases.push(CaseClause { patterns, body });
 
@@ -593,15 +638,19 @@ impl Parser {
     fn parse_group_command(&mut self) -> Result<Command, String> {
         self.expect(&TokenKind::LeftBrace)?;
 
-        let list = self.parse_list()?;
+        let list = self.parse_compound_list(&[TokenKind::RightBrace])?;
 
         self.expect(&TokenKind::RightBrace)?;
 
         Ok(Command::Group(Box::new(list)))
     }
 
+    // <FUNCTION-DEF> ::= 'function' <WORD> ['(' ')'] <NEWLINE-LIST> <FUNCTION-BODY>
+    //                 | <WORD> '(' ')' <NEWLINE-LIST> <FUNCTION-BODY>
     fn parse_function_def(&mut self) -> Result<Command, String> {
-        self.expect(&TokenKind::Function)?;
+        if self.check(&TokenKind::Function) {
+            self.advance();
+        }
 
         let name_token = self.expect(&TokenKind::Word)?;
         let name = name_token.value.clone();
@@ -613,11 +662,45 @@ impl Parser {
 
         self.skip_newlines();
 
-        let body = Box::new(self.parse_group_command()?);
+        let body = Box::new(self.parse_function_body()?);
 
         Ok(Command::FunctionDef(FunctionDef { name, body }))
     }
 
+    // <FUNCTION-BODY> ::= <COMPOUND-COMMAND> [<REDIRECTION-LIST>]
+    // Redirections belong to the body, so they apply on every call
+    fn parse_function_body(&mut self) -> Result<Command, String> {
+        let body = match self.current().kind {
+            TokenKind::LeftBrace => self.parse_group_command()?,
+            TokenKind::LeftParen => self.parse_subshell()?,
+            TokenKind::Arithmetic => Command::Arithmetic(self.advance().value.clone()),
+            TokenKind::If => self.parse_if_command()?,
+            TokenKind::While => self.parse_while_command()?,
+            TokenKind::Until => self.parse_until_command()?,
+            TokenKind::For => self.parse_for_command()?,
+            TokenKind::Case => self.parse_case_command()?,
+            ref kind => {
+                return Err(tr!(
+                    "Expected compound command as function body, got {} at {}:{}",
+                    format!("{:?}", kind),
+                    self.current().position.line,
+                    self.current().position.column
+                ));
+            }
+        };
+
+        let redirections = self.parse_redirection_list()?;
+        Ok(body.redirect_all(redirections))
+    }
+
+    /// Whether the next tokens are `name ( )`, starting a POSIX-style function definition
+    fn is_posix_function_def(&self) -> bool {
+        let kind_at = |offset: usize| self.tokens.get(self.position + offset).map(|t| &t.kind);
+        self.check(&TokenKind::Word)
+            && kind_at(1) == Some(&TokenKind::LeftParen)
+            && kind_at(2) == Some(&TokenKind::RightParen)
+    }
+
     fn is_redirection(&self) -> bool {
         if self.check(&TokenKind::Number) {
             if self.position + 1 < self.tokens.len() {
@@ -628,6 +711,7 @@ impl Parser {
                         | TokenKind::Less
                         | TokenKind::GreatGreat
                         | TokenKind::LessLess
+                        | TokenKind::LessLessLess
                         | TokenKind::LessAnd
                         | TokenKind::GreatAnd
                         | TokenKind::LessGreat
@@ -647,7 +731,9 @@ impl Parser {
                     | TokenKind::LessGreat
                     | TokenKind::GreatPipe
                     | TokenKind::AndGreat
+                    | TokenKind::AndGreatGreat
                     | TokenKind::LessLessDash
+                    | TokenKind::LessLessLess
             )
         }
     }
@@ -677,25 +763,38 @@ impl Parser {
         if self.check(kind) {
             Ok(self.advance())
         } else {
-            Err(format!(
-                "Expected {:?}, got {:?} at {}:{}",
-                kind,
-                self.current().kind,
+            Err(tr!(
+                "Expected {}, got {} at {}:{}",
+                format!("{:?}", kind),
+                format!("{:?}", self.current().kind),
                 self.current().position.line,
                 self.current().position.column
             ))
         }
     }
 
-    fn is_at_end(&self) -> bool {
+    /// Line of the token the parser stopped at, for locating errors
+    pub fn line(&self) -> usize {
+        self.current().position.line
+    }
+
+    pub fn is_at_end(&self) -> bool {
         self.position >= self.tokens.len() || self.current().kind == TokenKind::Eof
     }
 }
 
+/// A word, with the commands of its `$(...)` substitutions parsed
+fn parse_word(value: String) -> Result<Word, String> {
+    let substitutions = lexer::command_substitutions(&value)
+        .into_iter()
+        .map(|source| Parser::new(Lexer::new(source).tokenize()?).parse())
+        .collect::<Result<_, _>>()?;
+    Ok(Word { value, substitutions })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::lexer::Lexer;
 
     #[test]
     fn test_simple_command() {
@@ -710,6 +809,70 @@ mod tests {
         assert_eq!(commands.len(), 1);
     }
 
+    #[test]
+    fn test_time_prefix() {
+        let parse = |source: &str| Parser::new(Lexer::new(source).tokenize().unwrap()).parse().unwrap().remove(0);
+        let Command::Timed(timed) = parse("time -p ls | wc") else {
+            panic!("expected a timed pipeline");
+        };
+        assert!(timed.posix);
+        assert!(matches!(*timed.command, Command::Pipeline(_)));
+
+        assert_eq!(parse("! time ls").to_string(), "! time ls");
+        assert_eq!(parse("time ! ls").to_string(), "time ! ls");
+        assert_eq!(parse("time; echo time").to_string(), "time; echo time");
+    }
+
+    #[test]
+    fn test_arithmetic_command() {
+        let parse = |source: &str| Parser::new(Lexer::new(source).tokenize().unwrap()).parse().unwrap().remove(0);
+        let Command::If(if_cmd) = parse("if (( x > (y + 1) )); then echo big; fi") else {
+            panic!("expected an if command");
+        };
+        assert_eq!(*if_cmd.condition, Command::Arithmetic(" x > (y + 1) ".to_string()));
+        assert_eq!(parse("((i++)) > log").to_string(), "((i++)) > log");
+
+        // Parentheses closed apart open nested subshells
+        assert_eq!(parse("((cd src; make) )").to_string(), "((cd src; make))");
+        assert!(matches!(parse("((cd src; make) )"), Command::Subshell(_)));
+    }
+
+    #[test]
+    fn test_assignment_after_command_name_is_an_argument() {
+        let tokens = Lexer::new("CC=gcc let x=1").tokenize().unwrap();
+        let commands = Parser::new(tokens).parse().unwrap();
+        let Command::Simple(cmd) = &commands[0] else {
+            panic!("expected a simple command");
+        };
+        assert_eq!(cmd.assignments.len(), 1);
+        let words: Vec<&str> = cmd.words.iter().map(|word| word.value.as_str()).collect();
+        assert_eq!(words, ["let", "x=1"]);
+    }
+
+    #[test]
+    fn test_parallel_for() {
+        let parse = |source: &str| Parser::new(Lexer::new(source).tokenize().unwrap()).parse();
+        let command = parse("for -P 4 f in a b; do gzip $f; done").unwrap().remove(0);
+        let Command::For(for_cmd) = &command else {
+            panic!("expected a for loop");
+        };
+        assert_eq!(for_cmd.parallel, Some(4));
+        assert_eq!(command.to_string(), "for -P 4 f in a b; do gzip $f; done");
+        assert!(parse("for -P 0 f in a; do true; done").is_err());
+        assert!(parse("for -P f in a; do true; done").is_err());
+    }
+
+    #[test]
+    fn test_lone_dash_is_an_argument() {
+        let tokens = Lexer::new("trap - INT").tokenize().unwrap();
+        let commands = Parser::new(tokens).parse().unwrap();
+        let Command::Simple(cmd) = &commands[0] else {
+            panic!("expected a simple command");
+        };
+        let words: Vec<&str> = cmd.words.iter().map(|word| word.value.as_str()).collect();
+        assert_eq!(words, ["trap", "-", "INT"]);
+    }
+
     #[test]
     fn test_pipeline() {
         let mut lexer = Lexer::new("cat file | grep foo");
@@ -719,6 +882,15 @@ mod tests {
         assert!(matches!(commands[0], Command::Pipeline(_)));
     }
 
+    #[test]
+    fn test_pipe_with_stderr() {
+        let mut lexer = Lexer::new("make 2> /dev/null |& less | wc; { a; } > log |&\n b");
+        let tokens = lexer.tokenize().unwrap();
+        let mut parser = Parser::new(tokens);
+        let commands = parser.parse().unwrap();
+        assert_eq!(commands[0].to_string(), "make 2> /dev/null 2>&1 | less | wc; { a; } > log 2>&1 | b");
+    }
+
     #[test]
     fn test_if_command() {
         let mut lexer = Lexer::new("if true; then echo yes; fi");
@@ -780,6 +952,61 @@ mod tests {
         assert!(matches!(commands[0], Command::List(_)));
     }
 
+    #[test]
+    fn test_group_command_with_redirection() {
+        let mut lexer = Lexer::new("{ echo a; echo b; } > out.txt");
+        let tokens = lexer.tokenize().unwrap();
+        let mut parser = Parser::new(tokens);
+        let commands = parser.parse().unwrap();
+        assert_eq!(commands.len(), 1);
+        match &commands[0] {
+            Command::Redirected(redirected) => {
+                assert!(matches!(*redirected.command, Command::Group(_)));
+                assert_eq!(redirected.redirections.len(), 1);
+            }
+            other => panic!("Expected redirected group, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_heredoc_delimiter_quoting() {
+        let quoting = |source: &str| -> bool {
+            let commands = Parser::new(Lexer::new(source).tokenize().unwrap()).parse().unwrap();
+            match &commands[0] {
+                Command::Simple(cmd) => match &cmd.redirections[0].target {
+                    RedirectionTarget::Heredoc { body, quoted, .. } => {
+                        assert_eq!(body, "$X\n");
+                        *quoted
+                    }
+                    other => panic!("Expected a here-document, got {:?}", other),
+                },
+                other => panic!("Expected simple command, got {:?}", other),
+            }
+        };
+        assert!(!quoting("cat <<EOF\n$X\nEOF"));
+        assert!(!quoting("cat <<-EOF\n$X\nEOF"));
+        assert!(quoting("cat <<'EOF'\n$X\nEOF"));
+        assert!(quoting("cat <<\"EOF\"\n$X\nEOF"));
+        assert!(quoting("cat <<\\EOF\n$X\nEOF"));
+        assert!(quoting("cat <<E'O'F\n$X\nEOF"));
+    }
+
+    #[test]
+    fn test_case_command_with_glob_patterns() {
+        let mut lexer = Lexer::new("case $x in\n  foo*|bar) echo foo\n    ;;\n  *) ;;\nesac");
+        let tokens = lexer.tokenize().unwrap();
+        let mut parser = Parser::new(tokens);
+        let commands = parser.parse().unwrap();
+        match &commands[0] {
+            Command::Case(case_cmd) => {
+                assert_eq!(case_cmd.cases.len(), 2);
+                assert_eq!(case_cmd.cases[0].patterns, vec!["foo*", "bar"]);
+                assert_eq!(case_cmd.cases[1].patterns, vec!["*"]);
+            }
+            other => panic!("Expected case command, got {:?}", other),
+        }
+    }
+
     #[test]
     fn test_compound_list_in_if() {
         // Test compound_list with multiple commands
@@ -801,4 +1028,31 @@ mod tests {
         assert_eq!(commands.len(), 1);
         assert!(matches!(commands[0], Command::If(_)));
     }
+
+    #[test]
+    fn test_posix_function_def() {
+        let mut lexer = Lexer::new("greet() {\n  echo hi\n} > log.txt\ngreet");
+        let tokens = lexer.tokenize().unwrap();
+        let mut parser = Parser::new(tokens);
+        let commands = parser.parse().unwrap();
+        assert_eq!(commands.len(), 1);
+        let Command::List(list) = &commands[0] else {
+            panic!("Expected list, got {:?}", commands[0]);
+        };
+        match &list.items[0].command {
+            Command::FunctionDef(def) => {
+                assert_eq!(def.name, "greet");
+                assert!(matches!(*def.body, Command::Redirected(_)));
+            }
+            other => panic!("Expected function definition, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_posix_function_def_requires_compound_body() {
+        let mut lexer = Lexer::new("greet() echo hi");
+        let tokens = lexer.tokenize().unwrap();
+        let mut parser = Parser::new(tokens);
+        assert!(parser.parse().is_err());
+    }
 }
@@ -0,0 +1,180 @@
+use crate::messages::tr;
+
+/// What `path` does with its operands
+#[derive(Debug, PartialEq)]
+pub enum Operation {
+    Normalize,
+    Absolute,
+    Relative,
+    Join,
+    Basename,
+    Dirname,
+    Ext,
+}
+
+impl Operation {
+    pub fn parse(name: &str) -> Result<Self, String> {
+        Ok(match name {
+            "normalize" => Self::Normalize,
+            "absolute" => Self::Absolute,
+            "relative" => Self::Relative,
+            "join" => Self::Join,
+            "basename" => Self::Basename,
+            "dirname" => Self::Dirname,
+            "ext" => Self::Ext,
+            _ => return Err(tr!("path: {}: unknown operation", name)),
+        })
+    }
+
+    /// The lines of output for `paths`: one for each, except that `join`
+    /// makes one of them all and `relative` takes a target and an optional
+    /// base. `cwd` is where relative paths start.
+    pub fn apply(&self, paths: &[String], cwd: &str) -> Result<Vec<String>, String> {
+        let each = |transform: &dyn Fn(&str) -> String| paths.iter().map(|path| transform(path)).collect();
+        Ok(match self {
+            Self::Normalize => each(&normalize),
+            Self::Absolute => each(&|path| absolute(path, cwd)),
+            Self::Basename => each(&basename),
+            Self::Dirname => each(&dirname),
+            Self::Ext => each(&extension),
+            Self::Join => vec![join(paths)],
+            Self::Relative => match paths {
+                [target] => vec![relative(target, cwd, cwd)],
+                [target, base] => vec![relative(target, base, cwd)],
+                _ => return Err(tr!("path: usage: path relative TARGET [BASE]")),
+            },
+        })
+    }
+}
+
+/// `path` without `.` components, repeated or trailing slashes, or `..`
+/// that can be resolved without looking at the file system. An absolute
+/// path has no `..` left; a relative one keeps those leading out of it.
+pub fn normalize(path: &str) -> String {
+    let absolute = path.starts_with('/');
+    let mut components: Vec<&str> = Vec::new();
+    for component in path.split('/') {
+        match component {
+            "" | "." => {}
+            ".." if components.last().is_some_and(|last| *last != "..") => {
+                components.pop();
+            }
+            ".." if absolute => {}
+            component => components.push(component),
+        }
+    }
+    let joined = components.join("/");
+    match (absolute, joined.is_empty()) {
+        (true, _) => format!("/{}", joined),
+        (false, true) => ".".to_string(),
+        (false, false) => joined,
+    }
+}
+
+/// `path` made absolute from `cwd` and normalized
+pub fn absolute(path: &str, cwd: &str) -> String {
+    if path.starts_with('/') { normalize(path) } else { normalize(&format!("{}/{}", cwd, path)) }
+}
+
+/// The path that leads from the directory `base` to `target`, both taken
+/// from `cwd` if relative
+pub fn relative(target: &str, base: &str, cwd: &str) -> String {
+    let target = absolute(target, cwd);
+    let base = absolute(base, cwd);
+    let components = |path: &str| path.split('/').filter(|component| !component.is_empty()).map(str::to_string).collect::<Vec<_>>();
+    let (target, base) = (components(&target), components(&base));
+    let common = target.iter().zip(&base).take_while(|(a, b)| a == b).count();
+    let mut steps = vec!["..".to_string(); base.len() - common];
+    steps.extend_from_slice(&target[common..]);
+    if steps.is_empty() { ".".to_string() } else { steps.join("/") }
+}
+
+/// `parts` joined with single slashes; an absolute part starts over, as
+/// `cd` would
+pub fn join(parts: &[String]) -> String {
+    let mut joined = String::new();
+    for part in parts.iter().filter(|part| !part.is_empty()) {
+        if part.starts_with('/') {
+            joined.clear();
+        } else if !joined.is_empty() && !joined.ends_with('/') {
+            joined.push('/');
+        }
+        joined.push_str(part);
+    }
+    joined
+}
+
+/// The last component of `path`, ignoring trailing slashes, as basename(1)
+pub fn basename(path: &str) -> String {
+    let trimmed = path.trim_end_matches('/');
+    match trimmed.rsplit_once('/') {
+        Some((_, name)) => name.to_string(),
+        None if trimmed.is_empty() && !path.is_empty() => "/".to_string(),
+        None if trimmed.is_empty() => ".".to_string(),
+        None => trimmed.to_string(),
+    }
+}
+
+/// `path` without its last component, as dirname(1)
+pub fn dirname(path: &str) -> String {
+    let trimmed = path.trim_end_matches('/');
+    match trimmed.rsplit_once('/') {
+        Some((parent, _)) if parent.trim_end_matches('/').is_empty() => "/".to_string(),
+        Some((parent, _)) => parent.trim_end_matches('/').to_string(),
+        None if trimmed.is_empty() && !path.is_empty() => "/".to_string(),
+        None => ".".to_string(),
+    }
+}
+
+/// The extension of the last component, without its dot: empty if there
+/// is none, or if the name only starts with a dot as `.bashrc` does
+pub fn extension(path: &str) -> String {
+    let name = basename(path);
+    match name.rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() => extension.to_string(),
+        _ => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize() {
+        assert_eq!(normalize("/usr//local/./bin/"), "/usr/local/bin");
+        assert_eq!(normalize("/usr/local/../lib"), "/usr/lib");
+        assert_eq!(normalize("/../.."), "/");
+        assert_eq!(normalize("a/../../b/./"), "../b");
+        assert_eq!(normalize("a/.."), ".");
+        assert_eq!(normalize(""), ".");
+        assert_eq!(absolute("../x/", "/home/me"), "/home/x");
+        assert_eq!(absolute("/tmp/./y", "/home/me"), "/tmp/y");
+    }
+
+    #[test]
+    fn test_relative() {
+        assert_eq!(relative("/usr/share/doc", "/usr/lib", "/"), "../share/doc");
+        assert_eq!(relative("src/main.rs", "src", "/repo"), "main.rs");
+        assert_eq!(relative("/repo", "/repo/src/", "/"), "..");
+        assert_eq!(relative("/repo/", "/repo", "/"), ".");
+    }
+
+    #[test]
+    fn test_components() {
+        let parts = |parts: &[&str]| parts.iter().map(|part| part.to_string()).collect::<Vec<_>>();
+        assert_eq!(join(&parts(&["a/", "b", "", "c.txt"])), "a/b/c.txt");
+        assert_eq!(join(&parts(&["a", "/etc", "hosts"])), "/etc/hosts");
+        assert_eq!(basename("/usr/lib/"), "lib");
+        assert_eq!(basename("//"), "/");
+        assert_eq!(basename("file"), "file");
+        assert_eq!(dirname("/usr/lib/"), "/usr");
+        assert_eq!(dirname("/usr"), "/");
+        assert_eq!(dirname("a//b"), "a");
+        assert_eq!(dirname("file"), ".");
+        assert_eq!(dirname("/"), "/");
+        assert_eq!(extension("dir.d/archive.tar.gz"), "gz");
+        assert_eq!(extension("/home/me/.bashrc"), "");
+        assert_eq!(extension("Makefile"), "");
+    }
+}
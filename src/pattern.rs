@@ -0,0 +1,391 @@
+/// Shell pattern matching as used by `case`, pathname expansion and the
+/// pattern-based parameter expansions.
+///
+/// Supported syntax:
+/// - `*` matches any string, including the empty string
+/// - `?` matches any single character
+/// - `[...]` matches one character from the set, with ranges (`a-z`),
+///   negation (`[!...]` or `[^...]`) and POSIX classes (`[[:digit:]]`)
+/// - `\c` matches the character `c` literally
+/// - with `shopt -s extglob`, `?(a|b)`, `*(a|b)`, `+(a|b)`, `@(a|b)` and
+///   `!(a|b)` match zero or one, zero or more, one or more, exactly one,
+///   or none of the `|`-separated patterns. The shell escapes their `(`
+///   while the option is off, so the matcher always knows them.
+pub fn matches(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    match_from(&pattern, &text, false)
+}
+
+/// Like `matches`, but a letter matches itself in either case, as with
+/// `shopt -s nocaseglob` or `nocasematch`
+pub fn matches_ignoring_case(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    match_from(&pattern, &text, true)
+}
+
+/// `text` without the shortest, or with `longest` the longest, prefix
+/// that `pattern` matches, as in `${name#pattern}` and `${name##pattern}`.
+/// With `suffix`, the same at the end, as `%` and `%%` do.
+pub fn remove(pattern: &str, text: &str, suffix: bool, longest: bool) -> String {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let mut cuts: Box<dyn Iterator<Item = usize>> = if suffix == longest {
+        Box::new(0..=text.len())
+    } else {
+        Box::new((0..=text.len()).rev())
+    };
+    let kept = match cuts.find(|&cut| if suffix { match_from(&pattern, &text[cut..], false) } else { match_from(&pattern, &text[..cut], false) }) {
+        Some(cut) if suffix => &text[..cut],
+        Some(cut) => &text[cut..],
+        None => &text[..],
+    };
+    kept.iter().collect()
+}
+
+/// Which matches of `${name/pattern/string}` are replaced
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Anchor {
+    /// `/`: the first
+    First,
+    /// `//`: every one
+    All,
+    /// `/#`: one at the start
+    Start,
+    /// `/%`: one at the end
+    End,
+}
+
+/// `text` with the longest matches of `pattern` where `anchor` says
+/// replaced with `replacement`. Only an anchored pattern may match the
+/// empty string.
+pub fn replace(pattern: &str, text: &str, replacement: &str, anchor: Anchor) -> String {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let mut replaced = String::new();
+    let rest = match anchor {
+        Anchor::Start => match (0..=text.len()).rev().find(|&end| match_from(&pattern, &text[..end], false)) {
+            Some(end) => {
+                replaced.push_str(replacement);
+                &text[end..]
+            }
+            None => &text[..],
+        },
+        Anchor::End => match (0..=text.len()).find(|&start| match_from(&pattern, &text[start..], false)) {
+            Some(start) => {
+                replaced.extend(&text[..start]);
+                replaced.push_str(replacement);
+                &[]
+            }
+            None => &text[..],
+        },
+        Anchor::First | Anchor::All => {
+            let mut start = 0;
+            while start < text.len() && !pattern.is_empty() {
+                match (start + 1..=text.len()).rev().find(|&end| match_from(&pattern, &text[start..end], false)) {
+                    Some(end) => {
+                        replaced.push_str(replacement);
+                        start = end;
+                        if anchor == Anchor::First {
+                            break;
+                        }
+                    }
+                    None => {
+                        replaced.push(text[start]);
+                        start += 1;
+                    }
+                }
+            }
+            &text[start..]
+        }
+    };
+    replaced.extend(rest);
+    replaced
+}
+
+fn match_from(pattern: &[char], text: &[char], nocase: bool) -> bool {
+    let mut p = 0;
+    let mut t = 0;
+    // Where to resume after the most recent `*` if a later match fails
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    loop {
+        let group = group_at(&pattern[p..]);
+        if pattern.get(p) == Some(&'*') && group.is_none() {
+            backtrack = Some((p, t));
+            p += 1;
+            continue;
+        }
+
+        let step = if let Some((kind, alternatives, len)) = group {
+            // A group matches the rest of the text together with what
+            // follows it, trying each way of splitting the two
+            if match_group(kind, &alternatives, &pattern[p + len..], &text[t..], nocase) {
+                return true;
+            }
+            None
+        } else if t == text.len() {
+            return p == pattern.len();
+        } else {
+            match pattern.get(p) {
+                Some('?') => Some(1),
+                Some('[') => match match_bracket(&pattern[p..], text[t], nocase) {
+                    Some((true, len)) => Some(len),
+                    Some((false, _)) => None,
+                    // An unterminated bracket matches a literal '['
+                    None if text[t] == '[' => Some(1),
+                    None => None,
+                },
+                Some('\\') if p + 1 < pattern.len() => {
+                    if same(pattern[p + 1], text[t], nocase) {
+                        Some(2)
+                    } else {
+                        None
+                    }
+                }
+                Some(&ch) if same(ch, text[t], nocase) => Some(1),
+                _ => None,
+            }
+        };
+
+        match step {
+            Some(len) => {
+                p += len;
+                t += 1;
+            }
+            None => match backtrack {
+                Some((star_p, star_t)) if star_t < text.len() => {
+                    p = star_p + 1;
+                    t = star_t + 1;
+                    backtrack = Some((star_p, star_t + 1));
+                }
+                _ => return false,
+            },
+        }
+    }
+}
+
+/// The extended pattern at the start of `pattern`, such as `@(a|b)`: its
+/// kind, its `|`-separated alternatives and its length. None without a
+/// closing `)`.
+fn group_at(pattern: &[char]) -> Option<(char, Vec<&[char]>, usize)> {
+    let kind = *pattern.first().filter(|&&ch| matches!(ch, '?' | '*' | '+' | '@' | '!'))?;
+    if pattern.get(1) != Some(&'(') {
+        return None;
+    }
+    let mut alternatives = Vec::new();
+    let mut start = 2;
+    let mut depth = 0;
+    let mut i = 2;
+    while i < pattern.len() {
+        match pattern[i] {
+            '\\' => i += 1,
+            '(' => depth += 1,
+            ')' if depth > 0 => depth -= 1,
+            ')' => {
+                alternatives.push(&pattern[start..i]);
+                return Some((kind, alternatives, i + 1));
+            }
+            '|' if depth == 0 => {
+                alternatives.push(&pattern[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    None
+}
+
+/// Whether the group `kind(alternatives)` followed by `rest` matches `text`
+fn match_group(kind: char, alternatives: &[&[char]], rest: &[char], text: &[char], nocase: bool) -> bool {
+    let any = |part: &[char]| alternatives.iter().any(|alternative| match_from(alternative, part, nocase));
+    let mut splits = 0..=text.len();
+    match kind {
+        '?' => match_from(rest, text, nocase) || splits.any(|k| any(&text[..k]) && match_from(rest, &text[k..], nocase)),
+        '@' => splits.any(|k| any(&text[..k]) && match_from(rest, &text[k..], nocase)),
+        '!' => splits.any(|k| !any(&text[..k]) && match_from(rest, &text[k..], nocase)),
+        '+' => splits.any(|k| any(&text[..k]) && match_repeated(alternatives, rest, &text[k..], nocase)),
+        _ => match_repeated(alternatives, rest, text, nocase),
+    }
+}
+
+/// Whether any number of `alternatives` followed by `rest` match `text`.
+/// Each repetition takes at least one character, so this ends.
+fn match_repeated(alternatives: &[&[char]], rest: &[char], text: &[char], nocase: bool) -> bool {
+    match_from(rest, text, nocase)
+        || (1..=text.len()).any(|k| {
+            alternatives.iter().any(|alternative| match_from(alternative, &text[..k], nocase))
+                && match_repeated(alternatives, rest, &text[k..], nocase)
+        })
+}
+
+/// Match `ch` against the bracket expression at the start of `pattern`.
+/// Returns whether it matched and the length of the expression, or None if
+/// the bracket is not terminated.
+fn match_bracket(pattern: &[char], ch: char, nocase: bool) -> Option<(bool, usize)> {
+    let mut i = 1;
+    let negated = matches!(pattern.get(i), Some('!' | '^'));
+    if negated {
+        i += 1;
+    }
+
+    let mut matched = false;
+    let mut first = true;
+
+    loop {
+        let current = *pattern.get(i)?;
+        if current == ']' && !first {
+            return Some((matched != negated, i + 1));
+        }
+        first = false;
+
+        if current == '[' && pattern.get(i + 1) == Some(&':') {
+            let rest: String = pattern[i + 2..].iter().collect();
+            if let Some(end) = rest.find(":]") {
+                let class = &rest[..end];
+                matched |= match_class(class, ch);
+                i += 2 + class.chars().count() + 2;
+                continue;
+            }
+        }
+
+        let start = if current == '\\' {
+            i += 1;
+            *pattern.get(i)?
+        } else {
+            current
+        };
+
+        if pattern.get(i + 1) == Some(&'-') && pattern.get(i + 2).is_some_and(|&c| c != ']') {
+            let end = pattern[i + 2];
+            let in_range = |ch: char| start <= ch && ch <= end;
+            matched |= in_range(ch) || nocase && (ch.to_lowercase().any(in_range) || ch.to_uppercase().any(in_range));
+            i += 3;
+        } else {
+            matched |= same(start, ch, nocase);
+            i += 1;
+        }
+    }
+}
+
+/// Whether `a` and `b` are the same character, or with `nocase` the same
+/// letter in either case
+fn same(a: char, b: char, nocase: bool) -> bool {
+    a == b || nocase && a.to_lowercase().eq(b.to_lowercase())
+}
+
+fn match_class(class: &str, ch: char) -> bool {
+    match class {
+        "alnum" => ch.is_alphanumeric(),
+        "alpha" => ch.is_alphabetic(),
+        "blank" => ch == ' ' || ch == '\t',
+        "cntrl" => ch.is_control(),
+        "digit" => ch.is_ascii_digit(),
+        "graph" => !ch.is_whitespace() && !ch.is_control(),
+        "lower" => ch.is_lowercase(),
+        "print" => !ch.is_control(),
+        "punct" => ch.is_ascii_punctuation(),
+        "space" => ch.is_whitespace(),
+        "upper" => ch.is_uppercase(),
+        "xdigit" => ch.is_ascii_hexdigit(),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_star_and_question() {
+        assert!(matches("foo*", "foobar"));
+        assert!(matches("*bar", "foobar"));
+        assert!(matches("f*o*r", "foobar"));
+        assert!(matches("*", ""));
+        assert!(matches("?oo", "foo"));
+        assert!(!matches("?oo", "fooo"));
+        assert!(!matches("foo*", "fobar"));
+    }
+
+    #[test]
+    fn test_brackets() {
+        assert!(matches("[abc]x", "bx"));
+        assert!(!matches("[abc]x", "dx"));
+        assert!(matches("[a-z][0-9]", "q7"));
+        assert!(matches("[!a-z]*", "Zed"));
+        assert!(!matches("[^a-z]*", "zed"));
+        assert!(matches("[]]", "]"));
+        assert!(matches("[[:digit:]][[:upper:]]", "1A"));
+    }
+
+    #[test]
+    fn test_remove() {
+        assert_eq!(remove("*/", "/usr/local/bin", false, false), "usr/local/bin");
+        assert_eq!(remove("*/", "/usr/local/bin", false, true), "bin");
+        assert_eq!(remove(".*", "archive.tar.gz", true, false), "archive.tar");
+        assert_eq!(remove(".*", "archive.tar.gz", true, true), "archive");
+        assert_eq!(remove("x*", "archive", false, true), "archive");
+        assert_eq!(remove("*", "héllo", false, false), "héllo");
+        assert_eq!(remove("*", "héllo", true, true), "");
+    }
+
+    #[test]
+    fn test_replace() {
+        assert_eq!(replace("o", "foo boo", "0", Anchor::First), "f0o boo");
+        assert_eq!(replace("o", "foo boo", "0", Anchor::All), "f00 b00");
+        assert_eq!(replace("o*", "foo boo", "", Anchor::First), "f");
+        assert_eq!(replace("[[:space:]]", "a b\tc", "_", Anchor::All), "a_b_c");
+        assert_eq!(replace("f?", "foo", "F", Anchor::Start), "Fo");
+        assert_eq!(replace("o", "foo", "F", Anchor::Start), "foo");
+        assert_eq!(replace("", "foo", "pre-", Anchor::Start), "pre-foo");
+        assert_eq!(replace(".*", "a.tar.gz", ".zip", Anchor::End), "a.zip");
+        assert_eq!(replace("", "foo", "X", Anchor::All), "foo");
+        assert_eq!(replace("é", "héhé", "e", Anchor::All), "hehe");
+    }
+
+    #[test]
+    fn test_escapes_and_literals() {
+        assert!(matches("a\\*b", "a*b"));
+        assert!(!matches("a\\*b", "axb"));
+        assert!(matches("[", "["));
+    }
+
+    #[test]
+    fn test_ignoring_case() {
+        assert!(matches_ignoring_case("*.JPG", "photo.jpg"));
+        assert!(matches_ignoring_case("readme.?D", "README.md"));
+        assert!(matches_ignoring_case("[a-c]x", "Bx"));
+        assert!(matches_ignoring_case("[!a-c]x", "Dx"));
+        assert!(!matches_ignoring_case("[!a-c]x", "Cx"));
+        assert!(matches_ignoring_case("\\Yes", "yes"));
+        assert!(matches_ignoring_case("@(yes|no)", "NO"));
+        assert!(matches_ignoring_case("[[:upper:]]", "A"));
+        assert!(!matches("*.JPG", "photo.jpg"));
+    }
+
+    #[test]
+    fn test_extended_patterns() {
+        assert!(matches("*.@(jpg|png)", "cat.png"));
+        assert!(!matches("*.@(jpg|png)", "cat.gif"));
+        assert!(matches("a?(b)c", "ac"));
+        assert!(matches("a?(b)c", "abc"));
+        assert!(!matches("a?(b)c", "abbc"));
+        assert!(matches("a*(b|c)d", "abcbd"));
+        assert!(matches("a*(b)", "a"));
+        assert!(!matches("a+(b)", "a"));
+        assert!(matches("a+(b|cd)", "abcdb"));
+        assert!(matches("!(*.txt)", "notes.md"));
+        assert!(!matches("!(*.txt)", "notes.txt"));
+        assert!(matches("*!(.txt)", "notes.txt"));
+        assert!(matches("@(a|@(b|c))x", "cx"));
+        assert!(matches("*@(|x)", ""));
+        // Escaped, or never closed, they are literal
+        assert!(matches("@\\(a)", "@(a)"));
+        assert!(matches("+(a", "+(a"));
+        assert_eq!(remove("+([0-9])", "2024-10", false, true), "-10");
+        assert_eq!(remove("@(.tar|).gz", "a.tar.gz", true, true), "a");
+        assert_eq!(replace("+(o)", "foo boo", "0", Anchor::All), "f0 b0");
+    }
+}
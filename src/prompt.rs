@@ -0,0 +1,341 @@
+//! The interactive prompt, put together from segments that `PS1` names as
+//! `\{name}`. A segment renders one piece of the shell's state; a new one
+//! only has to implement `PromptSegment` and be registered with `Prompt`.
+
+use crate::datetime;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// The prompt when `PS1` is unset or empty
+pub const DEFAULT_PS1: &str = "$ ";
+/// Commands quicker than this don't show how long they took
+const MIN_DURATION: Duration = Duration::from_secs(2);
+const TIME_FORMAT: &str = "%H:%M:%S";
+
+/// What segments can show, taken just before the prompt is drawn
+#[derive(Debug, Clone, Default)]
+pub struct ShellState {
+    pub cwd: PathBuf,
+    /// Shown as `~` at the start of the working directory
+    pub home: Option<PathBuf>,
+    /// The exit status of the last command
+    pub status: i32,
+    /// Jobs running or stopped in the background
+    pub jobs: usize,
+    /// How long the last command line took to run, if one was run
+    pub duration: Option<Duration>,
+    /// Seconds since the epoch
+    pub time: i64,
+}
+
+/// A segment's text and the SGR parameters to show it with, such as
+/// `"1;34"` for bold blue, or `""` for none. Empty text leaves the segment
+/// out of the prompt.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StyledText {
+    pub text: String,
+    pub style: &'static str,
+}
+
+impl StyledText {
+    pub fn new(text: impl Into<String>, style: &'static str) -> Self {
+        Self { text: text.into(), style }
+    }
+
+    pub fn empty() -> Self {
+        Self::new("", "")
+    }
+}
+
+/// One piece of the prompt
+pub trait PromptSegment {
+    /// What `PS1` calls it, as in `\{name}`
+    fn name(&self) -> &'static str;
+    fn render(&self, state: &ShellState) -> StyledText;
+}
+
+/// The prompt as it is measured and as it is shown
+#[derive(Debug, Clone, PartialEq)]
+pub struct RenderedPrompt {
+    pub plain: String,
+    /// With the segments' colors
+    pub styled: String,
+}
+
+/// The segments `PS1` can use
+pub struct Prompt {
+    segments: Vec<Box<dyn PromptSegment>>,
+}
+
+impl Prompt {
+    /// A prompt with the built-in segments: cwd, git, status, jobs,
+    /// duration and time
+    pub fn new() -> Self {
+        let mut prompt = Self { segments: Vec::new() };
+        let builtins: [Box<dyn PromptSegment>; 6] =
+            [Box::new(Cwd), Box::new(Git), Box::new(Status), Box::new(Jobs), Box::new(LastDuration), Box::new(Time)];
+        for segment in builtins {
+            prompt.register(segment);
+        }
+        prompt
+    }
+
+    /// Add a segment, replacing any of the same name
+    pub fn register(&mut self, segment: Box<dyn PromptSegment>) {
+        self.segments.retain(|existing| existing.name() != segment.name());
+        self.segments.push(segment);
+    }
+
+    /// `ps1` with each `\{name}` replaced by that segment, `\$` by `#` for
+    /// root and `$` for anyone else, `\n` by a newline and `\\` by a
+    /// backslash. A segment with nothing to show takes the space after it
+    /// along, so `\{status} \$ ` is just `$ ` after a success. Unknown
+    /// names are left as they are.
+    pub fn render(&self, ps1: &str, state: &ShellState) -> RenderedPrompt {
+        let mut rendered = RenderedPrompt { plain: String::new(), styled: String::new() };
+        let mut rest = ps1;
+        while let Some(index) = rest.find('\\') {
+            rendered.push(&rest[..index], "");
+            rest = &rest[index + 1..];
+            let Some(next) = rest.chars().next() else {
+                rendered.push("\\", "");
+                break;
+            };
+            rest = &rest[next.len_utf8()..];
+            match next {
+                '{' => {
+                    let segment = rest
+                        .split_once('}')
+                        .and_then(|(name, after)| Some((self.segments.iter().find(|s| s.name() == name)?, after)));
+                    let Some((segment, after)) = segment else {
+                        rendered.push("\\{", "");
+                        continue;
+                    };
+                    rest = after;
+                    let text = segment.render(state);
+                    if text.text.is_empty() {
+                        rest = rest.strip_prefix(' ').unwrap_or(rest);
+                    }
+                    rendered.push(&text.text, text.style);
+                }
+                '$' => rendered.push(if unsafe { libc::geteuid() } == 0 { "#" } else { "$" }, ""),
+                'n' => rendered.push("\n", ""),
+                '\\' => rendered.push("\\", ""),
+                other => {
+                    rendered.push("\\", "");
+                    rendered.push(other.encode_utf8(&mut [0; 4]), "");
+                }
+            }
+        }
+        rendered.push(rest, "");
+        rendered
+    }
+}
+
+impl Default for Prompt {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RenderedPrompt {
+    fn push(&mut self, text: &str, style: &str) {
+        self.plain.push_str(text);
+        if style.is_empty() || text.is_empty() {
+            self.styled.push_str(text);
+        } else {
+            self.styled.push_str(&format!("\x1b[{}m{}\x1b[0m", style, text));
+        }
+    }
+}
+
+/// The working directory, with `~` for home
+struct Cwd;
+
+impl PromptSegment for Cwd {
+    fn name(&self) -> &'static str {
+        "cwd"
+    }
+
+    fn render(&self, state: &ShellState) -> StyledText {
+        let home = state.home.as_deref().filter(|home| *home != Path::new("/"));
+        let text = match home.and_then(|home| state.cwd.strip_prefix(home).ok()) {
+            Some(rest) if rest.as_os_str().is_empty() => "~".to_string(),
+            Some(rest) => format!("~/{}", rest.display()),
+            None => state.cwd.display().to_string(),
+        };
+        StyledText::new(text, "1;34")
+    }
+}
+
+/// The branch checked out in the repository the shell is in, or the
+/// commit when none is. Read from `HEAD` rather than by running git, which
+/// would slow every prompt down.
+struct Git;
+
+impl PromptSegment for Git {
+    fn name(&self) -> &'static str {
+        "git"
+    }
+
+    fn render(&self, state: &ShellState) -> StyledText {
+        match git_head(&state.cwd) {
+            Some(head) => StyledText::new(head, "35"),
+            None => StyledText::empty(),
+        }
+    }
+}
+
+fn git_head(cwd: &Path) -> Option<String> {
+    let dot_git = cwd.ancestors().map(|dir| dir.join(".git")).find(|path| path.exists())?;
+    // Worktrees and submodules have a file pointing at the git directory
+    let git_dir = match std::fs::read_to_string(&dot_git) {
+        Ok(link) => dot_git.parent()?.join(link.strip_prefix("gitdir:")?.trim()),
+        Err(_) => dot_git,
+    };
+    let head = std::fs::read_to_string(git_dir.join("HEAD")).ok()?;
+    let head = head.trim();
+    match head.strip_prefix("ref: ") {
+        Some(reference) => Some(reference.strip_prefix("refs/heads/").unwrap_or(reference).to_string()),
+        None => Some(head.chars().take(7).collect()),
+    }
+}
+
+/// The last command's exit status, when it failed
+struct Status;
+
+impl PromptSegment for Status {
+    fn name(&self) -> &'static str {
+        "status"
+    }
+
+    fn render(&self, state: &ShellState) -> StyledText {
+        if state.status == 0 {
+            return StyledText::empty();
+        }
+        StyledText::new(state.status.to_string(), "31")
+    }
+}
+
+/// How many jobs there are, after an `&` as they were started with
+struct Jobs;
+
+impl PromptSegment for Jobs {
+    fn name(&self) -> &'static str {
+        "jobs"
+    }
+
+    fn render(&self, state: &ShellState) -> StyledText {
+        if state.jobs == 0 {
+            return StyledText::empty();
+        }
+        StyledText::new(format!("&{}", state.jobs), "33")
+    }
+}
+
+/// How long the last command line took, when it was slow enough to notice
+struct LastDuration;
+
+impl PromptSegment for LastDuration {
+    fn name(&self) -> &'static str {
+        "duration"
+    }
+
+    fn render(&self, state: &ShellState) -> StyledText {
+        match state.duration.filter(|duration| *duration >= MIN_DURATION) {
+            Some(duration) => StyledText::new(format_duration(duration), "33"),
+            None => StyledText::empty(),
+        }
+    }
+}
+
+/// Whole seconds, with minutes and hours above them: `5s`, `2m05s`, `1h02m03s`
+fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    match (seconds / 3600, seconds / 60 % 60, seconds % 60) {
+        (0, 0, s) => format!("{}s", s),
+        (0, m, s) => format!("{}m{:02}s", m, s),
+        (h, m, s) => format!("{}h{:02}m{:02}s", h, m, s),
+    }
+}
+
+/// The local time
+struct Time;
+
+impl PromptSegment for Time {
+    fn name(&self) -> &'static str {
+        "time"
+    }
+
+    fn render(&self, state: &ShellState) -> StyledText {
+        StyledText::new(datetime::format(TIME_FORMAT, state.time, false).unwrap_or_default(), "2")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state() -> ShellState {
+        ShellState {
+            cwd: PathBuf::from("/home/user/src"),
+            home: Some(PathBuf::from("/home/user")),
+            ..ShellState::default()
+        }
+    }
+
+    #[test]
+    fn test_render() {
+        let prompt = Prompt::new();
+        let mut state = state();
+        let rendered = prompt.render("\\{cwd} \\{status} \\{jobs} \\{duration} > ", &state);
+        assert_eq!(rendered.plain, "~/src > ");
+        assert_eq!(rendered.styled, "\x1b[1;34m~/src\x1b[0m > ");
+
+        state.status = 130;
+        state.jobs = 2;
+        state.duration = Some(Duration::from_secs(125));
+        let rendered = prompt.render("\\{status} \\{jobs} \\{duration}\\n\\{unknown}\\\\ \\w", &state);
+        assert_eq!(rendered.plain, "130 &2 2m05s\n\\{unknown}\\ \\w");
+
+        state.cwd = PathBuf::from("/home/user");
+        assert_eq!(prompt.render("\\{cwd}\\", &state).plain, "~\\");
+        state.cwd = PathBuf::from("/home/username");
+        assert_eq!(prompt.render("\\{cwd}", &state).plain, "/home/username");
+        assert_eq!(prompt.render("\\{time}", &ShellState::default()).plain.len(), 8);
+        assert_eq!(format_duration(Duration::from_secs(3723)), "1h02m03s");
+    }
+
+    #[test]
+    fn test_custom_segment() {
+        struct Shout;
+        impl PromptSegment for Shout {
+            fn name(&self) -> &'static str {
+                "cwd"
+            }
+            fn render(&self, state: &ShellState) -> StyledText {
+                StyledText::new(state.cwd.display().to_string().to_uppercase(), "")
+            }
+        }
+        let mut prompt = Prompt::new();
+        prompt.register(Box::new(Shout));
+        assert_eq!(prompt.render("\\{cwd}", &state()).styled, "/HOME/USER/SRC");
+    }
+
+    #[test]
+    fn test_git_head() {
+        let root = std::env::temp_dir().join(format!("clam-test-{}-prompt-git", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("repo/.git")).unwrap();
+        std::fs::create_dir_all(root.join("repo/src/deep")).unwrap();
+        std::fs::create_dir_all(root.join("worktree")).unwrap();
+        std::fs::write(root.join("repo/.git/HEAD"), "ref: refs/heads/feature/x\n").unwrap();
+
+        assert_eq!(git_head(&root.join("repo/src/deep")).as_deref(), Some("feature/x"));
+        std::fs::write(root.join("worktree/.git"), "gitdir: ../repo/.git\n").unwrap();
+        std::fs::write(root.join("repo/.git/HEAD"), "0123456789abcdef\n").unwrap();
+        assert_eq!(git_head(&root.join("worktree")).as_deref(), Some("0123456"));
+        assert_eq!(git_head(&root), None);
+        let _ = std::fs::remove_dir_all(&root);
+    }
+}
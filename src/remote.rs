@@ -0,0 +1,108 @@
+use crate::lexer;
+use crate::messages::tr;
+use crate::script;
+use std::io::Write;
+use std::os::unix::process::ExitStatusExt;
+use std::process::{Command as ProcessCommand, Stdio};
+
+/// The shell that runs the script on the remote host, `sh` by default
+const REMOTE_SHELL_VAR: &str = "CLAM_REMOTE_SHELL";
+/// What ssh exits with when it fails itself
+const SSH_ERROR_STATUS: i32 = 255;
+
+/// `clam --ssh HOST SCRIPT [ARGS...]`: check the script's syntax here,
+/// then feed it to a shell on `host` with `args` as its positional
+/// parameters, and return the status it exits with. Nothing runs remotely
+/// if the script doesn't parse.
+pub fn run_over_ssh(host: &str, path: &str, args: &[String]) -> i32 {
+    let source = match std::fs::read_to_string(path) {
+        Ok(source) => source,
+        Err(e) => {
+            eprintln!("clam: {}", tr!("{}: {}", path, e));
+            return 127;
+        }
+    };
+    if let Err(error) = script::parse(&source) {
+        eprintln!("clam: {}", script::syntax_error_message(path, &error));
+        return script::SYNTAX_ERROR_STATUS;
+    }
+
+    let shell = std::env::var(REMOTE_SHELL_VAR).unwrap_or_else(|_| "sh".to_string());
+    match ssh_args(host).and_then(|ssh_args| stream("ssh", &ssh_args, &remote_command(&shell, args), &source)) {
+        Ok(status) => status,
+        Err(e) => {
+            eprintln!("clam: {}", tr!("--ssh: {}", e));
+            SSH_ERROR_STATUS
+        }
+    }
+}
+
+/// ssh's arguments before the command. A host starting with `-` would be
+/// taken for an option, and `-oProxyCommand=...` runs a command here, so
+/// it is refused, and `--` ends the options in any case.
+fn ssh_args(host: &str) -> Result<[&str; 2], String> {
+    if host.starts_with('-') {
+        return Err(tr!("{}: invalid host", host));
+    }
+    Ok(["--", host])
+}
+
+/// The command line for ssh to run: `shell` reading the script from its
+/// stdin. ssh joins its arguments for the remote login shell to parse
+/// again, so each one is quoted.
+fn remote_command(shell: &str, args: &[String]) -> String {
+    let mut command = format!("{} -s --", shell);
+    for arg in args {
+        command.push(' ');
+        command.push_str(&lexer::quote(arg));
+    }
+    command
+}
+
+/// Run `program` with `args` and then `command`, writing `source` to its
+/// stdin, and return its exit status the way the shell reports one
+fn stream(program: &str, args: &[&str], command: &str, source: &str) -> Result<i32, String> {
+    let mut child = ProcessCommand::new(program)
+        .args(args)
+        .arg(command)
+        .stdin(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("{}: {}", program, e))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        // The remote shell exits early on `exit`, closing the pipe
+        let _ = stdin.write_all(source.as_bytes());
+    }
+    let status = child.wait().map_err(|e| format!("{}: {}", program, e))?;
+    match (status.code(), status.signal()) {
+        (Some(code), _) => Ok(code),
+        (None, Some(signal)) => Ok(128 + signal),
+        _ => Err(tr!("{}: exited abnormally", program)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_remote_command() {
+        let args = ["a b".to_string(), "it's".to_string(), String::new()];
+        assert_eq!(remote_command("sh", &args), "sh -s -- 'a b' 'it'\\''s' ''");
+    }
+
+    #[test]
+    fn test_ssh_args_refuse_options() {
+        assert_eq!(ssh_args("user@example.com"), Ok(["--", "user@example.com"]));
+        assert!(ssh_args("-oProxyCommand=touch /tmp/owned").is_err());
+        assert!(ssh_args("-p2222").is_err());
+    }
+
+    #[test]
+    fn test_stream_relays_status() {
+        // `sh -c` stands in for ssh, parsing the command line the way the
+        // remote login shell would
+        let command = remote_command("sh", &["two words".to_string()]);
+        assert_eq!(stream("sh", &["-c"], &command, "test \"$1\" = 'two words' || exit 9\nexit 3\n"), Ok(3));
+        assert_eq!(stream("sh", &["-c"], &command, "exit $#\n"), Ok(1));
+    }
+}
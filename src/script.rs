@@ -0,0 +1,152 @@
+use crate::ast::Command;
+use crate::executor::Executor;
+use crate::lexer::Lexer;
+use crate::messages::tr;
+use crate::parser::Parser;
+
+/// When enabled (the default), a script is parsed completely before any of
+/// it runs, so a syntax error anywhere means nothing is executed
+const STRICT_SYNTAX_VAR: &str = "CLAM_STRICT_SYNTAX";
+/// Exit status for syntax errors, as in bash
+pub const SYNTAX_ERROR_STATUS: i32 = 2;
+
+/// A syntax error and the line it was found on
+#[derive(Debug, PartialEq)]
+pub struct SyntaxError {
+    pub line: usize,
+    pub message: String,
+    /// The input ended in the middle of a command
+    pub incomplete: bool,
+}
+
+/// Run the script at `path` with `args` as its positional parameters and
+/// return the shell's exit status
+pub fn run_script(executor: &mut Executor, path: &str, args: &[String]) -> i32 {
+    let source = match std::fs::read_to_string(path) {
+        Ok(source) => source,
+        Err(e) => {
+            eprintln!("clam: {}", tr!("{}: {}", path, e));
+            return 127;
+        }
+    };
+
+    executor.set_shell_name(path);
+    executor.set_positional(args.to_vec());
+
+    let strict = std::env::var(STRICT_SYNTAX_VAR)
+        .map(|value| value != "0")
+        .unwrap_or(true);
+    let result = if strict {
+        parse(&source).map(|commands| run_commands(executor, path, &commands))
+    } else {
+        run_incrementally(executor, path, &source)
+    };
+
+    match result {
+        Ok(()) => {
+            executor.clean_up_on_exit();
+            executor.get_last_exit_status()
+        }
+        Err(error) => {
+            eprintln!("clam: {}", syntax_error_message(path, &error));
+            SYNTAX_ERROR_STATUS
+        }
+    }
+}
+
+/// How a syntax error in the script at `path` is reported
+pub fn syntax_error_message(path: &str, error: &SyntaxError) -> String {
+    let message = if error.incomplete { tr!("unexpected end of file") } else { error.message.clone() };
+    tr!("{}: line {}: syntax error: {}", path, error.line, message)
+}
+
+/// Parse a complete source text
+pub fn parse(source: &str) -> Result<Vec<Command>, SyntaxError> {
+    let mut lexer = Lexer::new(source);
+    let tokens = lexer.tokenize().map_err(|message| SyntaxError {
+        line: lexer.line(),
+        message,
+        incomplete: lexer.is_eof(),
+    })?;
+
+    let mut parser = Parser::new(tokens);
+    parser.parse().map_err(|message| SyntaxError {
+        line: parser.line(),
+        message,
+        incomplete: parser.is_at_end(),
+    })
+}
+
+/// Run each complete command as soon as it has been read, like bash does,
+/// stopping at the first syntax error
+fn run_incrementally(executor: &mut Executor, path: &str, source: &str) -> Result<(), SyntaxError> {
+    let mut buffer = String::new();
+    let mut start_line = 1;
+
+    for (index, line) in source.lines().enumerate() {
+        if buffer.is_empty() {
+            start_line = index + 1;
+        }
+        buffer.push_str(line);
+        buffer.push('\n');
+
+        match parse(&buffer) {
+            Ok(commands) => {
+                run_commands(executor, path, &commands);
+                buffer.clear();
+            }
+            Err(error) if error.incomplete => continue,
+            Err(error) => {
+                return Err(SyntaxError {
+                    line: start_line + error.line - 1,
+                    ..error
+                });
+            }
+        }
+    }
+
+    if buffer.trim().is_empty() {
+        return Ok(());
+    }
+    Err(SyntaxError {
+        line: source.lines().count(),
+        message: tr!("unexpected end of file"),
+        incomplete: true,
+    })
+}
+
+fn run_commands(executor: &mut Executor, path: &str, commands: &[Command]) {
+    for command in commands {
+        if let Err(e) = executor.execute_isolated(command) {
+            eprintln!("clam: {}", tr!("{}: {}", path, e));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_reports_error_line() {
+        let error = parse("echo one\necho two\necho )\n").unwrap_err();
+        assert_eq!(error.line, 3);
+        assert!(!error.incomplete);
+
+        let error = parse("if true; then\n  echo yes\n").unwrap_err();
+        assert!(error.incomplete);
+    }
+
+    #[test]
+    fn test_incremental_runs_up_to_error() {
+        let mut executor = Executor::new();
+        let source = "A=1\nif true; then\n  B=2\nfi\necho )\nC=3\n";
+
+        let error = run_incrementally(&mut executor, "test", source).unwrap_err();
+
+        assert_eq!(error.line, 5);
+        assert_eq!(executor.get_variable("A"), "1");
+        assert_eq!(executor.get_variable("B"), "2");
+        assert_eq!(executor.get_variable("C"), "");
+    }
+}
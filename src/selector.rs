@@ -0,0 +1,269 @@
+use crate::lexer;
+use crate::tui::{Key, ListView, Terminal};
+use rustyline::{Cmd, ConditionalEventHandler, Event, EventContext, Movement, RepeatCount};
+use std::io;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+/// History entries shared between the REPL and the history widget
+pub type SharedHistory = Arc<Mutex<Vec<String>>>;
+
+const MAX_FILE_DEPTH: usize = 8;
+const MAX_FILE_CANDIDATES: usize = 20000;
+
+/// Score `candidate` against `query` as a fuzzy subsequence match.
+/// Returns None when not every query character appears in order.
+/// Matching is case-insensitive unless the query contains an uppercase letter.
+pub fn fuzzy_score(query: &str, candidate: &str) -> Option<i64> {
+    if query.is_empty() {
+        return Some(0);
+    }
+
+    let case_sensitive = query.chars().any(|c| c.is_uppercase());
+    let normalize = |c: char| {
+        if case_sensitive {
+            c
+        } else {
+            c.to_lowercase().next().unwrap_or(c)
+        }
+    };
+
+    let mut query_chars = query.chars().map(normalize).peekable();
+    let mut score = 0i64;
+    let mut previous: Option<char> = None;
+    let mut last_match: Option<usize> = None;
+
+    for (index, ch) in candidate.chars().enumerate() {
+        let Some(&wanted) = query_chars.peek() else {
+            break;
+        };
+
+        if normalize(ch) == wanted {
+            score += 1;
+            match last_match {
+                Some(last) if last + 1 == index => score += 5,
+                Some(last) => score -= (index - last - 1).min(3) as i64,
+                None => {}
+            }
+            if matches!(previous, None | Some('/' | '-' | '_' | ' ' | '.')) {
+                score += 3;
+            }
+            last_match = Some(index);
+            query_chars.next();
+        }
+
+        previous = Some(ch);
+    }
+
+    if query_chars.peek().is_some() {
+        return None;
+    }
+
+    // Prefer shorter candidates among otherwise equal matches
+    Some(score * 100 - candidate.chars().count().min(99) as i64)
+}
+
+/// Indices of the candidates matching `query`, best match first.
+/// Equal scores keep the original candidate order.
+pub fn filter(query: &str, candidates: &[String]) -> Vec<usize> {
+    let mut matches: Vec<(usize, i64)> = candidates
+        .iter()
+        .enumerate()
+        .filter_map(|(index, candidate)| fuzzy_score(query, candidate).map(|score| (index, score)))
+        .collect();
+
+    matches.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    matches.into_iter().map(|(index, _)| index).collect()
+}
+
+/// Full-screen fuzzy selector over `candidates`.
+/// Returns the chosen candidate, or None if the user cancelled.
+pub fn select(prompt: &str, candidates: &[String], initial_query: &str) -> io::Result<Option<String>> {
+    let mut terminal = Terminal::open_raw()?;
+    terminal.enter_alternate_screen()?;
+
+    let result = run_selector(&mut terminal, prompt, candidates, initial_query);
+
+    terminal.leave_alternate_screen()?;
+    result
+}
+
+fn run_selector(
+    terminal: &mut Terminal,
+    prompt: &str,
+    candidates: &[String],
+    initial_query: &str,
+) -> io::Result<Option<String>> {
+    let mut query = initial_query.to_string();
+    let mut view = ListView::default();
+
+    loop {
+        let matches = filter(&query, candidates);
+        let (rows, cols) = terminal.size();
+        let visible = rows.saturating_sub(2).max(1);
+        view.scroll(matches.len(), visible);
+
+        render(terminal, prompt, &query, candidates, &matches, &view, visible, cols)?;
+
+        let key = match terminal.read_key()? {
+            // Also the keys of fzf for moving through the list
+            Key::Ctrl('k') => Key::Up,
+            Key::Ctrl('j') | Key::Tab => Key::Down,
+            key => key,
+        };
+        if view.handle_key(key, matches.len(), visible) {
+            continue;
+        }
+        match key {
+            Key::Enter => {
+                return Ok(matches.get(view.selected()).map(|&index| candidates[index].clone()));
+            }
+            Key::Escape | Key::Ctrl('c') | Key::Ctrl('g') | Key::Ctrl('d') => return Ok(None),
+            Key::Backspace | Key::Ctrl('h') => {
+                query.pop();
+                view.select(0);
+            }
+            Key::Ctrl('u') => {
+                query.clear();
+                view.select(0);
+            }
+            Key::Char(ch) => {
+                query.push(ch);
+                view.select(0);
+            }
+            _ => {}
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn render(
+    terminal: &mut Terminal,
+    prompt: &str,
+    query: &str,
+    candidates: &[String],
+    matches: &[usize],
+    view: &ListView,
+    visible: usize,
+    cols: usize,
+) -> io::Result<()> {
+    let mut screen = String::from("\x1b[H\x1b[2J");
+    screen.push_str(&format!("{}> {}\r\n", prompt, query));
+    screen.push_str(&format!("\x1b[2m  {}/{}\x1b[0m\r\n", matches.len(), candidates.len()));
+    screen.push_str(&view.render(matches.iter().map(|&index| candidates[index].as_str()), visible, cols));
+
+    // Park the cursor at the end of the query line
+    let query_width = prompt.chars().count() + 2 + query.chars().count();
+    screen.push_str(&format!("\x1b[1;{}H", query_width + 1));
+
+    terminal.write(&screen)?;
+    terminal.flush()
+}
+
+fn collect_files(dir: &Path, prefix: &str, depth: usize, files: &mut Vec<String>) {
+    if depth > MAX_FILE_DEPTH || files.len() >= MAX_FILE_CANDIDATES {
+        return;
+    }
+
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+
+    let mut entries: Vec<_> = entries.filter_map(|entry| entry.ok()).collect();
+    entries.sort_by_key(|entry| entry.file_name());
+
+    for entry in entries {
+        if files.len() >= MAX_FILE_CANDIDATES {
+            return;
+        }
+
+        let name = entry.file_name().to_string_lossy().to_string();
+        if name.starts_with('.') {
+            continue;
+        }
+
+        let path = format!("{}{}", prefix, name);
+        let is_dir = entry.file_type().map(|t| t.is_dir()).unwrap_or(false);
+        if is_dir {
+            files.push(format!("{}/", path));
+            collect_files(&entry.path(), &format!("{}/", path), depth + 1, files);
+        } else {
+            files.push(path);
+        }
+    }
+}
+
+/// Ctrl-R: replace the line with a fuzzy-picked history entry
+pub struct HistoryWidget {
+    history: SharedHistory,
+}
+
+impl HistoryWidget {
+    pub fn new(history: SharedHistory) -> Self {
+        Self { history }
+    }
+}
+
+impl ConditionalEventHandler for HistoryWidget {
+    fn handle(&self, _evt: &Event, _n: RepeatCount, _positive: bool, ctx: &EventContext) -> Option<Cmd> {
+        // Most recent first, without duplicates
+        let mut entries: Vec<String> = Vec::new();
+        for entry in self.history.lock().ok()?.iter().rev() {
+            if !entries.contains(entry) {
+                entries.push(entry.clone());
+            }
+        }
+
+        match select("history", &entries, ctx.line()) {
+            Ok(Some(line)) => Some(Cmd::Replace(Movement::WholeLine, Some(line))),
+            Ok(None) => Some(Cmd::Repaint),
+            // No usable terminal: fall back to the default reverse search
+            Err(_) => None,
+        }
+    }
+}
+
+/// Ctrl-T: insert a fuzzy-picked path below the current directory
+pub struct FileWidget;
+
+impl ConditionalEventHandler for FileWidget {
+    fn handle(&self, _evt: &Event, _n: RepeatCount, _positive: bool, _ctx: &EventContext) -> Option<Cmd> {
+        let mut files = Vec::new();
+        collect_files(Path::new("."), "", 0, &mut files);
+
+        match select("files", &files, "") {
+            Ok(Some(path)) => Some(Cmd::Insert(1, lexer::quote(&path))),
+            Ok(None) => Some(Cmd::Repaint),
+            Err(_) => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fuzzy_subsequence() {
+        assert!(fuzzy_score("gco", "git checkout").is_some());
+        assert!(fuzzy_score("gco", "cargo build").is_none());
+        assert!(fuzzy_score("", "anything").is_some());
+    }
+
+    #[test]
+    fn test_fuzzy_smart_case() {
+        assert!(fuzzy_score("make", "Makefile").is_some());
+        assert!(fuzzy_score("Make", "makefile").is_none());
+    }
+
+    #[test]
+    fn test_filter_prefers_consecutive_matches() {
+        let candidates = vec![
+            "cat src/main.rs".to_string(),
+            "cargo test".to_string(),
+            "cd target".to_string(),
+        ];
+        let matches = filter("cargo", &candidates);
+        assert_eq!(matches[0], 1);
+    }
+}
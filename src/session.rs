@@ -0,0 +1,103 @@
+use crate::ast::Command;
+use crate::history;
+use crate::messages::tr;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+
+/// Where `session save` keeps sessions, by default `clam/sessions` in
+/// `$XDG_DATA_HOME`
+pub const SESSION_DIR_VAR: &str = "CLAM_SESSION_DIR";
+
+/// The state a user built up in a shell, as `session save` writes it and
+/// `session restore` reads it back
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Session {
+    pub cwd: Option<PathBuf>,
+    pub variables: BTreeMap<String, String>,
+    pub functions: BTreeMap<String, Command>,
+    /// The `set -o` options and whether each is on
+    pub options: BTreeMap<String, bool>,
+}
+
+impl Session {
+    /// Write the session called `name` in `dir`, replacing any saved
+    /// before. Only the user can read it, as variables may hold secrets.
+    pub fn save(&self, dir: &Path, name: &str) -> Result<PathBuf, String> {
+        let path = session_file(dir, name)?;
+        let json = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+        std::fs::create_dir_all(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(&path)
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+        writeln!(file, "{}", json).map_err(|e| format!("{}: {}", path.display(), e))?;
+        Ok(path)
+    }
+
+    pub fn load(dir: &Path, name: &str) -> Result<Self, String> {
+        let path = session_file(dir, name)?;
+        let json = std::fs::read_to_string(&path).map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => tr!("{}: no such session", name),
+            _ => format!("{}: {}", path.display(), e),
+        })?;
+        serde_json::from_str(&json).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+}
+
+/// The sessions saved in `dir`, by name
+pub fn list(dir: &Path) -> Vec<String> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut names: Vec<String> = entries
+        .flatten()
+        .filter_map(|entry| entry.file_name().to_str()?.strip_suffix(".json").map(str::to_string))
+        .collect();
+    names.sort();
+    names
+}
+
+/// Where sessions are kept: `configured`, the value of `CLAM_SESSION_DIR`,
+/// unless it is empty
+pub fn directory(configured: &str) -> PathBuf {
+    if configured.is_empty() {
+        history::data_dir().join("clam/sessions")
+    } else {
+        PathBuf::from(configured)
+    }
+}
+
+fn session_file(dir: &Path, name: &str) -> Result<PathBuf, String> {
+    if name.is_empty() || name.starts_with('.') || name.contains('/') {
+        return Err(tr!("{}: invalid session name", name));
+    }
+    Ok(dir.join(format!("{}.json", name)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_session_round_trip() {
+        let dir = std::env::temp_dir().join(format!("clam-test-{}-sessions", std::process::id()));
+        let mut session = Session { cwd: Some(PathBuf::from("/tmp")), ..Session::default() };
+        session.variables.insert("EDITOR".to_string(), "vi".to_string());
+        session.options.insert("pipefail".to_string(), true);
+
+        session.save(&dir, "work").unwrap();
+        assert_eq!(Session::load(&dir, "work").unwrap(), session);
+        assert_eq!(list(&dir), ["work"]);
+        assert!(Session::load(&dir, "play").is_err());
+        assert!(session.save(&dir, "../escape").is_err());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
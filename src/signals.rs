@@ -0,0 +1,303 @@
+use std::os::raw::c_int;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicI32, AtomicUsize, Ordering};
+use std::time::Duration;
+
+/// Signals that end an interactive shell, after it has cleaned up
+const EXIT_SIGNALS: [c_int; 2] = [libc::SIGTERM, libc::SIGHUP];
+/// How long the shell gets to notice an exit signal before dying from it
+/// anyway, as when a foreground command keeps it from reading input
+const EXIT_GRACE: Duration = Duration::from_secs(2);
+
+/// Signal names without the SIG prefix, as used by `kill`
+const SIGNAL_NAMES: &[(&str, c_int)] = &[
+    ("HUP", libc::SIGHUP),
+    ("INT", libc::SIGINT),
+    ("QUIT", libc::SIGQUIT),
+    ("ILL", libc::SIGILL),
+    ("TRAP", libc::SIGTRAP),
+    ("ABRT", libc::SIGABRT),
+    ("BUS", libc::SIGBUS),
+    ("FPE", libc::SIGFPE),
+    ("KILL", libc::SIGKILL),
+    ("USR1", libc::SIGUSR1),
+    ("SEGV", libc::SIGSEGV),
+    ("USR2", libc::SIGUSR2),
+    ("PIPE", libc::SIGPIPE),
+    ("ALRM", libc::SIGALRM),
+    ("TERM", libc::SIGTERM),
+    ("CHLD", libc::SIGCHLD),
+    ("CONT", libc::SIGCONT),
+    ("STOP", libc::SIGSTOP),
+    ("TSTP", libc::SIGTSTP),
+    ("TTIN", libc::SIGTTIN),
+    ("TTOU", libc::SIGTTOU),
+    ("URG", libc::SIGURG),
+    ("XCPU", libc::SIGXCPU),
+    ("XFSZ", libc::SIGXFSZ),
+    ("VTALRM", libc::SIGVTALRM),
+    ("PROF", libc::SIGPROF),
+    ("WINCH", libc::SIGWINCH),
+    ("IO", libc::SIGIO),
+    ("SYS", libc::SIGSYS),
+];
+
+/// One more than the highest signal number
+const SIGNAL_SLOTS: usize = 65;
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+/// The SIGINT handler `catch_interrupts` replaced, if it was a function:
+/// the line editor's, which is how it learns to stop reading
+static PREVIOUS_INTERRUPT: AtomicUsize = AtomicUsize::new(0);
+/// The exit signal that arrived, until the shell takes it, and then -1
+static EXITING: AtomicI32 = AtomicI32::new(0);
+
+/// Signals with a trap set, those of them that arrived since their trap
+/// last ran, and those ignored by an empty trap
+static CAUGHT: [AtomicBool; SIGNAL_SLOTS] = [const { AtomicBool::new(false) }; SIGNAL_SLOTS];
+static PENDING: [AtomicBool; SIGNAL_SLOTS] = [const { AtomicBool::new(false) }; SIGNAL_SLOTS];
+static IGNORED: [AtomicBool; SIGNAL_SLOTS] = [const { AtomicBool::new(false) }; SIGNAL_SLOTS];
+
+/// How each signal was handled before a trap changed it
+static ORIGINAL: Mutex<Vec<(c_int, libc::sigaction)>> = Mutex::new(Vec::new());
+
+extern "C" fn record_interrupt(signal: c_int) {
+    INTERRUPTED.store(true, Ordering::SeqCst);
+    let previous = PREVIOUS_INTERRUPT.load(Ordering::SeqCst);
+    if previous != 0 {
+        let handler: extern "C" fn(c_int) = unsafe { std::mem::transmute(previous) };
+        handler(signal);
+    }
+}
+
+/// Catch SIGINT instead of dying from it, so an interactive shell can
+/// abandon the command line it is running and return to the prompt.
+/// Interrupted system calls are not restarted, so blocking builtins like
+/// `read` notice too.
+pub fn catch_interrupts() {
+    let handler = record_interrupt as *const () as libc::sighandler_t;
+    unsafe {
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = handler;
+        libc::sigemptyset(&mut action.sa_mask);
+        let mut previous: libc::sigaction = std::mem::zeroed();
+        libc::sigaction(libc::SIGINT, &action, &mut previous);
+        let chained = ![libc::SIG_DFL, libc::SIG_IGN, handler].contains(&previous.sa_sigaction)
+            && previous.sa_flags & libc::SA_SIGINFO == 0;
+        if chained {
+            PREVIOUS_INTERRUPT.store(previous.sa_sigaction, Ordering::SeqCst);
+        }
+    }
+}
+
+/// Whether SIGINT arrived since the last `take_interrupt`
+pub fn interrupt_pending() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+pub fn take_interrupt() -> bool {
+    INTERRUPTED.swap(false, Ordering::SeqCst)
+}
+
+extern "C" fn record_signal(signal: c_int) {
+    if let Some(pending) = PENDING.get(signal as usize) {
+        pending.store(true, Ordering::SeqCst);
+    }
+}
+
+/// Catch `signal` for a trap, to be run by the shell at the next
+/// opportunity. Blocking calls are interrupted, so `wait` notices it.
+pub fn catch(signal: c_int) -> std::io::Result<()> {
+    set_action(signal, record_signal as *const () as libc::sighandler_t)?;
+    CAUGHT[signal as usize].store(true, Ordering::SeqCst);
+    IGNORED[signal as usize].store(false, Ordering::SeqCst);
+    Ok(())
+}
+
+/// Ignore `signal`, as for an empty trap. Commands inherit this.
+pub fn ignore(signal: c_int) -> std::io::Result<()> {
+    set_action(signal, libc::SIG_IGN)?;
+    CAUGHT[signal as usize].store(false, Ordering::SeqCst);
+    IGNORED[signal as usize].store(true, Ordering::SeqCst);
+    Ok(())
+}
+
+/// Handle `signal` the way the shell did before any trap changed it
+pub fn restore(signal: c_int) {
+    let slot = signal as usize;
+    if signal <= 0 || slot >= SIGNAL_SLOTS {
+        return;
+    }
+    CAUGHT[slot].store(false, Ordering::SeqCst);
+    PENDING[slot].store(false, Ordering::SeqCst);
+    IGNORED[slot].store(false, Ordering::SeqCst);
+    let original = ORIGINAL.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some((_, action)) = original.iter().find(|(number, _)| *number == signal) {
+        unsafe { libc::sigaction(signal, action, std::ptr::null_mut()) };
+    }
+}
+
+/// The lowest-numbered caught signal that arrived since it was last taken
+pub fn take_caught() -> Option<c_int> {
+    (1..SIGNAL_SLOTS)
+        .find(|&signal| PENDING[signal].swap(false, Ordering::SeqCst))
+        .map(|signal| signal as c_int)
+}
+
+/// Whether a caught signal is waiting for its trap to run
+pub fn caught_pending() -> Option<c_int> {
+    (1..SIGNAL_SLOTS).find(|&signal| PENDING[signal].load(Ordering::SeqCst)).map(|signal| signal as c_int)
+}
+
+fn set_action(signal: c_int, handler: libc::sighandler_t) -> std::io::Result<()> {
+    if signal <= 0 || signal as usize >= SIGNAL_SLOTS {
+        return Err(std::io::Error::from_raw_os_error(libc::EINVAL));
+    }
+    let mut action: libc::sigaction = unsafe { std::mem::zeroed() };
+    action.sa_sigaction = handler;
+    unsafe { libc::sigemptyset(&mut action.sa_mask) };
+
+    let mut previous: libc::sigaction = unsafe { std::mem::zeroed() };
+    if unsafe { libc::sigaction(signal, &action, &mut previous) } < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    let mut original = ORIGINAL.lock().unwrap_or_else(|e| e.into_inner());
+    if !original.iter().any(|(number, _)| *number == signal) {
+        original.push((signal, previous));
+    }
+    Ok(())
+}
+
+/// Parse a signal given as a number or a name, with or without the SIG
+/// prefix and in any case
+pub fn parse(spec: &str) -> Option<c_int> {
+    if let Ok(number) = spec.parse::<c_int>() {
+        return (number == 0 || name(number).is_some()).then_some(number);
+    }
+    let upper = spec.to_ascii_uppercase();
+    let bare = upper.strip_prefix("SIG").unwrap_or(&upper);
+    SIGNAL_NAMES.iter().find(|(name, _)| *name == bare).map(|&(_, number)| number)
+}
+
+/// The name of `signal` without the SIG prefix
+pub fn name(signal: c_int) -> Option<&'static str> {
+    SIGNAL_NAMES.iter().find(|&&(_, number)| number == signal).map(|&(name, _)| name)
+}
+
+/// All signal names, ordered by number
+pub fn names() -> Vec<(c_int, &'static str)> {
+    let mut names: Vec<(c_int, &str)> = SIGNAL_NAMES.iter().map(|&(name, number)| (number, name)).collect();
+    names.sort();
+    names
+}
+
+/// Handle SIGTERM and SIGHUP on a dedicated thread, unless a trap is set
+/// for them: run `cleanup`, then interrupt the main thread as SIGINT
+/// would, so the line editor stops waiting for input, and leave it to the
+/// shell to see `exit_requested`, run its EXIT trap and `reraise` the
+/// signal. If it doesn't take the signal in time, the thread dies from it
+/// instead. A thread is needed because the line editor retries reads
+/// interrupted by other signals, so the main thread never gets to see
+/// them. Must be called from the main thread before any other starts, as
+/// threads inherit the signal mask.
+pub fn on_exit_signal(cleanup: impl Fn(c_int) + Send + 'static) {
+    let set = exit_signal_set();
+    let main = unsafe { libc::pthread_self() };
+    unsafe { libc::pthread_sigmask(libc::SIG_BLOCK, &set, std::ptr::null_mut()) };
+
+    std::thread::spawn(move || {
+        let mut signal = 0;
+        loop {
+            if unsafe { libc::sigwait(&set, &mut signal) } != 0 {
+                return;
+            }
+            if !CAUGHT[signal as usize].load(Ordering::SeqCst) {
+                break;
+            }
+            record_signal(signal);
+        }
+        EXITING.store(signal, Ordering::SeqCst);
+        cleanup(signal);
+        unsafe { libc::pthread_kill(main, libc::SIGINT) };
+        std::thread::sleep(EXIT_GRACE);
+        if EXITING.compare_exchange(signal, -1, Ordering::SeqCst, Ordering::SeqCst).is_ok() {
+            reraise(signal);
+        }
+    });
+}
+
+/// Whether an exit signal arrived that the shell has yet to take
+pub fn exit_requested() -> bool {
+    EXITING.load(Ordering::SeqCst) > 0
+}
+
+/// The exit signal that arrived, if any, which the shell must now die from
+pub fn take_exit_signal() -> Option<c_int> {
+    let signal = EXITING.load(Ordering::SeqCst);
+    let taken = signal > 0 && EXITING.compare_exchange(signal, -1, Ordering::SeqCst, Ordering::SeqCst).is_ok();
+    taken.then_some(signal)
+}
+
+/// Die from `signal` as if the shell had never handled it
+pub fn reraise(signal: c_int) -> ! {
+    unsafe {
+        let mut set: libc::sigset_t = std::mem::zeroed();
+        libc::sigemptyset(&mut set);
+        libc::sigaddset(&mut set, signal);
+        libc::signal(signal, libc::SIG_DFL);
+        libc::pthread_sigmask(libc::SIG_UNBLOCK, &set, std::ptr::null_mut());
+        libc::raise(signal);
+    }
+    std::process::exit(128 + signal);
+}
+
+/// Undo the shell's own signal handling in a forked child, so it can be
+/// interrupted and terminated normally. SIGPIPE, which the Rust runtime
+/// ignores, is restored too: a pipeline stage running builtins must die
+/// when its reader goes away, instead of writing into the void forever.
+/// Trapped signals go back to their defaults, as subshells don't inherit
+/// traps; ignored ones stay ignored.
+pub fn reset_in_child() {
+    let set = exit_signal_set();
+    unsafe { libc::pthread_sigmask(libc::SIG_UNBLOCK, &set, std::ptr::null_mut()) };
+    for signal in [libc::SIGINT, libc::SIGPIPE] {
+        if !IGNORED[signal as usize].load(Ordering::SeqCst) {
+            unsafe { libc::signal(signal, libc::SIG_DFL) };
+        }
+    }
+    for (signal, caught) in CAUGHT.iter().enumerate() {
+        PENDING[signal].store(false, Ordering::SeqCst);
+        if caught.swap(false, Ordering::SeqCst) {
+            unsafe { libc::signal(signal as c_int, libc::SIG_DFL) };
+        }
+    }
+}
+
+fn exit_signal_set() -> libc::sigset_t {
+    let mut set: libc::sigset_t = unsafe { std::mem::zeroed() };
+    unsafe {
+        libc::sigemptyset(&mut set);
+        for signal in EXIT_SIGNALS {
+            libc::sigaddset(&mut set, signal);
+        }
+    }
+    set
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_signal_names() {
+        assert_eq!(parse("TERM"), Some(libc::SIGTERM));
+        assert_eq!(parse("sigterm"), Some(libc::SIGTERM));
+        assert_eq!(parse("SIGHUP"), Some(libc::SIGHUP));
+        assert_eq!(parse("9"), Some(libc::SIGKILL));
+        assert_eq!(parse("0"), Some(0));
+        assert_eq!(parse("NOPE"), None);
+        assert_eq!(parse("999"), None);
+        assert_eq!(name(libc::SIGINT), Some("INT"));
+        assert_eq!(names()[0], (libc::SIGHUP, "HUP"));
+    }
+}
@@ -0,0 +1,151 @@
+use std::os::unix::process::CommandExt;
+use std::process::Command as ProcessCommand;
+
+/// What became of a command the shell started
+#[derive(Debug, PartialEq)]
+pub enum Spawned {
+    /// It runs as this process, for the shell to wait for
+    Running(libc::pid_t),
+    /// It is already done, with this status; only test spawners finish
+    /// commands at once
+    Exited(i32),
+}
+
+/// Starts the external commands the shell runs without forking first, so
+/// tests can see what each would have been given instead of running it
+pub trait Spawner {
+    /// Start `process`, which inherits the shell's fds as they are now
+    fn spawn(&mut self, process: &mut ProcessCommand) -> std::io::Result<Spawned>;
+}
+
+/// Runs commands for real
+pub struct ProcessSpawner;
+
+impl Spawner for ProcessSpawner {
+    fn spawn(&mut self, process: &mut ProcessCommand) -> std::io::Result<Spawned> {
+        process.spawn().map(|child| Spawned::Running(child.id() as libc::pid_t))
+    }
+}
+
+/// An I/O scheduling class, with its level from 0, the highest, to 7
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IoClass {
+    Realtime(u8),
+    BestEffort(u8),
+    Idle,
+}
+
+impl IoClass {
+    /// `idle`, or `best-effort` or `realtime` with an optional `:level`
+    pub fn parse(text: &str) -> Option<IoClass> {
+        let (name, level) = match text.split_once(':') {
+            Some((name, level)) => (name, level.parse().ok().filter(|&level| level <= 7)?),
+            None => (text, 4),
+        };
+        match name {
+            "realtime" => Some(IoClass::Realtime(level)),
+            "best-effort" => Some(IoClass::BestEffort(level)),
+            "idle" if !text.contains(':') => Some(IoClass::Idle),
+            _ => None,
+        }
+    }
+
+    /// The priority as ioprio_set(2) takes it: the class above the level
+    fn ioprio(self) -> libc::c_int {
+        let (class, level) = match self {
+            IoClass::Realtime(level) => (1, level),
+            IoClass::BestEffort(level) => (2, level),
+            IoClass::Idle => (3, 0),
+        };
+        class << 13 | libc::c_int::from(level)
+    }
+}
+
+/// The scheduling priority a command starts with, set in the child between
+/// fork and exec so it holds wherever the command runs: in a pipeline, in
+/// the background or on its own
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Priority {
+    /// Added to the niceness the command would have had, as nice(1) does
+    pub nice: Option<i32>,
+    pub io: Option<IoClass>,
+}
+
+impl Priority {
+    /// Have `process` start with this priority. If it can't be set, as
+    /// raising it takes privileges, the command isn't run.
+    pub fn apply(self, process: &mut ProcessCommand) {
+        unsafe {
+            process.pre_exec(move || self.set());
+        }
+    }
+
+    fn set(self) -> std::io::Result<()> {
+        if let Some(increment) = self.nice {
+            // -1 is a niceness as well as the error value
+            set_errno(0);
+            let current = unsafe { libc::getpriority(libc::PRIO_PROCESS, 0) };
+            if current == -1 && std::io::Error::last_os_error().raw_os_error() != Some(0) {
+                return Err(std::io::Error::last_os_error());
+            }
+            if unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, current.saturating_add(increment)) } < 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+        }
+        if let Some(class) = self.io {
+            set_io_priority(class)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn set_errno(value: libc::c_int) {
+    unsafe { *libc::__errno_location() = value };
+}
+
+#[cfg(not(target_os = "linux"))]
+fn set_errno(value: libc::c_int) {
+    unsafe { *libc::__error() = value };
+}
+
+#[cfg(target_os = "linux")]
+fn set_io_priority(class: IoClass) -> std::io::Result<()> {
+    const IOPRIO_WHO_PROCESS: libc::c_int = 1;
+    if unsafe { libc::syscall(libc::SYS_ioprio_set, IOPRIO_WHO_PROCESS, 0, class.ioprio()) } < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Only Linux has I/O scheduling classes
+#[cfg(not(target_os = "linux"))]
+fn set_io_priority(_class: IoClass) -> std::io::Result<()> {
+    Err(std::io::ErrorKind::Unsupported.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_io_class() {
+        assert_eq!(IoClass::parse("idle"), Some(IoClass::Idle));
+        assert_eq!(IoClass::parse("best-effort"), Some(IoClass::BestEffort(4)));
+        assert_eq!(IoClass::parse("realtime:0"), Some(IoClass::Realtime(0)));
+        assert_eq!(IoClass::parse("best-effort:7").map(IoClass::ioprio), Some(2 << 13 | 7));
+        assert_eq!(IoClass::parse("best-effort:8"), None);
+        assert_eq!(IoClass::parse("idle:1"), None);
+        assert_eq!(IoClass::parse("fast"), None);
+    }
+
+    #[test]
+    fn test_priority() {
+        let current = unsafe { libc::getpriority(libc::PRIO_PROCESS, 0) };
+        let mut process = ProcessCommand::new("sh");
+        process.args(["-c", "nice"]);
+        Priority { nice: Some(3), io: None }.apply(&mut process);
+        let output = process.output().unwrap();
+        assert_eq!(String::from_utf8_lossy(&output.stdout).trim(), (current + 3).min(19).to_string());
+    }
+}
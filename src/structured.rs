@@ -0,0 +1,214 @@
+use crate::messages::tr;
+use regex::Regex;
+use serde_json::Value;
+use std::cmp::Ordering;
+use std::io::{BufRead, Read, Write};
+
+/// `from-json`: read JSON values from `input` and write each as a record
+/// of its own line, splitting arrays into their elements
+pub fn from_json(input: impl Read, output: &mut impl Write) -> Result<(), String> {
+    for value in serde_json::Deserializer::from_reader(input).into_iter::<Value>() {
+        match value.map_err(|e| e.to_string())? {
+            Value::Array(values) => {
+                for value in values {
+                    write_record(output, &value)?;
+                }
+            }
+            value => write_record(output, &value)?,
+        }
+    }
+    Ok(())
+}
+
+/// `to-json`: gather the records into one JSON array
+pub fn to_json(input: impl BufRead, output: &mut impl Write, pretty: bool) -> Result<(), String> {
+    let records = Value::Array(records(input).collect::<Result<_, _>>()?);
+    let json = if pretty { serde_json::to_string_pretty(&records) } else { serde_json::to_string(&records) };
+    writeln!(output, "{}", json.map_err(|e| e.to_string())?).map_err(|e| e.to_string())
+}
+
+/// `where`: pass on the records that meet `condition`
+pub fn filter(input: impl BufRead, output: &mut impl Write, condition: &Condition) -> Result<(), String> {
+    for record in records(input) {
+        let record = record?;
+        if condition.matches(&record) {
+            write_record(output, &record)?;
+        }
+    }
+    Ok(())
+}
+
+/// `select-field`: pass on each record with only `fields`, in that order.
+/// A field the record lacks is null.
+pub fn select(input: impl BufRead, output: &mut impl Write, fields: &[String]) -> Result<(), String> {
+    for record in records(input) {
+        let record = record?;
+        // Written by hand, as a serde_json map would sort the fields
+        let mut line = String::from("{");
+        for (index, name) in fields.iter().enumerate() {
+            if index > 0 {
+                line.push(',');
+            }
+            let value = field(&record, name).unwrap_or(&Value::Null);
+            line.push_str(&Value::from(name.as_str()).to_string());
+            line.push(':');
+            line.push_str(&value.to_string());
+        }
+        line.push('}');
+        writeln!(output, "{}", line).map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+/// A test of one field of a record: `FIELD OPERATOR VALUE`
+pub struct Condition {
+    field: String,
+    operator: Operator,
+    value: String,
+}
+
+enum Operator {
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    Matches(Regex),
+}
+
+impl Condition {
+    pub fn parse(args: &[String]) -> Result<Self, String> {
+        let [field, operator, value] = args else {
+            return Err(tr!("where: usage: where FIELD OPERATOR VALUE"));
+        };
+        let operator = match operator.as_str() {
+            "==" | "-eq" => Operator::Equal,
+            "-ne" => Operator::NotEqual,
+            "-lt" => Operator::Less,
+            "-le" => Operator::LessEqual,
+            "-gt" => Operator::Greater,
+            "-ge" => Operator::GreaterEqual,
+            "=~" => Operator::Matches(Regex::new(value).map_err(|e| tr!("where: {}", e))?),
+            _ => return Err(tr!("where: {}: unknown operator", operator)),
+        };
+        Ok(Self { field: field.clone(), operator, value: value.clone() })
+    }
+
+    /// Whether `record` has the field and it passes the test. Numbers are
+    /// compared as numbers when the value is one too, anything else as text.
+    pub fn matches(&self, record: &Value) -> bool {
+        let Some(field) = field(record, &self.field) else {
+            return false;
+        };
+        if let Operator::Matches(regex) = &self.operator {
+            return regex.is_match(&text(field));
+        }
+        let ordering = match (field.as_f64(), self.value.parse::<f64>()) {
+            (Some(number), Ok(value)) => number.partial_cmp(&value),
+            _ => Some(text(field).as_str().cmp(self.value.as_str())),
+        };
+        let Some(ordering) = ordering else {
+            return false;
+        };
+        match self.operator {
+            Operator::Equal => ordering == Ordering::Equal,
+            Operator::NotEqual => ordering != Ordering::Equal,
+            Operator::Less => ordering == Ordering::Less,
+            Operator::LessEqual => ordering != Ordering::Greater,
+            Operator::Greater => ordering == Ordering::Greater,
+            Operator::GreaterEqual => ordering != Ordering::Less,
+            Operator::Matches(_) => unreachable!(),
+        }
+    }
+}
+
+/// The records of a stream, one JSON value per line. Blank lines are
+/// skipped.
+fn records(input: impl BufRead) -> impl Iterator<Item = Result<Value, String>> {
+    input.lines().enumerate().filter_map(|(index, line)| match line {
+        Ok(line) if line.trim().is_empty() => None,
+        Ok(line) => Some(serde_json::from_str(&line).map_err(|e| tr!("line {}: {}", index + 1, e))),
+        Err(e) => Some(Err(e.to_string())),
+    })
+}
+
+fn write_record(output: &mut impl Write, record: &Value) -> Result<(), String> {
+    writeln!(output, "{}", record).map_err(|e| e.to_string())
+}
+
+/// The field at `path` in `record`, following `.` into nested objects, or
+/// into arrays by index, as in `owner.name` or `tags.0`
+fn field<'a>(record: &'a Value, path: &str) -> Option<&'a Value> {
+    path.split('.').try_fold(record, |value, key| match value {
+        Value::Object(fields) => fields.get(key),
+        Value::Array(values) => values.get(key.parse::<usize>().ok()?),
+        _ => None,
+    })
+}
+
+/// A value as text to compare: a string without its quotes, anything else
+/// as JSON
+fn text(value: &Value) -> String {
+    match value {
+        Value::String(text) => text.clone(),
+        value => value.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(input: &str, stage: impl FnOnce(&[u8], &mut Vec<u8>) -> Result<(), String>) -> String {
+        let mut output = Vec::new();
+        stage(input.as_bytes(), &mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    fn condition(args: &[&str]) -> Condition {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        Condition::parse(&args).unwrap()
+    }
+
+    #[test]
+    fn test_from_json() {
+        let records = run(r#"[{"a": 1}, {"a": 2}] {"a": 3}"#, |input, output| from_json(input, output));
+        assert_eq!(records, "{\"a\":1}\n{\"a\":2}\n{\"a\":3}\n");
+        assert!(from_json("[1,".as_bytes(), &mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn test_where() {
+        let input = r#"{"name": "a.rs", "size": 120, "owner": {"name": "root"}}
+{"name": "b.md", "size": 9}
+
+{"name": "c.rs", "size": 4000, "owner": {"name": "me"}}
+"#;
+        let names = |args: &[&str]| {
+            let output = run(input, |input, output| filter(input, output, &condition(args)));
+            output.lines().map(|line| field(&serde_json::from_str(line).unwrap(), "name").unwrap().to_string()).collect::<Vec<_>>()
+        };
+        assert_eq!(names(&["size", "-gt", "100"]), ["\"a.rs\"", "\"c.rs\""]);
+        // 9 < 100 as numbers, though not as text
+        assert_eq!(names(&["size", "-lt", "100"]), ["\"b.md\""]);
+        assert_eq!(names(&["name", "=~", r"\.rs$"]), ["\"a.rs\"", "\"c.rs\""]);
+        assert_eq!(names(&["owner.name", "==", "me"]), ["\"c.rs\""]);
+        assert_eq!(names(&["owner.name", "-ne", "me"]), ["\"a.rs\""]);
+
+        assert!(Condition::parse(&["size".to_string()]).is_err());
+        assert!(Condition::parse(&["a".to_string(), "<>".to_string(), "b".to_string()]).is_err());
+        assert!(filter("not json\n".as_bytes(), &mut Vec::new(), &condition(&["a", "==", "b"])).is_err());
+    }
+
+    #[test]
+    fn test_select_and_to_json() {
+        let input = "{\"size\": 1, \"name\": \"a\", \"tags\": [\"x\"]}\n";
+        let fields = ["name".to_string(), "size".to_string(), "tags.0".to_string(), "mode".to_string()];
+        let selected = run(input, |input, output| select(input, output, &fields));
+        assert_eq!(selected, "{\"name\":\"a\",\"size\":1,\"tags.0\":\"x\",\"mode\":null}\n");
+
+        assert_eq!(run("1\n\"two\"\n", |input, output| to_json(input, output, false)), "[1,\"two\"]\n");
+        assert_eq!(run("", |input, output| to_json(input, output, false)), "[]\n");
+    }
+}
@@ -0,0 +1,139 @@
+use std::fs::OpenOptions;
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::io::{AsRawFd, RawFd};
+
+/// Terminal settings of `fd`, saved so they can be put back after a
+/// foreground job that may have changed them. Does nothing when `fd` is
+/// not a terminal.
+#[derive(Clone)]
+pub struct TerminalModes {
+    fd: RawFd,
+    saved: Option<libc::termios>,
+}
+
+impl std::fmt::Debug for TerminalModes {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("TerminalModes").field("fd", &self.fd).field("saved", &self.saved.is_some()).finish()
+    }
+}
+
+impl TerminalModes {
+    pub fn save(fd: RawFd) -> Self {
+        let mut modes: libc::termios = unsafe { std::mem::zeroed() };
+        let saved = (unsafe { libc::tcgetattr(fd, &mut modes) } == 0).then_some(modes);
+        Self { fd, saved }
+    }
+
+    pub fn restore(&self) {
+        if let Some(modes) = &self.saved {
+            unsafe { libc::tcsetattr(self.fd, libc::TCSADRAIN, modes) };
+        }
+    }
+}
+
+/// Settings in the spirit of `stty sane`: line editing, echo and signal
+/// keys on, with the usual control characters
+pub fn set_sane_modes(fd: RawFd) -> io::Result<()> {
+    let mut modes: libc::termios = unsafe { std::mem::zeroed() };
+    if unsafe { libc::tcgetattr(fd, &mut modes) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    modes.c_iflag &= !(libc::IGNBRK | libc::INLCR | libc::IGNCR | libc::IXOFF | libc::ISTRIP);
+    modes.c_iflag |= libc::BRKINT | libc::ICRNL | libc::IXON | libc::IMAXBEL;
+    modes.c_oflag |= libc::OPOST | libc::ONLCR;
+    modes.c_oflag &= !(libc::OCRNL | libc::ONOCR | libc::ONLRET);
+    modes.c_cflag |= libc::CREAD;
+    modes.c_lflag |= libc::ICANON | libc::ISIG | libc::IEXTEN | libc::ECHO | libc::ECHOE | libc::ECHOK;
+    modes.c_lflag |= libc::ECHOCTL | libc::ECHOKE;
+    modes.c_lflag &= !(libc::ECHONL | libc::NOFLSH | libc::TOSTOP);
+
+    let control_chars = [
+        (libc::VINTR, 0x03),
+        (libc::VQUIT, 0x1c),
+        (libc::VERASE, 0x7f),
+        (libc::VKILL, 0x15),
+        (libc::VEOF, 0x04),
+        (libc::VSTART, 0x11),
+        (libc::VSTOP, 0x13),
+        (libc::VSUSP, 0x1a),
+    ];
+    for (index, value) in control_chars {
+        modes.c_cc[index] = value;
+    }
+
+    if unsafe { libc::tcsetattr(fd, libc::TCSANOW, &modes) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    // Undo what a killed full-screen program may have left: alternate
+    // screen, hidden cursor and text attributes
+    let reset = b"\x1b[?1049l\x1b[?25h\x1b[0m";
+    unsafe { libc::write(fd, reset.as_ptr() as *const libc::c_void, reset.len()) };
+    Ok(())
+}
+
+/// Make `pgid` the foreground process group of the terminal `fd`, so it
+/// receives keyboard signals and may read input
+pub fn give_terminal(fd: RawFd, pgid: libc::pid_t) {
+    unsafe { libc::tcsetpgrp(fd, pgid) };
+}
+
+/// Prompt on the controlling terminal and read a line without echoing it
+pub fn read_password(prompt: &str) -> io::Result<String> {
+    let mut tty = OpenOptions::new().read(true).write(true).open("/dev/tty")?;
+    let fd = tty.as_raw_fd();
+
+    let mut original: libc::termios = unsafe { std::mem::zeroed() };
+    if unsafe { libc::tcgetattr(fd, &mut original) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let mut silent = original;
+    silent.c_lflag &= !libc::ECHO;
+    unsafe { libc::tcsetattr(fd, libc::TCSANOW, &silent) };
+
+    tty.write_all(prompt.as_bytes())?;
+    let mut line = String::new();
+    let result = BufReader::new(&tty).read_line(&mut line);
+
+    unsafe { libc::tcsetattr(fd, libc::TCSANOW, &original) };
+    tty.write_all(b"\n")?;
+
+    result?;
+    Ok(line.trim_end_matches(['\r', '\n']).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sane_modes_restore_line_editing() {
+        let (mut master, mut slave) = (0, 0);
+        let result = unsafe {
+            libc::openpty(&mut master, &mut slave, std::ptr::null_mut(), std::ptr::null(), std::ptr::null())
+        };
+        assert_eq!(result, 0);
+
+        let mut modes: libc::termios = unsafe { std::mem::zeroed() };
+        unsafe {
+            libc::tcgetattr(slave, &mut modes);
+            libc::cfmakeraw(&mut modes);
+            modes.c_cc[libc::VINTR] = 0;
+            libc::tcsetattr(slave, libc::TCSANOW, &modes);
+        }
+
+        set_sane_modes(slave).unwrap();
+
+        unsafe { libc::tcgetattr(slave, &mut modes) };
+        assert_ne!(modes.c_lflag & libc::ICANON, 0);
+        assert_ne!(modes.c_lflag & libc::ECHO, 0);
+        assert_ne!(modes.c_lflag & libc::ISIG, 0);
+        assert_ne!(modes.c_oflag & libc::OPOST, 0);
+        assert_eq!(modes.c_cc[libc::VINTR], 0x03);
+        unsafe {
+            libc::close(slave);
+            libc::close(master);
+        }
+    }
+}
@@ -0,0 +1,68 @@
+use crate::executor::Executor;
+use crate::messages::tr;
+use crate::script;
+use std::io::Write;
+
+/// Assertions made by one test script
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Assertions {
+    pub passed: usize,
+    pub failed: usize,
+}
+
+impl Assertions {
+    pub fn record(&mut self, passed: bool) {
+        if passed {
+            self.passed += 1;
+        } else {
+            self.failed += 1;
+        }
+    }
+}
+
+/// `clam --test script...`: run each script in a fresh shell with the
+/// assertion builtins enabled, then report. A script fails if any of its
+/// assertions do or it exits with a non-zero status. Returns the exit
+/// status: 0 when every script passed.
+pub fn run_tests(paths: &[String]) -> i32 {
+    let mut stdout = std::io::stdout();
+    let mut totals = Assertions::default();
+    let mut failed_scripts = 0;
+
+    for path in paths {
+        let mut executor = Executor::new();
+        executor.enable_assertions();
+        let status = script::run_script(&mut executor, path, &[]);
+        let assertions = executor.assertions().unwrap_or_default();
+        totals.passed += assertions.passed;
+        totals.failed += assertions.failed;
+
+        let count = assertions.passed + assertions.failed;
+        let failure = if assertions.failed > 0 {
+            Some(tr!("{} of {} assertions failed", assertions.failed, count))
+        } else if status != 0 {
+            Some(tr!("exit status {}", status))
+        } else {
+            None
+        };
+        let _ = match failure {
+            Some(reason) => {
+                failed_scripts += 1;
+                writeln!(stdout, "FAIL {} ({})", path, reason)
+            }
+            None => writeln!(stdout, "ok   {} ({})", path, tr!("{} assertions", count)),
+        };
+    }
+
+    let summary = tr!(
+        "{} scripts: {} passed, {} failed; {} assertions: {} passed, {} failed",
+        paths.len(),
+        paths.len() - failed_scripts,
+        failed_scripts,
+        totals.passed + totals.failed,
+        totals.passed,
+        totals.failed
+    );
+    let _ = writeln!(stdout, "\n{}", summary);
+    if failed_scripts > 0 { 1 } else { 0 }
+}
@@ -0,0 +1,122 @@
+use crate::messages::tr;
+
+/// What `str` does to its input
+#[derive(Debug, PartialEq)]
+pub enum Operation {
+    Upper,
+    Lower,
+    Trim,
+    Length,
+    /// Each field between separators on a line of its own; an empty
+    /// separator splits at runs of whitespace
+    Split(String),
+    /// All of the input on one line, with the separator between the pieces
+    Join(String),
+    /// Every occurrence of the first string replaced with the second
+    Replace(String, String),
+}
+
+impl Operation {
+    /// The operation `args` start with, and the text operands after it
+    pub fn parse(args: &[String]) -> Result<(Self, &[String]), String> {
+        let Some((name, rest)) = args.split_first() else {
+            return Err(tr!("str: usage: str [-v name] operation [args ...]"));
+        };
+        let missing = || tr!("str: {}: missing operand", name);
+        let operation = match name.as_str() {
+            "upper" => Self::Upper,
+            "lower" => Self::Lower,
+            "trim" => Self::Trim,
+            "length" => Self::Length,
+            "split" | "join" => {
+                let (separator, rest) = rest.split_first().ok_or_else(missing)?;
+                let separator = separator.clone();
+                let operation = if name == "split" { Self::Split(separator) } else { Self::Join(separator) };
+                return Ok((operation, rest));
+            }
+            "replace" => {
+                let [from, to, rest @ ..] = rest else {
+                    return Err(missing());
+                };
+                if from.is_empty() {
+                    return Err(tr!("str: replace: empty search string"));
+                }
+                return Ok((Self::Replace(from.clone(), to.clone()), rest));
+            }
+            _ => return Err(tr!("str: {}: unknown operation", name)),
+        };
+        Ok((operation, rest))
+    }
+
+    /// The lines of output for `pieces` of input: the words to join, or else
+    /// each text to transform on its own
+    pub fn apply(&self, pieces: &[String]) -> Vec<String> {
+        if let Self::Join(separator) = self {
+            return vec![pieces.join(separator)];
+        }
+        let mut lines = Vec::new();
+        for piece in pieces {
+            match self {
+                Self::Upper => lines.push(piece.to_uppercase()),
+                Self::Lower => lines.push(piece.to_lowercase()),
+                Self::Trim => lines.push(piece.trim().to_string()),
+                Self::Length => lines.push(piece.chars().count().to_string()),
+                Self::Split(separator) if separator.is_empty() => {
+                    lines.extend(piece.split_whitespace().map(str::to_string));
+                }
+                Self::Split(separator) => lines.extend(piece.split(separator.as_str()).map(str::to_string)),
+                Self::Replace(from, to) => lines.push(piece.replace(from.as_str(), to)),
+                Self::Join(_) => unreachable!(),
+            }
+        }
+        lines
+    }
+
+    /// Whether the operands are separate words, rather than one text
+    pub fn takes_words(&self) -> bool {
+        matches!(self, Self::Join(_))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(words: &[&str]) -> Vec<String> {
+        words.iter().map(|word| word.to_string()).collect()
+    }
+
+    fn apply(args: &[&str], pieces: &[&str]) -> Vec<String> {
+        let args = strings(args);
+        let (operation, rest) = Operation::parse(&args).unwrap();
+        assert!(rest.is_empty());
+        operation.apply(&strings(pieces))
+    }
+
+    #[test]
+    fn test_parse() {
+        let args = strings(&["replace", "a", "b", "banana", "bread"]);
+        let (operation, rest) = Operation::parse(&args).unwrap();
+        assert_eq!(operation, Operation::Replace("a".to_string(), "b".to_string()));
+        assert_eq!(rest, ["banana", "bread"]);
+
+        assert!(Operation::parse(&[]).is_err());
+        assert!(Operation::parse(&strings(&["reverse"])).is_err());
+        assert!(Operation::parse(&strings(&["split"])).is_err());
+        assert!(Operation::parse(&strings(&["replace", "a"])).is_err());
+        assert!(Operation::parse(&strings(&["replace", "", "b"])).is_err());
+    }
+
+    #[test]
+    fn test_apply() {
+        assert_eq!(apply(&["upper"], &["Grüße", "ok"]), ["GRÜSSE", "OK"]);
+        assert_eq!(apply(&["lower"], &["ABC"]), ["abc"]);
+        assert_eq!(apply(&["trim"], &["  a b \t"]), ["a b"]);
+        assert_eq!(apply(&["length"], &["héllo", ""]), ["5", "0"]);
+        assert_eq!(apply(&["split", ":"], &["/bin::/usr/bin"]), ["/bin", "", "/usr/bin"]);
+        assert_eq!(apply(&["split", ""], &[" a  b\tc "]), ["a", "b", "c"]);
+        assert_eq!(apply(&["join", ","], &["a", "b", "c"]), ["a,b,c"]);
+        assert_eq!(apply(&["join", ","], &[]), [""]);
+        assert_eq!(apply(&["replace", "na", "NA"], &["banana"]), ["baNANA"]);
+    }
+}
@@ -20,6 +20,7 @@ pub enum TokenKind {
 
     // Operators
     Pipe,           // |
+    PipeAnd,        // |&
     And,            // &&
     Or,             // ||
     Semicolon,      // ;
@@ -35,8 +36,10 @@ pub enum TokenKind {
     LessAnd,        // <&
     GreatAnd,       // >&
     LessLessDash,   // <<-
+    LessLessLess,   // <<<
     GreatPipe,      // >|
     AndGreat,       // &>
+    AndGreatGreat,  // &>>
     LessGreat,      // <>
 
     // Parentheses and braces
@@ -63,6 +66,12 @@ pub enum TokenKind {
     Function,
     Time,
 
+    // Expression of an arithmetic command, ((expression))
+    Arithmetic,
+
+    // Here-document body, following its delimiter word
+    HeredocBody,
+
     // Separators
     Newline,
     Dash,           // -
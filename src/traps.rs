@@ -0,0 +1,113 @@
+use crate::signals;
+use std::collections::BTreeMap;
+use std::os::raw::c_int;
+
+/// Something a trap can be set on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Condition {
+    /// The shell exiting
+    Exit,
+    Signal(c_int),
+    /// Before each simple command
+    Debug,
+    /// After a command fails, unless its status is being tested
+    Err,
+    /// After a function or sourced file returns
+    Return,
+}
+
+impl Condition {
+    /// Parse `EXIT`, `DEBUG`, `ERR`, `RETURN`, `0`, or a signal name or
+    /// number
+    pub fn parse(spec: &str) -> Option<Condition> {
+        let pseudo = [
+            ("EXIT", Condition::Exit),
+            ("DEBUG", Condition::Debug),
+            ("ERR", Condition::Err),
+            ("RETURN", Condition::Return),
+        ];
+        if let Some(&(_, condition)) = pseudo.iter().find(|(name, _)| spec.eq_ignore_ascii_case(name)) {
+            return Some(condition);
+        }
+        match signals::parse(spec)? {
+            0 => Some(Condition::Exit),
+            signal => Some(Condition::Signal(signal)),
+        }
+    }
+
+    pub fn name(&self) -> String {
+        match self {
+            Condition::Exit => "EXIT".to_string(),
+            Condition::Signal(signal) => format!("SIG{}", signals::name(*signal).unwrap_or("?")),
+            Condition::Debug => "DEBUG".to_string(),
+            Condition::Err => "ERR".to_string(),
+            Condition::Return => "RETURN".to_string(),
+        }
+    }
+}
+
+/// Commands to run when a condition occurs. An empty command means a
+/// signal is ignored.
+#[derive(Clone, Default)]
+pub struct TrapTable {
+    actions: BTreeMap<Condition, String>,
+}
+
+impl TrapTable {
+    pub fn get(&self, condition: Condition) -> Option<&str> {
+        self.actions.get(&condition).map(String::as_str)
+    }
+
+    pub fn set(&mut self, condition: Condition, action: &str) {
+        self.actions.insert(condition, action.to_string());
+    }
+
+    pub fn reset(&mut self, condition: Condition) -> Option<String> {
+        self.actions.remove(&condition)
+    }
+
+    pub fn conditions(&self) -> Vec<Condition> {
+        self.actions.keys().copied().collect()
+    }
+
+    /// The trap as a command that would set it again, as `trap -p` prints
+    pub fn format(&self, condition: Condition) -> Option<String> {
+        let action = self.get(condition)?;
+        Some(format!("trap -- '{}' {}", action.replace('\'', "'\\''"), condition.name()))
+    }
+
+    /// A subshell keeps only the traps that ignore signals
+    pub fn clear_for_subshell(&mut self) {
+        self.actions.retain(|condition, action| matches!(condition, Condition::Signal(_)) && action.is_empty());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_conditions() {
+        assert_eq!(Condition::parse("EXIT"), Some(Condition::Exit));
+        assert_eq!(Condition::parse("0"), Some(Condition::Exit));
+        assert_eq!(Condition::parse("int"), Some(Condition::Signal(libc::SIGINT)));
+        assert_eq!(Condition::parse("SIGTERM"), Some(Condition::Signal(libc::SIGTERM)));
+        assert_eq!(Condition::parse("15"), Some(Condition::Signal(libc::SIGTERM)));
+        assert_eq!(Condition::parse("err"), Some(Condition::Err));
+        assert_eq!(Condition::parse("RETURN"), Some(Condition::Return));
+        assert_eq!(Condition::parse("NOPE"), None);
+    }
+
+    #[test]
+    fn test_format_and_subshell_traps() {
+        let mut traps = TrapTable::default();
+        traps.set(Condition::Exit, "echo 'bye'");
+        traps.set(Condition::Signal(libc::SIGINT), "");
+        traps.set(Condition::Signal(libc::SIGTERM), "cleanup");
+        assert_eq!(traps.format(Condition::Exit).unwrap(), "trap -- 'echo '\\''bye'\\''' EXIT");
+        assert_eq!(traps.format(Condition::Signal(libc::SIGINT)).unwrap(), "trap -- '' SIGINT");
+
+        traps.clear_for_subshell();
+        assert_eq!(traps.conditions(), vec![Condition::Signal(libc::SIGINT)]);
+    }
+}
@@ -0,0 +1,352 @@
+//! The pieces interactive widgets share: the terminal in raw mode, keys
+//! read from it, and a scrolling list to pick from that keeps up with the
+//! window being resized
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::unix::io::AsRawFd;
+
+/// How often a wait for a key checks whether the window was resized
+const RESIZE_POLL_MS: i32 = 100;
+
+/// A key press decoded from raw terminal input
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Key {
+    Char(char),
+    Ctrl(char),
+    Enter,
+    Tab,
+    Backspace,
+    Escape,
+    Up,
+    Down,
+    Left,
+    Right,
+    Home,
+    End,
+    PageUp,
+    PageDown,
+    /// Not a key: the window changed size, so what is shown should be
+    /// drawn again
+    Resize,
+    Unknown,
+}
+
+/// The controlling terminal, opened independently of stdin/stdout so that
+/// interactive widgets keep working while the shell's fds are redirected.
+/// Raw mode is enabled on open and the original settings restored on drop.
+pub struct Terminal {
+    tty: File,
+    original: libc::termios,
+    /// The size last reported, to notice when it changes
+    size: (usize, usize),
+}
+
+impl Terminal {
+    pub fn open_raw() -> io::Result<Self> {
+        let tty = OpenOptions::new().read(true).write(true).open("/dev/tty")?;
+        Self::raw(tty)
+    }
+
+    /// Put `tty` in raw mode
+    fn raw(tty: File) -> io::Result<Self> {
+        let fd = tty.as_raw_fd();
+
+        let mut original: libc::termios = unsafe { std::mem::zeroed() };
+        if unsafe { libc::tcgetattr(fd, &mut original) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut raw = original;
+        unsafe { libc::cfmakeraw(&mut raw) };
+        raw.c_cc[libc::VMIN] = 1;
+        raw.c_cc[libc::VTIME] = 0;
+        if unsafe { libc::tcsetattr(fd, libc::TCSANOW, &raw) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut terminal = Self { tty, original, size: (0, 0) };
+        terminal.size = terminal.query_size();
+        Ok(terminal)
+    }
+
+    /// Terminal size as (rows, columns), falling back to 24x80
+    pub fn size(&self) -> (usize, usize) {
+        self.size
+    }
+
+    fn query_size(&self) -> (usize, usize) {
+        let mut winsize: libc::winsize = unsafe { std::mem::zeroed() };
+        let result = unsafe { libc::ioctl(self.tty.as_raw_fd(), libc::TIOCGWINSZ, &mut winsize) };
+        if result < 0 || winsize.ws_row == 0 || winsize.ws_col == 0 {
+            (24, 80)
+        } else {
+            (winsize.ws_row as usize, winsize.ws_col as usize)
+        }
+    }
+
+    pub fn write(&mut self, text: &str) -> io::Result<()> {
+        self.tty.write_all(text.as_bytes())
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.tty.flush()
+    }
+
+    pub fn enter_alternate_screen(&mut self) -> io::Result<()> {
+        self.write("\x1b[?1049h\x1b[H")
+    }
+
+    pub fn leave_alternate_screen(&mut self) -> io::Result<()> {
+        self.write("\x1b[?1049l")?;
+        self.flush()
+    }
+
+    /// The next key pressed, or `Key::Resize` as soon as the window size
+    /// changes while waiting for one
+    pub fn read_key(&mut self) -> io::Result<Key> {
+        while !self.poll_input(RESIZE_POLL_MS)? {
+            let size = self.query_size();
+            if size != self.size {
+                self.size = size;
+                return Ok(Key::Resize);
+            }
+        }
+        let byte = self.read_byte()?;
+
+        let key = match byte {
+            b'\r' | b'\n' => Key::Enter,
+            b'\t' => Key::Tab,
+            0x7f | 0x08 => Key::Backspace,
+            0x1b => self.read_escape_sequence()?,
+            0x01..=0x1a => Key::Ctrl((b'a' + byte - 1) as char),
+            _ if byte < 0x80 => Key::Char(byte as char),
+            _ => self.read_utf8(byte)?,
+        };
+
+        Ok(key)
+    }
+
+    fn read_escape_sequence(&mut self) -> io::Result<Key> {
+        // A lone ESC is not followed by anything within a short delay
+        if !self.poll_input(25)? {
+            return Ok(Key::Escape);
+        }
+
+        let next = self.read_byte()?;
+        if next != b'[' && next != b'O' {
+            return Ok(Key::Unknown);
+        }
+
+        let key = match self.read_byte()? {
+            b'A' => Key::Up,
+            b'B' => Key::Down,
+            b'C' => Key::Right,
+            b'D' => Key::Left,
+            b'H' => Key::Home,
+            b'F' => Key::End,
+            mut byte => {
+                // `ESC [ n ~` keys, by their first parameter, skipping the
+                // rest of sequences we don't handle
+                let (mut number, mut first) = (0u32, true);
+                while byte.is_ascii_digit() || byte == b';' {
+                    if byte == b';' {
+                        first = false;
+                    } else if first {
+                        number = number.saturating_mul(10).saturating_add(u32::from(byte - b'0'));
+                    }
+                    byte = self.read_byte()?;
+                }
+                match (number, byte) {
+                    (1 | 7, b'~') => Key::Home,
+                    (4 | 8, b'~') => Key::End,
+                    (5, b'~') => Key::PageUp,
+                    (6, b'~') => Key::PageDown,
+                    _ => Key::Unknown,
+                }
+            }
+        };
+
+        Ok(key)
+    }
+
+    fn read_utf8(&mut self, first: u8) -> io::Result<Key> {
+        let len = if first >= 0xf0 {
+            4
+        } else if first >= 0xe0 {
+            3
+        } else {
+            2
+        };
+
+        let mut bytes = vec![first];
+        for _ in 1..len {
+            bytes.push(self.read_byte()?);
+        }
+
+        Ok(std::str::from_utf8(&bytes)
+            .ok()
+            .and_then(|s| s.chars().next())
+            .map(Key::Char)
+            .unwrap_or(Key::Unknown))
+    }
+
+    fn read_byte(&mut self) -> io::Result<u8> {
+        let mut buf = [0u8; 1];
+        loop {
+            match self.tty.read(&mut buf) {
+                Ok(0) => return Err(io::Error::from(io::ErrorKind::UnexpectedEof)),
+                Ok(_) => return Ok(buf[0]),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    fn poll_input(&self, timeout_ms: i32) -> io::Result<bool> {
+        let mut pollfd = libc::pollfd {
+            fd: self.tty.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        loop {
+            let result = unsafe { libc::poll(&mut pollfd, 1, timeout_ms) };
+            if result >= 0 {
+                return Ok(result > 0);
+            }
+            // SIGWINCH itself may cut the wait short
+            let error = io::Error::last_os_error();
+            if error.kind() != io::ErrorKind::Interrupted {
+                return Err(error);
+            }
+        }
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        unsafe { libc::tcsetattr(self.tty.as_raw_fd(), libc::TCSANOW, &self.original) };
+    }
+}
+
+/// Which of a list's rows is selected and which are scrolled into view,
+/// for widgets that show more entries than fit on the screen
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct ListView {
+    selected: usize,
+    /// The first row shown
+    offset: usize,
+}
+
+impl ListView {
+    pub fn selected(&self) -> usize {
+        self.selected
+    }
+
+    /// Select the row at `index`, as when the list is filtered anew
+    pub fn select(&mut self, index: usize) {
+        self.selected = index;
+    }
+
+    /// Move the selection for a navigation key among `len` rows with
+    /// `visible` of them on screen. False for any other key, which the
+    /// widget handles itself.
+    pub fn handle_key(&mut self, key: Key, len: usize, visible: usize) -> bool {
+        let last = len.saturating_sub(1);
+        self.selected = match key {
+            Key::Up | Key::Ctrl('p') => self.selected.saturating_sub(1),
+            Key::Down | Key::Ctrl('n') => (self.selected + 1).min(last),
+            Key::PageUp => self.selected.saturating_sub(visible.max(1)),
+            Key::PageDown => (self.selected + visible.max(1)).min(last),
+            Key::Home => 0,
+            Key::End => last,
+            _ => return false,
+        };
+        true
+    }
+
+    /// Keep the selection among `len` rows and scroll it into view with
+    /// `visible` rows on screen, which may have changed with the window
+    pub fn scroll(&mut self, len: usize, visible: usize) {
+        let visible = visible.max(1);
+        self.selected = self.selected.min(len.saturating_sub(1));
+        if self.selected < self.offset {
+            self.offset = self.selected;
+        } else if self.selected >= self.offset + visible {
+            self.offset = self.selected + 1 - visible;
+        }
+        // Don't leave rows blank below the end of a list that fits
+        self.offset = self.offset.min(len.saturating_sub(visible));
+    }
+
+    /// The rows from `rows` that are in view, one line each, cut to `cols`
+    /// columns, with control characters blanked out and the selected one
+    /// marked and shown in reverse video
+    pub fn render<'a>(&self, rows: impl Iterator<Item = &'a str>, visible: usize, cols: usize) -> String {
+        let mut screen = String::new();
+        for (index, row) in rows.enumerate().skip(self.offset).take(visible.max(1)) {
+            let line: String = row
+                .chars()
+                .map(|c| if c.is_control() { ' ' } else { c })
+                .take(cols.saturating_sub(2))
+                .collect();
+            if index == self.selected {
+                screen.push_str(&format!("\x1b[7m> {}\x1b[0m\r\n", line));
+            } else {
+                screen.push_str(&format!("  {}\r\n", line));
+            }
+        }
+        screen
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::io::FromRawFd;
+
+    #[test]
+    fn test_list_view() {
+        let mut view = ListView::default();
+        assert!(view.handle_key(Key::Down, 10, 3));
+        assert!(view.handle_key(Key::PageDown, 10, 3));
+        assert_eq!(view.selected(), 4);
+        view.scroll(10, 3);
+        let rows = ["a", "b", "c", "d", "e\tf", "g"];
+        assert_eq!(view.render(rows.iter().copied(), 3, 80), "  c\r\n  d\r\n\x1b[7m> e f\x1b[0m\r\n");
+
+        assert!(view.handle_key(Key::End, 10, 3));
+        assert_eq!(view.selected(), 9);
+        assert!(!view.handle_key(Key::Char('x'), 10, 3));
+        // The list shrinks, as when it is filtered, and the window grows
+        view.scroll(2, 5);
+        assert_eq!(view.selected(), 1);
+        assert_eq!(view.render(["x", "y"].into_iter(), 5, 80), "  x\r\n\x1b[7m> y\x1b[0m\r\n");
+        assert!(view.handle_key(Key::Home, 2, 5));
+        assert!(view.handle_key(Key::Up, 2, 5));
+        assert_eq!(view.selected(), 0);
+    }
+
+    #[test]
+    fn test_keys_and_resizing() {
+        let (mut master, mut slave) = (0, 0);
+        let mut winsize = libc::winsize { ws_row: 20, ws_col: 60, ws_xpixel: 0, ws_ypixel: 0 };
+        let result = unsafe { libc::openpty(&mut master, &mut slave, std::ptr::null_mut(), std::ptr::null(), &winsize) };
+        assert_eq!(result, 0);
+        let mut master = unsafe { File::from_raw_fd(master) };
+        let mut terminal = Terminal::raw(unsafe { File::from_raw_fd(slave) }).unwrap();
+        assert_eq!(terminal.size(), (20, 60));
+
+        master.write_all("a\x1b[A\x1b[5~\x1b[1;5H\x1b[F\x03é\r".as_bytes()).unwrap();
+        let keys: Vec<Key> = (0..8).map(|_| terminal.read_key().unwrap()).collect();
+        assert_eq!(
+            keys,
+            [Key::Char('a'), Key::Up, Key::PageUp, Key::Unknown, Key::End, Key::Ctrl('c'), Key::Char('é'), Key::Enter]
+        );
+
+        winsize.ws_row = 30;
+        unsafe { libc::ioctl(master.as_raw_fd(), libc::TIOCSWINSZ, &winsize) };
+        assert_eq!(terminal.read_key().unwrap(), Key::Resize);
+        assert_eq!(terminal.size(), (30, 60));
+    }
+}
@@ -0,0 +1,232 @@
+use crate::ast::*;
+
+/// Walks a command tree. Each method defaults to visiting the node's
+/// children through the matching `walk_` function, so an implementation
+/// overrides only the nodes it cares about, and calls `walk_` itself to
+/// keep descending below them.
+///
+/// `for` words and `case` patterns are plain strings rather than `Word`s,
+/// and are not visited.
+pub trait Visitor {
+    fn visit_command(&mut self, command: &Command) {
+        walk_command(self, command);
+    }
+
+    fn visit_simple_command(&mut self, command: &SimpleCommand) {
+        walk_simple_command(self, command);
+    }
+
+    fn visit_assignment(&mut self, _assignment: &Assignment) {}
+
+    fn visit_word(&mut self, word: &Word) {
+        walk_word(self, word);
+    }
+
+    fn visit_redirection(&mut self, _redirection: &Redirection) {}
+}
+
+pub fn walk_command<V: Visitor + ?Sized>(visitor: &mut V, command: &Command) {
+    match command {
+        Command::Simple(cmd) => visitor.visit_simple_command(cmd),
+        Command::Pipeline(pipeline) => {
+            for stage in &pipeline.commands {
+                visitor.visit_command(stage);
+            }
+        }
+        Command::List(list) => {
+            for item in &list.items {
+                visitor.visit_command(&item.command);
+            }
+        }
+        Command::Subshell(inner) | Command::Group(inner) => visitor.visit_command(inner),
+        Command::If(if_cmd) => {
+            visitor.visit_command(&if_cmd.condition);
+            visitor.visit_command(&if_cmd.then_part);
+            for (condition, body) in &if_cmd.elif_parts {
+                visitor.visit_command(condition);
+                visitor.visit_command(body);
+            }
+            if let Some(else_part) = &if_cmd.else_part {
+                visitor.visit_command(else_part);
+            }
+        }
+        Command::While(WhileCommand { condition, body }) | Command::Until(UntilCommand { condition, body }) => {
+            visitor.visit_command(condition);
+            visitor.visit_command(body);
+        }
+        Command::For(for_cmd) => visitor.visit_command(&for_cmd.body),
+        Command::Case(case_cmd) => {
+            for clause in &case_cmd.cases {
+                visitor.visit_command(&clause.body);
+            }
+        }
+        Command::FunctionDef(def) => visitor.visit_command(&def.body),
+        Command::Redirected(redirected) => {
+            visitor.visit_command(&redirected.command);
+            for redirection in &redirected.redirections {
+                visitor.visit_redirection(redirection);
+            }
+        }
+        Command::Timed(timed) => visitor.visit_command(&timed.command),
+        Command::Arithmetic(_) => {}
+    }
+}
+
+pub fn walk_simple_command<V: Visitor + ?Sized>(visitor: &mut V, command: &SimpleCommand) {
+    for assignment in &command.assignments {
+        visitor.visit_assignment(assignment);
+    }
+    for word in &command.words {
+        visitor.visit_word(word);
+    }
+    for redirection in &command.redirections {
+        visitor.visit_redirection(redirection);
+    }
+}
+
+/// Visit the commands of the word's `$(...)` substitutions
+pub fn walk_word<V: Visitor + ?Sized>(visitor: &mut V, word: &Word) {
+    for command in word.substitutions.iter().flatten() {
+        visitor.visit_command(command);
+    }
+}
+
+/// Like `Visitor`, but with mutable access to rewrite the tree in place
+#[allow(dead_code)]
+pub trait VisitorMut {
+    fn visit_command_mut(&mut self, command: &mut Command) {
+        walk_command_mut(self, command);
+    }
+
+    fn visit_simple_command_mut(&mut self, command: &mut SimpleCommand) {
+        walk_simple_command_mut(self, command);
+    }
+
+    fn visit_assignment_mut(&mut self, _assignment: &mut Assignment) {}
+
+    fn visit_word_mut(&mut self, word: &mut Word) {
+        walk_word_mut(self, word);
+    }
+
+    fn visit_redirection_mut(&mut self, _redirection: &mut Redirection) {}
+}
+
+#[allow(dead_code)]
+pub fn walk_command_mut<V: VisitorMut + ?Sized>(visitor: &mut V, command: &mut Command) {
+    match command {
+        Command::Simple(cmd) => visitor.visit_simple_command_mut(cmd),
+        Command::Pipeline(pipeline) => {
+            for stage in &mut pipeline.commands {
+                visitor.visit_command_mut(stage);
+            }
+        }
+        Command::List(list) => {
+            for item in &mut list.items {
+                visitor.visit_command_mut(&mut item.command);
+            }
+        }
+        Command::Subshell(inner) | Command::Group(inner) => visitor.visit_command_mut(inner),
+        Command::If(if_cmd) => {
+            visitor.visit_command_mut(&mut if_cmd.condition);
+            visitor.visit_command_mut(&mut if_cmd.then_part);
+            for (condition, body) in &mut if_cmd.elif_parts {
+                visitor.visit_command_mut(condition);
+                visitor.visit_command_mut(body);
+            }
+            if let Some(else_part) = &mut if_cmd.else_part {
+                visitor.visit_command_mut(else_part);
+            }
+        }
+        Command::While(WhileCommand { condition, body }) | Command::Until(UntilCommand { condition, body }) => {
+            visitor.visit_command_mut(condition);
+            visitor.visit_command_mut(body);
+        }
+        Command::For(for_cmd) => visitor.visit_command_mut(&mut for_cmd.body),
+        Command::Case(case_cmd) => {
+            for clause in &mut case_cmd.cases {
+                visitor.visit_command_mut(&mut clause.body);
+            }
+        }
+        Command::FunctionDef(def) => visitor.visit_command_mut(&mut def.body),
+        Command::Redirected(redirected) => {
+            visitor.visit_command_mut(&mut redirected.command);
+            for redirection in &mut redirected.redirections {
+                visitor.visit_redirection_mut(redirection);
+            }
+        }
+        Command::Timed(timed) => visitor.visit_command_mut(&mut timed.command),
+        Command::Arithmetic(_) => {}
+    }
+}
+
+#[allow(dead_code)]
+pub fn walk_simple_command_mut<V: VisitorMut + ?Sized>(visitor: &mut V, command: &mut SimpleCommand) {
+    for assignment in &mut command.assignments {
+        visitor.visit_assignment_mut(assignment);
+    }
+    for word in &mut command.words {
+        visitor.visit_word_mut(word);
+    }
+    for redirection in &mut command.redirections {
+        visitor.visit_redirection_mut(redirection);
+    }
+}
+
+#[allow(dead_code)]
+pub fn walk_word_mut<V: VisitorMut + ?Sized>(visitor: &mut V, word: &mut Word) {
+    for command in word.substitutions.iter_mut().flatten() {
+        visitor.visit_command_mut(command);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::script;
+
+    #[derive(Default)]
+    struct Collector {
+        words: Vec<String>,
+        redirections: usize,
+    }
+
+    impl Visitor for Collector {
+        fn visit_word(&mut self, word: &Word) {
+            self.words.push(word.value.clone());
+            walk_word(self, word);
+        }
+
+        fn visit_redirection(&mut self, _redirection: &Redirection) {
+            self.redirections += 1;
+        }
+    }
+
+    /// Run every command through `timeout`, as instrumentation might
+    struct AddTimeout;
+
+    impl VisitorMut for AddTimeout {
+        fn visit_simple_command_mut(&mut self, command: &mut SimpleCommand) {
+            if !command.words.is_empty() {
+                command.words.splice(0..0, [Word::new("timeout"), Word::new("5")]);
+            }
+        }
+    }
+
+    #[test]
+    fn test_visitors() {
+        let source = "if true; then f() { a 1 > x; }; fi; while b | c; do time d $(e); done < y";
+        let mut commands = script::parse(source).unwrap();
+
+        let mut collector = Collector::default();
+        commands.iter().for_each(|command| collector.visit_command(command));
+        assert_eq!(collector.words, ["true", "a", "1", "b", "c", "d", "$(e)", "e"]);
+        assert_eq!(collector.redirections, 2);
+
+        commands.iter_mut().for_each(|command| AddTimeout.visit_command_mut(command));
+        assert_eq!(
+            commands[0].to_string(),
+            "if timeout 5 true; then f() { timeout 5 a 1 > x; }; fi; \
+             while timeout 5 b | timeout 5 c; do time timeout 5 d $(e); done < y"
+        );
+    }
+}
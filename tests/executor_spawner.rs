@@ -0,0 +1,210 @@
+//! Scripts run with a fake spawner, checking what each external command
+//! would have been started with: its arguments, environment, working
+//! directory and fds
+
+use clam_shell::executor::Executor;
+use clam_shell::lexer::Lexer;
+use clam_shell::parser::Parser;
+use clam_shell::spawner::{Spawned, Spawner};
+use std::collections::{BTreeMap, HashMap};
+use std::os::unix::io::RawFd;
+use std::path::{Path, PathBuf};
+use std::process::Command as ProcessCommand;
+use std::sync::{Arc, Mutex};
+
+/// Held by tests that redirect the fds of the test process, which all its
+/// threads share
+static FD_LOCK: Mutex<()> = Mutex::new(());
+
+fn run(executor: &mut Executor, input: &str) -> Result<i32, String> {
+    let tokens = Lexer::new(input).tokenize()?;
+    let commands = Parser::new(tokens).parse()?;
+    let mut status = 0;
+    for command in &commands {
+        status = executor.execute(command)?;
+    }
+    Ok(status)
+}
+
+fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("clam-test-{}-{}", std::process::id(), name))
+}
+
+/// A file an fd is open on, as its device and inode numbers
+type FileId = (u64, u64);
+
+fn file_id(fd: RawFd) -> Option<FileId> {
+    let mut stat: libc::stat = unsafe { std::mem::zeroed() };
+    (unsafe { libc::fstat(fd, &mut stat) } == 0).then_some((stat.st_dev as u64, stat.st_ino as u64))
+}
+
+fn path_id(path: &Path) -> FileId {
+    let metadata = std::fs::metadata(path).unwrap();
+    (std::os::unix::fs::MetadataExt::dev(&metadata), std::os::unix::fs::MetadataExt::ino(&metadata))
+}
+
+/// A command the fake spawner was asked to start
+#[derive(Debug)]
+struct Spawn {
+    argv: Vec<String>,
+    /// The variables set for it, beyond those of the test process
+    env: BTreeMap<String, String>,
+    cwd: Option<PathBuf>,
+    /// The fds below 10 it would inherit, and the file each is open on
+    fds: BTreeMap<RawFd, FileId>,
+}
+
+/// Records the commands it is asked to start, each of which exits at once
+/// with the status given for its name, or 0. Those named `missing-*` are
+/// not found.
+#[derive(Clone, Default)]
+struct FakeSpawner {
+    spawned: Arc<Mutex<Vec<Spawn>>>,
+    statuses: HashMap<String, i32>,
+}
+
+impl FakeSpawner {
+    /// The commands started since the last call
+    fn take(&self) -> Vec<Spawn> {
+        std::mem::take(&mut *self.spawned.lock().unwrap())
+    }
+}
+
+impl Spawner for FakeSpawner {
+    fn spawn(&mut self, process: &mut ProcessCommand) -> std::io::Result<Spawned> {
+        let program = process.get_program().to_string_lossy().into_owned();
+        if program.starts_with("missing-") {
+            return Err(std::io::ErrorKind::NotFound.into());
+        }
+        let args = process.get_args().map(|arg| arg.to_string_lossy().into_owned());
+        let env = process
+            .get_envs()
+            .filter_map(|(name, value)| Some((name.to_string_lossy().into_owned(), value?.to_string_lossy().into_owned())))
+            .collect();
+        // Closed fds fail the close-on-exec check too
+        let fds = (0..10)
+            .filter(|&fd| unsafe { libc::fcntl(fd, libc::F_GETFD) } & libc::FD_CLOEXEC == 0)
+            .filter_map(|fd| Some((fd, file_id(fd)?)))
+            .collect();
+        self.spawned.lock().unwrap().push(Spawn {
+            argv: std::iter::once(program.clone()).chain(args).collect(),
+            env,
+            cwd: process.get_current_dir().map(PathBuf::from),
+            fds,
+        });
+        Ok(Spawned::Exited(self.statuses.get(&program).copied().unwrap_or(0)))
+    }
+}
+
+fn fake_executor(statuses: &[(&str, i32)]) -> (Executor, FakeSpawner) {
+    let spawner = FakeSpawner {
+        statuses: statuses.iter().map(|(name, status)| (name.to_string(), *status)).collect(),
+        ..FakeSpawner::default()
+    };
+    let mut executor = Executor::new();
+    executor.set_spawner(Box::new(spawner.clone()));
+    (executor, spawner)
+}
+
+fn argvs(spawned: &[Spawn]) -> Vec<Vec<&str>> {
+    spawned.iter().map(|spawn| spawn.argv.iter().map(String::as_str).collect()).collect()
+}
+
+#[test]
+fn test_arguments() {
+    let (mut executor, spawner) = fake_executor(&[]);
+    executor.set_variable("V", "two  words");
+    executor.set_variable("EMPTY", "");
+
+    run(&mut executor, "compile -o 'out file' $V \"$V\" $EMPTY x{1,2} \"a\"'b'c").unwrap();
+    assert_eq!(argvs(&spawner.take()), [["compile", "-o", "out file", "two", "words", "two  words", "x1", "x2", "abc"]]);
+
+    run(&mut executor, "for f in a b; do compile $f; done; f() { compile $2 $1; }; f 1 2").unwrap();
+    assert_eq!(argvs(&spawner.take()), [vec!["compile", "a"], vec!["compile", "b"], vec!["compile", "2", "1"]]);
+
+    run(&mut executor, "g() { compile \"$@\" \"\"; }; g 1 '2 3'").unwrap();
+    assert_eq!(argvs(&spawner.take()), [["compile", "1", "2 3", ""]]);
+}
+
+#[test]
+fn test_environment() {
+    let (mut executor, spawner) = fake_executor(&[]);
+    executor.set_variable("SHELL_VAR", "kept");
+    executor.set_variable("N", "3");
+
+    run(&mut executor, "MODE=release LEVEL=$N compile").unwrap();
+    let spawned = spawner.take();
+    assert_eq!(spawned[0].env.get("MODE").map(String::as_str), Some("release"));
+    assert_eq!(spawned[0].env.get("LEVEL").map(String::as_str), Some("3"));
+    assert_eq!(spawned[0].env.get("SHELL_VAR").map(String::as_str), Some("kept"));
+    assert_eq!(spawned[0].cwd, None);
+    // The assignments are only for the command
+    assert_eq!(executor.get_variable("MODE"), "");
+
+    run(&mut executor, "with --cwd / --env TARGET=arm compile").unwrap();
+    let spawned = spawner.take();
+    assert_eq!(spawned[0].cwd.as_deref(), Some(Path::new("/")));
+    assert_eq!(spawned[0].env.get("TARGET").map(String::as_str), Some("arm"));
+}
+
+#[test]
+fn test_priority() {
+    let _guard = FD_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let (mut executor, spawner) = fake_executor(&[]);
+
+    run(&mut executor, "prio -n 10 --io idle -- compile -O2; prio --io best-effort:7 link").unwrap();
+    assert_eq!(argvs(&spawner.take()), [vec!["compile", "-O2"], vec!["link"]]);
+
+    assert_eq!(run(&mut executor, "prio --io fast compile 2> /dev/null").unwrap(), 1);
+    assert_eq!(run(&mut executor, "prio -n ten compile 2> /dev/null").unwrap(), 1);
+    assert_eq!(run(&mut executor, "prio -n 1 2> /dev/null").unwrap(), 2);
+    assert!(spawner.take().is_empty());
+}
+
+#[test]
+fn test_redirections() {
+    let _guard = FD_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let (input, output) = (temp_path("spawn-input"), temp_path("spawn-output"));
+    std::fs::write(&input, "").unwrap();
+    let (mut executor, spawner) = fake_executor(&[]);
+    executor.set_variable("IN", &input.display().to_string());
+    executor.set_variable("OUT", &output.display().to_string());
+    let stdout = file_id(1).unwrap();
+
+    run(&mut executor, "compile < $IN > $OUT 2>&1").unwrap();
+    let (input_id, output_id) = (path_id(&input), path_id(&output));
+    let fds = &spawner.take()[0].fds;
+    assert_eq!(fds.get(&0), Some(&input_id));
+    assert_eq!(fds.get(&1), Some(&output_id));
+    assert_eq!(fds.get(&2), Some(&output_id));
+    assert_eq!(file_id(1), Some(stdout));
+
+    // Order matters: stderr is copied from stdout before it is redirected
+    run(&mut executor, "compile 2>&1 >> $OUT 3< $IN").unwrap();
+    let fds = &spawner.take()[0].fds;
+    assert_eq!(fds.get(&1), Some(&output_id));
+    assert_eq!(fds.get(&2), Some(&stdout));
+    assert_eq!(fds.get(&3), Some(&input_id));
+
+    // Closed fds aren't passed on, nor those the shell keeps for itself
+    run(&mut executor, "compile <&-").unwrap();
+    let fds = &spawner.take()[0].fds;
+    assert_eq!(fds.keys().copied().collect::<Vec<_>>(), [1, 2]);
+    let _ = std::fs::remove_file(&input);
+    let _ = std::fs::remove_file(&output);
+}
+
+#[test]
+fn test_statuses() {
+    let (mut executor, spawner) = fake_executor(&[("fails", 3)]);
+
+    assert_eq!(run(&mut executor, "fails").unwrap(), 3);
+    assert_eq!(executor.get_variable("?"), "3");
+    run(&mut executor, "fails && skipped; fails || recovered; if fails; then no; else yes; fi").unwrap();
+    assert_eq!(argvs(&spawner.take()), [vec!["fails"], vec!["fails"], vec!["fails"], vec!["recovered"], vec!["fails"], vec!["yes"]]);
+
+    assert_eq!(run(&mut executor, "missing-tool arg").unwrap(), 127);
+    // Builtins and functions aren't spawned
+    assert_eq!(run(&mut executor, "x=1; let x+=1; g() { return 4; }; g").unwrap(), 4);
+    assert!(spawner.take().is_empty());
+}
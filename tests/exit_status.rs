@@ -0,0 +1,29 @@
+//! The status the shell exits with once its input runs out
+
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+/// Run `input` through the shell and return the status it exits with
+fn exit_status(input: &str) -> Option<i32> {
+    let dir = std::env::temp_dir().join(format!("clam-test-{}-exit-status", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    // Started there, so the history file isn't left in the source tree
+    let mut shell = Command::new(env!("CARGO_BIN_EXE_clam-shell"))
+        .current_dir(&dir)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    shell.stdin.take().unwrap().write_all(input.as_bytes()).unwrap();
+    let status = shell.wait().unwrap().code();
+    let _ = std::fs::remove_dir_all(&dir);
+    status
+}
+
+#[test]
+fn test_exits_with_last_status() {
+    assert_eq!(exit_status(""), Some(0));
+    assert_eq!(exit_status("true\nfalse\n"), Some(1));
+    assert_eq!(exit_status("false\ntrue\n"), Some(0));
+}